@@ -1,15 +1,1219 @@
+#include <setjmp.h>
+#include <stdint.h>
 #include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
 
-int read_int() {
+int64_t *free_ptr;
+int64_t *fromspace_begin;
+int64_t *fromspace_end;
+static int argc;
+static char **argv;
+/* state of the generator of random, RANDOM_SEED replaces the fixed seed */
+static uint64_t random_state = 0x2545f4914f6cdd1d;
+/* values are printed to stdout, or to stderr by error */
+static FILE *output;
+
+/* The heap is a list of chunks, each one is a sequence of blocks walked by their
+   headers. A vector has bit 0 set and its length in bits 1-6, an array has bits 0
+   and 63 set. Bit 0 is clear in the headers written by the runtime, which have the
+   size in words from bit 2: bit 1 is set for a string, whose length and bytes follow
+   the header, or a big integer, whose words are not pointers either, and clear for a
+   free block.
+
+   The collector marks the blocks reachable from the stack, the registers and the data
+   of the program, a word which looks like a pointer to a block is taken as a pointer,
+   so the blocks never move and the others are freed in place. The free pointer bumps
+   through the free blocks in turn, the next collection comes when they are used up. */
+
+#define MAX_CHUNKS 48
+#define FREE_HEADER(words) ((int64_t)(words) << 2)
+#define STRING_HEADER(words) ((int64_t)(words) << 2 | 2)
+
+/* what the word of a chunk references */
+#define REF_OBJECT 1
+#define REF_STRING 2
+#define REF_MARKED 4
+
+typedef struct chunk {
+  int64_t *begin;
+  int64_t *end;
+  uint8_t *refs;
+} chunk;
+
+static chunk chunks[MAX_CHUNKS];
+static int64_t chunk_count;
+static int64_t heap_words;
+/* the bounds of the chunks, to skip the words which are not pointers */
+static int64_t *heap_low;
+static int64_t *heap_high;
+
+/* the free blocks in address order, the free pointer is in the one before next */
+static int64_t **free_blocks;
+static int64_t free_count;
+static int64_t free_cap;
+static int64_t free_next;
+
+static int64_t **mark_stack;
+static int64_t mark_count;
+static int64_t mark_cap;
+
+extern void *__libc_stack_end;
+extern char __data_start[];
+extern char _end[];
+
+static void out_of_memory() {
+  fprintf(stderr, "error: out of memory\n");
+  exit(1);
+}
+
+static void push_free_block(int64_t *block) {
+  if (free_count == free_cap) {
+    free_cap = free_cap == 0 ? 64 : free_cap * 2;
+    free_blocks = realloc(free_blocks, free_cap * sizeof(int64_t *));
+    if (free_blocks == NULL) {
+      out_of_memory();
+    }
+  }
+  free_blocks[free_count++] = block;
+}
+
+static void add_chunk(int64_t words) {
+  chunk *c;
+  if (chunk_count == MAX_CHUNKS) {
+    out_of_memory();
+  }
+  c = &chunks[chunk_count++];
+  c->begin = malloc(words * sizeof(int64_t));
+  c->refs = malloc(words);
+  if (c->begin == NULL || c->refs == NULL) {
+    out_of_memory();
+  }
+  c->end = c->begin + words;
+  c->begin[0] = FREE_HEADER(words);
+  if (heap_low == NULL || c->begin < heap_low) {
+    heap_low = c->begin;
+  }
+  if (c->end > heap_high) {
+    heap_high = c->end;
+  }
+  heap_words += words;
+  push_free_block(c->begin);
+}
+
+static int64_t block_words(int64_t *block) {
+  uint64_t header = block[0];
+  if ((header & 1) == 0) {
+    return header >> 2;
+  }
+  if (header >> 63) {
+    return 1 + (header >> 1 & (((uint64_t)1 << 61) - 1));
+  }
+  return 1 + (header >> 1 & 0x3f);
+}
+
+/* move the free pointer to the next free block with room for the bytes */
+static int take_free_block(int64_t bytes) {
+  while (free_next < free_count) {
+    int64_t *block = free_blocks[free_next++];
+    int64_t words = block_words(block);
+    if (words * (int64_t)sizeof(int64_t) > bytes) {
+      free_ptr = block;
+      fromspace_end = block + words;
+      return 1;
+    }
+  }
+  return 0;
+}
+
+static void mark_word(int64_t word) {
+  int64_t *p = (int64_t *)(word & ~7);
+  int64_t i;
+  if (p < heap_low || p >= heap_high) {
+    return;
+  }
+  for (i = 0; i < chunk_count; i++) {
+    chunk *c = &chunks[i];
+    if (p >= c->begin && p < c->end) {
+      uint8_t *ref = &c->refs[p - c->begin];
+      if (*ref == 0 || *ref & REF_MARKED) {
+        return;
+      }
+      *ref |= REF_MARKED;
+      if (*ref & REF_OBJECT) {
+        if (mark_count == mark_cap) {
+          mark_cap = mark_cap == 0 ? 1024 : mark_cap * 2;
+          mark_stack = realloc(mark_stack, mark_cap * sizeof(int64_t *));
+          if (mark_stack == NULL) {
+            out_of_memory();
+          }
+        }
+        mark_stack[mark_count++] = p;
+      }
+      return;
+    }
+  }
+}
+
+static int is_marked(int64_t *p) {
+  int64_t i;
+  for (i = 0; i < chunk_count; i++) {
+    chunk *c = &chunks[i];
+    if (p >= c->begin && p < c->end) {
+      return c->refs[p - c->begin] & REF_MARKED;
+    }
+  }
+  return 0;
+}
+
+static void sweep_interned();
+
+static void mark_range(int64_t *begin, int64_t *end) {
+  for (; begin < end; begin++) {
+    mark_word(*begin);
+  }
+}
+
+/* the registers are saved on the stack by collect */
+__attribute__((noinline)) static void mark_and_sweep() {
+  int64_t stack_top;
+  int64_t i;
+  int64_t *p;
+  for (i = 0; i < chunk_count; i++) {
+    chunk *c = &chunks[i];
+    memset(c->refs, 0, c->end - c->begin);
+    for (p = c->begin; p < c->end; p += block_words(p)) {
+      if (p[0] & 1) {
+        c->refs[p - c->begin] = REF_OBJECT;
+      } else if (p[0] & 2) {
+        c->refs[p + 1 - c->begin] = REF_STRING;
+      }
+    }
+  }
+
+  mark_range(&stack_top, (int64_t *)__libc_stack_end);
+  mark_range((int64_t *)__data_start, (int64_t *)_end);
+  while (mark_count > 0) {
+    p = mark_stack[--mark_count];
+    mark_range(p + 1, p + block_words(p));
+  }
+  sweep_interned();
+
+  free_count = 0;
+  free_next = 0;
+  for (i = 0; i < chunk_count; i++) {
+    chunk *c = &chunks[i];
+    int64_t *free_begin = NULL;
+    for (p = c->begin; p < c->end; p += block_words(p)) {
+      int64_t ref = p[0] & 1 ? p - c->begin : p + 1 - c->begin;
+      if (c->refs[ref] & REF_MARKED) {
+        if (free_begin != NULL) {
+          free_begin[0] = FREE_HEADER(p - free_begin);
+          push_free_block(free_begin);
+          free_begin = NULL;
+        }
+      } else if (free_begin == NULL) {
+        free_begin = p;
+      }
+    }
+    if (free_begin != NULL) {
+      free_begin[0] = FREE_HEADER(c->end - free_begin);
+      push_free_block(free_begin);
+    }
+  }
+}
+
+/* called when the bytes do not fit before the end of the free block */
+void collect(int64_t bytes) {
+  int64_t i;
+  int64_t free_words = 0;
+  if (free_ptr < fromspace_end) {
+    free_ptr[0] = FREE_HEADER(fromspace_end - free_ptr);
+  }
+  if (take_free_block(bytes)) {
+    return;
+  }
+  __builtin_unwind_init();
+  mark_and_sweep();
+  for (i = 0; i < free_count; i++) {
+    free_words += block_words(free_blocks[i]);
+  }
+  /* the heap doubles when the live blocks take more than half of it */
+  if (free_words < heap_words / 2) {
+    add_chunk(heap_words);
+  }
+  if (!take_free_block(bytes)) {
+    add_chunk(bytes / sizeof(int64_t) + 1);
+    take_free_block(bytes);
+  }
+}
+
+/* the blocks allocated by the runtime are in the heap too */
+static int64_t *allocate(int64_t words) {
+  int64_t *block;
+  if (words >= fromspace_end - free_ptr) {
+    collect(words * sizeof(int64_t));
+  }
+  block = free_ptr;
+  free_ptr += words;
+  return block;
+}
+
+/* the header of the block is before the length of the string */
+static int64_t *allocate_string(int64_t len) {
+  int64_t words = 2 + (len + sizeof(int64_t) - 1) / sizeof(int64_t);
+  int64_t *block = allocate(words);
+  block[0] = STRING_HEADER(words);
+  block[1] = len;
+  return block + 1;
+}
+
+void initialize(uint64_t heap_size, int64_t main_argc, char **main_argv) {
+  argc = main_argc;
+  argv = main_argv;
+  const char *seed = getenv("RANDOM_SEED");
+  if (seed != NULL) {
+    random_state = strtoull(seed, NULL, 10);
+  }
+  output = stdout;
+  add_chunk(heap_size / sizeof(int64_t));
+  fromspace_begin = chunks[0].begin;
+  take_free_block(0);
+}
+
+static void input_failed(const char *msg) {
+  fprintf(stderr, "error: %s\n", msg);
+  exit(1);
+}
+
+static int is_space(int c) {
+  return c == ' ' || c == '\t' || c == '\n' || c == '\r';
+}
+
+/* the first character after the whitespace */
+static int skip_space() {
   int c;
-  int n = 0;
-  while (1) {
+  do {
+    c = getchar();
+  } while (is_space(c));
+  return c;
+}
+
+/* fixnums are 64 bits, an optional sign and the digits are read,
+   the character after the digits is left in the input */
+/* the magnitude is accumulated unsigned, so the minimum can be read too */
+int64_t read_int() {
+  int64_t sign = 1;
+  uint64_t n = 0;
+  int c = skip_space();
+  if (c == '-') {
+    sign = -1;
+    c = getchar();
+  }
+  if (c < '0' || c > '9') {
+    input_failed("malformed integer input");
+  }
+  uint64_t limit = sign > 0 ? INT64_MAX : (uint64_t)INT64_MAX + 1;
+  for (; c >= '0' && c <= '9'; c = getchar()) {
+    uint64_t digit = c - '0';
+    if (n > (limit - digit) / 10) {
+      input_failed("malformed integer input");
+    }
+    n = n * 10 + digit;
+  }
+  ungetc(c, stdin);
+  return sign > 0 ? (int64_t)n : (int64_t)(0 - n);
+}
+
+/* a boolean is #t, #f, #true or #false */
+int64_t read_bool() {
+  char buf[8];
+  int64_t len = 0;
+  int c = skip_space();
+  for (; c != EOF && !is_space(c); c = getchar()) {
+    if (len == sizeof(buf) - 1) {
+      input_failed("malformed boolean input");
+    }
+    buf[len++] = c;
+  }
+  ungetc(c, stdin);
+  buf[len] = '\0';
+  if (strcmp(buf, "#t") == 0 || strcmp(buf, "#true") == 0) {
+    return 1;
+  }
+  if (strcmp(buf, "#f") == 0 || strcmp(buf, "#false") == 0) {
+    return 0;
+  }
+  input_failed("malformed boolean input");
+  return 0;
+}
+
+/* a character is decoded from UTF-8, whitespace is not skipped */
+int64_t read_char() {
+  int c = getchar();
+  int64_t n;
+  int64_t code;
+  if (c == EOF) {
+    input_failed("unexpected end of input");
+  }
+  if (c < 0x80) {
+    return c;
+  } else if ((c & 0xe0) == 0xc0) {
+    n = 1;
+    code = c & 0x1f;
+  } else if ((c & 0xf0) == 0xe0) {
+    n = 2;
+    code = c & 0x0f;
+  } else if ((c & 0xf8) == 0xf0) {
+    n = 3;
+    code = c & 0x07;
+  } else {
+    input_failed("malformed character input");
+    return 0;
+  }
+  for (; n > 0; n--) {
+    c = getchar();
+    if ((c & 0xc0) != 0x80) {
+      input_failed("malformed character input");
+    }
+    code = code << 6 | (c & 0x3f);
+  }
+  return code;
+}
+
+void print_int(int64_t i) { fprintf(output, "%ld", i); }
+
+void print_bool(int64_t b) { fputs(b ? "#t" : "#f", output); }
+
+/* a character is its code point, which is written in UTF-8 */
+void print_char(int64_t c) {
+  if (c < 0x80) {
+    putc(c, output);
+  } else if (c < 0x800) {
+    putc(0xc0 | c >> 6, output);
+    putc(0x80 | (c & 0x3f), output);
+  } else if (c < 0x10000) {
+    putc(0xe0 | c >> 12, output);
+    putc(0x80 | (c >> 6 & 0x3f), output);
+    putc(0x80 | (c & 0x3f), output);
+  } else {
+    putc(0xf0 | c >> 18, output);
+    putc(0x80 | (c >> 12 & 0x3f), output);
+    putc(0x80 | (c >> 6 & 0x3f), output);
+    putc(0x80 | (c & 0x3f), output);
+  }
+}
+
+/* a string is the length followed by the bytes */
+/* a float is passed by its bits, it is written with a fraction like 1.0 */
+void print_float(int64_t bits) {
+  double d;
+  char buf[32];
+  memcpy(&d, &bits, sizeof(d));
+  snprintf(buf, sizeof(buf), "%.15g", d);
+  if (strspn(buf, "-0123456789") == strlen(buf)) {
+    strcat(buf, ".0");
+  }
+  fputs(buf, output);
+}
+
+void print_string(int64_t *s) { fwrite(s + 1, 1, s[0], output); }
+
+/* a handler is installed by the frame of with_handler,
+   a raise jumps back to the frame of the latest handler */
+typedef struct handler {
+  jmp_buf env;
+  struct handler *prev;
+} handler;
+
+static handler *handlers;
+static int64_t *raised;
+
+/* an exception without a handler aborts the program */
+void raise_exception(int64_t *msg) {
+  if (handlers == NULL) {
+    fflush(stdout);
+    output = stderr;
+    fputs("error: ", output);
+    print_string(msg);
+    putc('\n', output);
+    exit(1);
+  }
+  raised = msg;
+  longjmp(handlers->env, 1);
+}
+
+/* a closure is called with itself and the arguments,
+   the address of the function is the first element */
+int64_t with_handler(int64_t *thunk, int64_t *fun) {
+  handler h;
+  int64_t value;
+  h.prev = handlers;
+  if (setjmp(h.env) == 0) {
+    handlers = &h;
+    value = ((int64_t(*)(int64_t *))thunk[1])(thunk);
+    handlers = h.prev;
+    return value;
+  }
+  handlers = h.prev;
+  return ((int64_t(*)(int64_t *, int64_t *))fun[1])(fun, raised);
+}
+
+/* a continuation is a closure of the state saved by call_cc,
+   the state is cleared when call_cc returns */
+typedef struct continuation {
+  jmp_buf env;
+  handler *handlers;
+  int64_t value;
+} continuation;
+
+static void escape(int64_t *k, int64_t value) {
+  continuation *c = (continuation *)k[2];
+  if (c == NULL) {
+    fprintf(stderr, "error: continuation is called after call/cc returns\n");
+    exit(1);
+  }
+  c->value = value;
+  longjmp(c->env, 1);
+}
+
+int64_t call_cc(int64_t *fun) {
+  continuation c;
+  int64_t *k = allocate(3);
+  k[0] = 2 << 1 | 1;
+  k[1] = (int64_t)escape;
+  k[2] = (int64_t)&c;
+  c.handlers = handlers;
+  if (setjmp(c.env) == 0) {
+    c.value = ((int64_t(*)(int64_t *, int64_t *))fun[1])(fun, k);
+  }
+  handlers = c.handlers;
+  k[2] = 0;
+  return c.value;
+}
+
+static char *error_buf;
+static size_t error_len;
+
+/* the message and the value of error are printed to a buffer,
+   which becomes the message of the exception */
+void error_begin(int64_t *msg) {
+  output = open_memstream(&error_buf, &error_len);
+  print_string(msg);
+}
+
+void error_end() {
+  fclose(output);
+  output = stdout;
+  int64_t *s = allocate_string(error_len);
+  memcpy(s + 1, error_buf, error_len);
+  free(error_buf);
+  raise_exception(s);
+}
+
+/* the output of format is captured by a buffer, formats may be nested */
+typedef struct capture {
+  FILE *prev;
+  char *buf;
+  size_t len;
+  struct capture *next;
+} capture;
+
+static capture *captures;
+
+void format_begin() {
+  capture *c = malloc(sizeof(capture));
+  c->prev = output;
+  c->next = captures;
+  captures = c;
+  output = open_memstream(&c->buf, &c->len);
+}
+
+int64_t *format_end() {
+  capture *c = captures;
+  fclose(output);
+  output = c->prev;
+  captures = c->next;
+  int64_t *s = allocate_string(c->len);
+  memcpy(s + 1, c->buf, c->len);
+  free(c->buf);
+  free(c);
+  return s;
+}
+
+int64_t string_equal(int64_t *a, int64_t *b) {
+  return a[0] == b[0] && memcmp(a + 1, b + 1, a[0]) == 0;
+}
+
+int64_t *string_append(int64_t *a, int64_t *b) {
+  int64_t len = a[0] + b[0];
+  int64_t *s = allocate_string(len);
+  memcpy(s + 1, a + 1, a[0]);
+  memcpy((char *)(s + 1) + a[0], b + 1, b[0]);
+  return s;
+}
+
+/* an array is a header followed by the elements, the header has bit 0 set,
+   bit 63 set to tell it from a vector, bit 62 set if the elements are pointers
+   and the length in bits 1-61 */
+int64_t *make_array(int64_t len, int64_t init, int64_t is_pointer) {
+  int64_t i;
+  int64_t *array;
+  if (len < 0) {
+    fprintf(stderr, "error: negative array length\n");
+    exit(1);
+  }
+  array = allocate(1 + len);
+  array[0] = (int64_t)((uint64_t)1 << 63 | (uint64_t)is_pointer << 62 |
+                       (uint64_t)len << 1 | 1);
+  for (i = 0; i < len; i++) {
+    array[i + 1] = init;
+  }
+  return array;
+}
+
+/* an array of the arguments after the program name, each one is a string */
+int64_t *command_line_arguments() {
+  int64_t i;
+  int64_t *array = make_array(argc - 1, 0, 1);
+  for (i = 1; i < argc; i++) {
+    int64_t len = strlen(argv[i]);
+    int64_t *s = allocate_string(len);
+    memcpy(s + 1, argv[i], len);
+    array[i] = (int64_t)s;
+  }
+  return array;
+}
+
+/* a hash table chains the entries of each bucket and doubles the buckets when
+   the entries outnumber them. The table, its buckets and its entries are arrays
+   in the heap, so the collector traces them from the pointers to the table.
+   String keys are compared by their bytes, the other keys are words compared by
+   eq?, which compares big integers by their digits as they are interned. */
+typedef struct entry {
+  int64_t header;
+  struct entry *next;
+  int64_t key;
+  int64_t value;
+} entry;
+
+typedef struct hash_table {
+  int64_t header;
+  int64_t count;
+  int64_t len;
+  int64_t strings;
+  entry **buckets;
+} hash_table;
+
+/* a block of the words after the header, cleared */
+static int64_t *allocate_words(int64_t words) {
+  int64_t *block = allocate(1 + words);
+  block[0] = (int64_t)((uint64_t)1 << 63 | (uint64_t)1 << 62 |
+                       (uint64_t)words << 1 | 1);
+  memset(block + 1, 0, words * sizeof(int64_t));
+  return block;
+}
+
+static uint64_t hash_word(hash_table *table, int64_t key) {
+  uint64_t h = (uint64_t)key;
+  int64_t i;
+  if (table->strings) {
+    const char *bytes = (const char *)((int64_t *)key + 1);
+    h = 0xcbf29ce484222325;
+    for (i = 0; i < ((int64_t *)key)[0]; i++) {
+      h = (h ^ (uint8_t)bytes[i]) * 0x100000001b3;
+    }
+  }
+  h = (h ^ (h >> 30)) * 0xbf58476d1ce4e5b9;
+  h = (h ^ (h >> 27)) * 0x94d049bb133111eb;
+  return h ^ (h >> 31);
+}
+
+static int hash_same(hash_table *table, int64_t a, int64_t b) {
+  return a == b ||
+         (table->strings && string_equal((int64_t *)a, (int64_t *)b));
+}
+
+/* the buckets are the words after the header */
+static entry **hash_bucket(hash_table *table, entry **buckets, int64_t len,
+                           int64_t key) {
+  return &buckets[1 + hash_word(table, key) % len];
+}
+
+static hash_table *new_hash(int64_t strings) {
+  hash_table *table = (hash_table *)allocate_words(4);
+  table->strings = strings;
+  table->len = 8;
+  table->buckets = (entry **)allocate_words(table->len);
+  return table;
+}
+
+hash_table *make_hash() { return new_hash(0); }
+
+hash_table *make_string_hash() { return new_hash(1); }
+
+static entry *hash_find(hash_table *table, int64_t key) {
+  entry *e = *hash_bucket(table, table->buckets, table->len, key);
+  while (e != NULL && !hash_same(table, e->key, key)) {
+    e = e->next;
+  }
+  return e;
+}
+
+int64_t hash_ref(hash_table *table, int64_t key) {
+  entry *e = hash_find(table, key);
+  if (e == NULL) {
+    fprintf(stderr, "error: key not found in hash table\n");
+    exit(1);
+  }
+  return e->value;
+}
+
+int64_t hash_ref_default(hash_table *table, int64_t key, int64_t value) {
+  entry *e = hash_find(table, key);
+  return e == NULL ? value : e->value;
+}
+
+int64_t hash_set(hash_table *table, int64_t key, int64_t value) {
+  int64_t i;
+  entry **bucket;
+  entry *e = hash_find(table, key);
+  if (e != NULL) {
+    e->value = value;
+    return 0;
+  }
+  if (table->count >= table->len) {
+    entry **buckets = (entry **)allocate_words(table->len * 2);
+    for (i = 1; i <= table->len; i++) {
+      while (table->buckets[i] != NULL) {
+        e = table->buckets[i];
+        table->buckets[i] = e->next;
+        bucket = hash_bucket(table, buckets, table->len * 2, e->key);
+        e->next = *bucket;
+        *bucket = e;
+      }
+    }
+    table->buckets = buckets;
+    table->len *= 2;
+  }
+  e = (entry *)allocate_words(3);
+  e->key = key;
+  e->value = value;
+  bucket = hash_bucket(table, table->buckets, table->len, key);
+  e->next = *bucket;
+  *bucket = e;
+  table->count++;
+  return 0;
+}
+
+/* splitmix64, every state is a valid seed */
+int64_t random_int(int64_t bound) {
+  if (bound <= 0) {
+    fprintf(stderr, "error: random of a non-positive bound\n");
+    exit(1);
+  }
+  uint64_t z = random_state += 0x9e3779b97f4a7c15;
+  z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9;
+  z = (z ^ (z >> 27)) * 0x94d049bb133111eb;
+  return (int64_t)((z ^ (z >> 31)) % (uint64_t)bound);
+}
+
+void vector_index_out_of_range() {
+  fprintf(stderr, "error: vector index out of range\n");
+  exit(1);
+}
+
+void any_project_failed(int64_t line, int64_t col) {
+  fprintf(stderr, "error: %ld:%ld: value of Any has a different type\n", line,
+          col);
+  exit(1);
+}
+
+void overflow_failed() {
+  fprintf(stderr, "error: fixnum overflow\n");
+  exit(1);
+}
+
+void division_by_zero() {
+  fprintf(stderr, "error: division by zero\n");
+  exit(1);
+}
+
+void integer_not_char() {
+  fprintf(stderr, "error: integer is not a character\n");
+  exit(1);
+}
+
+void string_index_out_of_range() {
+  fprintf(stderr, "error: string index out of range\n");
+  exit(1);
+}
+
+void car_of_empty_list() {
+  fprintf(stderr, "error: car of empty list\n");
+  exit(1);
+}
+
+void cdr_of_empty_list() {
+  fprintf(stderr, "error: cdr of empty list\n");
+  exit(1);
+}
+
+/* With --bignum an integer is a fixnum shifted left by 1, or a pointer to a
+   big integer with bit 0 set. Big integers are interned, so equal integers
+   have equal representations, and a big integer never fits a fixnum. They are
+   blocks of the heap, the interned ones are dropped by the collector once they
+   are not reachable. */
+
+#define FIXNUM_MAX (((int64_t)1 << 62) - 1)
+#define FIXNUM_MIN (-((int64_t)1 << 62))
+#define INTERN_BUCKETS 1024
+
+/* a sign and a magnitude of 32 bits limbs, the least significant first */
+typedef struct bignum {
+  struct bignum *next;
+  int64_t sign;
+  int64_t len;
+  uint32_t limbs[];
+} bignum;
+
+/* the buckets are outside the heap, so they do not keep the integers alive */
+static bignum **interned;
+
+static void bignum_failed(const char *msg) {
+  fprintf(stderr, "error: %s\n", msg);
+  exit(1);
+}
+
+/* the header of the block is before the integer, which the collector does not trace */
+static bignum *big_new(int64_t len) {
+  int64_t bytes = sizeof(bignum) + len * sizeof(uint32_t);
+  int64_t words = 1 + (bytes + sizeof(int64_t) - 1) / sizeof(int64_t);
+  int64_t *block = allocate(words);
+  block[0] = STRING_HEADER(words);
+  memset(block + 1, 0, (words - 1) * sizeof(int64_t));
+  bignum *b = (bignum *)(block + 1);
+  b->sign = 1;
+  b->len = len;
+  return b;
+}
+
+/* called by the collector after marking, before the blocks are freed */
+static void sweep_interned() {
+  int64_t i;
+  bignum **link;
+  if (interned == NULL) {
+    return;
+  }
+  for (i = 0; i < INTERN_BUCKETS; i++) {
+    for (link = &interned[i]; *link != NULL;) {
+      if (is_marked((int64_t *)*link)) {
+        link = &(*link)->next;
+      } else {
+        *link = (*link)->next;
+      }
+    }
+  }
+}
+
+static bignum *big_from_int(int64_t n) {
+  bignum *b = big_new(2);
+  uint64_t m = n < 0 ? -(uint64_t)n : (uint64_t)n;
+  b->sign = n < 0 ? -1 : 1;
+  b->limbs[0] = (uint32_t)m;
+  b->limbs[1] = (uint32_t)(m >> 32);
+  return b;
+}
+
+static int is_fixnum(int64_t x) { return (x & 1) == 0; }
+
+static bignum *to_big(int64_t x) {
+  if (is_fixnum(x)) {
+    return big_from_int(x >> 1);
+  }
+  return (bignum *)(x & ~(int64_t)1);
+}
+
+static void trim(bignum *b) {
+  while (b->len > 0 && b->limbs[b->len - 1] == 0) {
+    b->len--;
+  }
+  if (b->len == 0) {
+    b->sign = 1;
+  }
+}
+
+static uint64_t big_hash(const bignum *b) {
+  uint64_t h = b->sign;
+  for (int64_t i = 0; i < b->len; i++) {
+    h = h * 31 + b->limbs[i];
+  }
+  return h;
+}
+
+static int big_same(const bignum *a, const bignum *b) {
+  return a->sign == b->sign && a->len == b->len &&
+         memcmp(a->limbs, b->limbs, a->len * sizeof(uint32_t)) == 0;
+}
+
+/* a result is a fixnum if it fits, otherwise the interned big integer */
+static int64_t normalize(bignum *b) {
+  trim(b);
+  if (b->len <= 2) {
+    uint64_t m = b->len == 0   ? 0
+                 : b->len == 1 ? b->limbs[0]
+                               : (uint64_t)b->limbs[1] << 32 | b->limbs[0];
+    if (b->sign > 0 && m <= (uint64_t)FIXNUM_MAX) {
+      return (int64_t)(m << 1);
+    }
+    if (b->sign < 0 && m <= -(uint64_t)FIXNUM_MIN) {
+      return (int64_t)(-m << 1);
+    }
+  }
+  if (interned == NULL) {
+    interned = calloc(INTERN_BUCKETS, sizeof(bignum *));
+    if (interned == NULL) {
+      bignum_failed("failed to allocate a big integer");
+    }
+  }
+  uint64_t bucket = big_hash(b) % INTERN_BUCKETS;
+  for (bignum *other = interned[bucket]; other != NULL; other = other->next) {
+    if (big_same(b, other)) {
+      return (int64_t)other | 1;
+    }
+  }
+  b->next = interned[bucket];
+  interned[bucket] = b;
+  return (int64_t)b | 1;
+}
+
+static uint32_t limb(const bignum *b, int64_t i) {
+  return i < b->len ? b->limbs[i] : 0;
+}
+
+static int mag_cmp(const bignum *a, const bignum *b) {
+  int64_t len = a->len > b->len ? a->len : b->len;
+  for (int64_t i = len - 1; i >= 0; i--) {
+    if (limb(a, i) != limb(b, i)) {
+      return limb(a, i) < limb(b, i) ? -1 : 1;
+    }
+  }
+  return 0;
+}
+
+static bignum *mag_add(const bignum *a, const bignum *b) {
+  int64_t len = (a->len > b->len ? a->len : b->len) + 1;
+  bignum *r = big_new(len);
+  uint64_t carry = 0;
+  for (int64_t i = 0; i < len; i++) {
+    uint64_t sum = (uint64_t)limb(a, i) + limb(b, i) + carry;
+    r->limbs[i] = (uint32_t)sum;
+    carry = sum >> 32;
+  }
+  return r;
+}
+
+/* |a| - |b| in place, |a| >= |b| */
+static void mag_sub_in_place(bignum *a, const bignum *b) {
+  int64_t borrow = 0;
+  for (int64_t i = 0; i < a->len; i++) {
+    int64_t diff = (int64_t)a->limbs[i] - limb(b, i) - borrow;
+    borrow = diff < 0;
+    a->limbs[i] = (uint32_t)(diff + (borrow << 32));
+  }
+}
+
+static bignum *big_copy(const bignum *b, int64_t len) {
+  bignum *r = big_new(len);
+  memcpy(r->limbs, b->limbs, b->len * sizeof(uint32_t));
+  r->sign = b->sign;
+  return r;
+}
+
+static bignum *big_add(const bignum *a, int64_t a_sign, const bignum *b,
+                       int64_t b_sign) {
+  bignum *r;
+  if (a_sign == b_sign) {
+    r = mag_add(a, b);
+    r->sign = a_sign;
+  } else if (mag_cmp(a, b) >= 0) {
+    r = big_copy(a, a->len);
+    mag_sub_in_place(r, b);
+    r->sign = a_sign;
+  } else {
+    r = big_copy(b, b->len);
+    mag_sub_in_place(r, a);
+    r->sign = b_sign;
+  }
+  return r;
+}
+
+static bignum *big_mul(const bignum *a, const bignum *b) {
+  bignum *r = big_new(a->len + b->len);
+  for (int64_t i = 0; i < a->len; i++) {
+    uint64_t carry = 0;
+    for (int64_t j = 0; j < b->len; j++) {
+      uint64_t t = (uint64_t)a->limbs[i] * b->limbs[j] + r->limbs[i + j] + carry;
+      r->limbs[i + j] = (uint32_t)t;
+      carry = t >> 32;
+    }
+    r->limbs[i + b->len] = (uint32_t)carry;
+  }
+  r->sign = a->sign * b->sign;
+  return r;
+}
+
+/* long division of the magnitudes bit by bit */
+static void mag_divmod(const bignum *a, const bignum *b, bignum **quotient,
+                       bignum **remainder) {
+  bignum *q = big_new(a->len);
+  bignum *r = big_new(b->len + 1);
+  for (int64_t i = a->len * 32 - 1; i >= 0; i--) {
+    for (int64_t j = r->len - 1; j > 0; j--) {
+      r->limbs[j] = r->limbs[j] << 1 | r->limbs[j - 1] >> 31;
+    }
+    r->limbs[0] = r->limbs[0] << 1 | (a->limbs[i / 32] >> (i % 32) & 1);
+    if (mag_cmp(r, b) >= 0) {
+      mag_sub_in_place(r, b);
+      q->limbs[i / 32] |= (uint32_t)1 << (i % 32);
+    }
+  }
+  *quotient = q;
+  *remainder = r;
+}
+
+/* divide the magnitude by a small number in place, return the remainder */
+static uint32_t mag_div_small(bignum *b, uint32_t d) {
+  uint64_t rem = 0;
+  for (int64_t i = b->len - 1; i >= 0; i--) {
+    uint64_t cur = rem << 32 | b->limbs[i];
+    b->limbs[i] = (uint32_t)(cur / d);
+    rem = cur % d;
+  }
+  trim(b);
+  return (uint32_t)rem;
+}
+
+int64_t integer_from_fixnum(int64_t n) {
+  if (n >= FIXNUM_MIN && n <= FIXNUM_MAX) {
+    return n << 1;
+  }
+  return normalize(big_from_int(n));
+}
+
+int64_t integer_add(int64_t a, int64_t b) {
+  bignum *x = to_big(a), *y = to_big(b);
+  return normalize(big_add(x, x->sign, y, y->sign));
+}
+
+int64_t integer_sub(int64_t a, int64_t b) {
+  bignum *x = to_big(a), *y = to_big(b);
+  return normalize(big_add(x, x->sign, y, -y->sign));
+}
+
+int64_t integer_mul(int64_t a, int64_t b) {
+  return normalize(big_mul(to_big(a), to_big(b)));
+}
+
+int64_t integer_neg(int64_t a) {
+  bignum *x = to_big(a);
+  bignum *r = big_copy(x, x->len);
+  r->sign = -x->sign;
+  return normalize(r);
+}
+
+/* -1, 0 or 1 by the order of the integers */
+int64_t integer_compare(int64_t a, int64_t b) {
+  bignum *x = to_big(a), *y = to_big(b);
+  trim(x);
+  trim(y);
+  if (x->sign != y->sign) {
+    return x->sign;
+  }
+  return mag_cmp(x, y) * x->sign;
+}
+
+/* the quotient is truncated, the remainder has the sign of the dividend */
+static void integer_divmod(int64_t a, int64_t b, bignum **q, bignum **r) {
+  bignum *x = to_big(a), *y = to_big(b);
+  trim(y);
+  if (y->len == 0) {
+    bignum_failed("division by zero");
+  }
+  mag_divmod(x, y, q, r);
+  (*q)->sign = x->sign * y->sign;
+  (*r)->sign = x->sign;
+}
+
+int64_t integer_quotient(int64_t a, int64_t b) {
+  bignum *q, *r;
+  integer_divmod(a, b, &q, &r);
+  return normalize(q);
+}
+
+int64_t integer_remainder(int64_t a, int64_t b) {
+  bignum *q, *r;
+  integer_divmod(a, b, &q, &r);
+  return normalize(r);
+}
+
+/* bitwise operations only take fixnums */
+static int64_t untag(int64_t x, const char *op) {
+  if (!is_fixnum(x)) {
+    fprintf(stderr, "error: %s of a big integer\n", op);
+    exit(1);
+  }
+  return x >> 1;
+}
+
+int64_t integer_and(int64_t a, int64_t b) {
+  return (untag(a, "bit-and") & untag(b, "bit-and")) << 1;
+}
+
+int64_t integer_or(int64_t a, int64_t b) {
+  return (untag(a, "bit-or") | untag(b, "bit-or")) << 1;
+}
+
+int64_t integer_xor(int64_t a, int64_t b) {
+  return (untag(a, "bit-xor") ^ untag(b, "bit-xor")) << 1;
+}
+
+int64_t integer_not(int64_t a) { return ~untag(a, "bit-not") << 1; }
+
+/* shifting left does not overflow, it moves the limbs up */
+int64_t integer_shl(int64_t a, int64_t b) {
+  int64_t count = untag(b, "shift count");
+  if (count <= 0) {
+    return a;
+  }
+  bignum *x = to_big(a);
+  int64_t words = count / 32, bits = count % 32;
+  bignum *r = big_new(x->len + words + 1);
+  for (int64_t i = 0; i < x->len; i++) {
+    uint64_t shifted = (uint64_t)x->limbs[i] << bits;
+    r->limbs[i + words] |= (uint32_t)shifted;
+    r->limbs[i + words + 1] |= (uint32_t)(shifted >> 32);
+  }
+  r->sign = x->sign;
+  return normalize(r);
+}
+
+int64_t integer_shr(int64_t a, int64_t b) {
+  uint64_t n = untag(a, "shr");
+  return integer_from_fixnum((int64_t)(n >> (untag(b, "shift count") & 63)));
+}
+
+int64_t integer_sar(int64_t a, int64_t b) {
+  int64_t n = untag(a, "ash");
+  return (n >> (untag(b, "shift count") & 63)) << 1;
+}
+
+int64_t integer_to_flonum(int64_t a) {
+  bignum *x = to_big(a);
+  double d = 0;
+  for (int64_t i = x->len - 1; i >= 0; i--) {
+    d = d * 4294967296.0 + x->limbs[i];
+  }
+  d *= x->sign;
+  int64_t bits;
+  memcpy(&bits, &d, sizeof(d));
+  return bits;
+}
+
+/* the float is truncated, its integral part is the mantissa times a power of 2 */
+int64_t integer_from_flonum(int64_t bits) {
+  double d;
+  memcpy(&d, &bits, sizeof(d));
+  if (d != d || d - d != 0) {
+    bignum_failed("flonum->fixnum of an infinity or NaN");
+  }
+  if (d > -4e18 && d < 4e18) {
+    return integer_from_fixnum((int64_t)d);
+  }
+  int64_t exponent = (bits >> 52 & 0x7ff) - 1075;
+  int64_t mantissa = (bits & (((int64_t)1 << 52) - 1)) | (int64_t)1 << 52;
+  int64_t n = integer_shl(integer_from_fixnum(mantissa), exponent << 1);
+  return d < 0 ? integer_neg(n) : n;
+}
+
+/* read an optional sign and the digits */
+int64_t read_integer() {
+  bignum *r = big_new(1);
+  bignum *ten = big_from_int(10);
+  int64_t sign = 1;
+  int c = skip_space();
+  if (c == '-') {
+    sign = -1;
     c = getchar();
-    if (c < '0' || c > '9')
-      break;
-    n = n * 10 + (c - '0');
   }
-  return n;
+  if (c < '0' || c > '9') {
+    input_failed("malformed integer input");
+  }
+  for (; c >= '0' && c <= '9'; c = getchar()) {
+    bignum *digit = big_from_int(c - '0');
+    r = big_add(big_mul(r, ten), 1, digit, 1);
+  }
+  ungetc(c, stdin);
+  r->sign = sign;
+  return normalize(r);
 }
 
-int print_int(int i) { printf("%d", i); }
+/* the digits are divided out by 10^9 */
+void print_integer(int64_t a) {
+  if (is_fixnum(a)) {
+    fprintf(output, "%ld", a >> 1);
+    return;
+  }
+  bignum *x = to_big(a);
+  bignum *b = big_copy(x, x->len);
+  uint32_t chunks[b->len * 2 + 1];
+  int64_t n = 0;
+  while (b->len > 0) {
+    chunks[n++] = mag_div_small(b, 1000000000);
+  }
+  if (x->sign < 0) {
+    putc('-', output);
+  }
+  fprintf(output, "%u", chunks[n - 1]);
+  for (int64_t i = n - 2; i >= 0; i--) {
+    fprintf(output, "%09u", chunks[i]);
+  }
+}
+
+/* a value of Any carries the tag of its type in the low 3 bits,
+   the length of a vector is in bits 1-6 of the header */
+void print_any(int64_t value, int64_t bignum) {
+  int64_t *vector;
+  switch (value & 7) {
+  case 1:
+    if (bignum) {
+      print_integer(value >> 3);
+    } else {
+      print_int(value >> 3);
+    }
+    break;
+  case 2:
+    vector = (int64_t *)(value & ~7);
+    fputs("#(", output);
+    for (int64_t i = 0; i < (vector[0] >> 1 & 0x3f); i++) {
+      if (i > 0) {
+        putc(' ', output);
+      }
+      print_any(vector[i + 1], bignum);
+    }
+    putc(')', output);
+    break;
+  case 3:
+    fputs("#<procedure>", output);
+    break;
+  case 4:
+    print_bool(value >> 3);
+    break;
+  case 5:
+    fputs("#<void>", output);
+    break;
+  case 6:
+    print_char(value >> 3);
+    break;
+  }
+}
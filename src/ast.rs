@@ -1,25 +1,32 @@
 use crate::graph::Graph;
 use std::collections::{HashMap, HashSet};
 
+/// Position of a token in the source, 1-based
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl std::fmt::Display for Pos {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 /// Token
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Token {
-    Neg,
-    Add,
-    Fixnum,
-    Read,
-    Program,
-    Let,
-    Var,
-    True,
-    False,
-    Not,
-    Eq,
-    Lt,
-    Lte,
-    Gt,
-    Gte,
-    If,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Atom(String),
+    Str(String),
+    /// 'exp is read as (quote exp)
+    Quote,
+    /// #(exp ...) is read as a vector literal
+    HashParen,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -29,51 +36,445 @@ pub enum CondCode {
     Le,
     G,
     Ge,
+    Ne,
+    /// overflow
+    O,
+    /// unsigned below or equal
+    Be,
 }
 
-/// AST node
+/// label of the handler of out of range vector indices
+pub const VECTOR_INDEX_ERROR: &str = "vector_index_error";
+
+/// label of the handler of out of range string indices
+pub const STRING_INDEX_ERROR: &str = "string_index_error";
+
+/// label of the handler of overflowed arithmetic
+pub const OVERFLOW_ERROR: &str = "overflow_error";
+
+/// label of the handler of divisions by zero
+pub const DIVIDE_ERROR: &str = "divide_error";
+
+/// label of the handler of integers which are not the code point of a character
+pub const CHAR_ERROR: &str = "char_error";
+
+/// label of the handler of projections from Any to a wrong type
+/// the handler reads the line from RAX and the column from R11
+pub const ANY_PROJECT_ERROR: &str = "any_project_error";
+
+/// the pointer mask of the header has a bit for each element
+pub const MAX_VECTOR_LEN: usize = 50;
+
+/// header of a vector, bit 0 is set if the vector is not forwarded,
+/// bits 1-6 are the length, bits 7-56 mark which elements are pointers
+pub fn vector_tag(len: usize, t: &Type) -> isize {
+    let elem_types = match t {
+        Type::Vector(elem_types) => elem_types,
+        t => panic!("unexpected {:?}", t),
+    };
+    assert!(len <= MAX_VECTOR_LEN, "vector is too long");
+    let pointer_mask = elem_types
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.is_pointer())
+        .fold(0, |mask, (i, _)| mask | (1 << i));
+    (pointer_mask << 7 | len << 1 | 1) as isize
+}
+
+/// an immediate operand of most instructions is a sign-extended 32 bits number
+pub fn is_imm32(num: isize) -> bool {
+    num >= i32::MIN as isize && num <= i32::MAX as isize
+}
+
+/// name of the function which reads a field of a struct
+pub fn accessor_name(name: &str, field: &str) -> String {
+    format!("{}-{}", name, field)
+}
+
+/// name of the function which writes a field of a struct
+pub fn mutator_name(name: &str, field: &str) -> String {
+    format!("set-{}-{}!", name, field)
+}
+
+/// name of the function which computes the default value of a parameter
+pub fn default_name(fun: &str, index: usize) -> String {
+    format!("{}.default.{}", fun, index)
+}
+
+/// name of a variable introduced by a derived form, the reader never reads
+/// an atom starting with a quote, so it cannot capture a variable of the program
+pub fn hidden_name(name: &str) -> String {
+    format!("'{}", name)
+}
+
+/// name of the function which tests the variant of a value of a data type
+pub fn predicate_name(variant: &str) -> String {
+    format!("{}?", variant)
+}
+
+/// Pattern of a match clause
 #[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Var(String),
+    /// a fixnum, a boolean, a character or a symbol
+    Literal(Node),
+    Vector(Vec<Pattern>),
+    /// a variant of a data type and the patterns of its fields
+    Variant(String, Vec<Pattern>),
+}
+
+impl Pattern {
+    /// variables bound by the pattern, in order of appearance
+    pub fn vars(&self) -> Vec<&String> {
+        match self {
+            Pattern::Var(name) => vec![name],
+            Pattern::Vector(patterns) | Pattern::Variant(_, patterns) => {
+                patterns.iter().flat_map(Pattern::vars).collect()
+            }
+            Pattern::Wildcard | Pattern::Literal(_) => Vec::new(),
+        }
+    }
+}
+
+/// AST node
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub enum Node {
+    #[default]
     NOP,
-    Program(Box<Node>),
+    /// position of a parsed expression, removed by type check
+    Located(Pos, Box<Node>),
+    /// the expression is checked against the type, removed by type check
+    Ann(Box<Node>, Type, Pos),
+    Program(Vec<Box<Node>>, Box<Node>),
+    /// names exported by a module, removed when the modules are loaded
+    Provide(Vec<String>),
+    /// a module loaded from the file, removed when the modules are loaded
+    Require(String, Pos),
+    Def {
+        name: String,
+        params: Vec<(String, Type)>,
+        ret: Type,
+        body: Vec<Box<Node>>,
+    },
+    /// a function whose last parameter is the list of the excess arguments,
+    /// the arguments are packed by lower variadic
+    Variadic(Box<Node>),
+    /// a global variable in .data, the value is computed at the start of main
+    /// unless it is a constant, which is left by reveal functions
+    DefGlobal {
+        name: String,
+        value: Box<Node>,
+    },
+    /// a constant is replaced by its value at compile time, it is removed by inline constants
+    DefConst {
+        name: String,
+        value: Box<Node>,
+    },
+    /// assign the global variable at the label
+    GlobalSet(String, Box<Node>),
+    /// a struct is expanded into its constructor, accessors and mutators by shrink
+    DefStruct {
+        name: String,
+        fields: Vec<(String, Type)>,
+    },
+    /// a value of a data type is a vector of the index of its variant and the fields,
+    /// each variant has a constructor and a predicate generated by shrink
+    DefType {
+        name: String,
+        variants: Vec<(String, Vec<Type>)>,
+    },
+    Apply(Box<Node>, Vec<Box<Node>>),
+    /// free variables are filled by type check
+    Lambda {
+        params: Vec<(String, Type)>,
+        ret: Type,
+        body: Box<Node>,
+        free_vars: Vec<(String, Type)>,
+    },
+    /// address of a function
+    FunRef(String),
+    /// a generic function applied to the types of its type variables, removed by monomorphize
+    Instance(String, Vec<Type>),
+    Return(Box<Node>),
     Add(Box<Node>, Box<Node>),
+    Sub(Box<Node>, Box<Node>),
+    Mul(Box<Node>, Box<Node>),
+    Quotient(Box<Node>, Box<Node>),
+    Remainder(Box<Node>, Box<Node>),
+    BitAnd(Box<Node>, Box<Node>),
+    BitOr(Box<Node>, Box<Node>),
+    BitXor(Box<Node>, Box<Node>),
+    BitNot(Box<Node>),
+    Ash(Box<Node>, Box<Node>),
+    Shl(Box<Node>, Box<Node>),
+    Shr(Box<Node>, Box<Node>),
+    Sar(Box<Node>, Box<Node>),
     Neg(Box<Node>),
+    Min(Box<Node>, Box<Node>),
+    Max(Box<Node>, Box<Node>),
     Var(String),
     Let {
         name: String,
         value: Box<Node>,
         exp: Box<Node>,
     },
+    LetStar {
+        bindings: Vec<(String, Box<Node>)>,
+        exp: Box<Node>,
+    },
+    LetRec {
+        bindings: Vec<(String, Box<Node>)>,
+        exp: Box<Node>,
+    },
+    /// a loop only called in tail position is a while loop after shrink,
+    /// type check makes any other loop a recursive function
+    NamedLet {
+        name: String,
+        bindings: Vec<(String, Box<Node>)>,
+        body: Box<Node>,
+        pos: Pos,
+    },
+    SetBang {
+        name: String,
+        value: Box<Node>,
+    },
+    Begin(Vec<Box<Node>>, Box<Node>),
+    // vectors
+    Vector(Vec<Box<Node>>),
+    /// a vector literal, which every evaluation shares when its elements are constants
+    /// and the program never sets a vector, it is removed by expose allocation
+    Literal(Box<Node>),
+    /// a vector of constants preinitialized in .data, the value is its address
+    StaticVector(Vec<Box<Node>>, Type),
+    VectorRef(Box<Node>, Box<Node>),
+    VectorLength(Box<Node>),
+    VectorSet(Box<Node>, Box<Node>, Box<Node>),
+    /// an array has a length only known at runtime and is allocated by the runtime,
+    /// the type of the elements is filled by type check
+    MakeArray(Box<Node>, Box<Node>, Type),
+    ArrayLength(Box<Node>),
+    ArrayRef(Box<Node>, Box<Node>),
+    ArraySet(Box<Node>, Box<Node>, Box<Node>),
+    // a box is a vector of one element after shrink
+    MakeBox(Box<Node>),
+    Unbox(Box<Node>),
+    SetBox(Box<Node>, Box<Node>),
+    // a pair is a vector of two elements after shrink, the empty list is the null pointer
+    Null,
+    Cons(Box<Node>, Box<Node>),
+    Car(Box<Node>),
+    Cdr(Box<Node>),
+    IsNull(Box<Node>),
+    HasType(Box<Node>, Type),
+    // a value of Any carries the tag of its type in the low 3 bits
+    Inject(Box<Node>, Type),
+    /// the position is reported if the value has a different type
+    Project(Box<Node>, Type, Pos),
+    /// conversion between types which only differ by Any
+    Cast {
+        exp: Box<Node>,
+        from: Type,
+        to: Type,
+        pos: Pos,
+    },
+    Allocate(usize, Type),
+    /// collect the garbage until the bytes fit before the end of the free block
+    Collect(usize),
     Fixnum(isize),
-    Read,
+    /// a character is its code, so it is compiled as a fixnum
+    Char(char),
+    CharToInteger(Box<Node>),
+    IntegerToChar(Box<Node>),
+    /// string literal, which is emitted to the read-only data
+    Str(String),
+    /// quoted symbol, which is the address of its name in the read-only data,
+    /// so equal symbols have the same address
+    Symbol(String),
+    StringEq(Box<Node>, Box<Node>),
+    StringLength(Box<Node>),
+    StringRef(Box<Node>, Box<Node>),
+    StringAppend(Box<Node>, Box<Node>),
+    /// hash tables are calls to the runtime after shrink, keys are compared by eq?
+    MakeHashTable(Type, Type),
+    /// the default value is returned if the key is not found
+    HashTableRef(Box<Node>, Box<Node>, Option<Box<Node>>),
+    HashTableSet(Box<Node>, Box<Node>, Box<Node>),
+    /// bits of a double-precision float, so the node can be compared
+    Flonum(u64),
+    FAdd(Box<Node>, Box<Node>),
+    FSub(Box<Node>, Box<Node>),
+    FMul(Box<Node>, Box<Node>),
+    FDiv(Box<Node>, Box<Node>),
+    FixnumToFlonum(Box<Node>),
+    FlonumToFixnum(Box<Node>),
+    /// read a fixnum, a boolean or a character from the input
+    Read(Type),
+    /// a random fixnum between 0 and the bound, drawn by the runtime
+    Random(Box<Node>),
+    /// exit the process with the code, the output is flushed
+    Exit(Box<Node>),
+    /// arguments of the process after the program name
+    CommandLineArguments,
+    /// raise an exception with the message, which is caught by the latest handler
+    Raise(Box<Node>),
+    /// call the thunk with the handler installed, the handler is called with the message
+    /// of an exception raised in the thunk, the return type of the thunk is filled by type check
+    WithHandler(Box<Node>, Box<Node>),
+    /// call the function with the continuation of the call,
+    /// which escapes to the call until it returns
+    CallCC(Box<Node>),
+    /// raise an error with the position if the condition is false,
+    /// asserts are removed in release builds
+    Assert(Box<Node>, Pos),
+    /// raise an exception with the message and the value,
+    /// the type of the value is filled by type check
+    Error(Box<Node>, Option<Box<Node>>, Type),
+    /// the value is written by the printer of its type, which is filled by type check
+    Print(Box<Node>, Type),
+    /// several results, the types are filled by type check
+    Values(Vec<Box<Node>>, Vec<Type>),
+    /// bind the variables of each clause to the results of its values
+    LetValues {
+        bindings: Vec<(Vec<String>, Box<Node>)>,
+        exp: Box<Node>,
+    },
+    /// a string of the text pieces with the values written between them,
+    /// the types of the values are filled by type check
+    Format(Vec<String>, Vec<Box<Node>>, Vec<Type>),
     Assign(String, Box<Node>),
+    /// the value coming from the block of each label in SSA form,
+    /// the entry block is labeled by the name of the function
+    Phi(Vec<(String, Box<Node>)>),
     MOVQ {
         target: Box<Node>,
         source: Box<Node>,
     },
-    CALLQ(&'static str),
+    /// label and the number of arguments passed in registers
+    CALLQ(String, usize),
+    /// call the address in the operand
+    ICALLQ(Box<Node>, usize),
+    LEAQ {
+        target: Box<Node>,
+        source: Box<Node>,
+    },
     ADDQ {
         target: Box<Node>,
         arg: Box<Node>,
     },
+    SUBQ {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    NEGQ(Box<Node>),
+    IMULQ {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    ANDQ {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    ORQ {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    XORQ {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    NOTQ(Box<Node>),
+    SALQ {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    SARQ {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    SHRQ {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    CQTO,
+    IDIVQ(Box<Node>),
+    // the target of SSE arithmetic must be a xmm register
+    ADDSD {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    SUBSD {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    MULSD {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    DIVSD {
+        target: Box<Node>,
+        arg: Box<Node>,
+    },
+    /// convert a fixnum to a float, the target must be a xmm register
+    CVTSI2SDQ {
+        target: Box<Node>,
+        source: Box<Node>,
+    },
+    /// truncate a float to a fixnum, the target must be a general register
+    CVTTSD2SIQ {
+        target: Box<Node>,
+        source: Box<Node>,
+    },
     CMPQ(Box<Node>, Box<Node>),
     SET(CondCode, Box<Node>),
     MOVZBQ {
         source: Box<Node>,
         target: Box<Node>,
     },
+    /// move if the condition holds, the source can not be an immediate
+    /// and the target must be a register
+    CMOV {
+        cond: CondCode,
+        source: Box<Node>,
+        target: Box<Node>,
+    },
     JMPIF(CondCode, String),
     JMP(String),
+    /// jump to a function in the frame of the caller
+    TAILJMP(String, usize),
+    ITAILJMP(Box<Node>, usize),
     Label(String),
     StackLoc(isize),
+    Deref(Box<Node>, isize),
+    /// the address of base plus index times scale, the operands are registers
+    Indexed(Box<Node>, Box<Node>, usize),
+    /// a global variable of the program or the runtime at the label
+    Global(String),
     // registers
     RAX,
     RBX,
+    RCX,
+    RDX,
+    RSI,
+    RDI,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
     AL,
+    CL,
+    /// xmm registers hold floats
+    XMM(usize),
     // bool logic
     True,
     False,
     Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
     Eq(Box<Node>, Box<Node>),
     Lt(Box<Node>, Box<Node>),
     Lte(Box<Node>, Box<Node>),
@@ -83,18 +484,62 @@ pub enum Node {
         cond: Box<Node>,
         if_exps: Vec<Box<Node>>,
         else_exps: Vec<Box<Node>>,
-        if_live_afters: Vec<LiveSet>,
-        else_live_afters: Vec<LiveSet>,
     },
+    /// compiled into tests by compile match, the position is reported if the clauses
+    /// do not cover every value
+    Match {
+        exp: Box<Node>,
+        clauses: Vec<(Pattern, Box<Node>)>,
+        pos: Pos,
+    },
+    Cond {
+        clauses: Vec<(Box<Node>, Box<Node>)>,
+        else_exp: Option<Box<Node>>,
+    },
+    When(Box<Node>, Box<Node>),
+    Unless(Box<Node>, Box<Node>),
+    /// each run of the body binds the variables to the values of the steps of the run before,
+    /// the result is the value once the test holds, do is a while loop after shrink
+    Do {
+        bindings: Vec<(String, Box<Node>, Option<Box<Node>>)>,
+        test: Box<Node>,
+        result: Box<Node>,
+        body: Box<Node>,
+    },
+    // loop
+    While {
+        cond_exps: Vec<Box<Node>>,
+        cond: Box<Node>,
+        body_exps: Vec<Box<Node>>,
+    },
+    Void,
+    // control flow after explicate control
+    Goto(String),
+    IfGoto {
+        cond: Box<Node>,
+        then_label: String,
+        else_label: String,
+    },
+    TailCall(Box<Node>, Vec<Box<Node>>),
 }
 
-impl Default for Node {
-    fn default() -> Self {
-        Node::NOP
+impl Node {
+    pub fn new_if(cond: Box<Node>, if_exp: Box<Node>, else_exp: Box<Node>) -> Self {
+        Self::If {
+            cond,
+            if_exps: vec![if_exp],
+            else_exps: vec![else_exp],
+        }
+    }
+
+    pub fn new_while(cond: Box<Node>, body: Box<Node>) -> Self {
+        Self::While {
+            cond_exps: Vec::new(),
+            cond,
+            body_exps: vec![body],
+        }
     }
-}
 
-impl Node {
     pub fn var(&self) -> Option<&String> {
         match &self {
             Self::Var(name) => Some(name),
@@ -105,11 +550,148 @@ impl Node {
     pub fn var_or_reg_name(&self) -> Option<String> {
         match &self {
             Self::Var(name) => Some(name.to_owned()),
-            Self::RAX => Some("RAX".to_string()),
+            reg if reg.is_register() || reg.is_xmm() => Some(format!("{:?}", reg)),
+            // shift count lives in CL, which is a part of RCX
+            Self::CL => Some("RCX".to_string()),
+            _ => None,
+        }
+    }
+
+    /// the operand written by an instruction
+    pub fn written(&self) -> Option<&Node> {
+        use Node::*;
+
+        match self {
+            MOVQ { target, .. }
+            | LEAQ { target, .. }
+            | MOVZBQ { target, .. }
+            | CMOV { target, .. }
+            | ADDQ { target, .. }
+            | SUBQ { target, .. }
+            | IMULQ { target, .. }
+            | ANDQ { target, .. }
+            | ORQ { target, .. }
+            | XORQ { target, .. }
+            | SALQ { target, .. }
+            | SARQ { target, .. }
+            | SHRQ { target, .. }
+            | ADDSD { target, .. }
+            | SUBSD { target, .. }
+            | MULSD { target, .. }
+            | DIVSD { target, .. }
+            | CVTSI2SDQ { target, .. }
+            | CVTTSD2SIQ { target, .. } => Some(target),
+            NEGQ(target) | NOTQ(target) | SET(_, target) => Some(target),
             _ => None,
         }
     }
 
+    /// the operands of an instruction, which are variables before allocation
+    pub fn operands(&self) -> Vec<&Node> {
+        use Node::*;
+
+        match self {
+            MOVQ { target, source }
+            | MOVZBQ { target, source }
+            | CVTSI2SDQ { target, source }
+            | CVTTSD2SIQ { target, source }
+            | CMOV { target, source, .. } => vec![target, source],
+            ADDQ { target, arg }
+            | SUBQ { target, arg }
+            | IMULQ { target, arg }
+            | ANDQ { target, arg }
+            | ORQ { target, arg }
+            | XORQ { target, arg }
+            | SALQ { target, arg }
+            | SARQ { target, arg }
+            | SHRQ { target, arg }
+            | ADDSD { target, arg }
+            | SUBSD { target, arg }
+            | MULSD { target, arg }
+            | DIVSD { target, arg } => vec![target, arg],
+            LEAQ { target, .. } | NEGQ(target) | NOTQ(target) | SET(_, target) => vec![target],
+            IDIVQ(arg) | ICALLQ(arg, _) | ITAILJMP(arg, _) => vec![arg],
+            CMPQ(lhs, rhs) => vec![lhs, rhs],
+            _ => Vec::new(),
+        }
+    }
+
+    /// 64 bits registers, AL is treated as a part of RAX
+    pub fn is_register(&self) -> bool {
+        use Node::*;
+
+        matches!(
+            self,
+            RAX | RBX | RCX | RDX | RSI | RDI | R8 | R9 | R10 | R11 | R12 | R13 | R14 | R15
+        )
+    }
+
+    /// the instructions using the flags of the instruction before them
+    pub fn reads_flags(&self) -> bool {
+        matches!(self, Self::JMPIF(..) | Self::SET(..) | Self::CMOV { .. })
+    }
+
+    /// an operation whose value only depends on its operands, it writes nothing,
+    /// but it may trap, see may_trap
+    pub fn is_pure(&self) -> bool {
+        use Node::*;
+
+        matches!(
+            self,
+            Add(..)
+                | Sub(..)
+                | Mul(..)
+                | Neg(_)
+                | Quotient(..)
+                | Remainder(..)
+                | BitAnd(..)
+                | BitOr(..)
+                | BitXor(..)
+                | BitNot(_)
+                | Shl(..)
+                | Shr(..)
+                | Sar(..)
+                | Min(..)
+                | Max(..)
+                | Not(_)
+                | Eq(..)
+                | Lt(..)
+                | Lte(..)
+                | Gt(..)
+                | Gte(..)
+                | FAdd(..)
+                | FSub(..)
+                | FMul(..)
+                | FDiv(..)
+                | FixnumToFlonum(_)
+                | FlonumToFixnum(_)
+                | CharToInteger(_)
+                | IntegerToChar(_)
+                | VectorLength(_)
+                | ArrayLength(_)
+                | StringLength(_)
+                | StringRef(..)
+        )
+    }
+
+    /// a pure operation which may exit the program instead of returning its value,
+    /// an arithmetic traps on overflow if it is checked
+    pub fn may_trap(&self, options: &Options) -> bool {
+        use Node::*;
+
+        match self {
+            Add(..) | Sub(..) | Mul(..) | Neg(_) => options.overflow_check,
+            Quotient(..) | Remainder(..) | FlonumToFixnum(_) | IntegerToChar(_) | StringRef(..) => {
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_xmm(&self) -> bool {
+        matches!(self, Self::XMM(_))
+    }
+
     pub fn fixnum(&self) -> Option<isize> {
         match self {
             Self::Fixnum(num) => Some(*num),
@@ -117,6 +699,13 @@ impl Node {
         }
     }
 
+    pub fn flonum(&self) -> Option<f64> {
+        match self {
+            Self::Flonum(bits) => Some(f64::from_bits(*bits)),
+            _ => None,
+        }
+    }
+
     pub fn boolean(&self) -> Option<bool> {
         match self {
             Self::True => Some(true),
@@ -125,24 +714,927 @@ impl Node {
         }
     }
 
+    /// condition code and operands of a comparison
+    pub fn comparison(&self) -> Option<(CondCode, &Node, &Node)> {
+        match self {
+            Self::Eq(lhs, rhs) => Some((CondCode::E, lhs, rhs)),
+            Self::Lt(lhs, rhs) => Some((CondCode::L, lhs, rhs)),
+            Self::Lte(lhs, rhs) => Some((CondCode::Le, lhs, rhs)),
+            Self::Gt(lhs, rhs) => Some((CondCode::G, lhs, rhs)),
+            Self::Gte(lhs, rhs) => Some((CondCode::Ge, lhs, rhs)),
+            _ => None,
+        }
+    }
+
     pub fn is_literal(&self) -> bool {
-        self.fixnum().is_some() || self.boolean().is_some()
+        self.fixnum().is_some()
+            || self.boolean().is_some()
+            || matches!(self, Self::Char(_) | Self::Flonum(_))
+    }
+
+    /// variables used but not bound in the expression, in order of appearance
+    pub fn free_vars(&self) -> Vec<String> {
+        use Node::*;
+
+        fn collect(node: &Node, bound: &mut Vec<String>, vars: &mut Vec<String>) {
+            match node {
+                Var(name) | SetBang { name, .. } => {
+                    if !bound.contains(name) && !vars.contains(name) {
+                        vars.push(name.clone());
+                    }
+                    if let SetBang { value, .. } = node {
+                        collect(value, bound, vars);
+                    }
+                }
+                Let { name, value, exp } => {
+                    collect(value, bound, vars);
+                    bound.push(name.clone());
+                    collect(exp, bound, vars);
+                    bound.pop();
+                }
+                LetStar { bindings, exp } => {
+                    for (name, value) in bindings {
+                        collect(value, bound, vars);
+                        bound.push(name.clone());
+                    }
+                    collect(exp, bound, vars);
+                    bound.truncate(bound.len() - bindings.len());
+                }
+                LetRec { bindings, exp } => {
+                    bound.extend(bindings.iter().map(|(name, _)| name.clone()));
+                    for (_, value) in bindings {
+                        collect(value, bound, vars);
+                    }
+                    collect(exp, bound, vars);
+                    bound.truncate(bound.len() - bindings.len());
+                }
+                NamedLet {
+                    name,
+                    bindings,
+                    body,
+                    ..
+                } => {
+                    for (_, value) in bindings {
+                        collect(value, bound, vars);
+                    }
+                    bound.push(name.clone());
+                    bound.extend(bindings.iter().map(|(param, _)| param.clone()));
+                    collect(body, bound, vars);
+                    bound.truncate(bound.len() - bindings.len() - 1);
+                }
+                LetValues { bindings, exp } => {
+                    for (_, value) in bindings {
+                        collect(value, bound, vars);
+                    }
+                    let len = bound.len();
+                    bound.extend(bindings.iter().flat_map(|(names, _)| names.iter().cloned()));
+                    collect(exp, bound, vars);
+                    bound.truncate(len);
+                }
+                Do {
+                    bindings,
+                    test,
+                    result,
+                    body,
+                } => {
+                    for (_, init, _) in bindings {
+                        collect(init, bound, vars);
+                    }
+                    bound.extend(bindings.iter().map(|(name, _, _)| name.clone()));
+                    for step in bindings.iter().filter_map(|(_, _, step)| step.as_ref()) {
+                        collect(step, bound, vars);
+                    }
+                    collect(test, bound, vars);
+                    collect(result, bound, vars);
+                    collect(body, bound, vars);
+                    bound.truncate(bound.len() - bindings.len());
+                }
+                Lambda { params, body, .. } => {
+                    bound.extend(params.iter().map(|(name, _)| name.clone()));
+                    collect(body, bound, vars);
+                    bound.truncate(bound.len() - params.len());
+                }
+                Match { exp, clauses, .. } => {
+                    collect(exp, bound, vars);
+                    for (pattern, body) in clauses {
+                        let pattern_vars = pattern.vars();
+                        bound.extend(pattern_vars.iter().map(|name| name.to_string()));
+                        collect(body, bound, vars);
+                        bound.truncate(bound.len() - pattern_vars.len());
+                    }
+                }
+                node => {
+                    for child in node.children() {
+                        collect(child, bound, vars);
+                    }
+                }
+            }
+        }
+
+        let mut vars = Vec::new();
+        collect(self, &mut Vec::new(), &mut vars);
+        vars
+    }
+
+    /// number of the nodes of the expression
+    pub fn size(&self) -> usize {
+        1 + self.children().into_iter().map(Node::size).sum::<usize>()
+    }
+
+    /// rebuild the node like map_children, f also takes the variables
+    /// the node binds around the sub expression
+    pub fn map_scoped(self, f: &mut impl FnMut(Box<Node>, &[String]) -> Box<Node>) -> Node {
+        use Node::*;
+
+        fn names<T>(bindings: &[(String, T)]) -> Vec<String> {
+            bindings.iter().map(|(name, _)| name.clone()).collect()
+        }
+
+        match self {
+            Def {
+                name,
+                params,
+                ret,
+                body,
+            } => {
+                let bound = names(&params);
+                Def {
+                    name,
+                    params,
+                    ret,
+                    body: body.into_iter().map(|exp| f(exp, &bound)).collect(),
+                }
+            }
+            Lambda {
+                params,
+                ret,
+                body,
+                free_vars,
+            } => {
+                let body = f(body, &names(&params));
+                Lambda {
+                    params,
+                    ret,
+                    body,
+                    free_vars,
+                }
+            }
+            Let { name, value, exp } => {
+                let value = f(value, &[]);
+                let exp = f(exp, std::slice::from_ref(&name));
+                Let { name, value, exp }
+            }
+            // each binding is visible to the bindings after it
+            LetStar { bindings, exp } => {
+                let bound = names(&bindings);
+                let bindings = bindings
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, (name, value))| (name, f(value, &bound[..i])))
+                    .collect();
+                LetStar {
+                    bindings,
+                    exp: f(exp, &bound),
+                }
+            }
+            LetRec { bindings, exp } => {
+                let bound = names(&bindings);
+                let bindings = bindings
+                    .into_iter()
+                    .map(|(name, value)| (name, f(value, &bound)))
+                    .collect();
+                LetRec {
+                    bindings,
+                    exp: f(exp, &bound),
+                }
+            }
+            NamedLet {
+                name,
+                bindings,
+                body,
+                pos,
+            } => {
+                let mut bound = vec![name.clone()];
+                bound.extend(names(&bindings));
+                let bindings = bindings
+                    .into_iter()
+                    .map(|(param, value)| (param, f(value, &[])))
+                    .collect();
+                NamedLet {
+                    name,
+                    bindings,
+                    body: f(body, &bound),
+                    pos,
+                }
+            }
+            LetValues { bindings, exp } => {
+                let bound: Vec<_> = bindings
+                    .iter()
+                    .flat_map(|(names, _)| names.iter().cloned())
+                    .collect();
+                let bindings = bindings
+                    .into_iter()
+                    .map(|(names, value)| (names, f(value, &[])))
+                    .collect();
+                LetValues {
+                    bindings,
+                    exp: f(exp, &bound),
+                }
+            }
+            Do {
+                bindings,
+                test,
+                result,
+                body,
+            } => {
+                let bound: Vec<_> = bindings.iter().map(|(name, _, _)| name.clone()).collect();
+                let bindings = bindings
+                    .into_iter()
+                    .map(|(name, init, step)| {
+                        let init = f(init, &[]);
+                        (name, init, step.map(|step| f(step, &bound)))
+                    })
+                    .collect();
+                Do {
+                    bindings,
+                    test: f(test, &bound),
+                    result: f(result, &bound),
+                    body: f(body, &bound),
+                }
+            }
+            Match { exp, clauses, pos } => {
+                let exp = f(exp, &[]);
+                let clauses = clauses
+                    .into_iter()
+                    .map(|(pattern, body)| {
+                        let bound: Vec<_> = pattern.vars().into_iter().cloned().collect();
+                        let body = f(body, &bound);
+                        (pattern, body)
+                    })
+                    .collect();
+                Match { exp, clauses, pos }
+            }
+            node => node.map_children(&mut |child| f(child, &[])),
+        }
+    }
+
+    /// the sub expressions in the order map_children visits them
+    pub fn children(&self) -> Vec<&Node> {
+        use Node::*;
+
+        let mut children: Vec<&Node> = Vec::new();
+        match self {
+            Located(_, exp)
+            | Ann(exp, ..)
+            | Variadic(exp)
+            | GlobalSet(_, exp)
+            | DefGlobal { value: exp, .. }
+            | DefConst { value: exp, .. }
+            | SetBang { value: exp, .. }
+            | Lambda { body: exp, .. }
+            | Neg(exp)
+            | Not(exp)
+            | BitNot(exp)
+            | CharToInteger(exp)
+            | IntegerToChar(exp)
+            | StringLength(exp)
+            | FixnumToFlonum(exp)
+            | FlonumToFixnum(exp)
+            | Literal(exp)
+            | VectorLength(exp)
+            | ArrayLength(exp)
+            | MakeBox(exp)
+            | Unbox(exp)
+            | Car(exp)
+            | Cdr(exp)
+            | IsNull(exp)
+            | Random(exp)
+            | Exit(exp)
+            | Raise(exp)
+            | CallCC(exp)
+            | Assert(exp, _)
+            | Print(exp, _)
+            | HasType(exp, _)
+            | Inject(exp, _)
+            | Project(exp, ..)
+            | Cast { exp, .. } => children.push(exp),
+            Add(lhs, rhs)
+            | Sub(lhs, rhs)
+            | Mul(lhs, rhs)
+            | Quotient(lhs, rhs)
+            | Remainder(lhs, rhs)
+            | BitAnd(lhs, rhs)
+            | Min(lhs, rhs)
+            | Max(lhs, rhs)
+            | BitOr(lhs, rhs)
+            | BitXor(lhs, rhs)
+            | Ash(lhs, rhs)
+            | Shl(lhs, rhs)
+            | Shr(lhs, rhs)
+            | Sar(lhs, rhs)
+            | And(lhs, rhs)
+            | Or(lhs, rhs)
+            | Eq(lhs, rhs)
+            | Lt(lhs, rhs)
+            | Lte(lhs, rhs)
+            | Gt(lhs, rhs)
+            | Gte(lhs, rhs)
+            | StringEq(lhs, rhs)
+            | StringRef(lhs, rhs)
+            | StringAppend(lhs, rhs)
+            | FAdd(lhs, rhs)
+            | FSub(lhs, rhs)
+            | FMul(lhs, rhs)
+            | FDiv(lhs, rhs)
+            | Let {
+                value: lhs,
+                exp: rhs,
+                ..
+            }
+            | When(lhs, rhs)
+            | Unless(lhs, rhs)
+            | VectorRef(lhs, rhs)
+            | MakeArray(lhs, rhs, _)
+            | ArrayRef(lhs, rhs)
+            | SetBox(lhs, rhs)
+            | Cons(lhs, rhs)
+            | WithHandler(lhs, rhs) => children.extend([lhs.as_ref(), rhs.as_ref()]),
+            HashTableSet(vec, index, value)
+            | VectorSet(vec, index, value)
+            | ArraySet(vec, index, value) => {
+                children.extend([vec.as_ref(), index.as_ref(), value.as_ref()])
+            }
+            Program(exps, exp) | Begin(exps, exp) => {
+                children.extend(exps.iter().map(AsRef::as_ref));
+                children.push(exp);
+            }
+            Def { body: exps, .. } | Vector(exps) | Format(_, exps, _) | Values(exps, _) => {
+                children.extend(exps.iter().map(AsRef::as_ref))
+            }
+            Apply(fun, args) => {
+                children.push(fun);
+                children.extend(args.iter().map(AsRef::as_ref));
+            }
+            HashTableRef(table, key, default) => {
+                children.extend([table.as_ref(), key.as_ref()]);
+                children.extend(default.as_deref());
+            }
+            LetStar { bindings, exp }
+            | LetRec { bindings, exp }
+            | NamedLet {
+                bindings,
+                body: exp,
+                ..
+            } => {
+                children.extend(bindings.iter().map(|(_, value)| value.as_ref()));
+                children.push(exp);
+            }
+            LetValues { bindings, exp } => {
+                children.extend(bindings.iter().map(|(_, value)| value.as_ref()));
+                children.push(exp);
+            }
+            If {
+                cond,
+                if_exps,
+                else_exps,
+            } => {
+                children.push(cond);
+                children.extend(if_exps.iter().chain(else_exps).map(AsRef::as_ref));
+            }
+            Match { exp, clauses, .. } => {
+                children.push(exp);
+                children.extend(clauses.iter().map(|(_, body)| body.as_ref()));
+            }
+            Cond { clauses, else_exp } => {
+                for (test, exp) in clauses {
+                    children.extend([test.as_ref(), exp.as_ref()]);
+                }
+                children.extend(else_exp.as_deref());
+            }
+            Do {
+                bindings,
+                test,
+                result,
+                body,
+            } => {
+                for (_, init, step) in bindings {
+                    children.push(init);
+                    children.extend(step.as_deref());
+                }
+                children.extend([test.as_ref(), result.as_ref(), body.as_ref()]);
+            }
+            While {
+                cond_exps,
+                cond,
+                body_exps,
+            } => {
+                children.extend(cond_exps.iter().map(AsRef::as_ref));
+                children.push(cond);
+                children.extend(body_exps.iter().map(AsRef::as_ref));
+            }
+            Phi(args) => children.extend(args.iter().map(|(_, value)| value.as_ref())),
+            Error(msg, value, _) => {
+                children.push(msg);
+                children.extend(value.as_deref());
+            }
+            _ => {}
+        }
+        children
+    }
+
+    /// rebuild the node with f applied to each sub expression
+    pub fn map_children(self, f: &mut impl FnMut(Box<Node>) -> Box<Node>) -> Node {
+        use Node::*;
+
+        fn map_list(
+            exps: Vec<Box<Node>>,
+            f: &mut impl FnMut(Box<Node>) -> Box<Node>,
+        ) -> Vec<Box<Node>> {
+            exps.into_iter().map(f).collect()
+        }
+
+        match self {
+            Located(pos, exp) => Located(pos, f(exp)),
+            Ann(exp, t, pos) => Ann(f(exp), t, pos),
+            Program(defs, exp) => {
+                let defs = map_list(defs, f);
+                Program(defs, f(exp))
+            }
+            Def {
+                name,
+                params,
+                ret,
+                body,
+            } => Def {
+                name,
+                params,
+                ret,
+                body: map_list(body, f),
+            },
+            DefGlobal { name, value } => DefGlobal {
+                name,
+                value: f(value),
+            },
+            DefConst { name, value } => DefConst {
+                name,
+                value: f(value),
+            },
+            Variadic(def) => Variadic(f(def)),
+            GlobalSet(label, value) => GlobalSet(label, f(value)),
+            Apply(fun, args) => {
+                let fun = f(fun);
+                Apply(fun, map_list(args, f))
+            }
+            Lambda {
+                params,
+                ret,
+                body,
+                free_vars,
+            } => Lambda {
+                params,
+                ret,
+                body: f(body),
+                free_vars,
+            },
+            Neg(exp) => Neg(f(exp)),
+            Not(exp) => Not(f(exp)),
+            BitNot(exp) => BitNot(f(exp)),
+            Add(lhs, rhs) => Add(f(lhs), f(rhs)),
+            Sub(lhs, rhs) => Sub(f(lhs), f(rhs)),
+            Mul(lhs, rhs) => Mul(f(lhs), f(rhs)),
+            Quotient(lhs, rhs) => Quotient(f(lhs), f(rhs)),
+            Remainder(lhs, rhs) => Remainder(f(lhs), f(rhs)),
+            BitAnd(lhs, rhs) => BitAnd(f(lhs), f(rhs)),
+            Min(lhs, rhs) => Min(f(lhs), f(rhs)),
+            Max(lhs, rhs) => Max(f(lhs), f(rhs)),
+            BitOr(lhs, rhs) => BitOr(f(lhs), f(rhs)),
+            BitXor(lhs, rhs) => BitXor(f(lhs), f(rhs)),
+            Ash(lhs, rhs) => Ash(f(lhs), f(rhs)),
+            Shl(lhs, rhs) => Shl(f(lhs), f(rhs)),
+            Shr(lhs, rhs) => Shr(f(lhs), f(rhs)),
+            Sar(lhs, rhs) => Sar(f(lhs), f(rhs)),
+            And(lhs, rhs) => And(f(lhs), f(rhs)),
+            Or(lhs, rhs) => Or(f(lhs), f(rhs)),
+            Eq(lhs, rhs) => Eq(f(lhs), f(rhs)),
+            Lt(lhs, rhs) => Lt(f(lhs), f(rhs)),
+            Lte(lhs, rhs) => Lte(f(lhs), f(rhs)),
+            Gt(lhs, rhs) => Gt(f(lhs), f(rhs)),
+            Gte(lhs, rhs) => Gte(f(lhs), f(rhs)),
+            StringEq(lhs, rhs) => StringEq(f(lhs), f(rhs)),
+            CharToInteger(exp) => CharToInteger(f(exp)),
+            IntegerToChar(exp) => IntegerToChar(f(exp)),
+            StringLength(s) => StringLength(f(s)),
+            StringRef(s, index) => {
+                let s = f(s);
+                StringRef(s, f(index))
+            }
+            StringAppend(lhs, rhs) => StringAppend(f(lhs), f(rhs)),
+            HashTableRef(table, key, default) => {
+                let table = f(table);
+                let key = f(key);
+                HashTableRef(table, key, default.map(&mut *f))
+            }
+            HashTableSet(table, key, value) => {
+                let table = f(table);
+                let key = f(key);
+                HashTableSet(table, key, f(value))
+            }
+            FAdd(lhs, rhs) => FAdd(f(lhs), f(rhs)),
+            FSub(lhs, rhs) => FSub(f(lhs), f(rhs)),
+            FMul(lhs, rhs) => FMul(f(lhs), f(rhs)),
+            FDiv(lhs, rhs) => FDiv(f(lhs), f(rhs)),
+            FixnumToFlonum(exp) => FixnumToFlonum(f(exp)),
+            FlonumToFixnum(exp) => FlonumToFixnum(f(exp)),
+            Let { name, value, exp } => {
+                let value = f(value);
+                Let {
+                    name,
+                    value,
+                    exp: f(exp),
+                }
+            }
+            LetStar { bindings, exp } => LetStar {
+                bindings: bindings
+                    .into_iter()
+                    .map(|(name, value)| (name, f(value)))
+                    .collect(),
+                exp: f(exp),
+            },
+            LetRec { bindings, exp } => LetRec {
+                bindings: bindings
+                    .into_iter()
+                    .map(|(name, value)| (name, f(value)))
+                    .collect(),
+                exp: f(exp),
+            },
+            NamedLet {
+                name,
+                bindings,
+                body,
+                pos,
+            } => NamedLet {
+                name,
+                bindings: bindings
+                    .into_iter()
+                    .map(|(param, value)| (param, f(value)))
+                    .collect(),
+                body: f(body),
+                pos,
+            },
+            SetBang { name, value } => SetBang {
+                name,
+                value: f(value),
+            },
+            Begin(effects, exp) => {
+                let effects = map_list(effects, f);
+                Begin(effects, f(exp))
+            }
+            If {
+                cond,
+                if_exps,
+                else_exps,
+            } => {
+                let cond = f(cond);
+                let if_exps = map_list(if_exps, f);
+                If {
+                    cond,
+                    if_exps,
+                    else_exps: map_list(else_exps, f),
+                }
+            }
+            Match { exp, clauses, pos } => Match {
+                exp: f(exp),
+                clauses: clauses
+                    .into_iter()
+                    .map(|(pattern, body)| (pattern, f(body)))
+                    .collect(),
+                pos,
+            },
+            Cond { clauses, else_exp } => Cond {
+                clauses: clauses
+                    .into_iter()
+                    .map(|(test, exp)| {
+                        let test = f(test);
+                        (test, f(exp))
+                    })
+                    .collect(),
+                else_exp: else_exp.map(&mut *f),
+            },
+            When(cond, body) => When(f(cond), f(body)),
+            Unless(cond, body) => Unless(f(cond), f(body)),
+            Do {
+                bindings,
+                test,
+                result,
+                body,
+            } => Do {
+                bindings: bindings
+                    .into_iter()
+                    .map(|(name, init, step)| (name, f(init), step.map(&mut *f)))
+                    .collect(),
+                test: f(test),
+                result: f(result),
+                body: f(body),
+            },
+            While {
+                cond_exps,
+                cond,
+                body_exps,
+            } => {
+                let cond_exps = map_list(cond_exps, f);
+                let cond = f(cond);
+                While {
+                    cond_exps,
+                    cond,
+                    body_exps: map_list(body_exps, f),
+                }
+            }
+            Vector(elems) => Vector(map_list(elems, f)),
+            Literal(vec) => Literal(f(vec)),
+            VectorLength(vec) => VectorLength(f(vec)),
+            VectorRef(vec, index) => {
+                let vec = f(vec);
+                VectorRef(vec, f(index))
+            }
+            VectorSet(vec, index, value) => {
+                let vec = f(vec);
+                let index = f(index);
+                VectorSet(vec, index, f(value))
+            }
+            MakeArray(len, init, t) => {
+                let len = f(len);
+                MakeArray(len, f(init), t)
+            }
+            ArrayLength(array) => ArrayLength(f(array)),
+            ArrayRef(array, index) => {
+                let array = f(array);
+                ArrayRef(array, f(index))
+            }
+            ArraySet(array, index, value) => {
+                let array = f(array);
+                let index = f(index);
+                ArraySet(array, index, f(value))
+            }
+            MakeBox(exp) => MakeBox(f(exp)),
+            Unbox(exp) => Unbox(f(exp)),
+            SetBox(exp, value) => {
+                let exp = f(exp);
+                SetBox(exp, f(value))
+            }
+            Cons(car, cdr) => {
+                let car = f(car);
+                Cons(car, f(cdr))
+            }
+            Car(exp) => Car(f(exp)),
+            Cdr(exp) => Cdr(f(exp)),
+            IsNull(exp) => IsNull(f(exp)),
+            Random(bound) => Random(f(bound)),
+            Exit(code) => Exit(f(code)),
+            Phi(args) => Phi(args
+                .into_iter()
+                .map(|(label, value)| (label, f(value)))
+                .collect()),
+            Raise(msg) => Raise(f(msg)),
+            Format(pieces, args, types) => Format(pieces, map_list(args, f), types),
+            Values(exps, types) => Values(map_list(exps, f), types),
+            LetValues { bindings, exp } => LetValues {
+                bindings: bindings
+                    .into_iter()
+                    .map(|(names, value)| (names, f(value)))
+                    .collect(),
+                exp: f(exp),
+            },
+            CallCC(fun) => CallCC(f(fun)),
+            Assert(cond, pos) => Assert(f(cond), pos),
+            WithHandler(handler, thunk) => {
+                let handler = f(handler);
+                WithHandler(handler, f(thunk))
+            }
+            Error(msg, value, t) => {
+                let msg = f(msg);
+                Error(msg, value.map(f), t)
+            }
+            Print(exp, t) => Print(f(exp), t),
+            HasType(exp, t) => HasType(f(exp), t),
+            Inject(exp, t) => Inject(f(exp), t),
+            Project(exp, t, pos) => Project(f(exp), t, pos),
+            Cast { exp, from, to, pos } => Cast {
+                exp: f(exp),
+                from,
+                to,
+                pos,
+            },
+            node => node,
+        }
     }
 }
 
-#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Type {
     Fixnum,
     Boolean,
+    Void,
+    Vector(Vec<Type>),
+    Box(Box<Type>),
+    Function(Vec<Type>, Box<Type>),
+    Any,
+    String,
+    Char,
+    Flonum,
+    Symbol,
+    List(Box<Type>),
+    Array(Box<Type>),
+    /// a hash table is allocated by the runtime outside the heap
+    HashTable(Box<Type>, Box<Type>),
+    /// type of the empty list, which is converted to every list type
+    Null,
+    /// type of an expression which does not return, which is converted to every type
+    Bottom,
+    /// a struct or a data type defined by the program, which is a vector after shrink
+    Named(String),
+    /// a type variable of a generic function, removed by monomorphize
+    Var(String),
+    /// the results of values, which are a vector after shrink
+    Values(Vec<Type>),
+    /// type of a generic function over the type variables, which is instantiated by each call
+    Generic(Vec<String>, Box<Type>),
+    /// a parameter with a default value, which may be left out of a direct call
+    Optional(Box<Type>),
+}
+
+impl Type {
+    /// type variables in the order they appear
+    pub fn type_vars(&self) -> Vec<String> {
+        fn collect(t: &Type, vars: &mut Vec<String>) {
+            match t {
+                Type::Var(name) if !vars.contains(name) => vars.push(name.clone()),
+                Type::Vector(types) | Type::Values(types) => {
+                    types.iter().for_each(|t| collect(t, vars))
+                }
+                Type::Box(t) | Type::List(t) | Type::Array(t) | Type::Optional(t) => {
+                    collect(t, vars)
+                }
+                Type::HashTable(key_t, value_t) => {
+                    collect(key_t, vars);
+                    collect(value_t, vars);
+                }
+                Type::Function(params, ret) => {
+                    params.iter().for_each(|t| collect(t, vars));
+                    collect(ret, vars);
+                }
+                _ => {}
+            }
+        }
+        let mut vars = Vec::new();
+        collect(self, &mut vars);
+        vars
+    }
+
+    /// replace the type variables by their bindings
+    pub fn subst(&self, bindings: &HashMap<String, Type>) -> Type {
+        let subst = |t: &Type| Box::new(t.subst(bindings));
+        match self {
+            Type::Var(name) => bindings.get(name).cloned().unwrap_or_else(|| self.clone()),
+            Type::Vector(types) => Type::Vector(types.iter().map(|t| t.subst(bindings)).collect()),
+            Type::Values(types) => Type::Values(types.iter().map(|t| t.subst(bindings)).collect()),
+            Type::Box(t) => Type::Box(subst(t)),
+            Type::List(t) => Type::List(subst(t)),
+            Type::Array(t) => Type::Array(subst(t)),
+            Type::Optional(t) => Type::Optional(subst(t)),
+            Type::HashTable(key_t, value_t) => Type::HashTable(subst(key_t), subst(value_t)),
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|t| t.subst(bindings)).collect(),
+                subst(ret),
+            ),
+            t => t.clone(),
+        }
+    }
+
+    /// values of the type are pointers to the heap
+    pub fn is_pointer(&self) -> bool {
+        // functions are closures on the heap
+        matches!(
+            self,
+            Type::Vector(_)
+                | Type::Values(_)
+                | Type::Box(_)
+                | Type::Function(..)
+                | Type::String
+                | Type::Named(_)
+                | Type::List(_)
+                | Type::Array(_)
+        )
+    }
+
+    /// tag of the type in a value of Any, only types whose components are Any can be injected
+    pub fn any_tag(&self) -> Option<isize> {
+        match self {
+            Type::Fixnum => Some(0b001),
+            Type::Vector(elem_types) if elem_types.iter().all(|t| *t == Type::Any) => Some(0b010),
+            Type::Function(params, ret)
+                if **ret == Type::Any && params.iter().all(|t| *t == Type::Any) =>
+            {
+                Some(0b011)
+            }
+            Type::Boolean => Some(0b100),
+            Type::Void => Some(0b101),
+            Type::Char => Some(0b110),
+            _ => None,
+        }
+    }
 }
 
+/// registers that may be overwritten by a function call
+pub const CALLER_SAVED_REGISTERS: [Node; 9] = [
+    Node::RAX,
+    Node::RCX,
+    Node::RDX,
+    Node::RSI,
+    Node::RDI,
+    Node::R8,
+    Node::R9,
+    Node::R10,
+    Node::R11,
+];
+
+/// xmm registers are all overwritten by a function call
+pub const CALLER_SAVED_XMM_REGISTERS: [Node; 16] = [
+    Node::XMM(0),
+    Node::XMM(1),
+    Node::XMM(2),
+    Node::XMM(3),
+    Node::XMM(4),
+    Node::XMM(5),
+    Node::XMM(6),
+    Node::XMM(7),
+    Node::XMM(8),
+    Node::XMM(9),
+    Node::XMM(10),
+    Node::XMM(11),
+    Node::XMM(12),
+    Node::XMM(13),
+    Node::XMM(14),
+    Node::XMM(15),
+];
+
+/// registers to pass arguments in order
+pub const ARGUMENT_REGISTERS: [Node; 6] = [
+    Node::RDI,
+    Node::RSI,
+    Node::RDX,
+    Node::RCX,
+    Node::R8,
+    Node::R9,
+];
+
+/// registers that must be preserved by a function
+pub const CALLEE_SAVED_REGISTERS: [Node; 5] =
+    [Node::RBX, Node::R12, Node::R13, Node::R14, Node::R15];
+
 pub type LiveSet = HashSet<String>;
 
+/// Compile options
+#[derive(Default, Clone)]
+pub struct Options {
+    /// check vector indices at runtime
+    pub bounds_check: bool,
+    /// trap on overflowed fixnum arithmetic
+    pub overflow_check: bool,
+    /// promote overflowed fixnums to big integers
+    pub bignum: bool,
+    /// remove asserts
+    pub release: bool,
+    /// unroll counted loops by the factor, loops are left as they are below 2
+    pub unroll: usize,
+    /// reorder the instructions of blocks to hide the latency of loads
+    pub schedule: bool,
+    /// take the blocks through SSA form before instruction selection
+    pub ssa: bool,
+    /// the optimizations to run in their order instead of the default ones
+    pub passes: Option<Vec<String>>,
+    /// the optional passes left out of the pipeline
+    pub skip: Vec<String>,
+    /// check the invariants of the program after each pass
+    pub validate: bool,
+}
+
 #[derive(Default)]
 pub struct Info {
     pub stack_vars_count: usize,
+    /// number of the variables renamed by uniquify, which makes the names fresh
+    pub vars_count: usize,
+    /// labels and initial values of the globals, which are filled for main
+    pub globals: Vec<(String, isize)>,
+    pub used_callee_saved: Vec<Node>,
     pub vars_types: HashMap<String, Type>,
     pub live_afters: Vec<LiveSet>,
     pub interference_graph: Graph<String>,
     pub move_graph: Graph<String>,
 }
+
+/// A function compiled to instructions
+pub struct Function {
+    pub name: String,
+    pub body: Vec<Box<Node>>,
+    pub info: Info,
+}
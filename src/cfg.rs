@@ -0,0 +1,190 @@
+use crate::ast::Node;
+use std::collections::HashMap;
+
+/// A block of statements from a label to the next label
+pub struct Block {
+    pub label: String,
+    pub body: Vec<Box<Node>>,
+    pub succs: Vec<usize>,
+    pub preds: Vec<usize>,
+}
+
+/// The blocks of a function body, the entry block is labeled by the name of the function
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+}
+
+impl Cfg {
+    /// split the body at the labels, a block without a jump falls through to the next one
+    pub fn new(name: &str, body: Vec<Box<Node>>) -> Self {
+        use Node::*;
+
+        let block = |label| Block {
+            label,
+            body: Vec::new(),
+            succs: Vec::new(),
+            preds: Vec::new(),
+        };
+        let mut blocks = vec![block(name.to_string())];
+        for node in body {
+            match *node {
+                Label(label) => blocks.push(block(label)),
+                node => blocks.last_mut().unwrap().body.push(Box::new(node)),
+            }
+        }
+        let index: HashMap<String, usize> = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (block.label.clone(), i))
+            .collect();
+        for i in 0..blocks.len() {
+            let jump = blocks[i]
+                .body
+                .iter()
+                .find(|node| matches!(node.as_ref(), Goto(_) | IfGoto { .. } | TailCall(..)));
+            let succs = match jump.map(|node| node.as_ref()) {
+                Some(Goto(label)) => vec![index[label]],
+                Some(IfGoto {
+                    then_label,
+                    else_label,
+                    ..
+                }) if then_label == else_label => vec![index[then_label]],
+                Some(IfGoto {
+                    then_label,
+                    else_label,
+                    ..
+                }) => vec![index[then_label], index[else_label]],
+                Some(_) => Vec::new(),
+                None if i + 1 < blocks.len() => vec![i + 1],
+                None => Vec::new(),
+            };
+            for &succ in &succs {
+                blocks[succ].preds.push(i);
+            }
+            blocks[i].succs = succs;
+        }
+        Cfg { blocks }
+    }
+
+    /// the blocks in reverse postorder from the entry, the unreachable blocks are left out
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        fn visit(blocks: &[Block], i: usize, visited: &mut Vec<bool>, order: &mut Vec<usize>) {
+            visited[i] = true;
+            for &succ in &blocks[i].succs {
+                if !visited[succ] {
+                    visit(blocks, succ, visited, order);
+                }
+            }
+            order.push(i);
+        }
+
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        visit(&self.blocks, 0, &mut visited, &mut order);
+        order.reverse();
+        order
+    }
+
+    /// join the kept blocks back into a body in their order
+    pub fn into_body(self, keep: impl Fn(usize) -> bool) -> Vec<Box<Node>> {
+        self.blocks
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| keep(*i))
+            .flat_map(|(i, Block { label, body, .. })| {
+                let label = (i > 0).then(|| Box::new(Node::Label(label)));
+                label.into_iter().chain(body)
+            })
+            .collect()
+    }
+}
+
+/// The dominator tree of the reachable blocks and their dominance frontiers
+pub struct Dominators {
+    idom: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    frontiers: Vec<Vec<usize>>,
+}
+
+impl Dominators {
+    /// the iterative algorithm of Cooper, Harvey and Kennedy
+    pub fn new(cfg: &Cfg) -> Self {
+        let blocks = &cfg.blocks;
+        let order = cfg.reverse_postorder();
+        let mut position = vec![usize::MAX; blocks.len()];
+        for (pos, &i) in order.iter().enumerate() {
+            position[i] = pos;
+        }
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while position[a] > position[b] {
+                    a = idom[a].unwrap();
+                }
+                while position[b] > position[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut idom = vec![None; blocks.len()];
+        idom[0] = Some(0);
+        loop {
+            let mut changed = false;
+            for &i in order.iter().skip(1) {
+                let new_idom = blocks[i]
+                    .preds
+                    .iter()
+                    .filter(|&&pred| idom[pred].is_some())
+                    .copied()
+                    .reduce(|a, b| intersect(&idom, a, b));
+                if idom[i] != new_idom {
+                    idom[i] = new_idom;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut children = vec![Vec::new(); blocks.len()];
+        for &i in order.iter().skip(1) {
+            children[idom[i].unwrap()].push(i);
+        }
+        // a join is in the frontier of the blocks on the way from each predecessor to its dominator
+        let mut frontiers = vec![Vec::new(); blocks.len()];
+        for (i, block) in blocks.iter().enumerate() {
+            if block.preds.len() < 2 || idom[i].is_none() {
+                continue;
+            }
+            for &pred in block.preds.iter().filter(|&&pred| idom[pred].is_some()) {
+                let mut runner = pred;
+                while Some(runner) != idom[i] {
+                    if !frontiers[runner].contains(&i) {
+                        frontiers[runner].push(i);
+                    }
+                    runner = idom[runner].unwrap();
+                }
+            }
+        }
+        Dominators {
+            idom,
+            children,
+            frontiers,
+        }
+    }
+
+    pub fn is_reachable(&self, block: usize) -> bool {
+        self.idom[block].is_some()
+    }
+
+    /// the blocks immediately dominated by the block
+    pub fn children(&self, block: usize) -> &[usize] {
+        &self.children[block]
+    }
+
+    /// the joins where the dominance of the block ends
+    pub fn frontier(&self, block: usize) -> &[usize] {
+        &self.frontiers[block]
+    }
+}
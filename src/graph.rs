@@ -9,15 +9,12 @@ pub struct Graph<T: Eq + Hash + Default + Debug>(HashMap<T, HashSet<T>>);
 impl<T: Eq + Hash + Default + Clone + Debug> Graph<T> {
     /// insert a pair of adjacent vertex
     pub fn insert(&mut self, a: T, b: T) {
-        self.0
-            .entry(a.clone())
-            .or_insert_with(Default::default)
-            .insert(b.clone());
-        self.0.entry(b).or_insert_with(Default::default).insert(a);
+        self.0.entry(a.clone()).or_default().insert(b.clone());
+        self.0.entry(b).or_default().insert(a);
     }
 
     pub fn add_vertex(&mut self, a: T) {
-        self.0.entry(a.clone()).or_insert_with(Default::default);
+        self.0.entry(a.clone()).or_default();
     }
 
     pub fn get_adjacents_set(&self, v: &T) -> Option<&HashSet<T>> {
@@ -0,0 +1,622 @@
+/// Expand macros defined by define-syntax with syntax-rules,
+/// the forms are rewritten before they are parsed, the names bound by a template
+/// are renamed at each expansion, so they do not capture the names of the forms,
+/// and a local variable is renamed if a template refers to the global of its name
+use crate::ast::Pos;
+use crate::parser::Sexp;
+use std::collections::{HashMap, HashSet};
+use std::result::Result;
+
+const ELLIPSIS: &str = "...";
+
+/// suffix of the free names of a template, which the lexer never reads
+const MARK: char = '\u{1}';
+
+fn unmark(name: &str) -> &str {
+    name.strip_suffix(MARK).unwrap_or(name)
+}
+
+/// expansions of a macro may use other macros, which may not recurse forever
+const MAX_DEPTH: usize = 1000;
+
+struct Rule {
+    pattern: Sexp,
+    template: Sexp,
+}
+
+struct Macro {
+    literals: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+/// a pattern variable is bound to a form, or to the forms matched by an ellipsis
+#[derive(Clone)]
+enum Binding {
+    One(Sexp),
+    Many(Vec<Binding>),
+}
+
+fn parse_macro(sexp: &Sexp) -> Result<(String, Macro), String> {
+    let err = || {
+        format!(
+            "{}: expected (define-syntax name (syntax-rules (literal ...) [pattern template] ...))",
+            sexp.pos()
+        )
+    };
+    let (name, rules) = match sexp.list() {
+        Some([_, name, rules]) => (name.atom().ok_or_else(err)?, rules),
+        _ => return Err(err()),
+    };
+    let (literals, rules) = match rules.list() {
+        Some([head, literals, rules @ ..]) if head.atom() == Some("syntax-rules") => {
+            (literals.list().ok_or_else(err)?, rules)
+        }
+        _ => return Err(err()),
+    };
+    let literals = literals
+        .iter()
+        .map(|literal| literal.atom().map(str::to_string).ok_or_else(err))
+        .collect::<Result<_, _>>()?;
+    let rules = rules
+        .iter()
+        .map(|rule| match rule.list() {
+            Some([pattern @ Sexp::List(..), template]) => Ok(Rule {
+                pattern: pattern.clone(),
+                template: template.clone(),
+            }),
+            _ => Err(format!(
+                "{}: expected [(_ pattern ...) template]",
+                rule.pos()
+            )),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((name.to_string(), Macro { literals, rules }))
+}
+
+impl Macro {
+    fn is_var(&self, name: &str) -> bool {
+        name != "_" && name != ELLIPSIS && !self.literals.iter().any(|literal| literal == name)
+    }
+
+    fn bind(&self, pattern: &Sexp, sexp: &Sexp, bindings: &mut HashMap<String, Binding>) -> bool {
+        match (pattern, sexp) {
+            (Sexp::Atom(name, _), sexp) if self.is_var(name) => {
+                bindings.insert(name.clone(), Binding::One(sexp.clone()));
+                true
+            }
+            (Sexp::Atom(name, _), _) if name == "_" => true,
+            (Sexp::Atom(name, _), Sexp::Atom(other, _)) => name == unmark(other),
+            (Sexp::Str(s, _), Sexp::Str(other, _)) => s == other,
+            (Sexp::List(patterns, _), Sexp::List(sexps, _)) => {
+                self.bind_list(patterns, sexps, bindings)
+            }
+            _ => false,
+        }
+    }
+
+    /// a pattern followed by an ellipsis matches the forms left by the patterns after it
+    fn bind_list(
+        &self,
+        patterns: &[Sexp],
+        sexps: &[Sexp],
+        bindings: &mut HashMap<String, Binding>,
+    ) -> bool {
+        let ellipsis = patterns
+            .iter()
+            .position(|pattern| pattern.atom() == Some(ELLIPSIS));
+        let (repeated, before, after) = match ellipsis {
+            Some(index) if index > 0 => (
+                Some(&patterns[index - 1]),
+                &patterns[..index - 1],
+                &patterns[index + 1..],
+            ),
+            _ => (None, patterns, &patterns[..0]),
+        };
+        let fixed = before.len() + after.len();
+        if sexps.len() < fixed || (repeated.is_none() && sexps.len() != fixed) {
+            return false;
+        }
+        let rest = sexps.len() - after.len();
+        let binds_fixed = before
+            .iter()
+            .zip(&sexps[..before.len()])
+            .chain(after.iter().zip(&sexps[rest..]))
+            .all(|(pattern, sexp)| self.bind(pattern, sexp, bindings));
+        let repeated = match repeated {
+            Some(repeated) => repeated,
+            None => return binds_fixed,
+        };
+        let mut matches = Vec::new();
+        for sexp in &sexps[before.len()..rest] {
+            let mut sub_bindings = HashMap::new();
+            if !self.bind(repeated, sexp, &mut sub_bindings) {
+                return false;
+            }
+            matches.push(sub_bindings);
+        }
+        for var in self.pattern_vars(repeated) {
+            let many = matches
+                .iter_mut()
+                .map(|m| m.remove(&var).unwrap())
+                .collect();
+            bindings.insert(var, Binding::Many(many));
+        }
+        binds_fixed
+    }
+
+    fn pattern_vars(&self, pattern: &Sexp) -> Vec<String> {
+        match pattern {
+            Sexp::Atom(name, _) if self.is_var(name) => vec![name.clone()],
+            Sexp::List(patterns, _) => patterns
+                .iter()
+                .flat_map(|pattern| self.pattern_vars(pattern))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// variables of the template that are bound by an ellipsis
+fn repeated_vars(template: &Sexp, bindings: &HashMap<String, Binding>) -> Vec<String> {
+    match template {
+        Sexp::Atom(name, _) if matches!(bindings.get(name), Some(Binding::Many(_))) => {
+            vec![name.clone()]
+        }
+        Sexp::List(templates, _) => templates
+            .iter()
+            .flat_map(|template| repeated_vars(template, bindings))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// names bound by the let, lambda, do and for forms of the template,
+/// the pattern variables are the names of the forms
+fn bound_names(template: &Sexp, bindings: &HashMap<String, Binding>, names: &mut Vec<String>) {
+    fn bind(sexp: &Sexp, bindings: &HashMap<String, Binding>, names: &mut Vec<String>) {
+        let name = match sexp {
+            Sexp::Atom(name, _) => name,
+            // [var exp] or [var : type]
+            Sexp::List(sexps, _) => match sexps.first() {
+                Some(Sexp::Atom(name, _)) => name,
+                _ => return,
+            },
+            Sexp::Str(..) => return,
+        };
+        if name != ELLIPSIS && name != "." && !bindings.contains_key(name) && !names.contains(name)
+        {
+            names.push(name.clone());
+        }
+    }
+
+    let sexps = match template {
+        Sexp::List(sexps, _) => sexps,
+        _ => return,
+    };
+    let head = sexps.first().and_then(Sexp::atom);
+    let clauses = match (head, sexps.get(1)) {
+        // a named let binds the loop before its clauses
+        (Some("let"), Some(name @ Sexp::Atom(..))) => {
+            bind(name, bindings, names);
+            sexps.get(2)
+        }
+        (Some("let" | "let*" | "letrec" | "do" | "for" | "lambda"), clauses) => clauses,
+        (Some("let-values"), Some(Sexp::List(clauses, _))) => {
+            for clause in clauses {
+                if let Some([Sexp::List(vars, _), ..]) = clause.list() {
+                    vars.iter().for_each(|var| bind(var, bindings, names));
+                }
+            }
+            None
+        }
+        _ => None,
+    };
+    if let Some(Sexp::List(clauses, _)) = clauses {
+        clauses
+            .iter()
+            .for_each(|clause| bind(clause, bindings, names));
+    }
+    for sexp in sexps {
+        bound_names(sexp, bindings, names);
+    }
+}
+
+/// the names bound by the template are replaced by the renames, the free names are marked
+fn instantiate(
+    template: &Sexp,
+    bindings: &HashMap<String, Binding>,
+    renames: &HashMap<String, String>,
+) -> Result<Sexp, String> {
+    let (templates, pos) = match template {
+        Sexp::Atom(name, pos) => {
+            return match bindings.get(name) {
+                Some(Binding::One(sexp)) => Ok(sexp.clone()),
+                Some(Binding::Many(_)) => Err(format!("{}: {} is used without ...", pos, name)),
+                None => match renames.get(name) {
+                    Some(rename) => Ok(Sexp::Atom(rename.clone(), *pos)),
+                    None if name == ELLIPSIS => Ok(template.clone()),
+                    None => Ok(Sexp::Atom(format!("{}{}", name, MARK), *pos)),
+                },
+            }
+        }
+        Sexp::Str(..) => return Ok(template.clone()),
+        Sexp::List(templates, pos) => (templates, *pos),
+    };
+    let mut sexps = Vec::with_capacity(templates.len());
+    for (i, template) in templates.iter().enumerate() {
+        if template.atom() == Some(ELLIPSIS) {
+            continue;
+        }
+        if templates.get(i + 1).and_then(Sexp::atom) != Some(ELLIPSIS) {
+            sexps.push(instantiate(template, bindings, renames)?);
+            continue;
+        }
+        let vars = repeated_vars(template, bindings);
+        let lens: Vec<_> = vars
+            .iter()
+            .map(|var| match &bindings[var] {
+                Binding::Many(many) => many.len(),
+                Binding::One(_) => unreachable!(),
+            })
+            .collect();
+        let len = match lens.first() {
+            Some(len) if lens.iter().all(|other| other == len) => *len,
+            Some(_) => return Err(format!("{}: ... repeats forms of different lengths", pos)),
+            None => return Err(format!("{}: ... follows no pattern variable", pos)),
+        };
+        for index in 0..len {
+            let mut sub_bindings = bindings.clone();
+            for var in &vars {
+                if let Binding::Many(many) = &bindings[var] {
+                    sub_bindings.insert(var.clone(), many[index].clone());
+                }
+            }
+            sexps.push(instantiate(template, &sub_bindings, renames)?);
+        }
+    }
+    Ok(Sexp::List(sexps, pos))
+}
+
+/// a sub form of a binding form
+enum Part<'a> {
+    /// a variable bound by the form
+    Binder(&'a Sexp),
+    /// an expression, which sees the names of the form in the scope
+    Exp(&'a Sexp, Vec<String>),
+    /// the keyword, a type or the name of a global
+    Other(&'a Sexp),
+}
+
+fn is_colon(sexp: &Sexp) -> bool {
+    sexp.atom().map(unmark) == Some(":")
+}
+
+/// the variables of a clause or a parameter are followed by the expressions,
+/// a type follows a colon, the expression i of a clause sees the names in scopes[i]
+fn map_clause(
+    clause: &Sexp,
+    scopes: &[Vec<String>],
+    f: &mut impl FnMut(Part) -> Result<Sexp, String>,
+) -> Result<Sexp, String> {
+    let (sexps, pos) = match clause {
+        Sexp::List(sexps, pos) if !sexps.is_empty() => (sexps, *pos),
+        Sexp::Atom(name, _) if unmark(name) == "." => return f(Part::Other(clause)),
+        clause => return f(Part::Binder(clause)),
+    };
+    let vars = match &sexps[0] {
+        Sexp::List(vars, pos) => Sexp::List(
+            vars.iter()
+                .map(|var| f(Part::Binder(var)))
+                .collect::<Result<_, _>>()?,
+            *pos,
+        ),
+        var => f(Part::Binder(var))?,
+    };
+    let mut mapped = vec![vars];
+    let mut exps = 0;
+    for (i, sexp) in sexps.iter().enumerate().skip(1) {
+        let is_type = is_colon(sexp) || is_colon(&sexps[i - 1]);
+        mapped.push(if is_type {
+            f(Part::Other(sexp))?
+        } else {
+            exps += 1;
+            let scope = scopes[(exps - 1).min(scopes.len() - 1)].clone();
+            f(Part::Exp(sexp, scope))?
+        });
+    }
+    Ok(Sexp::List(mapped, pos))
+}
+
+/// the variables of the clauses or the parameters
+fn clause_vars(clauses: &[Sexp]) -> Vec<String> {
+    let mut vars = Vec::new();
+    for clause in clauses {
+        match clause {
+            Sexp::Atom(name, _) if unmark(name) != "." => vars.push(name.clone()),
+            Sexp::List(sexps, _) => match sexps.first() {
+                Some(Sexp::Atom(name, _)) => vars.push(name.clone()),
+                Some(Sexp::List(names, _)) => {
+                    vars.extend(names.iter().filter_map(Sexp::atom).map(str::to_string))
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    vars
+}
+
+/// rebuild a let, lambda, define, do, for or let-values form part by part,
+/// None if the sexps are no such form
+fn map_binding_form(
+    sexps: &[Sexp],
+    pos: Pos,
+    f: &mut impl FnMut(Part) -> Result<Sexp, String>,
+) -> Option<Result<Sexp, String>> {
+    let head = unmark(sexps.first()?.atom()?);
+    // the scopes of the expressions of each clause and the names the body sees
+    let (clauses, scopes, vars) = match (head, sexps) {
+        // a named let binds the loop before its clauses
+        ("let", [_, Sexp::Atom(name, _), clauses @ Sexp::List(list, _), ..]) => {
+            let mut vars = vec![name.clone()];
+            vars.extend(clause_vars(list));
+            (clauses, vec![vec![Vec::new()]; list.len()], vars)
+        }
+        ("let" | "for" | "let-values", [_, clauses @ Sexp::List(list, _), ..]) => (
+            clauses,
+            vec![vec![Vec::new()]; list.len()],
+            clause_vars(list),
+        ),
+        ("let*", [_, clauses @ Sexp::List(list, _), ..]) => {
+            let vars = clause_vars(list);
+            let scopes = (0..list.len()).map(|i| vec![vars[..i.min(vars.len())].to_vec()]);
+            (clauses, scopes.collect(), vars)
+        }
+        ("letrec" | "lambda", [_, clauses @ Sexp::List(list, _), ..]) => {
+            let vars = clause_vars(list);
+            (clauses, vec![vec![vars.clone()]; list.len()], vars)
+        }
+        // the step of a variable sees the variables, its initial value does not
+        ("do", [_, clauses @ Sexp::List(list, _), ..]) => {
+            let vars = clause_vars(list);
+            (
+                clauses,
+                vec![vec![Vec::new(), vars.clone()]; list.len()],
+                vars,
+            )
+        }
+        // the name of the function is global, the default values only see globals
+        ("define", [_, signature @ Sexp::List(list, _), ..]) if !list.is_empty() => {
+            let vars = clause_vars(&list[1..]);
+            (signature, vec![vec![Vec::new()]; list.len()], vars)
+        }
+        _ => return None,
+    };
+    Some(map_rest(
+        sexps,
+        pos,
+        clauses,
+        head == "define",
+        scopes,
+        vars,
+        f,
+    ))
+}
+
+/// map the clauses of the form, the sub forms after them see the vars, the keyword
+/// and a colon with a type are left, a name between the keyword and the clauses is bound
+fn map_rest(
+    sexps: &[Sexp],
+    pos: Pos,
+    clauses: &Sexp,
+    has_name: bool,
+    scopes: Vec<Vec<Vec<String>>>,
+    vars: Vec<String>,
+    f: &mut impl FnMut(Part) -> Result<Sexp, String>,
+) -> Result<Sexp, String> {
+    let (list, list_pos) = match clauses {
+        Sexp::List(list, pos) => (list, *pos),
+        _ => unreachable!(),
+    };
+    let index = sexps
+        .iter()
+        .position(|sexp| std::ptr::eq(sexp, clauses))
+        .unwrap();
+    let mut mapped = Vec::with_capacity(sexps.len());
+    mapped.push(f(Part::Other(&sexps[0]))?);
+    for sexp in &sexps[1..index] {
+        mapped.push(f(Part::Binder(sexp))?);
+    }
+    let mut clauses = Vec::with_capacity(list.len());
+    let mut list = list.iter();
+    if has_name {
+        clauses.push(f(Part::Other(list.next().unwrap()))?);
+    }
+    for (clause, scopes) in list.zip(scopes) {
+        clauses.push(map_clause(clause, &scopes, f)?);
+    }
+    mapped.push(Sexp::List(clauses, list_pos));
+    for (i, sexp) in sexps.iter().enumerate().skip(index + 1) {
+        mapped.push(if is_colon(sexp) || is_colon(&sexps[i - 1]) {
+            f(Part::Other(sexp))?
+        } else {
+            f(Part::Exp(sexp, vars.clone()))?
+        });
+    }
+    Ok(Sexp::List(mapped, pos))
+}
+
+/// fresh counts the expansions, whose number suffixes the names bound by the template,
+/// a local variable in bound shadows a macro of its name, unless the name is marked
+fn expand(
+    sexp: &Sexp,
+    macros: &HashMap<String, Macro>,
+    bound: &mut Vec<String>,
+    depth: usize,
+    fresh: &mut usize,
+) -> Result<Sexp, String> {
+    let (sexps, pos) = match sexp {
+        Sexp::List(sexps, pos) => (sexps, *pos),
+        sexp => return Ok(sexp.clone()),
+    };
+    let head = sexps.first().and_then(Sexp::atom);
+    if head.map(unmark) == Some("quote") {
+        return Ok(sexp.clone());
+    }
+    let is_macro = |name: &&str| unmark(name) != *name || !bound.iter().any(|var| var == name);
+    if let Some((name, m)) = head
+        .filter(is_macro)
+        .and_then(|name| macros.get_key_value(unmark(name)))
+    {
+        if depth == MAX_DEPTH {
+            return Err(format!("{}: expansion of {} is too deep", pos, name));
+        }
+        for rule in &m.rules {
+            let patterns = &rule.pattern.list().unwrap()[1..];
+            let mut bindings = HashMap::new();
+            if m.bind_list(patterns, &sexps[1..], &mut bindings) {
+                let mut names = Vec::new();
+                bound_names(&rule.template, &bindings, &mut names);
+                *fresh += 1;
+                let renames = names
+                    .into_iter()
+                    .map(|name| {
+                        let rename = format!("{}.macro.{}", name, fresh);
+                        (name, rename)
+                    })
+                    .collect();
+                let sexp = instantiate(&rule.template, &bindings, &renames)?;
+                return expand(&sexp, macros, bound, depth + 1, fresh);
+            }
+        }
+        return Err(format!("{}: no rule of {} matches", pos, name));
+    }
+    // the variables and the types of a binding form are not expanded
+    let form = map_binding_form(sexps, pos, &mut |part| match part {
+        Part::Binder(sexp) | Part::Other(sexp) => Ok(sexp.clone()),
+        Part::Exp(sexp, scope) => {
+            let len = bound.len();
+            bound.extend(scope);
+            let exp = expand(sexp, macros, bound, depth, fresh);
+            bound.truncate(len);
+            exp
+        }
+    });
+    if let Some(form) = form {
+        return form;
+    }
+    let sexps = sexps
+        .iter()
+        .map(|sexp| expand(sexp, macros, bound, depth, fresh))
+        .collect::<Result<_, _>>()?;
+    Ok(Sexp::List(sexps, pos))
+}
+
+/// the marked names in the expanded form
+fn marked_names(sexp: &Sexp, names: &mut HashSet<String>) {
+    match sexp {
+        Sexp::Atom(name, _) if unmark(name) != name => {
+            names.insert(unmark(name).to_string());
+        }
+        Sexp::List(sexps, _) => sexps.iter().for_each(|sexp| marked_names(sexp, names)),
+        _ => {}
+    }
+}
+
+fn strip_marks(sexp: &Sexp) -> Sexp {
+    match sexp {
+        Sexp::Atom(name, pos) => Sexp::Atom(unmark(name).to_string(), *pos),
+        Sexp::List(sexps, pos) => Sexp::List(sexps.iter().map(strip_marks).collect(), *pos),
+        sexp => sexp.clone(),
+    }
+}
+
+/// a local variable named like a marked name is renamed, so the marked name refers to
+/// the global, then the marks are removed
+fn resolve(
+    sexp: &Sexp,
+    marked: &HashSet<String>,
+    renames: &HashMap<String, String>,
+    fresh: &mut usize,
+) -> Result<Sexp, String> {
+    let (sexps, pos) = match sexp {
+        Sexp::Atom(name, pos) if unmark(name) == name => {
+            let name = renames.get(name).unwrap_or(name);
+            return Ok(Sexp::Atom(name.clone(), *pos));
+        }
+        Sexp::List(sexps, pos) => (sexps, *pos),
+        sexp => return Ok(strip_marks(sexp)),
+    };
+    if sexps.first().and_then(Sexp::atom).map(unmark) == Some("quote") {
+        return Ok(strip_marks(sexp));
+    }
+    let mut binders = Vec::new();
+    map_binding_form(sexps, pos, &mut |part| {
+        if let Part::Binder(Sexp::Atom(name, _)) = part {
+            binders.push(name.clone());
+        }
+        Ok(Sexp::List(Vec::new(), pos))
+    });
+    let mut local = HashMap::new();
+    for name in binders.into_iter().filter(|name| marked.contains(name)) {
+        *fresh += 1;
+        let rename = format!("{}.local.{}", name, fresh);
+        local.insert(name, rename);
+    }
+    let form = map_binding_form(sexps, pos, &mut |part| match part {
+        Part::Binder(Sexp::Atom(name, pos)) => Ok(Sexp::Atom(
+            local
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| unmark(name).to_string()),
+            *pos,
+        )),
+        Part::Binder(sexp) | Part::Other(sexp) => Ok(strip_marks(sexp)),
+        Part::Exp(sexp, scope) => {
+            let mut renames = renames.clone();
+            for name in scope {
+                match local.get(&name) {
+                    Some(rename) => renames.insert(name, rename.clone()),
+                    None => renames.remove(&name),
+                };
+            }
+            resolve(sexp, marked, &renames, fresh)
+        }
+    });
+    if let Some(form) = form {
+        return form;
+    }
+    let sexps = sexps
+        .iter()
+        .map(|sexp| resolve(sexp, marked, renames, fresh))
+        .collect::<Result<_, _>>()?;
+    Ok(Sexp::List(sexps, pos))
+}
+
+/// macros are visible to every form, the define-syntax forms are removed
+pub fn expand_forms(forms: &[Sexp]) -> Result<Vec<Sexp>, String> {
+    let mut macros = HashMap::new();
+    let mut rest = Vec::with_capacity(forms.len());
+    for form in forms {
+        match form.list() {
+            Some([head, ..]) if head.atom() == Some("define-syntax") => {
+                let (name, m) = parse_macro(form)?;
+                macros.insert(name, m);
+            }
+            _ => rest.push(form),
+        }
+    }
+    let mut fresh = 0;
+    let forms = rest
+        .into_iter()
+        .map(|form| expand(form, &macros, &mut Vec::new(), 0, &mut fresh))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut marked = HashSet::new();
+    forms
+        .iter()
+        .for_each(|form| marked_names(form, &mut marked));
+    forms
+        .iter()
+        .map(|form| resolve(form, &marked, &HashMap::new(), &mut fresh))
+        .collect()
+}
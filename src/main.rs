@@ -1,24 +1,37 @@
+// Nodes are passed around as `Box<Node>` and named after x86 instructions
+#![allow(clippy::vec_box, clippy::boxed_local, clippy::upper_case_acronyms)]
+
 mod ast;
+mod cfg;
 mod graph;
+mod macros;
+mod module;
 mod parser;
 mod pass;
+mod pipeline;
 mod printer;
+mod validate;
 
 use parser::Parser;
+use pipeline::{Ir, Pass, PassManager, Program};
 use printer::{print_ast, print_live_set, print_stmt};
 use std::env;
 use std::fs::{self, File};
-use std::io::Write;
-use std::process::Command;
+use std::io::{self, Write};
+use std::process::{self, Command, Stdio};
+
+fn parse(s: &str) -> Result<Box<ast::Node>, String> {
+    let mut parser = Parser::new(s.chars().collect())?;
+    parser.parse_program()
+}
 
 fn test_type_check(s: &str) -> Result<ast::Type, String> {
-    let mut parser = Parser::new(s.to_string().chars().collect());
-    let ast = parser.parse_program();
+    let mut ast = parse(s)?;
     println!("inputs:");
     print_ast(ast.clone());
     println!();
     let mut info = ast::Info::default();
-    let ret_t = pass::type_check(ast, &mut info);
+    let ret_t = pass::type_check(&mut ast, &mut info);
     match ret_t.as_ref() {
         Ok(ret_t) => println!("type check: OK, ret: {:?}", ret_t),
         Err(msg) => println!("type check: Error, msg: {}", msg),
@@ -27,109 +40,74 @@ fn test_type_check(s: &str) -> Result<ast::Type, String> {
     ret_t
 }
 
-fn test_r2(s: &str) {
-    let mut parser = Parser::new(s.to_string().chars().collect());
-    let ast = parser.parse_program();
-    println!("inputs:");
-    print_ast(ast.clone());
-    println!();
-    println!("partial eval:");
-    let ast = pass::partial_eval(ast);
-    print_ast(ast.clone());
-    println!();
-    println!("uniquify:");
-    let ast = pass::uniquify(ast);
-    print_ast(ast.clone());
-    println!();
-    println!("flattern:");
-    let ast = pass::flattern(ast);
-    print_stmt(ast.clone());
-    println!();
-    println!("select inst:");
-    let ast = pass::select_inst(ast);
-    print_stmt(ast.clone());
-    println!();
-    println!("uncover live:");
-    let mut info = ast::Info::default();
-    let ast = pass::uncover_live(ast, &mut info);
-    print_stmt(ast.clone());
-    print_live_set(&info.live_afters);
-    println!();
-    println!("build interference:");
-    let ast = pass::build_interference(ast, &mut info);
-    println!("{:?}", info.interference_graph);
-    println!();
-    println!("alloc registers:");
-    let ast = pass::allocate_registers(ast, &mut info);
-    print_stmt(ast.clone());
-    println!();
-    println!("lower conditionals:");
-    let ast = pass::lower_conditionals(ast);
-    print_stmt(ast.clone());
-    println!();
-    println!("patch inst:");
-    let ast = pass::patch_inst(ast);
-    print_stmt(ast.clone());
-    println!();
-    println!("print x86:");
-    let mut buf = Vec::new();
-    pass::print_x86(&mut buf, ast, info).unwrap();
-    println!("{}", String::from_utf8(buf.clone()).unwrap());
-    println!();
-    run_code(buf);
+/// Compile source to x86 assembly, print each stage if verbose is set
+fn compile(ast: Box<ast::Node>, options: &ast::Options, verbose: bool) -> Result<Vec<u8>, String> {
+    if verbose {
+        println!("inputs:");
+        print_ast(ast.clone());
+        println!();
+    }
+    let mut passes = PassManager::new(options);
+    if let Some(names) = &options.passes {
+        passes.reorder(names)?;
+    }
+    for name in &options.skip {
+        passes.skip(name)?;
+    }
+    let prog = passes.run(Program::new(ast), |pass, prog| {
+        if verbose {
+            print_stage(pass, prog);
+        }
+    })?;
+    match prog.ir {
+        Ir::Asm(buf) => Ok(buf),
+        _ => Err("the pipeline does not end with print x86".to_string()),
+    }
 }
 
-fn test(s: &str) {
-    let mut parser = Parser::new(s.to_string().chars().collect());
-    let ast = parser.parse_program();
-    println!("inputs:");
-    print_ast(ast.clone());
-    println!();
-    println!("partial eval:");
-    let ast = pass::partial_eval(ast);
-    print_ast(ast.clone());
-    println!();
-    println!("uniquify:");
-    let ast = pass::uniquify(ast);
-    print_ast(ast.clone());
-    println!();
-    println!("flattern:");
-    let ast = pass::flattern(ast);
-    print_stmt(ast.clone());
-    println!();
-    println!("select inst:");
-    let ast = pass::select_inst(ast);
-    print_stmt(ast.clone());
-    println!();
-    // println!("assign home:");
-    // let mut info = ast::Info::default();
-    // let ast = pass::assign_home(ast, &mut info);
-    // print_stmt(ast.clone());
-    // println!();
-    println!("uncover live:");
-    let mut info = ast::Info::default();
-    let ast = pass::uncover_live(ast, &mut info);
-    print_stmt(ast.clone());
-    print_live_set(&info.live_afters);
-    println!();
-    println!("build interference:");
-    let ast = pass::build_interference(ast, &mut info);
-    println!("{:?}", info.interference_graph);
-    println!();
-    println!("alloc registers:");
-    let ast = pass::allocate_registers(ast, &mut info);
-    print_stmt(ast.clone());
-    println!();
-    println!("patch inst:");
-    let ast = pass::patch_inst(ast);
-    print_stmt(ast.clone());
-    println!();
-    println!("print x86:");
-    let mut buf = Vec::new();
-    pass::print_x86(&mut buf, ast, info).unwrap();
-    println!("{}", String::from_utf8(buf.clone()).unwrap());
-    println!();
-    run_code(buf);
+/// Print the program returned by a pass
+fn print_stage(pass: &dyn Pass, prog: &Program) {
+    let header = pass.name().replace('_', " ");
+    match &prog.ir {
+        // the type checker only annotates the program
+        Ir::Exp(_) if pass.name() == "type_check" => {}
+        Ir::Exp(ast) => {
+            println!("{}:", header);
+            print_ast(ast.clone());
+            println!();
+        }
+        Ir::Defs(defs) => {
+            println!("{}:", header);
+            print_stmt(defs.clone());
+            println!();
+        }
+        Ir::Functions(functions) => {
+            for function in functions {
+                println!("{} ({}):", header, function.name);
+                match pass.name() {
+                    "build_interference" => println!("{:?}", function.info.interference_graph),
+                    "uncover_live" => {
+                        print_stmt(function.body.clone());
+                        print_live_set(&function.info.live_afters);
+                    }
+                    _ => print_stmt(function.body.clone()),
+                }
+                println!();
+            }
+        }
+        Ir::Asm(buf) => {
+            println!("{}:", header);
+            println!("{}", String::from_utf8_lossy(buf));
+            println!();
+        }
+    }
+}
+
+/// the input is written to the stdin of the program
+fn test(s: &str, input: &str) {
+    let ast = parse(s).expect("parse");
+    let buf = compile(ast, &ast::Options::default(), true).expect("compile");
+    run_code(buf, input);
 }
 
 fn run_cmd(cmd: String) {
@@ -148,7 +126,7 @@ fn build_runtime() {
     run_cmd("cc -c -o runtime/runtime.o runtime/runtime.c".to_string());
 }
 
-fn run_code(source: Vec<u8>) {
+fn run_code(source: Vec<u8>, input: &str) {
     let mut dir = env::current_dir().unwrap();
     dir.push("tmp");
     fs::create_dir(&dir).unwrap();
@@ -164,19 +142,112 @@ fn run_code(source: Vec<u8>) {
     };
     {
         let mut f = File::create(&source_file).unwrap();
-        f.write(&source).unwrap();
+        f.write_all(&source).unwrap();
     }
     run_cmd(format!(
         "cc -o {output} runtime/runtime.o {input}",
         input = source_file,
         output = output_file
     ));
-    run_cmd(format!("{}", output_file));
+    let mut child = Command::new(&output_file)
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let status = child.wait().expect("failed to execute process");
+    if !status.success() {
+        panic!("unsuccess cmd {}", output_file);
+    }
     println!();
     fs::remove_dir_all(dir).unwrap();
 }
 
+/// Compile a source file and the modules it requires, write the assembly to stdout
+fn compile_file(path: &str, options: &ast::Options, verbose: bool) -> Result<(), String> {
+    let ast = module::load_program(path.as_ref())?;
+    let buf = compile(ast, options, verbose)?;
+    if !verbose {
+        io::stdout()
+            .write_all(&buf)
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
 fn main() {
+    let mut verbose = false;
+    let mut options = ast::Options::default();
+    let mut path = None;
+    let mut list_passes = false;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            "--bounds-check" => options.bounds_check = true,
+            "--overflow-check" => options.overflow_check = true,
+            "--bignum" => options.bignum = true,
+            "--release" => options.release = true,
+            "-O2" => options.schedule = true,
+            "--ssa" => options.ssa = true,
+            "--list-passes" => list_passes = true,
+            "--validate" => options.validate = true,
+            arg if arg.starts_with("--passes=") => {
+                options.passes = Some(
+                    arg["--passes=".len()..]
+                        .split(',')
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                )
+            }
+            arg if arg.starts_with("--skip=") => options
+                .skip
+                .extend(arg["--skip=".len()..].split(',').map(str::to_string)),
+            arg if arg.starts_with("--unroll=") => match arg["--unroll=".len()..].parse() {
+                Ok(factor) => options.unroll = factor,
+                Err(_) => {
+                    eprintln!("error: invalid unroll factor {}", arg);
+                    process::exit(1);
+                }
+            },
+            arg if arg.starts_with('-') => {
+                eprintln!("error: unknown option {}", arg);
+                process::exit(1);
+            }
+            _ if path.is_some() => {
+                eprintln!("error: more than one source file");
+                process::exit(1);
+            }
+            _ => path = Some(arg),
+        }
+    }
+    if list_passes {
+        for pass in PassManager::new(&options).passes() {
+            if pass.is_optional() {
+                println!(
+                    "{} (optional, after {}, before {})",
+                    pass.name(),
+                    pass.runs_after().join(" and "),
+                    pass.runs_before().join(" and ")
+                );
+            } else {
+                println!("{}", pass.name());
+            }
+        }
+        return;
+    }
+    if let Some(path) = path {
+        if let Err(msg) = compile_file(&path, &options, verbose) {
+            eprintln!("error: {}", msg);
+            process::exit(1);
+        }
+        return;
+    }
+
     test_type_check("(program (+ (read) (let ([x 32]) (+ (let ([x 10]) x) x))))").unwrap();
     test_type_check("(program (+ 10 2))").unwrap();
     test_type_check("(program (== (+ 10 2) false))").unwrap_err();
@@ -184,12 +255,26 @@ fn main() {
     test_type_check("(program (== (> 10 2) false))").unwrap();
     test_type_check("(program (if false 0 42))").unwrap();
 
+    build_runtime();
+
+    // R4 language
+    test(
+        "(program (define (inc [x : Integer]) : Integer (+ x 1)) (inc 41))",
+        "",
+    );
+
     // R2 language
-    test_r2("(program (if false 0 42))");
-    test_r2("(program (if (== 4 10) 0 42))");
+    test("(program (if false 0 42))", "");
+    test("(program (if (== 4 10) 0 42))", "");
+    test(
+        "(program (let ([x (if (== 4 (+ 2 2)) #t #f)]) (if x (if (== 1 2) 1 2) 3)))",
+        "",
+    );
 
     // R1 language
-    build_runtime();
-    test("(program (+ (read) (let ([x 32]) (+ (let ([x 10]) x) x))))");
-    test("(program (+ 10 2))");
+    test(
+        "(program (+ (read) (let ([x 32]) (+ (let ([x 10]) x) x))))",
+        "10",
+    );
+    test("(program (+ 10 2))", "");
 }
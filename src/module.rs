@@ -0,0 +1,302 @@
+/// Load a program with the modules it requires, the definitions of every module are
+/// concatenated into one program, a module only sees the names provided by its requires,
+/// the functions and globals of a required module are prefixed, the types are shared
+use crate::ast::*;
+use crate::parser::Parser;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+struct Module {
+    path: PathBuf,
+    defs: Vec<Box<Node>>,
+    exp: Box<Node>,
+    provides: Vec<String>,
+    /// indexes of the required modules
+    requires: Vec<usize>,
+}
+
+fn unwrap_variadic(def: &Node) -> &Node {
+    match def {
+        Node::Variadic(def) => def,
+        def => def,
+    }
+}
+
+impl Module {
+    /// names of the functions and globals defined by the module,
+    /// including the functions generated for its types
+    fn defined_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for def in &self.defs {
+            match unwrap_variadic(def) {
+                Node::Def { name, .. }
+                | Node::DefGlobal { name, .. }
+                | Node::DefConst { name, .. } => names.push(name.clone()),
+                Node::DefStruct { name, fields } => {
+                    names.push(name.clone());
+                    for (field, _) in fields {
+                        names.push(accessor_name(name, field));
+                        names.push(mutator_name(name, field));
+                    }
+                }
+                Node::DefType { variants, .. } => {
+                    for (variant, _) in variants {
+                        names.push(variant.clone());
+                        names.push(predicate_name(variant));
+                    }
+                }
+                _ => {}
+            }
+        }
+        names
+    }
+
+    /// names of the functions and globals, which are renamed by the prefix of the module
+    fn value_names(&self) -> Vec<String> {
+        self.defs
+            .iter()
+            .filter_map(|def| match unwrap_variadic(def) {
+                Node::Def { name, .. }
+                | Node::DefGlobal { name, .. }
+                | Node::DefConst { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// variables referred by the definitions and the expression
+    fn referred_names(&self) -> Vec<String> {
+        let mut names = self.exp.free_vars();
+        for def in &self.defs {
+            if let Node::Def { params, body, .. } = unwrap_variadic(def) {
+                let vars = body[0].free_vars();
+                names.extend(
+                    vars.into_iter()
+                        .filter(|var| params.iter().all(|(param, _)| param != var)),
+                );
+            }
+        }
+        names
+    }
+}
+
+#[derive(Default)]
+struct Loader {
+    /// modules are pushed after their requires
+    modules: Vec<Module>,
+    /// index of each loaded file, None while its requires are loaded
+    loaded: HashMap<PathBuf, Option<usize>>,
+}
+
+impl Loader {
+    /// the file is identified by its canonical path
+    fn load(&mut self, path: &Path, key: PathBuf) -> Result<usize, String> {
+        let source =
+            fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let program = Parser::new(source.chars().collect())
+            .and_then(|mut parser| parser.parse_program())
+            .map_err(|msg| format!("{}: {}", path.display(), msg))?;
+        let (defs, exp) = match *program {
+            Node::Program(defs, exp) => (defs, exp),
+            node => panic!("unexpected {:?}", node),
+        };
+        let mut module = Module {
+            path: path.to_path_buf(),
+            defs: Vec::new(),
+            exp,
+            provides: Vec::new(),
+            requires: Vec::new(),
+        };
+        self.loaded.insert(key.clone(), None);
+        for def in defs {
+            match *def {
+                Node::Provide(names) => module.provides.extend(names),
+                Node::Require(file, pos) => {
+                    let required = path.parent().unwrap_or_else(|| Path::new("")).join(&file);
+                    let required_key = fs::canonicalize(&required)
+                        .map_err(|err| format!("{}: {}: {}: {}", path.display(), pos, file, err))?;
+                    let index = match self.loaded.get(&required_key) {
+                        Some(Some(index)) => *index,
+                        Some(None) => {
+                            return Err(format!(
+                                "{}: {}: cyclic require of {}",
+                                path.display(),
+                                pos,
+                                file
+                            ))
+                        }
+                        None => self.load(&required, required_key)?,
+                    };
+                    module.requires.push(index);
+                }
+                def => module.defs.push(Box::new(def)),
+            }
+        }
+        self.modules.push(module);
+        let index = self.modules.len() - 1;
+        self.loaded.insert(key, Some(index));
+        Ok(index)
+    }
+
+    /// a required module only has definitions, and provides the names it defines
+    fn check(&self, main: usize) -> Result<(), String> {
+        let mut owners: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, module) in self.modules.iter().enumerate() {
+            if index != main && *module.exp != Node::Void {
+                return Err(format!(
+                    "{}: a required module only has definitions",
+                    module.path.display()
+                ));
+            }
+            let names = module.defined_names();
+            if let Some(name) = module.provides.iter().find(|name| !names.contains(name)) {
+                return Err(format!(
+                    "{}: {} is provided but not defined",
+                    module.path.display(),
+                    name
+                ));
+            }
+            for name in names {
+                owners.entry(name).or_default().push(index);
+            }
+        }
+        for (index, module) in self.modules.iter().enumerate() {
+            let visible: HashSet<_> = module
+                .requires
+                .iter()
+                .flat_map(|&required| self.modules[required].provides.iter())
+                .collect();
+            for name in module.referred_names() {
+                match owners.get(&name) {
+                    Some(owners) if !owners.contains(&index) && !visible.contains(&name) => {
+                        return Err(format!(
+                            "{}: {} is not provided by a required module",
+                            module.path.display(),
+                            name
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the new names of the functions and globals the module sees, its own definitions
+    /// shadow the names provided by its requires
+    fn renames(&self, index: usize, main: usize) -> Result<HashMap<String, String>, String> {
+        let module = &self.modules[index];
+        let mut renames = HashMap::new();
+        let mut providers = HashMap::new();
+        for &required in &module.requires {
+            let values = self.modules[required].value_names();
+            for name in &self.modules[required].provides {
+                if !values.contains(name) {
+                    continue;
+                }
+                match providers.insert(name, required) {
+                    Some(other) if other != required => {
+                        return Err(format!(
+                            "{}: {} is provided by both {} and {}",
+                            module.path.display(),
+                            name,
+                            self.modules[other].path.display(),
+                            self.modules[required].path.display()
+                        ))
+                    }
+                    _ => renames.insert(name.clone(), prefixed(required, main, name)),
+                };
+            }
+        }
+        for name in module.value_names() {
+            renames.insert(name.clone(), prefixed(index, main, &name));
+        }
+        Ok(renames)
+    }
+}
+
+/// the names of the main program are kept
+fn prefixed(index: usize, main: usize, name: &str) -> String {
+    if index == main {
+        name.to_string()
+    } else {
+        format!("module{}.{}", index, name)
+    }
+}
+
+/// rename the free variables and the definitions, a variable bound inside is left
+fn rename(node: Box<Node>, renames: &HashMap<String, String>) -> Box<Node> {
+    use Node::*;
+
+    let renamed = |name: String| renames.get(&name).cloned().unwrap_or(name);
+    let node = match *node {
+        Var(name) => Var(renamed(name)),
+        SetBang { name, value } => SetBang {
+            name: renamed(name),
+            value: rename(value, renames),
+        },
+        Def {
+            name,
+            params,
+            ret,
+            body,
+        } => {
+            let bound: Vec<_> = params.iter().map(|(param, _)| param.clone()).collect();
+            Def {
+                name: renamed(name),
+                params,
+                ret,
+                body: body
+                    .into_iter()
+                    .map(|exp| rename_scoped(exp, &bound, renames))
+                    .collect(),
+            }
+        }
+        DefGlobal { name, value } => DefGlobal {
+            name: renamed(name),
+            value: rename(value, renames),
+        },
+        DefConst { name, value } => DefConst {
+            name: renamed(name),
+            value: rename(value, renames),
+        },
+        node => node.map_scoped(&mut |child, bound| rename_scoped(child, bound, renames)),
+    };
+    Box::new(node)
+}
+
+fn rename_scoped(
+    node: Box<Node>,
+    bound: &[String],
+    renames: &HashMap<String, String>,
+) -> Box<Node> {
+    if bound.iter().all(|name| !renames.contains_key(name)) {
+        return rename(node, renames);
+    }
+    let mut renames = renames.clone();
+    for name in bound {
+        renames.remove(name);
+    }
+    rename(node, &renames)
+}
+
+/// the expression of the program file is the expression of the program
+pub fn load_program(path: &Path) -> Result<Box<Node>, String> {
+    let mut loader = Loader::default();
+    let key = fs::canonicalize(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let main = loader.load(path, key)?;
+    loader.check(main)?;
+    let renames = (0..loader.modules.len())
+        .map(|index| loader.renames(index, main))
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut defs = Vec::new();
+    let mut exp = Box::new(Node::Void);
+    for ((index, module), renames) in loader.modules.into_iter().enumerate().zip(renames) {
+        defs.extend(module.defs.into_iter().map(|def| rename(def, &renames)));
+        if index == main {
+            exp = rename(module.exp, &renames);
+        }
+    }
+    Ok(Box::new(Node::Program(defs, exp)))
+}
@@ -1,185 +1,1313 @@
-use crate::ast::{Node, Token};
-use std::iter::FromIterator;
+use crate::ast::{default_name, hidden_name, Node, Pattern, Pos, Token, Type};
+use crate::macros;
+use std::num::IntErrorKind;
+use std::result::Result;
 
-pub struct Parser {
-    source: Vec<char>,
-    cur: usize,
+/// S-expression read from the token stream
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Sexp {
+    Atom(String, Pos),
+    Str(String, Pos),
+    List(Vec<Sexp>, Pos),
 }
 
-impl Parser {
-    pub fn new(source: Vec<char>) -> Self {
-        Parser { source, cur: 0 }
+impl Sexp {
+    pub fn pos(&self) -> Pos {
+        match self {
+            Sexp::Atom(_, pos) | Sexp::Str(_, pos) | Sexp::List(_, pos) => *pos,
+        }
     }
 
-    fn next_char(&mut self) -> Option<char> {
-        if self.cur == self.source.len() {
-            return None;
+    pub fn atom(&self) -> Option<&str> {
+        match self {
+            Sexp::Atom(atom, _) => Some(atom),
+            _ => None,
         }
-        while self.cur < self.source.len() && self.source[self.cur] == ' ' {
-            self.cur += 1;
+    }
+
+    pub fn list(&self) -> Option<&[Sexp]> {
+        match self {
+            Sexp::List(list, _) => Some(list),
+            _ => None,
         }
-        if self.cur == self.source.len() {
-            return None;
+    }
+}
+
+/// print the expression as it is written in the source
+impl std::fmt::Display for Sexp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Sexp::Atom(atom, _) => write!(f, "{}", atom),
+            Sexp::Str(s, _) => write!(f, "{:?}", s),
+            Sexp::List(list, _) => {
+                write!(f, "(")?;
+                for (i, sexp) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", sexp)?;
+                }
+                write!(f, ")")
+            }
         }
-        Some(self.source[self.cur])
     }
+}
 
-    fn match_str(&mut self, expected: &str) -> Result<(), String> {
-        self.next_char();
-        let end_at = std::cmp::min(self.source.len(), self.cur + expected.len());
-        let actual = &self.source[self.cur..end_at];
-        let matched = actual == &expected.chars().collect::<Vec<_>>()[..];
-        if matched {
-            self.cur += expected.len();
-            Ok(())
-        } else {
-            Err(String::from_iter(actual.iter()))
+/// Split source into tokens
+struct Lexer {
+    source: Vec<char>,
+    cur: usize,
+    pos: Pos,
+}
+
+impl Lexer {
+    fn new(source: Vec<char>) -> Self {
+        Lexer {
+            source,
+            cur: 0,
+            pos: Pos { line: 1, col: 1 },
         }
     }
 
-    fn expect_str(&mut self, expected: &str) {
-        if let Err(actual) = self.match_str(expected) {
-            panic!(
-                "parse token error at {}, expected {}, but got unexpected token: '{}'",
-                self.cur, expected, actual
-            );
+    fn peek_char(&self) -> Option<char> {
+        self.source.get(self.cur).cloned()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let chr = self.peek_char()?;
+        self.cur += 1;
+        if chr == '\n' {
+            self.pos.line += 1;
+            self.pos.col = 1;
+        } else {
+            self.pos.col += 1;
         }
+        Some(chr)
     }
 
-    fn read_fixnum(&mut self) -> Option<isize> {
-        let prev_cur = self.cur;
-        let mut num = 0;
-        loop {
-            let chr = self.source[self.cur];
-            if chr > '9' || chr < '0' {
+    fn skip_whitespace(&mut self) {
+        while let Some(chr) = self.peek_char() {
+            if chr == ';' {
+                // comment until the end of line
+                while !matches!(self.next_char(), Some('\n') | None) {}
+            } else if chr.is_whitespace() {
+                self.next_char();
+            } else {
                 break;
             }
-            num = num * 10 + chr.to_digit(10).unwrap() as isize;
-            self.cur += 1;
         }
-        if prev_cur == self.cur {
-            return None;
-        }
-        Some(num)
     }
 
-    fn read_var(&mut self) -> String {
-        let prev_cur = self.cur;
-        let mut var = String::new();
+    fn is_delimiter(chr: char) -> bool {
+        chr.is_whitespace() || matches!(chr, '(' | ')' | '[' | ']' | ';' | '"')
+    }
+
+    /// read a string after the opening quote
+    fn read_string(&mut self, pos: Pos) -> Result<String, String> {
+        let mut s = String::new();
         loop {
-            let chr = self.source[self.cur];
-            if chr > 'z' || chr < 'a' {
-                break;
-            }
-            self.cur += 1;
-            var.push(chr);
+            let chr = match self.next_char() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next_char() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some(chr @ '"') | Some(chr @ '\\') => chr,
+                    Some(chr) => {
+                        return Err(format!("{}: unknown escape \\{}", self.pos, chr));
+                    }
+                    None => return Err(format!("{}: unclosed string", pos)),
+                },
+                Some(chr) => chr,
+                None => return Err(format!("{}: unclosed string", pos)),
+            };
+            s.push(chr);
         }
-        if prev_cur == self.cur {
-            panic!(
-                "parse var error: {}",
-                String::from_iter(
-                    self.source[self.cur..std::cmp::min(self.source.len(), self.cur + 20)].iter()
-                )
-            );
+    }
+
+    fn read_token(&mut self) -> Option<Result<(Token, Pos), String>> {
+        self.skip_whitespace();
+        let pos = self.pos;
+        let token = match self.next_char()? {
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '\'' => Token::Quote,
+            '#' if self.peek_char() == Some('(') => {
+                self.next_char();
+                Token::HashParen
+            }
+            // the character after #\ may be a delimiter
+            '#' if self.peek_char() == Some('\\') => {
+                self.next_char();
+                let mut atom = "#\\".to_string();
+                atom.extend(self.next_char());
+                while let Some(chr) = self.peek_char() {
+                    if Self::is_delimiter(chr) {
+                        break;
+                    }
+                    atom.push(chr);
+                    self.next_char();
+                }
+                Token::Atom(atom)
+            }
+            '"' => match self.read_string(pos) {
+                Ok(s) => Token::Str(s),
+                Err(msg) => return Some(Err(msg)),
+            },
+            chr => {
+                let mut atom = chr.to_string();
+                while let Some(chr) = self.peek_char() {
+                    if Self::is_delimiter(chr) {
+                        break;
+                    }
+                    atom.push(chr);
+                    self.next_char();
+                }
+                Token::Atom(atom)
+            }
+        };
+        Some(Ok((token, pos)))
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(Token, Pos)>, String> {
+        let mut tokens = Vec::new();
+        while let Some(token) = self.read_token() {
+            tokens.push(token?);
         }
-        var
+        Ok(tokens)
+    }
+}
+
+pub struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    cur: usize,
+}
+
+impl Parser {
+    pub fn new(source: Vec<char>) -> Result<Self, String> {
+        let tokens = Lexer::new(source).tokenize()?;
+        Ok(Parser { tokens, cur: 0 })
     }
 
-    fn read_token(&mut self) -> Option<Token> {
-        use Token::*;
+    fn end_pos(&self) -> Pos {
+        self.tokens.last().map(|(_, pos)| *pos).unwrap_or_default()
+    }
 
-        let chr = match self.next_char() {
-            Some(chr) => chr,
-            None => return None,
+    fn read_sexp(&mut self) -> Result<Sexp, String> {
+        let (token, pos) = match self.tokens.get(self.cur) {
+            Some(token) => token.clone(),
+            None => return Err(format!("{}: unexpected end of input", self.end_pos())),
         };
-        let token = match chr {
-            'p' if self.match_str("program").is_ok() => Program,
-            'l' if self.match_str("let").is_ok() => Let,
-            '+' => {
-                self.cur += 1;
-                Add
-            }
-            '-' => {
-                self.cur += 1;
-                Neg
-            }
-            'n' if self.match_str("not").is_ok() => Not,
-            't' if self.match_str("true").is_ok() => True,
-            'f' if self.match_str("false").is_ok() => False,
-            '=' if self.match_str("==").is_ok() => Eq,
-            '<' if self.match_str("<=").is_ok() => Lte,
-            '<' => {
-                self.cur += 1;
-                Lt
-            }
-            '>' if self.match_str(">=").is_ok() => Gte,
-            '>' => {
-                self.cur += 1;
-                Gt
-            }
-            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => Fixnum,
-            'r' if self.match_str("read").is_ok() => Read,
-            'i' if self.match_str("if").is_ok() => If,
-            _ => Var,
+        self.cur += 1;
+        let close = match token {
+            Token::Atom(atom) => return Ok(Sexp::Atom(atom, pos)),
+            Token::Str(s) => return Ok(Sexp::Str(s, pos)),
+            Token::Quote => {
+                let exp = self.read_sexp()?;
+                let quote = Sexp::Atom("quote".to_string(), pos);
+                return Ok(Sexp::List(vec![quote, exp], pos));
+            }
+            Token::LParen | Token::HashParen => Token::RParen,
+            Token::LBracket => Token::RBracket,
+            Token::RParen | Token::RBracket => {
+                return Err(format!("{}: unexpected {:?}", pos, token));
+            }
         };
-        Some(token)
-    }
-
-    fn read_exp(&mut self) -> Box<Node> {
-        use Token::*;
-
-        let in_paren = self.match_str("(").is_ok();
-        let token = self.read_token().expect("token");
-        let node = match token {
-            Program => Node::Program(self.read_exp()),
-            Add => Node::Add(self.read_exp(), self.read_exp()),
-            Neg => Node::Neg(self.read_exp()),
-            Fixnum => Node::Fixnum(self.read_fixnum().expect("fixnum")),
-            Read => Node::Read,
-            Let => {
-                self.expect_str("(");
-                self.expect_str("[");
-                let name = self.read_var();
-                self.next_char();
-                let value = self.read_exp();
-                match value.as_ref() {
-                    Node::Fixnum(_) | Node::True | Node::False => {}
-                    v => {
-                        panic!("expect value, got {:?}", v);
-                    }
+        // the head of a vector literal is an atom no name can be read as
+        let mut list = match token {
+            Token::HashParen => vec![Sexp::Atom("#(".to_string(), pos)],
+            _ => Vec::new(),
+        };
+        loop {
+            match self.tokens.get(self.cur) {
+                Some((t, _)) if t == &close => {
+                    self.cur += 1;
+                    return Ok(Sexp::List(list, pos));
+                }
+                Some(_) => list.push(self.read_sexp()?),
+                None => return Err(format!("{}: unclosed {:?}", pos, token)),
+            }
+        }
+    }
+
+    /// a program is (program form ...) or the forms without the program
+    pub fn parse_program(&mut self) -> Result<Box<Node>, String> {
+        let sexp = self.read_sexp()?;
+        match sexp.list() {
+            Some([head, forms @ ..]) if head.atom() == Some("program") => {
+                if let Some((token, pos)) = self.tokens.get(self.cur) {
+                    return Err(format!("{}: unexpected {:?} after program", pos, token));
                 }
-                self.expect_str("]");
-                self.expect_str(")");
-                Node::Let {
-                    name,
-                    value,
-                    exp: self.read_exp(),
+                parse_forms(forms)
+            }
+            _ => {
+                let mut forms = vec![sexp];
+                while self.cur < self.tokens.len() {
+                    forms.push(self.read_sexp()?);
                 }
+                parse_forms(&forms)
+            }
+        }
+    }
+}
+
+fn is_def(sexp: &Sexp) -> bool {
+    matches!(
+        sexp.list(),
+        Some([head, ..]) if matches!(head.atom(), Some("define" | "define-const" | "define-struct" | "define-type" | "provide" | "require"))
+    )
+}
+
+/// definitions are collected and the expressions are evaluated in order,
+/// the value of the program is the value of the last expression
+fn parse_forms(forms: &[Sexp]) -> Result<Box<Node>, String> {
+    let mut defs = Vec::new();
+    let mut exps = Vec::new();
+    for form in &macros::expand_forms(forms)? {
+        if is_def(form) {
+            defs.extend(parse_def(form)?);
+        } else {
+            exps.push(parse_exp(form)?);
+        }
+    }
+    let exp = match exps.pop() {
+        Some(exp) if exps.is_empty() => exp,
+        Some(exp) => Box::new(Node::Begin(exps, exp)),
+        None => Box::new(Node::Void),
+    };
+    Ok(Box::new(Node::Program(defs, exp)))
+}
+
+fn expect_args(name: &str, args: &[Sexp], n: usize, pos: Pos) -> Result<(), String> {
+    if args.len() != n {
+        return Err(format!(
+            "{}: {} expects {} arguments, but got {}",
+            pos,
+            name,
+            n,
+            args.len()
+        ));
+    }
+    Ok(())
+}
+
+/// parse Integer, Boolean, Void, Nothing, Any, String, Char, Flonum, Symbol,
+/// (Vector type ...), (Values type ...), (Box type), (Listof type), (Arrayof type),
+/// (HashTable type type), (type ... -> type) or the name of a struct or a data type,
+/// which is checked by type check
+fn parse_type(sexp: &Sexp) -> Result<Type, String> {
+    let t = match sexp {
+        Sexp::Atom(atom, _) => match atom.as_str() {
+            "Integer" => Type::Fixnum,
+            "Boolean" => Type::Boolean,
+            "Void" => Type::Void,
+            "Nothing" => Type::Bottom,
+            "Any" => Type::Any,
+            "String" => Type::String,
+            "Char" => Type::Char,
+            "Flonum" => Type::Flonum,
+            "Symbol" => Type::Symbol,
+            _ if atom.starts_with(|c: char| c.is_ascii_alphabetic()) => Type::Named(atom.clone()),
+            _ => return Err(format!("{}: unknown type {}", sexp.pos(), atom)),
+        },
+        Sexp::Str(..) => return Err(format!("{}: expected type, got {}", sexp.pos(), sexp)),
+        Sexp::List(list, _) => match list.split_first() {
+            Some((head, elems)) if head.atom() == Some("Vector") => {
+                Type::Vector(elems.iter().map(parse_type).collect::<Result<_, _>>()?)
+            }
+            Some((head, types)) if head.atom() == Some("Values") => {
+                Type::Values(types.iter().map(parse_type).collect::<Result<_, _>>()?)
             }
-            Var => Node::Var(self.read_var()),
-            True => Node::True,
-            False => Node::False,
-            Not => Node::Not(self.read_exp()),
-            Eq => Node::Eq(self.read_exp(), self.read_exp()),
-            Lt => Node::Lt(self.read_exp(), self.read_exp()),
-            Lte => Node::Lte(self.read_exp(), self.read_exp()),
-            Gt => Node::Gt(self.read_exp(), self.read_exp()),
-            Gte => Node::Gte(self.read_exp(), self.read_exp()),
-            If => Node::If {
-                cond: self.read_exp(),
-                if_exps: vec![self.read_exp()],
-                else_exps: vec![self.read_exp()],
-                if_live_afters: Default::default(),
-                else_live_afters: Default::default(),
+            Some((head, [t])) if head.atom() == Some("Box") => Type::Box(Box::new(parse_type(t)?)),
+            Some((head, [t])) if head.atom() == Some("Listof") => {
+                Type::List(Box::new(parse_type(t)?))
+            }
+            Some((head, [t])) if head.atom() == Some("Arrayof") => {
+                Type::Array(Box::new(parse_type(t)?))
+            }
+            Some((head, [key_t, value_t])) if head.atom() == Some("HashTable") => {
+                Type::HashTable(Box::new(parse_type(key_t)?), Box::new(parse_type(value_t)?))
+            }
+            _ => match list.iter().position(|t| t.atom() == Some("->")) {
+                Some(arrow) if arrow == list.len() - 2 => {
+                    let params = list[..arrow]
+                        .iter()
+                        .map(parse_type)
+                        .collect::<Result<_, _>>()?;
+                    Type::Function(params, Box::new(parse_type(&list[arrow + 1])?))
+                }
+                _ => return Err(format!("{}: expected type, got {}", sexp.pos(), sexp)),
             },
-        };
-        if in_paren {
-            self.expect_str(")");
+        },
+    };
+    Ok(t)
+}
+
+/// parse (define (name [var : type] ...) : type exp), (define-struct name ([field : type] ...))
+/// or (define-type name (variant type ...) ...)
+fn parse_def(sexp: &Sexp) -> Result<Vec<Box<Node>>, String> {
+    match sexp.list() {
+        Some([head, name, fields]) if head.atom() == Some("define-struct") => {
+            let fields = fields
+                .list()
+                .ok_or_else(|| format!("{}: expected ([field : type] ...)", fields.pos()))?;
+            return Ok(vec![Box::new(Node::DefStruct {
+                name: parse_var(name)?,
+                fields: parse_params(fields)?,
+            })]);
+        }
+        Some([head, ..]) if head.atom() == Some("define-struct") => {
+            return Err(format!(
+                "{}: expected (define-struct name ([field : type] ...))",
+                sexp.pos()
+            ))
+        }
+        Some([head, name, variants @ ..])
+            if head.atom() == Some("define-type") && !variants.is_empty() =>
+        {
+            let variants = variants
+                .iter()
+                .map(|variant| match variant.list() {
+                    Some([name, types @ ..]) => Ok((
+                        parse_var(name)?,
+                        types.iter().map(parse_type).collect::<Result<_, _>>()?,
+                    )),
+                    _ => Err(format!("{}: expected (variant type ...)", variant.pos())),
+                })
+                .collect::<Result<_, _>>()?;
+            return Ok(vec![Box::new(Node::DefType {
+                name: parse_var(name)?,
+                variants,
+            })]);
+        }
+        Some([head, ..]) if head.atom() == Some("define-type") => {
+            return Err(format!(
+                "{}: expected (define-type name (variant type ...) ...)",
+                sexp.pos()
+            ))
+        }
+        Some([head, name @ Sexp::Atom(..), value]) if head.atom() == Some("define") => {
+            return Ok(vec![Box::new(Node::DefGlobal {
+                name: parse_var(name)?,
+                value: parse_exp(value)?,
+            })]);
+        }
+        Some([head, name @ Sexp::Atom(..), colon, t, value])
+            if head.atom() == Some("define") && colon.atom() == Some(":") =>
+        {
+            let value = Node::Ann(parse_exp(value)?, parse_type(t)?, sexp.pos());
+            return Ok(vec![Box::new(Node::DefGlobal {
+                name: parse_var(name)?,
+                value: Box::new(value),
+            })]);
+        }
+        Some([head, name, value]) if head.atom() == Some("define-const") => {
+            return Ok(vec![Box::new(Node::DefConst {
+                name: parse_var(name)?,
+                value: parse_exp(value)?,
+            })]);
+        }
+        Some([head, ..]) if head.atom() == Some("define-const") => {
+            return Err(format!("{}: expected (define-const name exp)", sexp.pos()))
+        }
+        Some([head, names @ ..]) if head.atom() == Some("provide") => {
+            let names = names.iter().map(parse_var).collect::<Result<_, _>>()?;
+            return Ok(vec![Box::new(Node::Provide(names))]);
+        }
+        Some([head, Sexp::Str(path, _)]) if head.atom() == Some("require") => {
+            return Ok(vec![Box::new(Node::Require(path.clone(), sexp.pos()))]);
+        }
+        Some([head, ..]) if head.atom() == Some("require") => {
+            return Err(format!("{}: expected (require \"file\")", sexp.pos()))
+        }
+        _ => {}
+    }
+    let (signature, ret, body) = match sexp.list() {
+        Some([head, signature, colon, ret, body])
+            if head.atom() == Some("define") && colon.atom() == Some(":") =>
+        {
+            (signature, ret, body)
+        }
+        _ => {
+            return Err(format!(
+                "{}: expected (define (name [var : type] ...) : type exp)",
+                sexp.pos()
+            ))
+        }
+    };
+    let (name, params) = match signature.list() {
+        Some([name, params @ ..]) => (parse_var(name)?, params),
+        _ => {
+            return Err(format!(
+                "{}: expected (name [var : type] ...)",
+                signature.pos()
+            ))
+        }
+    };
+    // the rest parameter after the dot is a list of the excess arguments
+    let (params, rest) = match params {
+        [params @ .., dot, rest] if dot.atom() == Some(".") => (params, Some(rest)),
+        params => (params, None),
+    };
+    // the parameters with default values are the last ones before the rest parameter
+    let mut defaults = Vec::new();
+    let mut params = params
+        .iter()
+        .enumerate()
+        .map(|(i, param)| match param.list() {
+            Some([var, colon, t, default]) if colon.atom() == Some(":") => {
+                let t = parse_type(t)?;
+                defaults.push(Box::new(Node::Def {
+                    name: default_name(&name, i),
+                    params: Vec::new(),
+                    ret: t.clone(),
+                    body: vec![parse_exp(default)?],
+                }));
+                Ok((parse_var(var)?, Type::Optional(Box::new(t))))
+            }
+            Some([_, colon, _]) if colon.atom() == Some(":") && !defaults.is_empty() => {
+                Err(format!("{}: expected [var : type exp]", param.pos()))
+            }
+            _ => Ok(parse_params(std::slice::from_ref(param))?.remove(0)),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if rest.is_some() && !defaults.is_empty() {
+        return Err(format!(
+            "{}: a function with a rest parameter can not have default values",
+            signature.pos()
+        ));
+    }
+    if let Some(rest) = rest {
+        let (name, t) = parse_params(std::slice::from_ref(rest))?.remove(0);
+        params.push((name, Type::List(Box::new(t))));
+    }
+    let def = Box::new(Node::Def {
+        name,
+        params,
+        ret: parse_type(ret)?,
+        body: vec![parse_exp(body)?],
+    });
+    let def = match rest {
+        Some(_) => Box::new(Node::Variadic(def)),
+        None => def,
+    };
+    // the default values are evaluated by the caller for each call
+    Ok(std::iter::once(def).chain(defaults).collect())
+}
+
+/// parse [var : type] ...
+fn parse_params(params: &[Sexp]) -> Result<Vec<(String, Type)>, String> {
+    params
+        .iter()
+        .map(|param| match param.list() {
+            Some([var, colon, t]) if colon.atom() == Some(":") => {
+                Ok((parse_var(var)?, parse_type(t)?))
+            }
+            _ => Err(format!("{}: expected [var : type]", param.pos())),
+        })
+        .collect()
+}
+
+fn parse_var(sexp: &Sexp) -> Result<String, String> {
+    match sexp.atom() {
+        Some(name) if number_digits(name).is_none() => Ok(name.to_string()),
+        _ => Err(format!("{}: expected variable, got {}", sexp.pos(), sexp)),
+    }
+}
+
+/// parse a non-empty sequence of expressions, the last one is the value
+fn parse_body(exps: &[Sexp]) -> Result<Box<Node>, String> {
+    let (last, effects) = exps.split_last().expect("non-empty body");
+    if effects.is_empty() {
+        return parse_exp(last);
+    }
+    let effects = effects.iter().map(parse_exp).collect::<Result<_, _>>()?;
+    Ok(Box::new(Node::Begin(effects, parse_exp(last)?)))
+}
+
+/// (for ([var (range [start] end [step])]) body) is a void while loop over a counter
+fn parse_for(clauses: &Sexp, body: &Sexp) -> Result<Box<Node>, String> {
+    use Node::*;
+
+    let (name, range) = match clauses.list() {
+        Some([clause]) => match clause.list() {
+            Some([name, range]) => (parse_var(name)?, range),
+            _ => return Err(format!("{}: expected [var (range ...)]", clause.pos())),
+        },
+        _ => return Err(format!("{}: expected ([var (range ...)])", clauses.pos())),
+    };
+    let is_range = |head: &Sexp| head.atom() == Some("range");
+    let (start, end, step) = match range.list() {
+        Some([head, end]) if is_range(head) => (None, end, None),
+        Some([head, start, end]) if is_range(head) => (Some(start), end, None),
+        Some([head, start, end, step]) if is_range(head) => (Some(start), end, Some(step)),
+        _ => {
+            return Err(format!(
+                "{}: expected (range [start] end [step])",
+                range.pos()
+            ))
+        }
+    };
+    let start = match start {
+        Some(start) => parse_exp(start)?,
+        None => Box::new(Fixnum(0)),
+    };
+    let end = parse_exp(end)?;
+    let step = step.map(parse_exp).transpose()?;
+    let (i, end_name, step_name) = (
+        hidden_name("for.i"),
+        hidden_name("for.end"),
+        hidden_name("for.step"),
+    );
+    let var = |name: &String| Box::new(Var(name.clone()));
+    let cond = match step {
+        Some(_) => Node::new_if(
+            Box::new(Gt(var(&step_name), Box::new(Fixnum(0)))),
+            Box::new(Lt(var(&i), var(&end_name))),
+            Box::new(Gt(var(&i), var(&end_name))),
+        ),
+        None => Lt(var(&i), var(&end_name)),
+    };
+    let next = match step {
+        Some(_) => Add(var(&i), var(&step_name)),
+        None => Add(var(&i), Box::new(Fixnum(1))),
+    };
+    let body = Box::new(Begin(
+        vec![Box::new(Let {
+            name,
+            value: var(&i),
+            exp: parse_exp(body)?,
+        })],
+        Box::new(SetBang {
+            name: i.clone(),
+            value: Box::new(next),
+        }),
+    ));
+    // start, end and step are bound in the order of the source
+    let exp = Box::new(Node::new_while(Box::new(cond), body));
+    let exp = match step {
+        Some(step) => Box::new(Let {
+            name: step_name,
+            value: step,
+            exp,
+        }),
+        None => exp,
+    };
+    let exp = Box::new(Let {
+        name: end_name,
+        value: end,
+        exp,
+    });
+    Ok(Box::new(Let {
+        name: i,
+        value: start,
+        exp,
+    }))
+}
+
+/// parse ([var exp] ...)
+fn parse_bindings(sexp: &Sexp) -> Result<Vec<(String, Box<Node>)>, String> {
+    sexp.list()
+        .ok_or_else(|| format!("{}: expected ([var exp] ...)", sexp.pos()))?
+        .iter()
+        .map(parse_binding)
+        .collect()
+}
+
+/// [var exp] or [var : type exp], the value is checked against the type
+fn parse_binding(binding: &Sexp) -> Result<(String, Box<Node>), String> {
+    match binding.list() {
+        Some([name, value]) => Ok((parse_var(name)?, parse_exp(value)?)),
+        Some([name, colon, t, value]) if colon.atom() == Some(":") => {
+            let value = Node::Ann(parse_exp(value)?, parse_type(t)?, binding.pos());
+            Ok((parse_var(name)?, Box::new(value)))
+        }
+        _ => Err(format!("{}: expected [var exp]", binding.pos())),
+    }
+}
+
+/// split the string at the directives ~a, ~s and ~v, which write a value,
+/// ~% is a newline and ~~ is a tilde
+fn parse_format(s: &str, pos: Pos) -> Result<Vec<String>, String> {
+    let mut pieces = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            pieces.last_mut().unwrap().push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('a' | 's' | 'v') => pieces.push(String::new()),
+            Some('%') => pieces.last_mut().unwrap().push('\n'),
+            Some('~') => pieces.last_mut().unwrap().push('~'),
+            Some(c) => return Err(format!("{}: unknown format directive ~{}", pos, c)),
+            None => return Err(format!("{}: format string ends with ~", pos)),
+        }
+    }
+    Ok(pieces)
+}
+
+/// digits of a number after the radix prefix, a decimal number starts with a digit after the sign
+fn number_digits(atom: &str) -> Option<(&str, u32)> {
+    let (digits, radix) = match atom.get(..2) {
+        Some("#x" | "#X") => (&atom[2..], 16),
+        Some("#b" | "#B") => (&atom[2..], 2),
+        Some("#o" | "#O") => (&atom[2..], 8),
+        _ => (atom, 10),
+    };
+    let unsigned = digits.strip_prefix(['-', '+']).unwrap_or(digits);
+    if radix == 10 && !unsigned.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((digits, radix))
+}
+
+fn parse_atom(atom: &str, pos: Pos) -> Result<Node, String> {
+    match number_digits(atom) {
+        Some((digits, 10)) if digits.contains('.') => {
+            return digits
+                .parse::<f64>()
+                .map(|num| Node::Flonum(num.to_bits()))
+                .map_err(|_| format!("{}: invalid flonum {}", pos, atom));
+        }
+        Some((digits, radix)) => {
+            return isize::from_str_radix(digits, radix)
+                .map(Node::Fixnum)
+                .map_err(|err| match err.kind() {
+                    IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+                        format!("{}: fixnum {} is out of range", pos, atom)
+                    }
+                    _ => format!("{}: invalid fixnum {}", pos, atom),
+                });
         }
-        return Box::new(node);
+        None => {}
+    }
+    if let Some(name) = atom.strip_prefix("#\\") {
+        let mut chars = name.chars();
+        let chr = match (chars.next(), chars.next()) {
+            (Some(chr), None) => chr,
+            _ => match name {
+                "space" => ' ',
+                "newline" => '\n',
+                "tab" => '\t',
+                "nul" => '\0',
+                _ => return Err(format!("{}: unknown character {}", pos, atom)),
+            },
+        };
+        return Ok(Node::Char(chr));
     }
+    let node = match atom {
+        "#t" | "true" => Node::True,
+        "#f" | "false" => Node::False,
+        "null" | "empty" => Node::Null,
+        name => Node::Var(name.to_string()),
+    };
+    Ok(node)
+}
+
+/// parse _, a variable, a literal, a quoted symbol, (vector pattern ...) or (variant pattern ...)
+fn parse_pattern(sexp: &Sexp) -> Result<Pattern, String> {
+    let pattern = match sexp {
+        Sexp::Atom(atom, _) if atom == "_" => Pattern::Wildcard,
+        Sexp::Atom(atom, pos) => match parse_atom(atom, *pos)? {
+            Node::Var(name) => Pattern::Var(name),
+            literal @ (Node::Fixnum(_) | Node::True | Node::False | Node::Char(_)) => {
+                Pattern::Literal(literal)
+            }
+            _ => return Err(format!("{}: unsupported pattern {}", pos, atom)),
+        },
+        Sexp::List(list, _) => match list.split_first() {
+            Some((head, [_])) if head.atom() == Some("quote") => match *parse_exp_inner(sexp)? {
+                literal @ (Node::Symbol(_)
+                | Node::Fixnum(_)
+                | Node::True
+                | Node::False
+                | Node::Char(_)) => Pattern::Literal(literal),
+                _ => return Err(format!("{}: unsupported pattern {}", sexp.pos(), sexp)),
+            },
+            Some((head, patterns)) => {
+                let patterns = patterns
+                    .iter()
+                    .map(parse_pattern)
+                    .collect::<Result<_, _>>()?;
+                match head.atom() {
+                    Some("vector") => Pattern::Vector(patterns),
+                    _ => Pattern::Variant(parse_var(head)?, patterns),
+                }
+            }
+            None => return Err(format!("{}: expected pattern", sexp.pos())),
+        },
+        Sexp::Str(..) => return Err(format!("{}: unsupported pattern {}", sexp.pos(), sexp)),
+    };
+    Ok(pattern)
+}
 
-    pub fn parse_program(&mut self) -> Box<Node> {
-        self.read_exp()
+/// parse an expression with its position, which is kept for the casts inserted by type check
+fn parse_exp(sexp: &Sexp) -> Result<Box<Node>, String> {
+    let exp = parse_exp_inner(sexp)?;
+    // a body of one expression is already located
+    if let Node::Located(..) = *exp {
+        return Ok(exp);
     }
+    Ok(Box::new(Node::Located(sexp.pos(), exp)))
+}
+
+fn parse_exp_inner(sexp: &Sexp) -> Result<Box<Node>, String> {
+    let pos = sexp.pos();
+    let list = match sexp {
+        Sexp::Atom(atom, pos) => return parse_atom(atom, *pos).map(Box::new),
+        Sexp::Str(s, _) => return Ok(Box::new(Node::Str(s.clone()))),
+        Sexp::List(list, _) => list,
+    };
+    let (head, args) = match list.split_first() {
+        Some((head, args)) => (head, args),
+        None => return Err(format!("{}: unexpected empty list", pos)),
+    };
+    let name = match head.atom() {
+        Some(name) => name,
+        None => return parse_apply(head, args),
+    };
+    let node = match name {
+        "+" => {
+            expect_args(name, args, 2, pos)?;
+            Node::Add(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        "*" => {
+            expect_args(name, args, 2, pos)?;
+            Node::Mul(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        "quotient" | "remainder" => {
+            expect_args(name, args, 2, pos)?;
+            let lhs = parse_exp(&args[0])?;
+            let rhs = parse_exp(&args[1])?;
+            match name {
+                "quotient" => Node::Quotient(lhs, rhs),
+                _ => Node::Remainder(lhs, rhs),
+            }
+        }
+        "bit-and" | "bit-or" | "bit-xor" => {
+            expect_args(name, args, 2, pos)?;
+            let lhs = parse_exp(&args[0])?;
+            let rhs = parse_exp(&args[1])?;
+            match name {
+                "bit-and" => Node::BitAnd(lhs, rhs),
+                "bit-or" => Node::BitOr(lhs, rhs),
+                _ => Node::BitXor(lhs, rhs),
+            }
+        }
+        "ash" | "arithmetic-shift" | "shl" | "shr" => {
+            expect_args(name, args, 2, pos)?;
+            let lhs = parse_exp(&args[0])?;
+            let rhs = parse_exp(&args[1])?;
+            match name {
+                "shl" => Node::Shl(lhs, rhs),
+                "shr" => Node::Shr(lhs, rhs),
+                _ => Node::Ash(lhs, rhs),
+            }
+        }
+        "min" | "max" => {
+            expect_args(name, args, 2, pos)?;
+            let lhs = parse_exp(&args[0])?;
+            let rhs = parse_exp(&args[1])?;
+            match name {
+                "min" => Node::Min(lhs, rhs),
+                _ => Node::Max(lhs, rhs),
+            }
+        }
+        "bit-not" => {
+            expect_args(name, args, 1, pos)?;
+            Node::BitNot(parse_exp(&args[0])?)
+        }
+        "-" if args.len() == 2 => Node::Sub(parse_exp(&args[0])?, parse_exp(&args[1])?),
+        "-" => {
+            expect_args(name, args, 1, pos)?;
+            Node::Neg(parse_exp(&args[0])?)
+        }
+        "read" | "read-int" => {
+            expect_args(name, args, 0, pos)?;
+            Node::Read(Type::Fixnum)
+        }
+        "command-line-arguments" => {
+            expect_args(name, args, 0, pos)?;
+            Node::CommandLineArguments
+        }
+        "read-bool" => {
+            expect_args(name, args, 0, pos)?;
+            Node::Read(Type::Boolean)
+        }
+        "read-char" => {
+            expect_args(name, args, 0, pos)?;
+            Node::Read(Type::Char)
+        }
+        "random" => {
+            expect_args(name, args, 1, pos)?;
+            Node::Random(parse_exp(&args[0])?)
+        }
+        "exit" => {
+            expect_args(name, args, 1, pos)?;
+            Node::Exit(parse_exp(&args[0])?)
+        }
+        "assert" => {
+            expect_args(name, args, 1, pos)?;
+            Node::Assert(parse_exp(&args[0])?, pos)
+        }
+        "raise" => {
+            expect_args(name, args, 1, pos)?;
+            Node::Raise(parse_exp(&args[0])?)
+        }
+        "call/cc" | "call-with-current-continuation" => {
+            expect_args(name, args, 1, pos)?;
+            Node::CallCC(parse_exp(&args[0])?)
+        }
+        "with-handler" => {
+            expect_args(name, args, 2, pos)?;
+            let thunk = Node::Lambda {
+                params: Vec::new(),
+                ret: Type::Void,
+                body: parse_exp(&args[1])?,
+                free_vars: Vec::new(),
+            };
+            Node::WithHandler(parse_exp(&args[0])?, Box::new(thunk))
+        }
+        "error" => {
+            if args.is_empty() || args.len() > 2 {
+                return Err(format!("{}: error expects 1 or 2 arguments", pos));
+            }
+            let value = args.get(1).map(parse_exp).transpose()?;
+            Node::Error(parse_exp(&args[0])?, value, Type::Void)
+        }
+        "format" => match args {
+            [Sexp::Str(s, _), args @ ..] => {
+                let pieces = parse_format(s, pos)?;
+                if pieces.len() != args.len() + 1 {
+                    return Err(format!(
+                        "{}: format expects {} arguments, but got {}",
+                        pos,
+                        pieces.len() - 1,
+                        args.len()
+                    ));
+                }
+                let args = args.iter().map(parse_exp).collect::<Result<_, _>>()?;
+                Node::Format(pieces, args, Vec::new())
+            }
+            _ => return Err(format!("{}: expected (format \"string\" exp ...)", pos)),
+        },
+        // one value is the value itself
+        "values" if args.len() == 1 => return parse_exp(&args[0]),
+        "values" => Node::Values(
+            args.iter().map(parse_exp).collect::<Result<_, _>>()?,
+            Vec::new(),
+        ),
+        "let-values" => {
+            expect_args(name, args, 2, pos)?;
+            let bindings = args[0]
+                .list()
+                .ok_or_else(|| format!("{}: expected ([(var ...) exp] ...)", args[0].pos()))?
+                .iter()
+                .map(|binding| match binding.list() {
+                    Some([Sexp::List(names, _), value]) => Ok((
+                        names.iter().map(parse_var).collect::<Result<_, _>>()?,
+                        parse_exp(value)?,
+                    )),
+                    _ => Err(format!("{}: expected [(var ...) exp]", binding.pos())),
+                })
+                .collect::<Result<_, _>>()?;
+            Node::LetValues {
+                bindings,
+                exp: parse_exp(&args[1])?,
+            }
+        }
+        "ann" => {
+            expect_args(name, args, 2, pos)?;
+            Node::Ann(parse_exp(&args[0])?, parse_type(&args[1])?, pos)
+        }
+        "print" | "display" => {
+            expect_args(name, args, 1, pos)?;
+            Node::Print(parse_exp(&args[0])?, Type::Void)
+        }
+        "let" if args.len() == 3 && args[0].atom().is_some() => Node::NamedLet {
+            name: parse_var(&args[0])?,
+            bindings: parse_bindings(&args[1])?,
+            body: parse_exp(&args[2])?,
+            pos,
+        },
+        "let" => {
+            expect_args(name, args, 2, pos)?;
+            let binding = match args[0].list() {
+                Some([binding]) => binding,
+                _ => return Err(format!("{}: expected ([var exp])", args[0].pos())),
+            };
+            let (name, value) = parse_binding(binding)?;
+            Node::Let {
+                name,
+                value,
+                exp: parse_exp(&args[1])?,
+            }
+        }
+        "let*" | "letrec" => {
+            expect_args(name, args, 2, pos)?;
+            let bindings = parse_bindings(&args[0])?;
+            let exp = parse_exp(&args[1])?;
+            match name {
+                "let*" => Node::LetStar { bindings, exp },
+                _ => Node::LetRec { bindings, exp },
+            }
+        }
+        "set!" => {
+            expect_args(name, args, 2, pos)?;
+            Node::SetBang {
+                name: parse_var(&args[0])?,
+                value: parse_exp(&args[1])?,
+            }
+        }
+        "begin" => {
+            if args.is_empty() {
+                return Err(format!("{}: begin expects at least 1 argument", pos));
+            }
+            return parse_body(args);
+        }
+        "not" => {
+            expect_args(name, args, 1, pos)?;
+            Node::Not(parse_exp(&args[0])?)
+        }
+        "eq?" | "==" | "<" | "<=" | ">" | ">=" => {
+            expect_args(name, args, 2, pos)?;
+            let lhs = parse_exp(&args[0])?;
+            let rhs = parse_exp(&args[1])?;
+            match name {
+                "eq?" | "==" => Node::Eq(lhs, rhs),
+                "<" => Node::Lt(lhs, rhs),
+                "<=" => Node::Lte(lhs, rhs),
+                ">" => Node::Gt(lhs, rhs),
+                _ => Node::Gte(lhs, rhs),
+            }
+        }
+        // characters are compared by their codes
+        "char=?" | "char<?" | "char<=?" | "char>?" | "char>=?" => {
+            expect_args(name, args, 2, pos)?;
+            let lhs = Box::new(Node::CharToInteger(parse_exp(&args[0])?));
+            let rhs = Box::new(Node::CharToInteger(parse_exp(&args[1])?));
+            match name {
+                "char=?" => Node::Eq(lhs, rhs),
+                "char<?" => Node::Lt(lhs, rhs),
+                "char<=?" => Node::Lte(lhs, rhs),
+                "char>?" => Node::Gt(lhs, rhs),
+                _ => Node::Gte(lhs, rhs),
+            }
+        }
+        "+." | "-." | "*." | "/." => {
+            expect_args(name, args, 2, pos)?;
+            let lhs = parse_exp(&args[0])?;
+            let rhs = parse_exp(&args[1])?;
+            match name {
+                "+." => Node::FAdd(lhs, rhs),
+                "-." => Node::FSub(lhs, rhs),
+                "*." => Node::FMul(lhs, rhs),
+                _ => Node::FDiv(lhs, rhs),
+            }
+        }
+        "fixnum->flonum" => {
+            expect_args(name, args, 1, pos)?;
+            Node::FixnumToFlonum(parse_exp(&args[0])?)
+        }
+        "flonum->fixnum" => {
+            expect_args(name, args, 1, pos)?;
+            Node::FlonumToFixnum(parse_exp(&args[0])?)
+        }
+        "string=?" => {
+            expect_args(name, args, 2, pos)?;
+            Node::StringEq(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        "char->integer" => {
+            expect_args(name, args, 1, pos)?;
+            Node::CharToInteger(parse_exp(&args[0])?)
+        }
+        "integer->char" => {
+            expect_args(name, args, 1, pos)?;
+            Node::IntegerToChar(parse_exp(&args[0])?)
+        }
+        "string-length" => {
+            expect_args(name, args, 1, pos)?;
+            Node::StringLength(parse_exp(&args[0])?)
+        }
+        "string-ref" => {
+            expect_args(name, args, 2, pos)?;
+            Node::StringRef(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        "string-append" => {
+            expect_args(name, args, 2, pos)?;
+            Node::StringAppend(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        "and" | "or" => {
+            expect_args(name, args, 2, pos)?;
+            let lhs = parse_exp(&args[0])?;
+            let rhs = parse_exp(&args[1])?;
+            match name {
+                "and" => Node::And(lhs, rhs),
+                _ => Node::Or(lhs, rhs),
+            }
+        }
+        // a one-armed if is void if the condition is false, like when
+        "if" if args.len() == 2 => Node::When(parse_exp(&args[0])?, parse_exp(&args[1])?),
+        "if" => {
+            if args.len() != 3 {
+                return Err(format!("{}: if expects 2 or 3 arguments", pos));
+            }
+            Node::new_if(
+                parse_exp(&args[0])?,
+                parse_exp(&args[1])?,
+                parse_exp(&args[2])?,
+            )
+        }
+        "cond" => {
+            let mut clauses = Vec::new();
+            let mut else_exp = None;
+            for (i, clause) in args.iter().enumerate() {
+                let (test, body) = match clause.list() {
+                    Some([test, body @ ..]) if !body.is_empty() => (test, parse_body(body)?),
+                    _ => return Err(format!("{}: expected [test exp ...]", clause.pos())),
+                };
+                if test.atom() == Some("else") {
+                    if i != args.len() - 1 {
+                        return Err(format!("{}: else must be the last clause", clause.pos()));
+                    }
+                    else_exp = Some(body);
+                } else {
+                    clauses.push((parse_exp(test)?, body));
+                }
+            }
+            Node::Cond { clauses, else_exp }
+        }
+        "match" => {
+            let (exp, clauses) = match args.split_first() {
+                Some((exp, clauses)) if !clauses.is_empty() => (exp, clauses),
+                _ => {
+                    return Err(format!(
+                        "{}: expected (match exp [pattern exp ...] ...)",
+                        pos
+                    ))
+                }
+            };
+            let clauses = clauses
+                .iter()
+                .map(|clause| match clause.list() {
+                    Some([pattern, body @ ..]) if !body.is_empty() => {
+                        Ok((parse_pattern(pattern)?, parse_body(body)?))
+                    }
+                    _ => Err(format!("{}: expected [pattern exp ...]", clause.pos())),
+                })
+                .collect::<Result<_, _>>()?;
+            Node::Match {
+                exp: parse_exp(exp)?,
+                clauses,
+                pos,
+            }
+        }
+        "when" | "unless" => {
+            if args.len() < 2 {
+                return Err(format!("{}: {} expects at least 2 arguments", pos, name));
+            }
+            let cond = parse_exp(&args[0])?;
+            let body = parse_body(&args[1..])?;
+            match name {
+                "when" => Node::When(cond, body),
+                _ => Node::Unless(cond, body),
+            }
+        }
+        "do" => {
+            let (bindings, clause, body) = match args {
+                [bindings, clause, body @ ..] => (bindings, clause, body),
+                _ => {
+                    return Err(format!(
+                        "{}: expected (do ([var init step] ...) (test result ...) body ...)",
+                        pos
+                    ))
+                }
+            };
+            let bindings = bindings
+                .list()
+                .ok_or_else(|| format!("{}: expected ([var init step] ...)", bindings.pos()))?
+                .iter()
+                .map(|binding| match binding.list() {
+                    Some([name, init]) => Ok((parse_var(name)?, parse_exp(init)?, None)),
+                    Some([name, init, step]) => {
+                        Ok((parse_var(name)?, parse_exp(init)?, Some(parse_exp(step)?)))
+                    }
+                    _ => Err(format!("{}: expected [var init step]", binding.pos())),
+                })
+                .collect::<Result<_, _>>()?;
+            let (test, result) = match clause.list() {
+                Some([test]) => (parse_exp(test)?, Box::new(Node::Void)),
+                Some([test, result @ ..]) => (parse_exp(test)?, parse_body(result)?),
+                _ => return Err(format!("{}: expected (test result ...)", clause.pos())),
+            };
+            let body = match body {
+                [] => Box::new(Node::Void),
+                body => parse_body(body)?,
+            };
+            Node::Do {
+                bindings,
+                test,
+                result,
+                body,
+            }
+        }
+        "lambda" => match args {
+            [params, colon, ret, body] if colon.atom() == Some(":") => {
+                let params = match params.list() {
+                    Some(params) => parse_params(params)?,
+                    None => return Err(format!("{}: expected ([var : type] ...)", params.pos())),
+                };
+                Node::Lambda {
+                    params,
+                    ret: parse_type(ret)?,
+                    body: parse_exp(body)?,
+                    free_vars: Vec::new(),
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "{}: expected (lambda ([var : type] ...) : type exp)",
+                    pos
+                ))
+            }
+        },
+        "vector" => Node::Vector(args.iter().map(parse_exp).collect::<Result<_, _>>()?),
+        "#(" => Node::Literal(Box::new(Node::Vector(
+            args.iter().map(parse_exp).collect::<Result<_, _>>()?,
+        ))),
+        "box" => {
+            expect_args(name, args, 1, pos)?;
+            Node::MakeBox(parse_exp(&args[0])?)
+        }
+        "unbox" => {
+            expect_args(name, args, 1, pos)?;
+            Node::Unbox(parse_exp(&args[0])?)
+        }
+        "set-box!" => {
+            expect_args(name, args, 2, pos)?;
+            Node::SetBox(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        // a quoted name is a symbol, a quoted literal is itself
+        "quote" => {
+            expect_args(name, args, 1, pos)?;
+            match &args[0] {
+                Sexp::Atom(atom, pos) if atom.starts_with('#') || number_digits(atom).is_some() => {
+                    parse_atom(atom, *pos)?
+                }
+                Sexp::Atom(atom, _) => Node::Symbol(atom.clone()),
+                Sexp::List(list, _) if list.is_empty() => Node::Null,
+                Sexp::Str(s, _) => Node::Str(s.clone()),
+                datum => return Err(format!("{}: unsupported quoted {}", pos, datum)),
+            }
+        }
+        "cons" => {
+            expect_args(name, args, 2, pos)?;
+            Node::Cons(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        "car" | "cdr" | "null?" => {
+            expect_args(name, args, 1, pos)?;
+            let exp = parse_exp(&args[0])?;
+            match name {
+                "car" => Node::Car(exp),
+                "cdr" => Node::Cdr(exp),
+                _ => Node::IsNull(exp),
+            }
+        }
+        // a list is built by pairs from the last element
+        "list" => {
+            let elems = args.iter().map(parse_exp).collect::<Result<Vec<_>, _>>()?;
+            *elems
+                .into_iter()
+                .rev()
+                .fold(Box::new(Node::Null), |list, elem| {
+                    Box::new(Node::Cons(elem, list))
+                })
+        }
+        "inject" | "project" => {
+            expect_args(name, args, 2, pos)?;
+            let exp = parse_exp(&args[0])?;
+            let t = parse_type(&args[1])?;
+            match name {
+                "inject" => Node::Inject(exp, t),
+                _ => Node::Project(exp, t, pos),
+            }
+        }
+        "make-array" => {
+            expect_args(name, args, 2, pos)?;
+            Node::MakeArray(parse_exp(&args[0])?, parse_exp(&args[1])?, Type::Void)
+        }
+        "array-length" => {
+            expect_args(name, args, 1, pos)?;
+            Node::ArrayLength(parse_exp(&args[0])?)
+        }
+        "array-ref" => {
+            expect_args(name, args, 2, pos)?;
+            Node::ArrayRef(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        "array-set!" => {
+            expect_args(name, args, 3, pos)?;
+            Node::ArraySet(
+                parse_exp(&args[0])?,
+                parse_exp(&args[1])?,
+                parse_exp(&args[2])?,
+            )
+        }
+        "make-hash" => {
+            expect_args(name, args, 2, pos)?;
+            Node::MakeHashTable(parse_type(&args[0])?, parse_type(&args[1])?)
+        }
+        "hash-ref" => {
+            if args.len() != 2 && args.len() != 3 {
+                return Err(format!("{}: hash-ref expects 2 or 3 arguments", pos));
+            }
+            let default = args.get(2).map(parse_exp).transpose()?;
+            Node::HashTableRef(parse_exp(&args[0])?, parse_exp(&args[1])?, default)
+        }
+        "hash-set!" => {
+            expect_args(name, args, 3, pos)?;
+            Node::HashTableSet(
+                parse_exp(&args[0])?,
+                parse_exp(&args[1])?,
+                parse_exp(&args[2])?,
+            )
+        }
+        "vector-length" => {
+            expect_args(name, args, 1, pos)?;
+            Node::VectorLength(parse_exp(&args[0])?)
+        }
+        "vector-ref" => {
+            expect_args(name, args, 2, pos)?;
+            Node::VectorRef(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        "vector-set!" => {
+            expect_args(name, args, 3, pos)?;
+            Node::VectorSet(
+                parse_exp(&args[0])?,
+                parse_exp(&args[1])?,
+                parse_exp(&args[2])?,
+            )
+        }
+        "while" => {
+            expect_args(name, args, 2, pos)?;
+            Node::new_while(parse_exp(&args[0])?, parse_exp(&args[1])?)
+        }
+        "for" => {
+            expect_args(name, args, 2, pos)?;
+            return parse_for(&args[0], &args[1]);
+        }
+        "void" => {
+            expect_args(name, args, 0, pos)?;
+            Node::Void
+        }
+        _ => return parse_apply(head, args),
+    };
+    Ok(Box::new(node))
+}
+
+/// parse a function call
+fn parse_apply(fun: &Sexp, args: &[Sexp]) -> Result<Box<Node>, String> {
+    let args = args.iter().map(parse_exp).collect::<Result<_, _>>()?;
+    Ok(Box::new(Node::Apply(parse_exp(fun)?, args)))
 }
@@ -4,6 +4,87 @@ use std::collections::{HashMap, HashSet};
 
 const WORD: usize = 8;
 
+/// registers used for allocation, the index of a register is its color,
+/// RAX and R11 are not in the list since we use them to patch instructions
+const REGISTERS: [Node; 12] = [
+    Node::RCX,
+    Node::RDX,
+    Node::RSI,
+    Node::RDI,
+    Node::R8,
+    Node::R9,
+    Node::R10,
+    Node::RBX,
+    Node::R12,
+    Node::R13,
+    Node::R14,
+    Node::R15,
+];
+
+/// xmm registers used for allocation of floats, XMM15 is used to patch instructions
+const XMM_REGISTERS: [Node; 15] = [
+    Node::XMM(0),
+    Node::XMM(1),
+    Node::XMM(2),
+    Node::XMM(3),
+    Node::XMM(4),
+    Node::XMM(5),
+    Node::XMM(6),
+    Node::XMM(7),
+    Node::XMM(8),
+    Node::XMM(9),
+    Node::XMM(10),
+    Node::XMM(11),
+    Node::XMM(12),
+    Node::XMM(13),
+    Node::XMM(14),
+];
+
+/// variables of floats are the operands of SSE instructions and the variables moved
+/// to or from them, other floats only pass through general registers
+fn float_vars(node_list: &[Box<Node>]) -> HashSet<String> {
+    use Node::*;
+
+    let mut vars: HashSet<String> = XMM_REGISTERS
+        .iter()
+        .map(|reg| format!("{:?}", reg))
+        .collect();
+    let mut moves = Vec::new();
+    for node in node_list {
+        match node.as_ref() {
+            ADDSD { target, arg }
+            | SUBSD { target, arg }
+            | MULSD { target, arg }
+            | DIVSD { target, arg } => {
+                vars.extend(target.var().cloned());
+                vars.extend(arg.var().cloned());
+            }
+            CVTSI2SDQ { target, .. } => vars.extend(target.var().cloned()),
+            CVTTSD2SIQ { source, .. } => vars.extend(source.var().cloned()),
+            MOVQ { target, source } => {
+                if let (Some(target), Some(source)) = (target.var(), source.var()) {
+                    moves.push((target, source));
+                }
+            }
+            _ => {}
+        }
+    }
+    // a variable keeps one type, so the class is spread through the moves
+    loop {
+        let mut changed = false;
+        for (target, source) in &moves {
+            if vars.contains(*target) != vars.contains(*source) {
+                vars.insert(target.to_string());
+                vars.insert(source.to_string());
+                changed = true;
+            }
+        }
+        if !changed {
+            return vars;
+        }
+    }
+}
+
 #[derive(Default)]
 struct Status {
     color: Option<usize>,
@@ -15,12 +96,16 @@ fn choose_a_color(
     var: &String,
     status: &HashMap<String, Status>,
     move_relation: &Graph<String>,
+    floats: &HashSet<String>,
 ) -> usize {
     let node_status = status.get(var).expect("status");
 
     // pick a color based on move relation
     if let Some(adjacents) = move_relation.get_adjacents_set(var) {
         for related in adjacents {
+            if floats.contains(related) != floats.contains(var) {
+                continue;
+            }
             if let Some(s) = status.get(related) {
                 // use color of related variables if it is possible
                 let color = match s.color {
@@ -53,17 +138,22 @@ fn find_most_saturated_vertex(
         .iter_vertex()
         .filter(|v| status.get(*v).expect("status").color.is_none())
         .max_by_key(|v| interference.get_adjacents_set(v).expect("adjacents").len());
-    v.map(Clone::clone)
+    v.cloned()
 }
 
+/// general registers and xmm registers are colored separately,
+/// so only the conflicts between variables of the same class are recorded
 fn color_graph(
     interference: &mut Graph<String>,
     move_relation: &mut Graph<String>,
+    floats: &HashSet<String>,
 ) -> HashMap<String, usize> {
-    // remove RAX, since we use RAX to patch instructions,
-    // so we do not allocate RAX for variables
-    // which means RAX wound not be interferenced with other variables / registers
+    // remove RAX, R11 and XMM15, since we use them to patch instructions,
+    // so we do not allocate them for variables
+    // which means they wound not be interferenced with other variables / registers
     interference.remove(&format!("{:?}", Node::RAX));
+    interference.remove(&format!("{:?}", Node::R11));
+    interference.remove(&format!("{:?}", Node::XMM(15)));
 
     // 1. find the most saturated vertex
     // 2. allocate a color
@@ -73,16 +163,33 @@ fn color_graph(
         .cloned()
         .map(|vertex| (vertex, Status::default()))
         .collect();
+
+    // registers are precolored
+    let registers = REGISTERS.iter().enumerate();
+    for (color, reg) in registers.chain(XMM_REGISTERS.iter().enumerate()) {
+        let reg = format!("{:?}", reg);
+        if let Some(adjacents) = interference.get_adjacents_set(&reg) {
+            for var in adjacents {
+                if floats.contains(var) == floats.contains(&reg) {
+                    status.get_mut(var).unwrap().conflicts.insert(color);
+                }
+            }
+            status.get_mut(&reg).unwrap().color = Some(color);
+        }
+    }
+
     while let Some(vertex) = find_most_saturated_vertex(&status, interference) {
-        let c = choose_a_color(&vertex, &status, move_relation);
+        let c = choose_a_color(&vertex, &status, move_relation, floats);
 
         // update color
-        let mut s: &mut Status = status.get_mut(&vertex).expect("vertex");
+        let s: &mut Status = status.get_mut(&vertex).expect("vertex");
         s.color = Some(c);
 
         // update adjacents' conflicts
         for var in interference.get_adjacents_set(&vertex).expect("adjacents") {
-            status.get_mut(var).unwrap().conflicts.insert(c);
+            if floats.contains(var) == floats.contains(&vertex) {
+                status.get_mut(var).unwrap().conflicts.insert(c);
+            }
         }
     }
 
@@ -111,41 +218,113 @@ fn replace_node(node: Box<Node>, var_to_reg: &HashMap<String, Node>) -> Box<Node
             let arg = map_var_node(var_to_reg, *arg);
             Box::new(ADDQ { target, arg })
         }
+        SUBQ { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(SUBQ { target, arg })
+        }
+        IMULQ { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(IMULQ { target, arg })
+        }
+        IDIVQ(arg) => Box::new(IDIVQ(map_var_node(var_to_reg, *arg))),
+        NEGQ(target) => Box::new(NEGQ(map_var_node(var_to_reg, *target))),
+        ANDQ { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(ANDQ { target, arg })
+        }
+        ORQ { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(ORQ { target, arg })
+        }
+        SALQ { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(SALQ { target, arg })
+        }
+        SARQ { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(SARQ { target, arg })
+        }
+        SHRQ { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(SHRQ { target, arg })
+        }
+        NOTQ(target) => Box::new(NOTQ(map_var_node(var_to_reg, *target))),
+        XORQ { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(XORQ { target, arg })
+        }
         MOVQ { target, source } => {
             let target = map_var_node(var_to_reg, *target);
             let source = map_var_node(var_to_reg, *source);
             Box::new(MOVQ { target, source })
         }
+        ADDSD { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(ADDSD { target, arg })
+        }
+        SUBSD { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(SUBSD { target, arg })
+        }
+        MULSD { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(MULSD { target, arg })
+        }
+        DIVSD { target, arg } => {
+            let target = map_var_node(var_to_reg, *target);
+            let arg = map_var_node(var_to_reg, *arg);
+            Box::new(DIVSD { target, arg })
+        }
+        CVTSI2SDQ { target, source } => {
+            let target = map_var_node(var_to_reg, *target);
+            let source = map_var_node(var_to_reg, *source);
+            Box::new(CVTSI2SDQ { target, source })
+        }
+        CVTTSD2SIQ { target, source } => {
+            let target = map_var_node(var_to_reg, *target);
+            let source = map_var_node(var_to_reg, *source);
+            Box::new(CVTTSD2SIQ { target, source })
+        }
         MOVZBQ { target, source } => {
             let target = map_var_node(var_to_reg, *target);
             let source = map_var_node(var_to_reg, *source);
             Box::new(MOVZBQ { target, source })
         }
-        var_node @ Var(_) => map_var_node(var_to_reg, var_node),
-        If {
+        CMOV {
             cond,
-            if_exps,
-            else_exps,
-            if_live_afters,
-            else_live_afters,
+            source,
+            target,
         } => {
-            let cond = replace_node(cond, var_to_reg);
-            let if_exps = if_exps
-                .into_iter()
-                .map(|node| replace_node(node, var_to_reg))
-                .collect();
-            let else_exps = else_exps
-                .into_iter()
-                .map(|node| replace_node(node, var_to_reg))
-                .collect();
-            Box::new(If {
+            let source = map_var_node(var_to_reg, *source);
+            let target = map_var_node(var_to_reg, *target);
+            Box::new(CMOV {
                 cond,
-                if_exps,
-                else_exps,
-                if_live_afters,
-                else_live_afters,
+                source,
+                target,
             })
         }
+        LEAQ { target, source } => {
+            let target = map_var_node(var_to_reg, *target);
+            Box::new(LEAQ { target, source })
+        }
+        ICALLQ(fun, arity) => Box::new(ICALLQ(map_var_node(var_to_reg, *fun), arity)),
+        ITAILJMP(fun, arity) => Box::new(ITAILJMP(map_var_node(var_to_reg, *fun), arity)),
+        CMPQ(lhs, rhs) => {
+            let lhs = map_var_node(var_to_reg, *lhs);
+            let rhs = map_var_node(var_to_reg, *rhs);
+            Box::new(CMPQ(lhs, rhs))
+        }
         value => Box::new(value),
     }
 }
@@ -153,16 +332,53 @@ fn replace_node(node: Box<Node>, var_to_reg: &HashMap<String, Node>) -> Box<Node
 pub fn allocate_registers(node_list: Vec<Box<Node>>, info: &mut Info) -> Vec<Box<Node>> {
     use Node::*;
 
-    let color_map = color_graph(&mut info.interference_graph, &mut info.move_graph);
-    let stack_vars_count = color_map.values().max().cloned().unwrap_or(0);
+    let floats = float_vars(&node_list);
+    let color_map = color_graph(&mut info.interference_graph, &mut info.move_graph, &floats);
+    let spills = |float: bool| {
+        let max_color = color_map
+            .iter()
+            .filter(|(var, _)| floats.contains(*var) == float)
+            .map(|(_, c)| *c)
+            .max();
+        let registers_count = if float {
+            XMM_REGISTERS.len()
+        } else {
+            REGISTERS.len()
+        };
+        max_color.map_or(0, |c| (c + 1).saturating_sub(registers_count))
+    };
+    // spilled floats are placed after spilled general variables
+    let int_spills = spills(false);
+    let stack_vars_count = int_spills + spills(true);
+
+    // callee-saved registers are pushed under RBP, stack variables are placed after them
+    let used_callee_saved: Vec<Node> = CALLEE_SAVED_REGISTERS
+        .iter()
+        .filter(|reg| {
+            let color = REGISTERS.iter().position(|r| r == *reg).expect("color");
+            color_map
+                .iter()
+                .any(|(var, c)| *c == color && !floats.contains(var))
+        })
+        .cloned()
+        .collect();
 
     // mapping color to registers
     let var_to_reg: HashMap<String, Node> = color_map
         .into_iter()
         .map(|(var, color)| {
-            let reg = match color {
-                0 => RBX,
-                offset => StackLoc(-((offset * WORD) as isize)),
+            let (registers, first_slot) = if floats.contains(&var) {
+                (&XMM_REGISTERS[..], int_spills)
+            } else {
+                (&REGISTERS[..], 0)
+            };
+            let reg = match registers.get(color) {
+                Some(reg) => reg.clone(),
+                None => {
+                    let slot = first_slot + color - registers.len();
+                    let offset = used_callee_saved.len() + slot + 1;
+                    StackLoc(-((offset * WORD) as isize))
+                }
             };
             (var, reg)
         })
@@ -173,5 +389,6 @@ pub fn allocate_registers(node_list: Vec<Box<Node>>, info: &mut Info) -> Vec<Box
         new_node_list.push(replace_node(node, &var_to_reg));
     }
     info.stack_vars_count = stack_vars_count;
+    info.used_callee_saved = used_callee_saved;
     new_node_list
 }
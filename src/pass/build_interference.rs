@@ -10,9 +10,25 @@ pub fn build_interference(node_list: Vec<Box<Node>>, info: &mut Info) -> Vec<Box
     )
 }
 
+/// target of an arithmetic instruction interferes with every live variable
+fn add_write_edges(target: &Node, live_set: &LiveSet, interference_graph: &mut Graph<String>) {
+    // writing to memory does not interfere with variables
+    let target_var = match target.var_or_reg_name() {
+        Some(var) => var,
+        None => return,
+    };
+    interference_graph.add_vertex(target_var.clone());
+
+    for var in live_set {
+        if var != &target_var {
+            interference_graph.insert(var.to_owned(), target_var.clone());
+        }
+    }
+}
+
 fn build_interference_inner(
     node_list: Vec<Box<Node>>,
-    live_afters: &Vec<LiveSet>,
+    live_afters: &[LiveSet],
     interference_graph: &mut Graph<String>,
     move_graph: &mut Graph<String>,
 ) -> Vec<Box<Node>> {
@@ -22,80 +38,141 @@ fn build_interference_inner(
     for (node, live_set) in node_list.into_iter().zip(live_afters.iter()) {
         let node = match *node {
             ADDQ { target, arg } => {
-                let target_var = target.var_or_reg_name().unwrap();
-                interference_graph.add_vertex(target_var.clone());
-
-                for var in live_set {
-                    if var != &target_var {
-                        interference_graph.insert(var.to_owned(), target_var.clone());
-                    }
-                }
+                add_write_edges(&target, live_set, interference_graph);
                 ADDQ { target, arg }
             }
+            SUBQ { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                SUBQ { target, arg }
+            }
+            CMOV {
+                cond,
+                source,
+                target,
+            } => {
+                add_write_edges(&target, live_set, interference_graph);
+                CMOV {
+                    cond,
+                    source,
+                    target,
+                }
+            }
+            ANDQ { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                ANDQ { target, arg }
+            }
+            ORQ { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                ORQ { target, arg }
+            }
+            SALQ { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                SALQ { target, arg }
+            }
+            SARQ { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                SARQ { target, arg }
+            }
+            SHRQ { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                SHRQ { target, arg }
+            }
+            NOTQ(target) => {
+                add_write_edges(&target, live_set, interference_graph);
+                NOTQ(target)
+            }
+            XORQ { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                XORQ { target, arg }
+            }
+            IMULQ { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                IMULQ { target, arg }
+            }
+            NEGQ(target) => {
+                add_write_edges(&target, live_set, interference_graph);
+                NEGQ(target)
+            }
             MOVQ { target, source } => {
-                let target_var = target.var_or_reg_name().unwrap();
-                interference_graph.add_vertex(target_var.clone());
+                if let Some(target_var) = target.var_or_reg_name() {
+                    interference_graph.add_vertex(target_var.clone());
 
-                let source_var_opt = source.var_or_reg_name();
-                // record move relation
-                if source != target && source_var_opt.is_some() {
-                    move_graph.insert(source_var_opt.clone().unwrap(), target_var.clone());
-                }
-                for var in live_set {
-                    if var != &target_var && Some(var) != source_var_opt.as_ref() {
-                        interference_graph.insert(var.to_owned(), target_var.to_owned());
+                    let source_var_opt = source.var_or_reg_name();
+                    // record move relation
+                    if source != target && source_var_opt.is_some() {
+                        move_graph.insert(source_var_opt.clone().unwrap(), target_var.clone());
+                    }
+                    for var in live_set {
+                        if var != &target_var && Some(var) != source_var_opt.as_ref() {
+                            interference_graph.insert(var.to_owned(), target_var.to_owned());
+                        }
                     }
                 }
                 MOVQ { target, source }
             }
             MOVZBQ { target, source } => {
-                let target_var = target.var_or_reg_name().unwrap();
-                interference_graph.add_vertex(target_var.clone());
+                if let Some(target_var) = target.var_or_reg_name() {
+                    interference_graph.add_vertex(target_var.clone());
 
-                let source_var_opt = source.var_or_reg_name();
-                // record move relation
-                if source != target && source_var_opt.is_some() {
-                    move_graph.insert(source_var_opt.clone().unwrap(), target_var.clone());
-                }
-                for var in live_set {
-                    if var != &target_var && Some(var) != source_var_opt.as_ref() {
-                        interference_graph.insert(var.to_owned(), target_var.to_owned());
+                    let source_var_opt = source.var_or_reg_name();
+                    // record move relation
+                    if source != target && source_var_opt.is_some() {
+                        move_graph.insert(source_var_opt.clone().unwrap(), target_var.clone());
+                    }
+                    for var in live_set {
+                        if var != &target_var && Some(var) != source_var_opt.as_ref() {
+                            interference_graph.insert(var.to_owned(), target_var.to_owned());
+                        }
                     }
                 }
                 MOVZBQ { target, source }
             }
-            node @ CALLQ(_) => {
-                for var in live_set {
-                    interference_graph.insert(var.to_owned(), format!("{:?}", RAX));
-                }
-                node
+            ADDSD { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                ADDSD { target, arg }
             }
-            If {
-                cond,
-                if_exps,
-                else_exps,
-                if_live_afters,
-                else_live_afters,
-            } => {
-                let if_exps = build_interference_inner(
-                    if_exps,
-                    &if_live_afters,
-                    interference_graph,
-                    move_graph,
-                );
-                let else_exps = build_interference_inner(
-                    else_exps,
-                    &else_live_afters,
-                    interference_graph,
-                    move_graph,
-                );
-                If {
-                    cond,
-                    if_exps,
-                    else_exps,
-                    if_live_afters,
-                    else_live_afters,
+            SUBSD { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                SUBSD { target, arg }
+            }
+            MULSD { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                MULSD { target, arg }
+            }
+            DIVSD { target, arg } => {
+                add_write_edges(&target, live_set, interference_graph);
+                DIVSD { target, arg }
+            }
+            CVTSI2SDQ { target, source } => {
+                add_write_edges(&target, live_set, interference_graph);
+                CVTSI2SDQ { target, source }
+            }
+            CVTTSD2SIQ { target, source } => {
+                add_write_edges(&target, live_set, interference_graph);
+                CVTTSD2SIQ { target, source }
+            }
+            CQTO => {
+                add_write_edges(&RDX, live_set, interference_graph);
+                CQTO
+            }
+            IDIVQ(arg) => {
+                add_write_edges(&RAX, live_set, interference_graph);
+                add_write_edges(&RDX, live_set, interference_graph);
+                IDIVQ(arg)
+            }
+            LEAQ { target, source } => {
+                add_write_edges(&target, live_set, interference_graph);
+                LEAQ { target, source }
+            }
+            node @ CALLQ(..) | node @ ICALLQ(..) => {
+                // caller-saved registers are overwritten by the call
+                for reg in CALLER_SAVED_REGISTERS
+                    .iter()
+                    .chain(CALLER_SAVED_XMM_REGISTERS.iter())
+                {
+                    add_write_edges(reg, live_set, interference_graph);
                 }
+                node
             }
             node => {
                 // do nothing
@@ -107,3 +184,61 @@ fn build_interference_inner(
     }
     new_node_list
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Box<Node> {
+        Box::new(Node::Var(name.to_string()))
+    }
+
+    fn live(vars: &[&str]) -> LiveSet {
+        vars.iter().map(|var| var.to_string()).collect()
+    }
+
+    fn adjacent(graph: &Graph<String>, a: &str, b: &str) -> bool {
+        graph
+            .get_adjacents_set(&a.to_string())
+            .is_some_and(|set| set.contains(b))
+    }
+
+    fn build(node_list: Vec<Box<Node>>, live_afters: &[LiveSet]) -> (Graph<String>, Graph<String>) {
+        let mut interference_graph = Graph::default();
+        let mut move_graph = Graph::default();
+        build_interference_inner(
+            node_list,
+            live_afters,
+            &mut interference_graph,
+            &mut move_graph,
+        );
+        (interference_graph, move_graph)
+    }
+
+    #[test]
+    fn move_source_does_not_interfere_with_target() {
+        let node_list = vec![Box::new(Node::MOVQ {
+            target: var("y"),
+            source: var("x"),
+        })];
+        let (interference_graph, move_graph) = build(node_list, &[live(&["x", "y", "z"])]);
+        assert!(!adjacent(&interference_graph, "y", "x"));
+        assert!(adjacent(&interference_graph, "y", "z"));
+        assert!(adjacent(&move_graph, "x", "y"));
+        assert!(!adjacent(&move_graph, "y", "z"));
+    }
+
+    #[test]
+    fn call_overwrites_caller_saved_registers() {
+        let node_list = vec![Box::new(Node::CALLQ("f".to_string(), 0))];
+        let (interference_graph, _) = build(node_list, &[live(&["x"])]);
+        for reg in CALLER_SAVED_REGISTERS
+            .iter()
+            .chain(CALLER_SAVED_XMM_REGISTERS.iter())
+        {
+            let reg = reg.var_or_reg_name().unwrap();
+            assert!(adjacent(&interference_graph, "x", &reg), "{}", reg);
+        }
+        assert!(!adjacent(&interference_graph, "x", "RBX"));
+    }
+}
@@ -0,0 +1,154 @@
+/// Lift lambdas to top-level functions, a function value becomes a closure,
+/// which is a vector of the function address, the arity and the captured variables
+use crate::ast::*;
+use std::collections::HashMap;
+
+#[derive(Default)]
+struct Context {
+    /// parameter types and return type of the top-level functions
+    funs: HashMap<String, (Vec<Type>, Type)>,
+    /// closures of the top-level functions
+    wrappers: HashMap<String, Box<Node>>,
+    /// lifted functions
+    defs: Vec<Box<Node>>,
+    cnt: usize,
+}
+
+impl Context {
+    fn alloc_name(&mut self, prefix: &str) -> String {
+        let name = format!("{}.{}", prefix, self.cnt);
+        self.cnt += 1;
+        name
+    }
+
+    /// lift a lambda and return the closure, the function takes the closure
+    /// as the first parameter and loads the captured variables from it
+    fn lift(
+        &mut self,
+        params: Vec<(String, Type)>,
+        ret: Type,
+        body: Box<Node>,
+        free_vars: Vec<(String, Type)>,
+    ) -> Box<Node> {
+        use Node::*;
+
+        let label = self.alloc_name("lambda");
+        let clos = self.alloc_name("clos");
+        // the address is not a pointer to the heap,
+        // the arity is checked by projections from Any
+        let elem_types = vec![Type::Fixnum, Type::Fixnum]
+            .into_iter()
+            .chain(free_vars.iter().map(|(_, t)| t.clone()))
+            .collect();
+        let clos_t = Type::Vector(elem_types);
+        let body = free_vars
+            .iter()
+            .enumerate()
+            .rev()
+            .fold(body, |exp, (i, (name, _))| {
+                Box::new(Let {
+                    name: name.clone(),
+                    value: Box::new(VectorRef(
+                        Box::new(Var(clos.clone())),
+                        Box::new(Fixnum(i as isize + 2)),
+                    )),
+                    exp,
+                })
+            });
+        let arity = params.len();
+        self.defs.push(Box::new(Def {
+            name: label.clone(),
+            params: std::iter::once((clos, clos_t.clone()))
+                .chain(params)
+                .collect(),
+            ret,
+            body: vec![body],
+        }));
+        let elems = vec![Box::new(FunRef(label)), Box::new(Fixnum(arity as isize))]
+            .into_iter()
+            .chain(free_vars.into_iter().map(|(name, _)| Box::new(Var(name))))
+            .collect();
+        Box::new(HasType(Box::new(Vector(elems)), clos_t))
+    }
+
+    /// a top-level function used as a value is wrapped by a lambda which calls it
+    fn wrap(&mut self, label: String) -> Box<Node> {
+        use Node::*;
+
+        if let Some(clos) = self.wrappers.get(&label) {
+            return clos.clone();
+        }
+        let (param_types, ret) = self.funs[&label].clone();
+        let params: Vec<_> = param_types
+            .into_iter()
+            .map(|t| (self.alloc_name("arg"), t))
+            .collect();
+        let args = params
+            .iter()
+            .map(|(name, _)| Box::new(Var(name.clone())))
+            .collect();
+        let body = Box::new(Apply(Box::new(FunRef(label.clone())), args));
+        // the closure captures nothing, so it can be created again
+        let clos = self.lift(params, ret, body, Vec::new());
+        self.wrappers.insert(label, clos.clone());
+        clos
+    }
+
+    fn convert(&mut self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        match *node {
+            Lambda {
+                params,
+                ret,
+                body,
+                free_vars,
+            } => {
+                let body = self.convert(body);
+                self.lift(params, ret, body, free_vars)
+            }
+            Apply(fun, args) => {
+                let args: Vec<_> = args.into_iter().map(|arg| self.convert(arg)).collect();
+                match *fun {
+                    // top-level functions are called directly
+                    FunRef(label) => Box::new(Apply(Box::new(FunRef(label)), args)),
+                    fun => {
+                        let clos = self.alloc_name("clos");
+                        let fun = self.convert(Box::new(fun));
+                        let clos_var = Box::new(Var(clos.clone()));
+                        let code = Box::new(VectorRef(clos_var.clone(), Box::new(Fixnum(0))));
+                        let args = std::iter::once(clos_var).chain(args).collect();
+                        Box::new(Let {
+                            name: clos,
+                            value: fun,
+                            exp: Box::new(Apply(code, args)),
+                        })
+                    }
+                }
+            }
+            FunRef(label) => self.wrap(label),
+            node => Box::new(node.map_children(&mut |node| self.convert(node))),
+        }
+    }
+}
+
+pub fn closure_conversion(node: Box<Node>) -> Box<Node> {
+    let (defs, exp) = match *node {
+        Node::Program(defs, exp) => (defs, exp),
+        node => panic!("unexpected {:?}", node),
+    };
+    let mut cxt = Context::default();
+    for def in &defs {
+        if let Node::Def {
+            name, params, ret, ..
+        } = def.as_ref()
+        {
+            let param_types = params.iter().map(|(_, t)| t.clone()).collect();
+            cxt.funs.insert(name.clone(), (param_types, ret.clone()));
+        }
+    }
+    let mut defs: Vec<_> = defs.into_iter().map(|def| cxt.convert(def)).collect();
+    let exp = cxt.convert(exp);
+    defs.append(&mut cxt.defs);
+    Box::new(Node::Program(defs, exp))
+}
@@ -0,0 +1,258 @@
+/// Compile match into a decision tree of tests, each value is tested once on a path,
+/// the clauses must cover every value of the matched type
+use crate::ast::*;
+use std::collections::HashMap;
+
+/// a clause with the patterns left to match against the occurrences
+#[derive(Clone)]
+struct Row {
+    patterns: Vec<Pattern>,
+    /// variables bound to occurrences
+    bindings: Vec<(String, String)>,
+    body: Box<Node>,
+}
+
+impl Row {
+    /// remove the pattern of a column, it is replaced by the patterns of its components
+    fn expand(mut self, col: usize, occ: &str, patterns: Vec<Pattern>) -> Self {
+        if let Pattern::Var(name) = &self.patterns[col] {
+            self.bindings.push((name.clone(), occ.to_string()));
+        }
+        self.patterns.splice(col..=col, patterns);
+        self
+    }
+}
+
+/// head of a refutable pattern
+#[derive(Clone, PartialEq)]
+enum Head {
+    Literal(Node),
+    Vector(usize),
+    Variant(String, usize),
+}
+
+impl Head {
+    fn of(pattern: &Pattern) -> Option<Head> {
+        match pattern {
+            Pattern::Literal(literal) => Some(Head::Literal(literal.clone())),
+            Pattern::Vector(patterns) => Some(Head::Vector(patterns.len())),
+            Pattern::Variant(name, patterns) => Some(Head::Variant(name.clone(), patterns.len())),
+            Pattern::Wildcard | Pattern::Var(_) => None,
+        }
+    }
+
+    fn arity(&self) -> usize {
+        match self {
+            Head::Literal(_) => 0,
+            Head::Vector(len) | Head::Variant(_, len) => *len,
+        }
+    }
+}
+
+/// components of a pattern whose head is matched
+fn sub_patterns(pattern: Pattern) -> Vec<Pattern> {
+    match pattern {
+        Pattern::Vector(patterns) | Pattern::Variant(_, patterns) => patterns,
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Default)]
+struct Context {
+    /// index of each variant and the number of variants of its type
+    variants: HashMap<String, (usize, usize)>,
+    pos: Pos,
+    cnt: usize,
+}
+
+impl Context {
+    fn alloc_var(&mut self) -> String {
+        let name = format!("match.{}", self.cnt);
+        self.cnt += 1;
+        name
+    }
+
+    /// the heads cover every value, so the last one needs no test
+    fn is_complete(&self, heads: &[Head]) -> bool {
+        match heads.first() {
+            Some(Head::Vector(_)) => true,
+            Some(Head::Variant(name, _)) => self.variants[name].1 == heads.len(),
+            Some(Head::Literal(Node::True | Node::False)) => heads.len() == 2,
+            _ => false,
+        }
+    }
+
+    fn test(&self, head: &Head, occ: &str) -> Box<Node> {
+        let value = Box::new(Node::Var(occ.to_string()));
+        match head {
+            Head::Literal(literal) => Box::new(Node::Eq(value, Box::new(literal.clone()))),
+            Head::Variant(name, _) => {
+                let tag = Box::new(Node::VectorRef(value, Box::new(Node::Fixnum(0))));
+                let index = self.variants[name].0 as isize;
+                Box::new(Node::Eq(tag, Box::new(Node::Fixnum(index))))
+            }
+            Head::Vector(_) => unreachable!("a vector is not tested"),
+        }
+    }
+
+    /// match the rows whose pattern in the column has the head,
+    /// the components of the occurrence are bound to new occurrences
+    fn specialize(
+        &mut self,
+        occs: &[String],
+        rows: &[Row],
+        col: usize,
+        head: &Head,
+    ) -> Result<Box<Node>, String> {
+        let fields: Vec<_> = (0..head.arity()).map(|_| self.alloc_var()).collect();
+        let rows = rows
+            .iter()
+            .filter_map(|row| {
+                let pattern = &row.patterns[col];
+                let patterns = match Head::of(pattern) {
+                    Some(other) if other == *head => sub_patterns(pattern.clone()),
+                    Some(_) => return None,
+                    None => vec![Pattern::Wildcard; fields.len()],
+                };
+                Some(row.clone().expand(col, &occs[col], patterns))
+            })
+            .collect();
+        let mut sub_occs = occs.to_vec();
+        sub_occs.splice(col..=col, fields.iter().cloned());
+        let exp = self.compile(&sub_occs, rows)?;
+        // the tag of a variant is the first element
+        let offset = match head {
+            Head::Variant(..) => 1,
+            _ => 0,
+        };
+        let exp = fields
+            .into_iter()
+            .enumerate()
+            .rev()
+            .fold(exp, |exp, (i, name)| {
+                let value = Box::new(Node::VectorRef(
+                    Box::new(Node::Var(occs[col].clone())),
+                    Box::new(Node::Fixnum((i + offset) as isize)),
+                ));
+                Box::new(Node::Let { name, value, exp })
+            });
+        Ok(exp)
+    }
+
+    /// match the rows whose pattern in the column is irrefutable
+    fn compile_default(
+        &mut self,
+        occs: &[String],
+        rows: &[Row],
+        col: usize,
+    ) -> Result<Box<Node>, String> {
+        let rows = rows
+            .iter()
+            .filter(|row| Head::of(&row.patterns[col]).is_none())
+            .map(|row| row.clone().expand(col, &occs[col], Vec::new()))
+            .collect();
+        let mut sub_occs = occs.to_vec();
+        sub_occs.remove(col);
+        self.compile(&sub_occs, rows)
+    }
+
+    fn compile(&mut self, occs: &[String], rows: Vec<Row>) -> Result<Box<Node>, String> {
+        let first = match rows.first() {
+            Some(row) => row,
+            None => return Err(format!("{}: match does not cover every value", self.pos)),
+        };
+        let col = match first.patterns.iter().position(|p| Head::of(p).is_some()) {
+            Some(col) => col,
+            // the first clause matches
+            None => {
+                let row = rows.into_iter().next().unwrap();
+                let row = (0..occs.len())
+                    .rev()
+                    .fold(row, |row, col| row.expand(col, &occs[col], Vec::new()));
+                let exp = row
+                    .bindings
+                    .into_iter()
+                    .rev()
+                    .fold(row.body, |exp, (name, occ)| {
+                        Box::new(Node::Let {
+                            name,
+                            value: Box::new(Node::Var(occ)),
+                            exp,
+                        })
+                    });
+                return Ok(exp);
+            }
+        };
+        let mut heads = Vec::new();
+        for row in &rows {
+            if let Some(head) = Head::of(&row.patterns[col]) {
+                if !heads.contains(&head) {
+                    heads.push(head);
+                }
+            }
+        }
+        let (mut exp, tested) = if self.is_complete(&heads) {
+            let last = heads.pop().unwrap();
+            (self.specialize(occs, &rows, col, &last)?, heads)
+        } else {
+            (self.compile_default(occs, &rows, col)?, heads)
+        };
+        for head in tested.iter().rev() {
+            let if_exp = self.specialize(occs, &rows, col, head)?;
+            exp = Box::new(Node::new_if(self.test(head, &occs[col]), if_exp, exp));
+        }
+        Ok(exp)
+    }
+
+    fn compile_match(&mut self, node: Box<Node>) -> Result<Box<Node>, String> {
+        use Node::*;
+
+        match *node {
+            Match { exp, clauses, pos } => {
+                let exp = self.compile_match(exp)?;
+                let rows = clauses
+                    .into_iter()
+                    .map(|(pattern, body)| {
+                        Ok(Row {
+                            patterns: vec![pattern],
+                            bindings: Vec::new(),
+                            body: self.compile_match(body)?,
+                        })
+                    })
+                    .collect::<Result<_, String>>()?;
+                let occ = self.alloc_var();
+                self.pos = pos;
+                Ok(Box::new(Let {
+                    name: occ.clone(),
+                    value: exp,
+                    exp: self.compile(&[occ], rows)?,
+                }))
+            }
+            node => {
+                let mut result = Ok(());
+                let node = node.map_children(&mut |node| match self.compile_match(node) {
+                    Ok(node) => node,
+                    Err(msg) => {
+                        result = Err(msg);
+                        Box::new(Node::NOP)
+                    }
+                });
+                result.map(|_| Box::new(node))
+            }
+        }
+    }
+}
+
+pub fn compile_match(node: Box<Node>) -> Result<Box<Node>, String> {
+    let mut cxt = Context::default();
+    if let Node::Program(defs, _) = node.as_ref() {
+        for def in defs {
+            if let Node::DefType { variants, .. } = def.as_ref() {
+                for (i, (name, _)) in variants.iter().enumerate() {
+                    cxt.variants.insert(name.clone(), (i, variants.len()));
+                }
+            }
+        }
+    }
+    cxt.compile_match(node)
+}
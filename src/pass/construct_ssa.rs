@@ -0,0 +1,242 @@
+/// Rename every assignment of the blocks to a fresh variable and join the versions
+/// of a variable at the dominance frontiers of its assignments with phis,
+/// a phi is only placed where the variable is live
+use crate::ast::*;
+use crate::cfg::{Block, Cfg, Dominators};
+use std::collections::{HashMap, HashSet};
+
+/// the variables read by a statement
+fn uses(node: &Node) -> Vec<String> {
+    use Node::*;
+
+    match node {
+        Assign(_, exp) | Return(exp) => exp.free_vars(),
+        IfGoto { cond, .. } => cond.free_vars(),
+        TailCall(fun, args) => std::iter::once(fun)
+            .chain(args)
+            .flat_map(|exp| exp.free_vars())
+            .collect(),
+        node => node.free_vars(),
+    }
+}
+
+/// the variables live at the start of each block
+fn live_ins(blocks: &[Block]) -> Vec<HashSet<String>> {
+    let mut live_ins = vec![HashSet::new(); blocks.len()];
+    loop {
+        let mut changed = false;
+        for i in (0..blocks.len()).rev() {
+            let mut live: HashSet<String> = blocks[i]
+                .succs
+                .iter()
+                .flat_map(|&succ| live_ins[succ].iter().cloned())
+                .collect();
+            for node in blocks[i].body.iter().rev() {
+                if let Node::Assign(name, _) = node.as_ref() {
+                    live.remove(name);
+                }
+                live.extend(uses(node));
+            }
+            if live != live_ins[i] {
+                live_ins[i] = live;
+                changed = true;
+            }
+        }
+        if !changed {
+            return live_ins;
+        }
+    }
+}
+
+/// place an empty phi at the start of a block for each variable joined there
+fn insert_phis(
+    blocks: &mut [Block],
+    params: &[(String, Type)],
+    doms: &Dominators,
+) -> Vec<Vec<String>> {
+    let live_ins = live_ins(blocks);
+    let mut defs: HashMap<String, Vec<usize>> = HashMap::new();
+    for (name, _) in params {
+        defs.entry(name.clone()).or_default().push(0);
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        for node in &block.body {
+            if let Node::Assign(name, _) = node.as_ref() {
+                let blocks = defs.entry(name.clone()).or_default();
+                if !blocks.contains(&i) {
+                    blocks.push(i);
+                }
+            }
+        }
+    }
+    // sort the variables so the phis are placed in the same order on each run
+    let mut defs: Vec<_> = defs.into_iter().collect();
+    defs.sort();
+    let mut phis = vec![Vec::new(); blocks.len()];
+    for (name, def_blocks) in defs {
+        let mut work_list = def_blocks.clone();
+        while let Some(i) = work_list.pop() {
+            for &j in doms.frontier(i) {
+                if phis[j].contains(&name) || !live_ins[j].contains(&name) {
+                    continue;
+                }
+                phis[j].push(name.clone());
+                if !def_blocks.contains(&j) {
+                    work_list.push(j);
+                }
+            }
+        }
+    }
+    for (block, names) in blocks.iter_mut().zip(&phis) {
+        let phis = names
+            .iter()
+            .map(|name| Box::new(Node::Assign(name.clone(), Box::new(Node::Phi(Vec::new())))));
+        block.body.splice(0..0, phis);
+    }
+    phis
+}
+
+struct Context {
+    /// versions of the variables assigned on the way from the entry
+    stacks: HashMap<String, Vec<String>>,
+    /// number of the versions of each variable
+    counts: HashMap<String, usize>,
+    /// the variables in the order of their assignments, to pop their versions
+    assigned: Vec<String>,
+}
+
+impl Context {
+    fn fresh(&mut self, name: &str) -> String {
+        let count = self.counts.entry(name.to_string()).or_insert(0);
+        *count += 1;
+        let version = format!("{}.ssa.{}", name, count);
+        self.stacks
+            .entry(name.to_string())
+            .or_default()
+            .push(version.clone());
+        self.assigned.push(name.to_string());
+        version
+    }
+
+    fn current(&self, name: &str) -> Option<&String> {
+        self.stacks.get(name).and_then(|stack| stack.last())
+    }
+
+    fn rename(&self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        let node = match *node {
+            Var(name) => Var(self.current(&name).cloned().unwrap_or(name)),
+            node => node.map_children(&mut |node| self.rename(node)),
+        };
+        Box::new(node)
+    }
+
+    fn rename_stmt(&mut self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        let node = match *node {
+            // the arguments of a phi are filled by the predecessors
+            Assign(name, exp) if matches!(*exp, Phi(_)) => Assign(self.fresh(&name), exp),
+            Assign(name, exp) => {
+                let exp = self.rename(exp);
+                Assign(self.fresh(&name), exp)
+            }
+            IfGoto {
+                cond,
+                then_label,
+                else_label,
+            } => IfGoto {
+                cond: self.rename(cond),
+                then_label,
+                else_label,
+            },
+            Return(value) => Return(self.rename(value)),
+            TailCall(fun, args) => TailCall(
+                self.rename(fun),
+                args.into_iter().map(|arg| self.rename(arg)).collect(),
+            ),
+            node => *self.rename(Box::new(node)),
+        };
+        Box::new(node)
+    }
+
+    /// rename the blocks in the order of the dominator tree
+    fn rename_block(
+        &mut self,
+        blocks: &mut [Block],
+        i: usize,
+        phis: &[Vec<String>],
+        doms: &Dominators,
+    ) {
+        let assigned = self.assigned.len();
+        let body = std::mem::take(&mut blocks[i].body);
+        blocks[i].body = body
+            .into_iter()
+            .map(|node| self.rename_stmt(node))
+            .collect();
+        let label = blocks[i].label.clone();
+        for succ in blocks[i].succs.clone() {
+            for (name, node) in phis[succ].iter().zip(blocks[succ].body.iter_mut()) {
+                // a variable without an assignment on the path is never read from the phi
+                let value = match self.current(name) {
+                    Some(version) => Node::Var(version.clone()),
+                    None => Node::Void,
+                };
+                if let Node::Assign(_, exp) = node.as_mut() {
+                    if let Node::Phi(args) = exp.as_mut() {
+                        args.push((label.clone(), Box::new(value)));
+                    }
+                }
+            }
+        }
+        for &child in doms.children(i) {
+            self.rename_block(blocks, child, phis, doms);
+        }
+        for name in self.assigned.split_off(assigned) {
+            self.stacks.get_mut(&name).unwrap().pop();
+        }
+    }
+}
+
+fn construct_body(name: &str, params: &[(String, Type)], body: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    let mut cfg = Cfg::new(name, body);
+    let doms = Dominators::new(&cfg);
+    let phis = insert_phis(&mut cfg.blocks, params, &doms);
+
+    // the parameters are the first versions
+    let mut cxt = Context {
+        stacks: params
+            .iter()
+            .map(|(param, _)| (param.clone(), vec![param.clone()]))
+            .collect(),
+        counts: HashMap::new(),
+        assigned: Vec::new(),
+    };
+    cxt.rename_block(&mut cfg.blocks, 0, &phis, &doms);
+    cfg.into_body(|i| doms.is_reachable(i))
+}
+
+pub fn construct_ssa(defs: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    defs.into_iter()
+        .map(|def| match *def {
+            Def {
+                name,
+                params,
+                ret,
+                body,
+            } => {
+                let body = construct_body(&name, &params, body);
+                Box::new(Def {
+                    name,
+                    params,
+                    ret,
+                    body,
+                })
+            }
+            def => Box::new(def),
+        })
+        .collect()
+}
@@ -0,0 +1,166 @@
+/// Box the variables which are both captured by a lambda and assigned,
+/// so the closure and the scope of the variable share the value
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+struct Context {
+    /// variables assigned in the scope being converted
+    assigned: HashSet<String>,
+    /// variables captured by the lambdas in the scope being converted
+    captured: HashMap<String, Type>,
+}
+
+impl Context {
+    /// the variables to box among the names going out of scope,
+    /// a name is bound once after uniquify, so it is forgotten
+    fn boxed(&mut self, names: &[String]) -> HashMap<String, Type> {
+        names
+            .iter()
+            .filter_map(|name| {
+                let assigned = self.assigned.remove(name);
+                let t = self.captured.remove(name)?;
+                assigned.then(|| (name.clone(), t))
+            })
+            .collect()
+    }
+
+    /// parameters to box are renamed, the body binds the box to the name
+    fn box_params(
+        &mut self,
+        params: Vec<(String, Type)>,
+        body: Box<Node>,
+    ) -> (Vec<(String, Type)>, Box<Node>) {
+        let body = self.convert(body);
+        let names: Vec<_> = params.iter().map(|(name, _)| name.clone()).collect();
+        let boxed = self.boxed(&names);
+        if boxed.is_empty() {
+            return (params, body);
+        }
+        let mut inits = Vec::new();
+        let params = params
+            .into_iter()
+            .map(|(name, t)| {
+                if !boxed.contains_key(&name) {
+                    return (name, t);
+                }
+                let param = format!("{}.param", name);
+                inits.push((name, new_box(Box::new(Node::Var(param.clone())), &t)));
+                (param, t)
+            })
+            .collect();
+        let body = inits
+            .into_iter()
+            .fold(box_vars(body, &boxed), |exp, (name, value)| {
+                Box::new(Node::Let { name, value, exp })
+            });
+        (params, body)
+    }
+
+    fn convert(&mut self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        let node = match *node {
+            SetBang { name, value } => {
+                self.assigned.insert(name.clone());
+                SetBang {
+                    name,
+                    value: self.convert(value),
+                }
+            }
+            Let { name, value, exp } => {
+                let value = self.convert(value);
+                let exp = self.convert(exp);
+                match self.boxed(std::slice::from_ref(&name)).remove(&name) {
+                    Some(t) => Let {
+                        value: new_box(value, &t),
+                        exp: box_vars(exp, &HashMap::from([(name.clone(), t)])),
+                        name,
+                    },
+                    None => Let { name, value, exp },
+                }
+            }
+            Lambda {
+                params,
+                ret,
+                body,
+                free_vars,
+            } => {
+                let (params, body) = self.box_params(params, body);
+                for (name, t) in &free_vars {
+                    self.captured.insert(name.clone(), t.clone());
+                }
+                Lambda {
+                    params,
+                    ret,
+                    body,
+                    free_vars,
+                }
+            }
+            Def {
+                name,
+                params,
+                ret,
+                mut body,
+            } => {
+                assert_eq!(body.len(), 1);
+                let (params, exp) = self.box_params(params, body.remove(0));
+                Def {
+                    name,
+                    params,
+                    ret,
+                    body: vec![exp],
+                }
+            }
+            node => node.map_children(&mut |node| self.convert(node)),
+        };
+        Box::new(node)
+    }
+}
+
+/// a box is a vector of one element
+fn new_box(value: Box<Node>, t: &Type) -> Box<Node> {
+    Box::new(Node::HasType(
+        Box::new(Node::Vector(vec![value])),
+        Type::Vector(vec![t.clone()]),
+    ))
+}
+
+/// the variables are read and assigned through their boxes
+fn box_vars(node: Box<Node>, boxed: &HashMap<String, Type>) -> Box<Node> {
+    use Node::*;
+
+    let node = match *node {
+        Var(name) if boxed.contains_key(&name) => {
+            VectorRef(Box::new(Var(name)), Box::new(Fixnum(0)))
+        }
+        SetBang { name, value } if boxed.contains_key(&name) => VectorSet(
+            Box::new(Var(name)),
+            Box::new(Fixnum(0)),
+            box_vars(value, boxed),
+        ),
+        Lambda {
+            params,
+            ret,
+            body,
+            free_vars,
+        } => Lambda {
+            params,
+            ret,
+            body: box_vars(body, boxed),
+            free_vars: free_vars
+                .into_iter()
+                .map(|(name, t)| match boxed.get(&name) {
+                    Some(t) => (name, Type::Vector(vec![t.clone()])),
+                    None => (name, t),
+                })
+                .collect(),
+        },
+        node => node.map_children(&mut |node| box_vars(node, boxed)),
+    };
+    Box::new(node)
+}
+
+pub fn convert_assignments(node: Box<Node>) -> Box<Node> {
+    Context::default().convert(node)
+}
@@ -0,0 +1,74 @@
+/// Replace the phis by moves, each predecessor moves its value into a variable of the phi
+/// before its jump and the block moves it from there, so the moves of the phis of a block
+/// are done in parallel and a move on the branch not taken writes an unused variable
+use crate::ast::*;
+use std::collections::HashMap;
+
+fn destruct_body(name: &str, body: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    // the moves at the end of each predecessor
+    let mut moves: HashMap<String, Vec<Box<Node>>> = HashMap::new();
+    let body: Vec<_> = body
+        .into_iter()
+        .map(|node| match *node {
+            Assign(var, exp) => match *exp {
+                Phi(args) => {
+                    let phi_var = format!("{}.phi", var);
+                    for (label, value) in args {
+                        moves
+                            .entry(label)
+                            .or_default()
+                            .push(Box::new(Assign(phi_var.clone(), value)));
+                    }
+                    Box::new(Assign(var, Box::new(Var(phi_var))))
+                }
+                exp => Box::new(Assign(var, Box::new(exp))),
+            },
+            node => Box::new(node),
+        })
+        .collect();
+
+    let mut new_body = Vec::with_capacity(body.len());
+    let mut block_moves = moves.remove(name);
+    for node in body {
+        match node.as_ref() {
+            Label(label) => {
+                // the block before falls through
+                new_body.extend(block_moves.take().into_iter().flatten());
+                block_moves = moves.remove(label);
+            }
+            Goto(_) | IfGoto { .. } | TailCall(..) => {
+                new_body.extend(block_moves.take().into_iter().flatten());
+            }
+            _ => {}
+        }
+        new_body.push(node);
+    }
+    new_body.extend(block_moves.into_iter().flatten());
+    new_body
+}
+
+pub fn destruct_ssa(defs: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    defs.into_iter()
+        .map(|def| match *def {
+            Def {
+                name,
+                params,
+                ret,
+                body,
+            } => {
+                let body = destruct_body(&name, body);
+                Box::new(Def {
+                    name,
+                    params,
+                    ret,
+                    body,
+                })
+            }
+            def => Box::new(def),
+        })
+        .collect()
+}
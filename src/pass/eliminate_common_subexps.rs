@@ -0,0 +1,118 @@
+/// Reuse the variable holding the value of an identical expression computed before,
+/// a read of memory is reused until a statement which may write memory
+use crate::ast::*;
+
+/// an expression reading a mutable element
+fn reads_memory(exp: &Node) -> bool {
+    matches!(exp, Node::VectorRef(..) | Node::ArrayRef(..))
+}
+
+fn is_atom(exp: &Node) -> bool {
+    exp.var().is_some() || exp.is_literal() || matches!(exp, Node::Void | Node::Str(_))
+}
+
+#[derive(Default)]
+struct Context {
+    /// the expressions available in variables
+    exps: Vec<(Node, String)>,
+}
+
+impl Context {
+    /// the expressions of a variable or using it are not available after it is assigned
+    fn kill_var(&mut self, name: &str) {
+        self.exps
+            .retain(|(exp, var)| var != name && !exp.free_vars().iter().any(|used| used == name));
+    }
+
+    fn kill_memory(&mut self) {
+        self.exps.retain(|(exp, _)| !reads_memory(exp));
+    }
+
+    fn eliminate(&mut self, node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
+        use Node::*;
+
+        node_list
+            .into_iter()
+            .map(|node| {
+                let node = match *node {
+                    Assign(name, exp) => {
+                        let exp = match self.exps.iter().find(|(available, _)| *available == *exp) {
+                            Some((_, var)) => Box::new(Var(var.clone())),
+                            None => exp,
+                        };
+                        let reusable = exp.is_pure() || reads_memory(&exp);
+                        if !reusable && !is_atom(&exp) {
+                            // a call or an allocation may write memory
+                            self.kill_memory();
+                        }
+                        self.kill_var(&name);
+                        if reusable && !exp.free_vars().contains(&name) {
+                            self.exps.push((*exp.clone(), name.clone()));
+                        }
+                        Assign(name, exp)
+                    }
+                    If {
+                        cond,
+                        if_exps,
+                        else_exps,
+                    } => {
+                        // the expressions available after both branches
+                        let before = self.exps.clone();
+                        let if_exps = self.eliminate(if_exps);
+                        let after_if = std::mem::replace(&mut self.exps, before.clone());
+                        let else_exps = self.eliminate(else_exps);
+                        self.exps
+                            .retain(|exp| before.contains(exp) && after_if.contains(exp));
+                        If {
+                            cond,
+                            if_exps,
+                            else_exps,
+                        }
+                    }
+                    While {
+                        cond_exps,
+                        cond,
+                        body_exps,
+                    } => {
+                        // the body may assign the variables of the expressions before the loop
+                        self.exps.clear();
+                        let cond_exps = self.eliminate(cond_exps);
+                        let body_exps = self.eliminate(body_exps);
+                        self.exps.clear();
+                        While {
+                            cond_exps,
+                            cond,
+                            body_exps,
+                        }
+                    }
+                    node => {
+                        self.kill_memory();
+                        node
+                    }
+                };
+                Box::new(node)
+            })
+            .collect()
+    }
+}
+
+pub fn eliminate_common_subexps(defs: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    defs.into_iter()
+        .map(|def| match *def {
+            Def {
+                name,
+                params,
+                ret,
+                body,
+            } => Box::new(Def {
+                name,
+                params,
+                ret,
+                body: Context::default().eliminate(body),
+            }),
+            def => Box::new(def),
+        })
+        .collect()
+}
@@ -0,0 +1,140 @@
+/// Lower structured if and while into labels and jumps
+use crate::ast::*;
+
+/// Move the return of a function into the branches of the trailing if,
+/// a call whose value is returned directly becomes a tail call
+fn explicate_tail(node_list: &mut Vec<Box<Node>>, value: Box<Node>, tail_calls: bool) {
+    use Node::*;
+
+    match node_list.pop().map(|node| *node) {
+        Some(If {
+            cond,
+            mut if_exps,
+            mut else_exps,
+        }) => {
+            explicate_tail(&mut if_exps, value.clone(), tail_calls);
+            explicate_tail(&mut else_exps, value, tail_calls);
+            node_list.push(Box::new(If {
+                cond,
+                if_exps,
+                else_exps,
+            }));
+        }
+        Some(Assign(var, exp)) if tail_calls && value.var() == Some(&var) => match *exp {
+            Apply(fun, args) => node_list.push(Box::new(TailCall(fun, args))),
+            // the copy is dead after the return
+            exp if exp.var().is_some() || exp.is_literal() => {
+                explicate_tail(node_list, Box::new(exp), tail_calls)
+            }
+            exp => {
+                node_list.push(Box::new(Assign(var, Box::new(exp))));
+                node_list.push(Box::new(Return(value)));
+            }
+        },
+        node => {
+            node_list.extend(node.map(Box::new));
+            node_list.push(Box::new(Return(value)));
+        }
+    }
+}
+
+/// labels are prefixed by the name of the function
+fn explicate_control_inner(
+    name: &str,
+    node_list: Vec<Box<Node>>,
+    new_node_list: &mut Vec<Box<Node>>,
+    cnt: &mut usize,
+) {
+    use Node::*;
+
+    for node in node_list {
+        match *node {
+            If {
+                cond,
+                if_exps,
+                else_exps,
+            } => {
+                let then_label = format!("{}.then_{}", name, cnt);
+                let else_label = format!("{}.else_{}", name, cnt);
+                let end_label = format!("{}.end_{}", name, cnt);
+                *cnt += 1;
+                new_node_list.push(Box::new(IfGoto {
+                    cond,
+                    then_label: then_label.clone(),
+                    else_label: else_label.clone(),
+                }));
+                new_node_list.push(Box::new(Label(else_label)));
+                explicate_control_inner(name, else_exps, new_node_list, cnt);
+                // a branch ending with a jump never reaches the end
+                let jumps = new_node_list
+                    .last()
+                    .is_some_and(|node| matches!(node.as_ref(), Goto(_) | TailCall(..)));
+                if !jumps {
+                    new_node_list.push(Box::new(Goto(end_label.clone())));
+                }
+                new_node_list.push(Box::new(Label(then_label)));
+                explicate_control_inner(name, if_exps, new_node_list, cnt);
+                new_node_list.push(Box::new(Label(end_label)));
+            }
+            While {
+                cond_exps,
+                cond,
+                body_exps,
+            } => {
+                let loop_label = format!("{}.loop_{}", name, cnt);
+                let body_label = format!("{}.body_{}", name, cnt);
+                let end_label = format!("{}.end_{}", name, cnt);
+                *cnt += 1;
+                // the condition block is the target of the back edge
+                new_node_list.push(Box::new(Label(loop_label.clone())));
+                explicate_control_inner(name, cond_exps, new_node_list, cnt);
+                new_node_list.push(Box::new(IfGoto {
+                    cond,
+                    then_label: body_label.clone(),
+                    else_label: end_label.clone(),
+                }));
+                new_node_list.push(Box::new(Label(body_label)));
+                explicate_control_inner(name, body_exps, new_node_list, cnt);
+                new_node_list.push(Box::new(Goto(loop_label)));
+                new_node_list.push(Box::new(Label(end_label)));
+            }
+            node => {
+                new_node_list.push(Box::new(node));
+            }
+        }
+    }
+}
+
+/// the body of each function becomes labeled blocks joined by gotos and conditional gotos,
+/// a block without a jump at its end falls through to the next label
+pub fn explicate_control(defs: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    defs.into_iter()
+        .map(|def| match *def {
+            Def {
+                name,
+                params,
+                ret,
+                mut body,
+            } => {
+                match body.pop().map(|node| *node) {
+                    // main prints the value, so it can not jump to another function
+                    Some(Return(value)) => explicate_tail(&mut body, value, name != "main"),
+                    node => panic!("unexpected {:?}", node),
+                }
+                let mut new_body = Vec::with_capacity(body.len());
+                let mut cnt = 0;
+                explicate_control_inner(&name, body, &mut new_body, &mut cnt);
+                Box::new(Def {
+                    name,
+                    params,
+                    ret,
+                    body: new_body,
+                })
+            }
+            def @ DefGlobal { .. } => Box::new(def),
+            node => panic!("unexpected {:?}", node),
+        })
+        .collect()
+}
@@ -0,0 +1,104 @@
+/// Turn vector creation into allocation and initialization, the garbage is collected
+/// before the allocation when the vector does not fit in the free block
+use crate::ast::*;
+
+const WORD: usize = 8;
+
+fn alloc_var(cnt: &mut usize, prefix: &str) -> String {
+    let name = format!("{}.{}", prefix, cnt);
+    *cnt += 1;
+    name
+}
+
+fn is_constant(node: &Node) -> bool {
+    use Node::*;
+
+    matches!(node, Fixnum(_) | True | False | Char(_) | Flonum(_) | Void)
+}
+
+/// collect the garbage unless free_ptr + bytes < fromspace_end
+fn collect_check(bytes: usize) -> Box<Node> {
+    use Node::*;
+
+    let end = Box::new(Add(
+        Box::new(Global("free_ptr".to_string())),
+        Box::new(Fixnum(bytes as isize)),
+    ));
+    Box::new(If {
+        cond: Box::new(Lt(end, Box::new(Global("fromspace_end".to_string())))),
+        if_exps: vec![Box::new(Void)],
+        else_exps: vec![Box::new(Collect(bytes))],
+    })
+}
+
+/// a vector of the program may be the target of vector-set!
+fn sets_vectors(node: &Node) -> bool {
+    matches!(node, Node::VectorSet(..)) || node.children().into_iter().any(sets_vectors)
+}
+
+fn expose_allocation_inner(node: Box<Node>, shared: bool, cnt: &mut usize) -> Box<Node> {
+    use Node::*;
+
+    match *node {
+        // the elements are known and no vector is ever set, so every evaluation
+        // shares a vector laid out before the program runs
+        Literal(vector) => match *vector {
+            HasType(vector, t) => match *vector {
+                Vector(elems) if shared && elems.iter().all(|elem| is_constant(elem)) => {
+                    Box::new(StaticVector(elems, t))
+                }
+                vector => {
+                    let vector = Box::new(HasType(Box::new(vector), t));
+                    expose_allocation_inner(vector, shared, cnt)
+                }
+            },
+            node => panic!("unexpected {:?}", node),
+        },
+        HasType(vector, t) => {
+            let elems = match *vector {
+                Vector(elems) => elems,
+                node => panic!("unexpected {:?}", node),
+            };
+            let len = elems.len();
+            // elements are evaluated before the allocation
+            let inits: Vec<(String, Box<Node>)> = elems
+                .into_iter()
+                .map(|elem| {
+                    let elem = expose_allocation_inner(elem, shared, cnt);
+                    (alloc_var(cnt, "vecinit"), elem)
+                })
+                .collect();
+            let vec = Box::new(Var(alloc_var(cnt, "alloc")));
+            let sets = inits
+                .iter()
+                .enumerate()
+                .map(|(i, (name, _))| {
+                    Box::new(VectorSet(
+                        vec.clone(),
+                        Box::new(Fixnum(i as isize)),
+                        Box::new(Var(name.clone())),
+                    ))
+                })
+                .collect();
+            let exp = Box::new(Let {
+                name: vec.var().unwrap().to_owned(),
+                value: Box::new(Begin(
+                    vec![collect_check((len + 1) * WORD)],
+                    Box::new(Allocate(len, t)),
+                )),
+                exp: Box::new(Begin(sets, vec)),
+            });
+            inits
+                .into_iter()
+                .rev()
+                .fold(exp, |exp, (name, value)| Box::new(Let { name, value, exp }))
+        }
+        node => Box::new(node.map_children(&mut |node| expose_allocation_inner(node, shared, cnt))),
+    }
+}
+
+pub fn expose_allocation(node: Box<Node>) -> Box<Node> {
+    let mut cnt = 0;
+    let shared = !sets_vectors(&node);
+    expose_allocation_inner(node, shared, &mut cnt)
+}
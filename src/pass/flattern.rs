@@ -1,10 +1,24 @@
 use crate::ast::*;
+use std::collections::HashSet;
 
+/// Flattern the program into definitions, the expression of the program becomes main,
+/// complex operands are assigned to temporaries, so every operand of an instruction is atomic
 pub fn flattern(node: Box<Node>) -> Vec<Box<Node>> {
-    let mut node_list = Vec::new();
+    let (defs, exp) = match *node {
+        Node::Program(defs, exp) => (defs, exp),
+        node => panic!("unexpected {:?}", node),
+    };
+    let main = Box::new(Node::Def {
+        name: "main".to_string(),
+        params: Vec::new(),
+        ret: Type::Fixnum,
+        body: vec![exp],
+    });
     let mut context = Context::default();
-    context.flattern_inner(node, &mut node_list);
-    node_list
+    defs.into_iter()
+        .chain(std::iter::once(main))
+        .map(|def| context.flattern_def(def))
+        .collect()
 }
 
 #[derive(Default)]
@@ -21,18 +35,183 @@ impl VarAlloc {
 #[derive(Default)]
 struct Context {
     var_allocator: VarAlloc,
+    /// variables assigned by set! in the definition being flatterned
+    mutated: HashSet<String>,
+}
+
+/// collect the targets of set!, a read of any other variable is atomic
+/// since its value never changes after the binding
+fn uncover_set_bangs(node: &Node, mutated: &mut HashSet<String>) {
+    if let Node::SetBang { name, .. } = node {
+        mutated.insert(name.clone());
+    }
+    for child in node.children() {
+        uncover_set_bangs(child, mutated);
+    }
+}
+
+/// check if a variable is assigned by the statements
+fn assigns(node_list: &[Box<Node>], name: &str) -> bool {
+    node_list.iter().any(|node| match node.as_ref() {
+        Node::Assign(var, _) => var == name,
+        Node::If {
+            if_exps, else_exps, ..
+        } => assigns(if_exps, name) || assigns(else_exps, name),
+        Node::While {
+            cond_exps,
+            body_exps,
+            ..
+        } => assigns(cond_exps, name) || assigns(body_exps, name),
+        _ => false,
+    })
 }
 
 impl Context {
+    /// flattern the body of a definition, the body returns its value
+    fn flattern_def(&mut self, def: Box<Node>) -> Box<Node> {
+        match *def {
+            Node::Def {
+                name,
+                params,
+                ret,
+                mut body,
+            } => {
+                assert_eq!(body.len(), 1);
+                self.mutated.clear();
+                uncover_set_bangs(&body[0], &mut self.mutated);
+                let mut node_list = Vec::new();
+                let value = self.flattern_inner(body.remove(0), &mut node_list);
+                node_list.push(Box::new(Node::Return(value)));
+                Box::new(Node::Def {
+                    name,
+                    params,
+                    ret,
+                    body: node_list,
+                })
+            }
+            def @ Node::DefGlobal { .. } => Box::new(def),
+            node => panic!("unexpected {:?}", node),
+        }
+    }
+
+    /// flattern arguments from left to right,
+    /// an argument is copied if its variable is mutated by the arguments after it,
+    /// so the read is not moved after the set!
+    fn flattern_args(
+        &mut self,
+        args: Vec<Box<Node>>,
+        node_list: &mut Vec<Box<Node>>,
+    ) -> Vec<Box<Node>> {
+        let mut lists = Vec::with_capacity(args.len());
+        let mut atoms = Vec::with_capacity(args.len());
+        for arg in args {
+            let mut list = Vec::new();
+            atoms.push(self.flattern_inner(arg, &mut list));
+            lists.push(list);
+        }
+        for i in 0..atoms.len() {
+            node_list.append(&mut lists[i]);
+            let mutated = match atoms[i].var() {
+                Some(name) if self.mutated.contains(name) => {
+                    lists[i + 1..].iter().any(|list| assigns(list, name))
+                }
+                _ => false,
+            };
+            if mutated {
+                let tmp = self.var_allocator.alloc();
+                let atom = std::mem::replace(&mut atoms[i], Box::new(Node::Var(tmp.clone())));
+                node_list.push(Box::new(Node::Assign(tmp, atom)));
+            }
+        }
+        atoms
+    }
+
+    fn flattern_operands(
+        &mut self,
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+        node_list: &mut Vec<Box<Node>>,
+    ) -> (Box<Node>, Box<Node>) {
+        let mut atoms = self.flattern_args(vec![lhs, rhs], node_list);
+        let rhs = atoms.pop().unwrap();
+        (atoms.pop().unwrap(), rhs)
+    }
+
+    /// SSE instructions take no immediate, so a float literal in the second operand
+    /// is assigned to a variable
+    fn flattern_sse_operands(
+        &mut self,
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+        node_list: &mut Vec<Box<Node>>,
+    ) -> (Box<Node>, Box<Node>) {
+        let (lhs, rhs) = self.flattern_operands(lhs, rhs, node_list);
+        if !rhs.is_literal() {
+            return (lhs, rhs);
+        }
+        let name = self.var_allocator.alloc();
+        node_list.push(Box::new(Node::Assign(name.clone(), rhs)));
+        (lhs, Box::new(Node::Var(name)))
+    }
+
+    /// flattern the condition of if, comparisons are kept with flatterned operands
+    fn flattern_cond(&mut self, cond: Box<Node>, node_list: &mut Vec<Box<Node>>) -> Box<Node> {
+        use Node::*;
+
+        let cond = match *cond {
+            Eq(lhs, rhs) => {
+                let (lhs, rhs) = self.flattern_operands(lhs, rhs, node_list);
+                Eq(lhs, rhs)
+            }
+            Lt(lhs, rhs) => {
+                let (lhs, rhs) = self.flattern_operands(lhs, rhs, node_list);
+                Lt(lhs, rhs)
+            }
+            Lte(lhs, rhs) => {
+                let (lhs, rhs) = self.flattern_operands(lhs, rhs, node_list);
+                Lte(lhs, rhs)
+            }
+            Gt(lhs, rhs) => {
+                let (lhs, rhs) = self.flattern_operands(lhs, rhs, node_list);
+                Gt(lhs, rhs)
+            }
+            Gte(lhs, rhs) => {
+                let (lhs, rhs) = self.flattern_operands(lhs, rhs, node_list);
+                Gte(lhs, rhs)
+            }
+            cond => {
+                let cond_var = self.flattern_inner(Box::new(cond), node_list);
+                if !cond_var.is_literal() {
+                    return cond_var;
+                }
+                let name = self.var_allocator.alloc();
+                let node = Box::new(Assign(name.clone(), cond_var));
+                node_list.push(node);
+                Var(name)
+            }
+        };
+        Box::new(cond)
+    }
+
     fn flattern_inner(&mut self, node: Box<Node>, node_list: &mut Vec<Box<Node>>) -> Box<Node> {
         use Node::*;
 
         match *node {
-            Program(sub_node) => {
+            Not(sub_node) => {
                 let sub_node = self.flattern_inner(sub_node, node_list);
-                let node = Box::new(Program(sub_node.clone()));
-                node_list.push(node);
-                sub_node
+                let not_node = Box::new(Not(sub_node));
+                let var_name = self.var_allocator.alloc();
+                let assign_node = Box::new(Assign(var_name.clone(), not_node));
+                node_list.push(assign_node);
+                Box::new(Var(var_name))
+            }
+            BitNot(sub_node) => {
+                let sub_node = self.flattern_inner(sub_node, node_list);
+                let not_node = Box::new(BitNot(sub_node));
+                let var_name = self.var_allocator.alloc();
+                let assign_node = Box::new(Assign(var_name.clone(), not_node));
+                node_list.push(assign_node);
+                Box::new(Var(var_name))
             }
             Neg(sub_node) => {
                 let sub_node = self.flattern_inner(sub_node, node_list);
@@ -40,45 +219,278 @@ impl Context {
                 let var_name = self.var_allocator.alloc();
                 let assign_node = Box::new(Assign(var_name.clone(), neg_node));
                 node_list.push(assign_node);
-                return Box::new(Var(var_name));
+                Box::new(Var(var_name))
             }
-            Read => {
-                let node = Box::new(Read);
+            CharToInteger(sub_node) => {
+                let sub_node = self.flattern_inner(sub_node, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(CharToInteger(sub_node));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            IntegerToChar(sub_node) => {
+                let sub_node = self.flattern_inner(sub_node, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(IntegerToChar(sub_node));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            FixnumToFlonum(sub_node) => {
+                let sub_node = self.flattern_inner(sub_node, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(FixnumToFlonum(sub_node));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            FlonumToFixnum(sub_node) => {
+                let sub_node = self.flattern_inner(sub_node, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(FlonumToFixnum(sub_node));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            v @ FAdd(..) | v @ FSub(..) | v @ FMul(..) | v @ FDiv(..) => {
+                type Op = fn(Box<Node>, Box<Node>) -> Node;
+                let (lhs, rhs, op): (_, _, Op) = match v {
+                    FAdd(lhs, rhs) => (lhs, rhs, FAdd),
+                    FSub(lhs, rhs) => (lhs, rhs, FSub),
+                    FMul(lhs, rhs) => (lhs, rhs, FMul),
+                    FDiv(lhs, rhs) => (lhs, rhs, FDiv),
+                    v => panic!("unexpected {:?}", v),
+                };
+                let (lhs, rhs) = self.flattern_sse_operands(lhs, rhs, node_list);
+                let var_name = self.var_allocator.alloc();
+                node_list.push(Box::new(Assign(var_name.clone(), Box::new(op(lhs, rhs)))));
+                Box::new(Var(var_name))
+            }
+            node @ Allocate(..) => {
+                let var_name = self.var_allocator.alloc();
+                node_list.push(Box::new(Assign(var_name.clone(), Box::new(node))));
+                Box::new(Var(var_name))
+            }
+            VectorLength(vec) => {
+                let vec = self.flattern_inner(vec, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(VectorLength(vec));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            VectorRef(vec, index) => {
+                let (vec, index) = self.flattern_operands(vec, index, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(VectorRef(vec, index));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            MakeArray(len, init, t) => {
+                let (len, init) = self.flattern_operands(len, init, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(MakeArray(len, init, t));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            ArrayLength(array) => {
+                let array = self.flattern_inner(array, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(ArrayLength(array));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            Random(bound) => {
+                let bound = self.flattern_inner(bound, node_list);
+                let var_name = self.var_allocator.alloc();
+                node_list.push(Box::new(Assign(var_name.clone(), Box::new(Random(bound)))));
+                Box::new(Var(var_name))
+            }
+            node @ Collect(_) => {
+                node_list.push(Box::new(node));
+                Box::new(Void)
+            }
+            // exit does not return, the variable only keeps an operand atomic
+            Exit(code) => {
+                let code = self.flattern_inner(code, node_list);
+                let var_name = self.var_allocator.alloc();
+                node_list.push(Box::new(Assign(var_name.clone(), Box::new(Exit(code)))));
+                Box::new(Var(var_name))
+            }
+            ArrayRef(array, index) => {
+                let (array, index) = self.flattern_operands(array, index, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(ArrayRef(array, index));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            ArraySet(array, index, value) => {
+                let mut atoms = self.flattern_args(vec![array, index, value], node_list);
+                let value = atoms.pop().unwrap();
+                let index = atoms.pop().unwrap();
+                let array = atoms.pop().unwrap();
+                node_list.push(Box::new(ArraySet(array, index, value)));
+                Box::new(Void)
+            }
+            StringLength(s) => {
+                let s = self.flattern_inner(s, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(StringLength(s));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            StringRef(s, index) => {
+                let (s, index) = self.flattern_operands(s, index, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(StringRef(s, index));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            // a global may be assigned by a call, so it is read into a variable
+            Global(label) => {
+                let var_name = self.var_allocator.alloc();
+                node_list.push(Box::new(Assign(var_name.clone(), Box::new(Global(label)))));
+                Box::new(Var(var_name))
+            }
+            GlobalSet(label, value) => {
+                let value = self.flattern_inner(value, node_list);
+                node_list.push(Box::new(GlobalSet(label, value)));
+                Box::new(Void)
+            }
+            VectorSet(vec, index, value) => {
+                let mut atoms = self.flattern_args(vec![vec, index, value], node_list);
+                let value = atoms.pop().unwrap();
+                let index = atoms.pop().unwrap();
+                let vec = atoms.pop().unwrap();
+                node_list.push(Box::new(VectorSet(vec, index, value)));
+                Box::new(Void)
+            }
+            Apply(fun, args) => {
+                // a closure is called through the address in a variable
+                let (fun, args) = match *fun {
+                    fun @ FunRef(_) => (Box::new(fun), self.flattern_args(args, node_list)),
+                    fun => {
+                        let args = std::iter::once(Box::new(fun)).chain(args).collect();
+                        let mut atoms = self.flattern_args(args, node_list);
+                        let fun = atoms.remove(0);
+                        (fun, atoms)
+                    }
+                };
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(Apply(fun, args));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            Inject(exp, t) => {
+                let exp = self.flattern_inner(exp, node_list);
+                let var_name = self.var_allocator.alloc();
+                node_list.push(Box::new(Assign(var_name.clone(), Box::new(Inject(exp, t)))));
+                Box::new(Var(var_name))
+            }
+            Project(exp, t, pos) => {
+                let exp = self.flattern_inner(exp, node_list);
+                let var_name = self.var_allocator.alloc();
+                let node = Box::new(Project(exp, t, pos));
+                node_list.push(Box::new(Assign(var_name.clone(), node)));
+                Box::new(Var(var_name))
+            }
+            node @ FunRef(_) | node @ Str(_) | node @ Symbol(_) | node @ StaticVector(..) => {
+                let var_name = self.var_allocator.alloc();
+                node_list.push(Box::new(Assign(var_name.clone(), Box::new(node))));
+                Box::new(Var(var_name))
+            }
+            Read(t) => {
+                let node = Box::new(Read(t));
                 let var_name = self.var_allocator.alloc();
                 let assign_node = Box::new(Assign(var_name.clone(), node));
                 node_list.push(assign_node);
-                return Box::new(Var(var_name));
+                Box::new(Var(var_name))
             }
-            v @ Add(..) | v @ Eq(..) | v @ Gt(..) | v @ Gte(..) | v @ Lt(..) | v @ Lte(..) => {
+            v @ Add(..)
+            | v @ Sub(..)
+            | v @ Mul(..)
+            | v @ Quotient(..)
+            | v @ Remainder(..)
+            | v @ BitAnd(..)
+            | v @ BitOr(..)
+            | v @ BitXor(..)
+            | v @ Shl(..)
+            | v @ Shr(..)
+            | v @ Sar(..)
+            | v @ Min(..)
+            | v @ Max(..)
+            | v @ Eq(..)
+            | v @ Gt(..)
+            | v @ Gte(..)
+            | v @ Lt(..)
+            | v @ Lte(..) => {
                 let node = match v {
                     Add(lhs, rhs) => {
-                        let lhs_var = self.flattern_inner(lhs, node_list);
-                        let rhs_var = self.flattern_inner(rhs, node_list);
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
                         Box::new(Add(lhs_var, rhs_var))
                     }
+                    Sub(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(Sub(lhs_var, rhs_var))
+                    }
+                    Mul(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(Mul(lhs_var, rhs_var))
+                    }
+                    Quotient(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(Quotient(lhs_var, rhs_var))
+                    }
+                    Remainder(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(Remainder(lhs_var, rhs_var))
+                    }
+                    BitAnd(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(BitAnd(lhs_var, rhs_var))
+                    }
+                    BitOr(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(BitOr(lhs_var, rhs_var))
+                    }
+                    BitXor(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(BitXor(lhs_var, rhs_var))
+                    }
+                    Shl(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(Shl(lhs_var, rhs_var))
+                    }
+                    Shr(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(Shr(lhs_var, rhs_var))
+                    }
+                    Sar(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(Sar(lhs_var, rhs_var))
+                    }
+                    Min(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(Min(lhs_var, rhs_var))
+                    }
+                    Max(lhs, rhs) => {
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
+                        Box::new(Max(lhs_var, rhs_var))
+                    }
                     Eq(lhs, rhs) => {
-                        let lhs_var = self.flattern_inner(lhs, node_list);
-                        let rhs_var = self.flattern_inner(rhs, node_list);
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
                         Box::new(Eq(lhs_var, rhs_var))
                     }
                     Gt(lhs, rhs) => {
-                        let lhs_var = self.flattern_inner(lhs, node_list);
-                        let rhs_var = self.flattern_inner(rhs, node_list);
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
                         Box::new(Gt(lhs_var, rhs_var))
                     }
                     Gte(lhs, rhs) => {
-                        let lhs_var = self.flattern_inner(lhs, node_list);
-                        let rhs_var = self.flattern_inner(rhs, node_list);
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
                         Box::new(Gte(lhs_var, rhs_var))
                     }
                     Lt(lhs, rhs) => {
-                        let lhs_var = self.flattern_inner(lhs, node_list);
-                        let rhs_var = self.flattern_inner(rhs, node_list);
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
                         Box::new(Lt(lhs_var, rhs_var))
                     }
                     Lte(lhs, rhs) => {
-                        let lhs_var = self.flattern_inner(lhs, node_list);
-                        let rhs_var = self.flattern_inner(rhs, node_list);
+                        let (lhs_var, rhs_var) = self.flattern_operands(lhs, rhs, node_list);
                         Box::new(Lte(lhs_var, rhs_var))
                     }
                     v => panic!("unexpected {:?}", v),
@@ -86,47 +498,55 @@ impl Context {
                 let var_name = self.var_allocator.alloc();
                 let assign_node = Box::new(Assign(var_name.clone(), node));
                 node_list.push(assign_node);
-                return Box::new(Var(var_name));
-            }
-            v @ Fixnum(..) | v @ Var(..) | v @ False | v @ True => {
-                return Box::new(v);
+                Box::new(Var(var_name))
             }
+            v @ Fixnum(..)
+            | v @ Char(..)
+            | v @ Flonum(..)
+            | v @ Var(..)
+            | v @ False
+            | v @ True
+            | v @ Void => Box::new(v),
             Let { name, value, exp } => {
+                let value = self.flattern_inner(value, node_list);
                 let assign_node = Box::new(Assign(name, value));
                 node_list.push(assign_node);
-                return self.flattern_inner(exp, node_list);
+                self.flattern_inner(exp, node_list)
+            }
+            Begin(effects, exp) => {
+                // values of effects are dropped
+                for effect in effects {
+                    self.flattern_inner(effect, node_list);
+                }
+                self.flattern_inner(exp, node_list)
+            }
+            SetBang { name, value } => {
+                let value = self.flattern_inner(value, node_list);
+                node_list.push(Box::new(Assign(name, value)));
+                Box::new(Void)
             }
             If {
-                cond,
+                mut cond,
                 mut if_exps,
                 mut else_exps,
-                if_live_afters,
-                else_live_afters,
             } => {
                 assert_eq!(if_exps.len(), 1);
                 assert_eq!(else_exps.len(), 1);
 
-                let mut cond_var = self.flattern_inner(cond, node_list);
-                if cond_var.is_literal() {
-                    let name = self.var_allocator.alloc();
-                    let node = Box::new(Assign(name.clone(), cond_var));
-                    node_list.push(node);
-                    cond_var = Box::new(Var(name));
+                // flip the branches instead of computing (not cond)
+                while let Not(sub_node) = *cond {
+                    cond = sub_node;
+                    std::mem::swap(&mut if_exps, &mut else_exps);
                 }
+
+                let cond_var = self.flattern_cond(cond, node_list);
                 let mut new_if_exps = Vec::new();
                 let mut new_else_exps = Vec::new();
                 let if_var = self.flattern_inner(if_exps.remove(0), &mut new_if_exps);
                 let else_var = self.flattern_inner(else_exps.remove(0), &mut new_else_exps);
+                // variables may be mutated, so the result goes to a new variable
                 let if_value_node = match (if_var.var(), else_var.var()) {
                     (Some(if_v), Some(else_v)) if if_v == else_v => if_var,
-                    (Some(if_v), _) => {
-                        new_else_exps.push(Box::new(Assign(if_v.to_owned(), else_var)));
-                        if_var
-                    }
-                    (_, Some(else_v)) => {
-                        new_if_exps.push(Box::new(Assign(else_v.to_owned(), if_var)));
-                        else_var
-                    }
                     _ => {
                         let v = self.var_allocator.alloc();
                         new_else_exps.push(Box::new(Assign(v.to_owned(), else_var)));
@@ -138,12 +558,27 @@ impl Context {
                     cond: cond_var,
                     if_exps: new_if_exps,
                     else_exps: new_else_exps,
-                    if_live_afters,
-                    else_live_afters,
                 });
                 node_list.push(node);
                 if_value_node
             }
+            While {
+                cond, body_exps, ..
+            } => {
+                // the condition is evaluated before each iteration
+                let mut cond_exps = Vec::new();
+                let cond = self.flattern_cond(cond, &mut cond_exps);
+                let mut new_body_exps = Vec::new();
+                for exp in body_exps {
+                    self.flattern_inner(exp, &mut new_body_exps);
+                }
+                node_list.push(Box::new(While {
+                    cond_exps,
+                    cond,
+                    body_exps: new_body_exps,
+                }));
+                Box::new(Void)
+            }
             val => {
                 panic!("unexpected {:?}", val);
             }
@@ -0,0 +1,149 @@
+/// Evaluate the instructions whose operands are known constants,
+/// the values of variables are known from the moves of immediates in the same block
+/// x86 specific pass
+use crate::ast::*;
+use std::collections::HashMap;
+
+struct Context {
+    /// variables holding a known constant
+    consts: HashMap<String, isize>,
+}
+
+impl Context {
+    fn value(&self, node: &Node) -> Option<isize> {
+        match node {
+            Node::Fixnum(num) => Some(*num),
+            Node::Var(name) => self.consts.get(name).copied(),
+            _ => None,
+        }
+    }
+
+    /// replace a variable read as an operand by its constant
+    fn propagate(&self, node: Box<Node>, small: bool) -> Box<Node> {
+        match self.value(&node) {
+            Some(num) if !small || is_imm32(num) => Box::new(Node::Fixnum(num)),
+            _ => node,
+        }
+    }
+
+    /// the result of an arithmetic instruction, if the result is known and it does not overflow
+    fn eval(&self, node: &Node) -> Option<isize> {
+        use Node::*;
+
+        match node {
+            ADDQ { target, arg } => self.value(target)?.checked_add(self.value(arg)?),
+            SUBQ { target, arg } => self.value(target)?.checked_sub(self.value(arg)?),
+            IMULQ { target, arg } => self.value(target)?.checked_mul(self.value(arg)?),
+            ANDQ { target, arg } => Some(self.value(target)? & self.value(arg)?),
+            ORQ { target, arg } => Some(self.value(target)? | self.value(arg)?),
+            XORQ { target, arg } => Some(self.value(target)? ^ self.value(arg)?),
+            SALQ { target, arg } => {
+                Some(self.value(target)?.wrapping_shl(arg.fixnum()? as u32 & 63))
+            }
+            SARQ { target, arg } => {
+                Some(self.value(target)?.wrapping_shr(arg.fixnum()? as u32 & 63))
+            }
+            SHRQ { target, arg } => Some(
+                (self.value(target)? as usize).wrapping_shr(arg.fixnum()? as u32 & 63) as isize,
+            ),
+            NEGQ(target) => self.value(target)?.checked_neg(),
+            NOTQ(target) => Some(!self.value(target)?),
+            _ => None,
+        }
+    }
+
+    fn fold(&self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        let node = match *node {
+            MOVQ { target, source } => MOVQ {
+                target,
+                source: self.propagate(source, false),
+            },
+            ADDQ { target, arg } => ADDQ {
+                target,
+                arg: self.propagate(arg, true),
+            },
+            SUBQ { target, arg } => SUBQ {
+                target,
+                arg: self.propagate(arg, true),
+            },
+            ANDQ { target, arg } => ANDQ {
+                target,
+                arg: self.propagate(arg, true),
+            },
+            ORQ { target, arg } => ORQ {
+                target,
+                arg: self.propagate(arg, true),
+            },
+            XORQ { target, arg } => XORQ {
+                target,
+                arg: self.propagate(arg, true),
+            },
+            CMPQ(lhs, rhs) => CMPQ(self.propagate(lhs, true), rhs),
+            node => node,
+        };
+        Box::new(node)
+    }
+
+    /// record the value of the variable written by the instruction
+    fn update(&mut self, node: &Node) {
+        match node {
+            Node::Label(_) => {
+                // a block may be entered from other blocks
+                self.consts.clear();
+            }
+            Node::MOVQ { target, source } => {
+                if let (Node::Var(name), Some(num)) = (target.as_ref(), source.fixnum()) {
+                    self.consts.insert(name.clone(), num);
+                } else if let Node::Var(name) = target.as_ref() {
+                    self.consts.remove(name);
+                }
+            }
+            node => {
+                if let Some(Node::Var(name)) = node.written() {
+                    self.consts.remove(name);
+                }
+            }
+        }
+    }
+}
+
+pub fn fold_constants(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    let mut cxt = Context {
+        consts: HashMap::new(),
+    };
+    let mut new_node_list: Vec<Box<Node>> = Vec::with_capacity(node_list.len());
+    let mut node_list = node_list.into_iter().peekable();
+
+    while let Some(node) = node_list.next() {
+        // the flags are left as they are if the instruction is evaluated
+        let flags_used = node_list.peek().is_some_and(|node| node.reads_flags());
+        let node = match (node.written(), cxt.eval(&node)) {
+            (Some(target @ Var(_)), Some(num)) if !flags_used => Box::new(MOVQ {
+                target: Box::new(target.clone()),
+                source: Box::new(Fixnum(num)),
+            }),
+            _ => cxt.fold(node),
+        };
+        // a move of an immediate is dead if the variable is overwritten right after it
+        if let MOVQ { target, source } = node.as_ref() {
+            if source.fixnum().is_some() && target.var().is_some() {
+                if let Some(MOVQ {
+                    target: last_target,
+                    source: last_source,
+                }) = new_node_list.last().map(|node| node.as_ref())
+                {
+                    if last_target == target && last_source.fixnum().is_some() {
+                        new_node_list.pop();
+                    }
+                }
+            }
+        }
+        cxt.update(&node);
+        new_node_list.push(node);
+    }
+    new_node_list
+}
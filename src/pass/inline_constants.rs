@@ -0,0 +1,110 @@
+/// Replace the uses of constants defined by define-const with their values,
+/// a value is folded to a literal when it is defined, so a constant has no storage
+use super::partial_eval;
+use crate::ast::*;
+use std::collections::HashMap;
+
+/// remove the positions of an expression, the position of the expression is returned
+fn unlocate(node: Box<Node>) -> (Box<Node>, Pos) {
+    fn strip(node: Box<Node>) -> Box<Node> {
+        match *node {
+            Node::Located(_, exp) => strip(exp),
+            node => Box::new(node.map_children(&mut strip)),
+        }
+    }
+
+    match *node {
+        Node::Located(pos, exp) => (strip(exp), pos),
+        node => (strip(Box::new(node)), Pos::default()),
+    }
+}
+
+struct Context {
+    constants: HashMap<String, Node>,
+    /// local variables shadowing the constants
+    bound: Vec<String>,
+    /// position of the expression being inlined
+    pos: Pos,
+}
+
+impl Context {
+    fn is_constant(&self, name: &str) -> bool {
+        self.constants.contains_key(name) && !self.bound.iter().any(|var| var == name)
+    }
+
+    fn inline(&mut self, node: Box<Node>) -> Result<Box<Node>, String> {
+        use Node::*;
+
+        let node = match *node {
+            Var(name) if self.is_constant(&name) => self.constants[&name].clone(),
+            SetBang { name, .. } if self.is_constant(&name) => {
+                return Err(format!("{}: Constant {} can not be set", self.pos, name))
+            }
+            Located(pos, exp) => {
+                self.pos = pos;
+                Located(pos, self.inline(exp)?)
+            }
+            node => {
+                let mut result = Ok(());
+                let node = node.map_scoped(&mut |node, names| {
+                    let len = self.bound.len();
+                    self.bound.extend_from_slice(names);
+                    let node = match self.inline(node) {
+                        Ok(node) => node,
+                        Err(msg) => {
+                            result = Err(msg);
+                            Box::new(NOP)
+                        }
+                    };
+                    self.bound.truncate(len);
+                    node
+                });
+                result?;
+                node
+            }
+        };
+        Ok(Box::new(node))
+    }
+
+    fn inline_list(&mut self, nodes: Vec<Box<Node>>) -> Result<Vec<Box<Node>>, String> {
+        nodes.into_iter().map(|node| self.inline(node)).collect()
+    }
+
+    /// the value may use the constants defined before it
+    fn define(&mut self, name: String, value: Box<Node>) -> Result<(), String> {
+        let (value, pos) = unlocate(self.inline(value)?);
+        let value = partial_eval(value);
+        if !value.is_literal() {
+            return Err(format!(
+                "{}: The value of constant {} is not known at compile time",
+                pos, name
+            ));
+        }
+        if self.constants.insert(name.clone(), *value).is_some() {
+            return Err(format!("Duplicate constant {}", name));
+        }
+        Ok(())
+    }
+}
+
+pub fn inline_constants(node: Box<Node>) -> Result<Box<Node>, String> {
+    let (defs, exp) = match *node {
+        Node::Program(defs, exp) => (defs, exp),
+        node => panic!("unexpected {:?}", node),
+    };
+    let mut cxt = Context {
+        constants: HashMap::new(),
+        bound: Vec::new(),
+        pos: Pos::default(),
+    };
+    let mut rest = Vec::with_capacity(defs.len());
+    for def in defs {
+        match *def {
+            Node::DefConst { name, value } => cxt.define(name, value)?,
+            def => rest.push(Box::new(def)),
+        }
+    }
+    let defs = cxt.inline_list(rest)?;
+    let exp = cxt.inline(exp)?;
+    Ok(Box::new(Node::Program(defs, exp)))
+}
@@ -0,0 +1,132 @@
+/// Replace the direct calls of small functions by their bodies,
+/// the parameters are bound to the arguments under fresh names
+use crate::ast::*;
+use std::collections::HashMap;
+
+/// the largest body to inline, counted in nodes
+const INLINE_BUDGET: usize = 16;
+
+/// a vector literal is one object, so a copy of it would be another object
+fn has_literal(node: &Node) -> bool {
+    matches!(node, Node::Literal(_)) || node.children().into_iter().any(has_literal)
+}
+
+/// rename the variables which are not shadowed
+fn rename(node: Box<Node>, names: &HashMap<String, String>) -> Box<Node> {
+    use Node::*;
+
+    let node = match *node {
+        Var(name) => Var(names.get(&name).cloned().unwrap_or(name)),
+        SetBang { name, value } => SetBang {
+            name: names.get(&name).cloned().unwrap_or(name),
+            value: rename(value, names),
+        },
+        Lambda {
+            params,
+            ret,
+            body,
+            free_vars,
+        } => {
+            let free_vars = free_vars
+                .into_iter()
+                .map(|(name, t)| (names.get(&name).cloned().unwrap_or(name), t))
+                .collect();
+            Lambda {
+                params,
+                ret,
+                body,
+                free_vars,
+            }
+            .map_scoped(&mut |node, bound| scoped_rename(node, bound, names))
+        }
+        node => node.map_scoped(&mut |node, bound| scoped_rename(node, bound, names)),
+    };
+    Box::new(node)
+}
+
+fn scoped_rename(node: Box<Node>, bound: &[String], names: &HashMap<String, String>) -> Box<Node> {
+    if !bound.iter().any(|name| names.contains_key(name)) {
+        return rename(node, names);
+    }
+    let names = names
+        .iter()
+        .filter(|(name, _)| !bound.contains(name))
+        .map(|(name, fresh)| (name.clone(), fresh.clone()))
+        .collect();
+    rename(node, &names)
+}
+
+struct Context {
+    /// parameters and bodies of the functions small enough to inline
+    funs: HashMap<String, (Vec<String>, Box<Node>)>,
+    /// number of the inlined calls
+    cnt: usize,
+}
+
+impl Context {
+    fn inline_call(&mut self, label: &str, args: Vec<Box<Node>>) -> Box<Node> {
+        let (params, body) = &self.funs[label];
+        self.cnt += 1;
+        // an argument may use a variable of the caller which has the name of a parameter
+        let names: HashMap<_, _> = params
+            .iter()
+            .map(|param| (param.clone(), format!("{}.inline.{}", param, self.cnt)))
+            .collect();
+        let body = rename(body.clone(), &names);
+        params
+            .iter()
+            .zip(args)
+            .rev()
+            .fold(body, |exp, (param, value)| {
+                Box::new(Node::Let {
+                    name: names[param].clone(),
+                    value,
+                    exp,
+                })
+            })
+    }
+
+    fn inline(&mut self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        let node = match *node {
+            Apply(fun, args) => {
+                let args: Vec<_> = args.into_iter().map(|arg| self.inline(arg)).collect();
+                match *fun {
+                    FunRef(label) if self.funs.contains_key(&label) => {
+                        return self.inline_call(&label, args)
+                    }
+                    fun => Apply(Box::new(fun), args),
+                }
+            }
+            node => node.map_children(&mut |node| self.inline(node)),
+        };
+        Box::new(node)
+    }
+}
+
+/// the bodies are inlined as they are before inlining, so a recursive call is inlined once
+pub fn inline_functions(node: Box<Node>) -> Box<Node> {
+    let (defs, exp) = match *node {
+        Node::Program(defs, exp) => (defs, exp),
+        node => panic!("unexpected {:?}", node),
+    };
+    let mut cxt = Context {
+        funs: HashMap::new(),
+        cnt: 0,
+    };
+    for def in &defs {
+        if let Node::Def {
+            name, params, body, ..
+        } = def.as_ref()
+        {
+            if body.len() == 1 && body[0].size() <= INLINE_BUDGET && !has_literal(&body[0]) {
+                let params = params.iter().map(|(param, _)| param.clone()).collect();
+                cxt.funs.insert(name.clone(), (params, body[0].clone()));
+            }
+        }
+    }
+    let defs = defs.into_iter().map(|def| cxt.inline(def)).collect();
+    let exp = cxt.inline(exp);
+    Box::new(Node::Program(defs, exp))
+}
@@ -0,0 +1,306 @@
+/// Pack the parameters of a function beyond the fourth into a tuple, so a function takes
+/// at most five arguments and a closure passes its environment in the sixth register,
+/// a call packs its arguments in the same way, after the default values are filled
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+
+/// the closure takes one of the argument registers
+const MAX_PARAMS: usize = ARGUMENT_REGISTERS.len() - 1;
+
+/// name of the parameter of the packed arguments
+const REST_PARAM: &str = "rest.args";
+
+fn limit_type(t: Type) -> Type {
+    match t {
+        Type::Function(params, ret) => {
+            let mut params: Vec<_> = params.into_iter().map(limit_type).collect();
+            if params.len() > MAX_PARAMS {
+                let rest = params.split_off(MAX_PARAMS - 1);
+                params.push(tuple_type(rest));
+            }
+            Type::Function(params, Box::new(limit_type(*ret)))
+        }
+        Type::Vector(types) => Type::Vector(types.into_iter().map(limit_type).collect()),
+        Type::Values(types) => Type::Values(types.into_iter().map(limit_type).collect()),
+        Type::Box(t) => Type::Box(Box::new(limit_type(*t))),
+        Type::List(t) => Type::List(Box::new(limit_type(*t))),
+        Type::Array(t) => Type::Array(Box::new(limit_type(*t))),
+        Type::Optional(t) => Type::Optional(Box::new(limit_type(*t))),
+        Type::HashTable(key_t, value_t) => {
+            Type::HashTable(Box::new(limit_type(*key_t)), Box::new(limit_type(*value_t)))
+        }
+        t => t,
+    }
+}
+
+/// the tuple of the dynamically typed arguments is dynamically typed too,
+/// so the function can still be converted to Any
+fn tuple_type(types: Vec<Type>) -> Type {
+    if types.iter().all(|t| *t == Type::Any) {
+        Type::Any
+    } else {
+        Type::Vector(types)
+    }
+}
+
+fn limit_params(params: Vec<(String, Type)>) -> Vec<(String, Type)> {
+    params
+        .into_iter()
+        .map(|(name, t)| (name, limit_type(t)))
+        .collect()
+}
+
+/// the parameter types of a constructor made by shrink, whose tuple is always a vector,
+/// as the casts are lowered before the constructors are made
+pub fn constructor_types(mut types: Vec<Type>) -> Vec<Type> {
+    if types.len() > MAX_PARAMS {
+        let rest = types.split_off(MAX_PARAMS - 1);
+        types.push(Type::Vector(rest));
+    }
+    types
+}
+
+/// the constructor of a struct or a variant takes its fields as constructor_types
+pub fn pack_constructor(
+    params: Vec<(String, Type)>,
+    body: Box<Node>,
+) -> (Vec<(String, Type)>, Box<Node>) {
+    pack_into(params, body, Type::Vector)
+}
+
+/// replace the parameters beyond the fourth by the tuple, the body binds them
+/// to the elements of the tuple, whose default values are filled by the callers
+fn pack_params(params: Vec<(String, Type)>, body: Box<Node>) -> (Vec<(String, Type)>, Box<Node>) {
+    pack_into(params, body, tuple_type)
+}
+
+fn pack_into(
+    mut params: Vec<(String, Type)>,
+    body: Box<Node>,
+    tuple_type: fn(Vec<Type>) -> Type,
+) -> (Vec<(String, Type)>, Box<Node>) {
+    use Node::*;
+
+    if params.len() <= MAX_PARAMS {
+        return (params, body);
+    }
+    let rest = params.split_off(MAX_PARAMS - 1);
+    let rest_t = tuple_type(
+        rest.iter()
+            .map(|(_, t)| match t {
+                Type::Optional(t) => *t.clone(),
+                t => t.clone(),
+            })
+            .collect(),
+    );
+    let tuple = match rest_t {
+        Type::Any => Box::new(Project(
+            Box::new(Var(REST_PARAM.to_string())),
+            Type::Vector(vec![Type::Any; rest.len()]),
+            Pos::default(),
+        )),
+        _ => Box::new(Var(REST_PARAM.to_string())),
+    };
+    let body = rest
+        .iter()
+        .enumerate()
+        .rev()
+        .fold(body, |exp, (i, (name, _))| {
+            Box::new(Let {
+                name: name.clone(),
+                value: Box::new(VectorRef(tuple.clone(), Box::new(Fixnum(i as isize)))),
+                exp,
+            })
+        });
+    params.push((REST_PARAM.to_string(), rest_t));
+    (params, body)
+}
+
+/// name of the function called directly
+fn callee(fun: &Node) -> Option<&String> {
+    match fun {
+        Node::Located(_, fun) => fun.var(),
+        fun => fun.var(),
+    }
+}
+
+/// the variables used other than as the function of a call
+fn collect_values(node: Box<Node>, values: &mut HashSet<String>) -> Box<Node> {
+    use Node::*;
+
+    let node = match *node {
+        Var(name) => {
+            values.insert(name.clone());
+            Var(name)
+        }
+        Apply(fun, args) if callee(&fun).is_some() => Apply(
+            fun,
+            args.into_iter()
+                .map(|arg| collect_values(arg, values))
+                .collect(),
+        ),
+        node => node.map_children(&mut |node| collect_values(node, values)),
+    };
+    Box::new(node)
+}
+
+/// a function with a parameter in each register which is not used as a value
+fn keeps_params(name: &str, params: &[(String, Type)], values: &HashSet<String>) -> bool {
+    params.len() == ARGUMENT_REGISTERS.len() && !values.contains(name)
+}
+
+struct Context {
+    /// the functions which keep their parameters
+    unlimited: HashSet<String>,
+    /// the number of parameters of the packed functions with default values
+    defaults: HashMap<String, usize>,
+    /// local variables shadowing the functions
+    bound: Vec<String>,
+    /// the variables used as values
+    values: HashSet<String>,
+}
+
+impl Context {
+    fn is_unlimited(&self, fun: &Node) -> bool {
+        callee(fun).is_some_and(|name| self.unlimited.contains(name) && !self.bound.contains(name))
+    }
+
+    /// the number of parameters of a packed function with default values, or 0
+    fn arity(&self, fun: &Node) -> usize {
+        match callee(fun) {
+            Some(name) if !self.bound.contains(name) => {
+                self.defaults.get(name).copied().unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    fn limit(&mut self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        let node = match *node {
+            Def {
+                name,
+                params,
+                ret,
+                mut body,
+            } => {
+                let len = self.bound.len();
+                self.bound
+                    .extend(params.iter().map(|(name, _)| name.clone()));
+                let exp = self.limit(body.pop().unwrap());
+                self.bound.truncate(len);
+                let params = limit_params(params);
+                let (params, exp) = if keeps_params(&name, &params, &self.values) {
+                    (params, exp)
+                } else {
+                    pack_params(params, exp)
+                };
+                body.push(exp);
+                Def {
+                    name,
+                    params,
+                    ret: limit_type(ret),
+                    body,
+                }
+            }
+            Lambda {
+                params,
+                ret,
+                body,
+                free_vars,
+            } => {
+                let len = self.bound.len();
+                self.bound
+                    .extend(params.iter().map(|(name, _)| name.clone()));
+                let body = self.limit(body);
+                self.bound.truncate(len);
+                let (params, body) = pack_params(limit_params(params), body);
+                Lambda {
+                    params,
+                    ret: limit_type(ret),
+                    body,
+                    free_vars,
+                }
+            }
+            // the tuple holds the parameters left out, so their default values are passed
+            Apply(fun, mut args) if self.arity(&fun) > args.len() => {
+                let name = callee(&fun).unwrap().clone();
+                args.extend(
+                    (args.len()..self.defaults[&name]).map(|i| {
+                        Box::new(Apply(Box::new(Var(default_name(&name, i))), Vec::new()))
+                    }),
+                );
+                return self.limit(Box::new(Apply(fun, args)));
+            }
+            Apply(fun, args) if args.len() > MAX_PARAMS && !self.is_unlimited(&fun) => {
+                let fun = self.limit(fun);
+                let mut args: Vec<_> = args.into_iter().map(|arg| self.limit(arg)).collect();
+                let rest = args.split_off(MAX_PARAMS - 1);
+                args.push(Box::new(Vector(rest)));
+                Apply(fun, args)
+            }
+            DefStruct { name, fields } => DefStruct {
+                name,
+                fields: limit_params(fields),
+            },
+            DefType { name, variants } => DefType {
+                name,
+                variants: variants
+                    .into_iter()
+                    .map(|(variant, types)| (variant, types.into_iter().map(limit_type).collect()))
+                    .collect(),
+            },
+            Ann(exp, t, pos) => Ann(self.limit(exp), limit_type(t), pos),
+            Inject(exp, t) => Inject(self.limit(exp), limit_type(t)),
+            Project(exp, t, pos) => Project(self.limit(exp), limit_type(t), pos),
+            MakeHashTable(key_t, value_t) => MakeHashTable(limit_type(key_t), limit_type(value_t)),
+            node => node.map_scoped(&mut |node, names| {
+                let len = self.bound.len();
+                self.bound.extend_from_slice(names);
+                let node = self.limit(node);
+                self.bound.truncate(len);
+                node
+            }),
+        };
+        Box::new(node)
+    }
+}
+
+pub fn limit_functions(node: Box<Node>) -> Box<Node> {
+    use Node::*;
+
+    let (defs, exp) = match *node {
+        Program(defs, exp) => (defs, exp),
+        node => panic!("unexpected {:?}", node),
+    };
+    let mut values = HashSet::new();
+    for def in defs.iter().chain(std::iter::once(&exp)) {
+        collect_values(def.clone(), &mut values);
+    }
+    let mut unlimited = HashSet::new();
+    let mut defaults = HashMap::new();
+    for def in &defs {
+        match def.as_ref() {
+            Def { name, params, .. } if keeps_params(name, params, &values) => {
+                unlimited.insert(name.clone());
+            }
+            Def { name, params, .. }
+                if params.len() > MAX_PARAMS
+                    && params.iter().any(|(_, t)| matches!(t, Type::Optional(_))) =>
+            {
+                defaults.insert(name.clone(), params.len());
+            }
+            _ => {}
+        }
+    }
+    let mut cxt = Context {
+        unlimited,
+        defaults,
+        bound: Vec::new(),
+        values,
+    };
+    let defs = defs.into_iter().map(|def| cxt.limit(def)).collect();
+    let exp = cxt.limit(exp);
+    Box::new(Program(defs, exp))
+}
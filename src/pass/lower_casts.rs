@@ -0,0 +1,82 @@
+/// Lower casts into injections and projections,
+/// a function is cast by a lambda which casts its arguments and result
+use crate::ast::*;
+
+fn alloc_var(cnt: &mut usize) -> String {
+    let name = format!("cast.{}", cnt);
+    *cnt += 1;
+    name
+}
+
+/// the type with a tag of Any that the type is cast through
+fn ground(t: &Type) -> Type {
+    match t {
+        Type::Function(params, _) => {
+            Type::Function(vec![Type::Any; params.len()], Box::new(Type::Any))
+        }
+        t => t.clone(),
+    }
+}
+
+fn cast(exp: Box<Node>, from: Type, to: Type, pos: Pos, cnt: &mut usize) -> Box<Node> {
+    use Node::*;
+
+    if from == to {
+        return exp;
+    }
+    match (from, to) {
+        (Type::Any, to) => {
+            let ground_t = ground(&to);
+            let exp = Box::new(Project(exp, ground_t.clone(), pos));
+            cast(exp, ground_t, to, pos, cnt)
+        }
+        (from, Type::Any) => {
+            let ground_t = ground(&from);
+            let exp = cast(exp, from, ground_t.clone(), pos, cnt);
+            Box::new(Inject(exp, ground_t))
+        }
+        (Type::Function(from_params, from_ret), Type::Function(to_params, to_ret)) => {
+            let fun = alloc_var(cnt);
+            let params: Vec<_> = to_params.into_iter().map(|t| (alloc_var(cnt), t)).collect();
+            let args = params
+                .iter()
+                .zip(from_params.iter())
+                .map(|((name, t), param_t)| {
+                    let arg = Box::new(Var(name.clone()));
+                    cast(arg, t.clone(), param_t.clone(), pos, cnt)
+                })
+                .collect();
+            let call = Box::new(Apply(Box::new(Var(fun.clone())), args));
+            let body = cast(call, *from_ret.clone(), *to_ret.clone(), pos, cnt);
+            let fun_t = Type::Function(from_params, from_ret);
+            Box::new(Let {
+                name: fun.clone(),
+                value: exp,
+                exp: Box::new(Lambda {
+                    params,
+                    ret: *to_ret,
+                    body,
+                    free_vars: vec![(fun, fun_t)],
+                }),
+            })
+        }
+        (from, to) => panic!("unexpected cast from {:?} to {:?}", from, to),
+    }
+}
+
+fn lower_casts_inner(node: Box<Node>, cnt: &mut usize) -> Box<Node> {
+    use Node::*;
+
+    match *node {
+        Cast { exp, from, to, pos } => {
+            let exp = lower_casts_inner(exp, cnt);
+            cast(exp, from, to, pos, cnt)
+        }
+        node => Box::new(node.map_children(&mut |node| lower_casts_inner(node, cnt))),
+    }
+}
+
+pub fn lower_casts(node: Box<Node>) -> Box<Node> {
+    let mut cnt = 0;
+    lower_casts_inner(node, &mut cnt)
+}
@@ -0,0 +1,327 @@
+/// Lower print, format, error and assert into calls of the runtime printers,
+/// the value of main is printed too,
+/// a compound value is written by a function generated for its type,
+/// which prints the components by their types
+use crate::ast::*;
+use std::collections::HashMap;
+
+fn call(fun: &str, args: Vec<Box<Node>>) -> Box<Node> {
+    Box::new(Node::Apply(Box::new(Node::FunRef(fun.to_string())), args))
+}
+
+fn print_str(s: &str) -> Box<Node> {
+    call("print_string", vec![Box::new(Node::Str(s.to_string()))])
+}
+
+fn seq(mut exps: Vec<Box<Node>>) -> Box<Node> {
+    let last = exps.pop().unwrap_or_else(|| Box::new(Node::Void));
+    Box::new(Node::Begin(exps, last))
+}
+
+struct Context {
+    /// field types of structs
+    structs: HashMap<String, Vec<Type>>,
+    /// variants of data types
+    data: HashMap<String, Vec<(String, Vec<Type>)>>,
+    /// printers generated for compound types
+    printers: Vec<(Type, String)>,
+    defs: Vec<Box<Node>>,
+    bignum: bool,
+    release: bool,
+}
+
+impl Context {
+    fn print(&mut self, exp: Box<Node>, t: &Type) -> Box<Node> {
+        // the value is evaluated before a constant is printed
+        let constant = |exp, s| seq(vec![exp, print_str(s)]);
+        match t {
+            Type::Fixnum if self.bignum => call("print_integer", vec![exp]),
+            Type::Fixnum => call("print_int", vec![exp]),
+            Type::Boolean => call("print_bool", vec![exp]),
+            Type::Char => call("print_char", vec![exp]),
+            Type::Flonum => call("print_float", vec![exp]),
+            // a symbol is laid out as a string
+            Type::String | Type::Symbol => call("print_string", vec![exp]),
+            Type::Any => {
+                let bignum = Box::new(if self.bignum { Node::True } else { Node::False });
+                call("print_any", vec![exp, bignum])
+            }
+            // the expression does not return
+            Type::Bottom => exp,
+            Type::Void => constant(exp, "#<void>"),
+            Type::Null => constant(exp, "()"),
+            Type::Function(..) => constant(exp, "#<procedure>"),
+            Type::HashTable(..) => constant(exp, "#<hash>"),
+            Type::Vector(_)
+            | Type::Values(_)
+            | Type::Box(_)
+            | Type::List(_)
+            | Type::Array(_)
+            | Type::Named(_) => {
+                let printer = self.printer(t);
+                Box::new(Node::Apply(Box::new(Node::Var(printer)), vec![exp]))
+            }
+            Type::Var(_) | Type::Generic(..) | Type::Optional(_) => {
+                panic!("unexpected print of {:?}", t)
+            }
+        }
+    }
+
+    /// the printer is registered before its body is built, so a recursive type calls it
+    fn printer(&mut self, t: &Type) -> String {
+        if let Some((_, name)) = self.printers.iter().find(|(other, _)| other == t) {
+            return name.clone();
+        }
+        let name = format!("print.{}", self.printers.len());
+        self.printers.push((t.clone(), name.clone()));
+        let value = Box::new(Node::Var("value".to_string()));
+        let body = match t {
+            Type::Vector(elem_types) => self.print_fields("#(", &value, 0, elem_types),
+            Type::Box(elem_t) => seq(vec![
+                print_str("#&"),
+                self.print(Box::new(Node::Unbox(value)), elem_t),
+            ]),
+            Type::Named(type_name) => match self.structs.get(type_name).cloned() {
+                Some(field_types) => {
+                    let open = format!("#(struct:{}", type_name);
+                    self.print_struct(&open, &value, 0, &field_types)
+                }
+                None => self.print_variants(&value, type_name),
+            },
+            Type::List(elem_t) => self.print_list(value, elem_t),
+            Type::Values(types) => self.print_values(&value, types),
+            Type::Array(elem_t) => self.print_array(value, elem_t),
+            t => panic!("unexpected printer of {:?}", t),
+        };
+        self.defs.push(Box::new(Node::Def {
+            name: name.clone(),
+            params: vec![("value".to_string(), t.clone())],
+            ret: Type::Void,
+            body: vec![body],
+        }));
+        name
+    }
+
+    /// elements are separated by spaces
+    fn print_fields(
+        &mut self,
+        open: &str,
+        value: &Node,
+        offset: usize,
+        field_types: &[Type],
+    ) -> Box<Node> {
+        let mut exps = vec![print_str(open)];
+        for (i, t) in field_types.iter().enumerate() {
+            if i > 0 {
+                exps.push(print_str(" "));
+            }
+            let field = Box::new(Node::VectorRef(
+                Box::new(value.clone()),
+                Box::new(Node::Fixnum((i + offset) as isize)),
+            ));
+            exps.push(self.print(field, t));
+        }
+        exps.push(print_str(")"));
+        seq(exps)
+    }
+
+    /// each result is on its own line
+    fn print_values(&mut self, value: &Node, types: &[Type]) -> Box<Node> {
+        let mut exps = Vec::new();
+        for (i, t) in types.iter().enumerate() {
+            if i > 0 {
+                exps.push(print_str("\n"));
+            }
+            let result = Box::new(Node::VectorRef(
+                Box::new(value.clone()),
+                Box::new(Node::Fixnum(i as isize)),
+            ));
+            exps.push(self.print(result, t));
+        }
+        seq(exps)
+    }
+
+    /// fields of a struct follow its name
+    fn print_struct(
+        &mut self,
+        open: &str,
+        value: &Node,
+        offset: usize,
+        field_types: &[Type],
+    ) -> Box<Node> {
+        let open = if field_types.is_empty() {
+            open.to_string()
+        } else {
+            format!("{} ", open)
+        };
+        self.print_fields(&open, value, offset, field_types)
+    }
+
+    /// the variant is tested by the tag in the first element
+    fn print_variants(&mut self, value: &Node, type_name: &str) -> Box<Node> {
+        let variants = self.data[type_name].clone();
+        let mut exp = Box::new(Node::Void);
+        for (tag, (variant, field_types)) in variants.iter().enumerate().rev() {
+            let open = format!("#(struct:{}", variant);
+            let print_exp = self.print_struct(&open, value, 1, field_types);
+            exp = if tag + 1 == variants.len() {
+                print_exp
+            } else {
+                let cond = Box::new(Node::Eq(
+                    Box::new(Node::VectorRef(
+                        Box::new(value.clone()),
+                        Box::new(Node::Fixnum(0)),
+                    )),
+                    Box::new(Node::Fixnum(tag as isize)),
+                ));
+                Box::new(Node::new_if(cond, print_exp, exp))
+            };
+        }
+        exp
+    }
+
+    fn print_list(&mut self, value: Box<Node>, elem_t: &Type) -> Box<Node> {
+        use Node::*;
+
+        let list = Box::new(Var("list".to_string()));
+        let body = seq(vec![
+            self.print(Box::new(Car(list.clone())), elem_t),
+            Box::new(SetBang {
+                name: "list".to_string(),
+                value: Box::new(Cdr(list.clone())),
+            }),
+            Box::new(Node::new_if(
+                Box::new(IsNull(list.clone())),
+                Box::new(Void),
+                print_str(" "),
+            )),
+        ]);
+        let exp = seq(vec![
+            print_str("("),
+            Box::new(Node::new_while(Box::new(Not(Box::new(IsNull(list)))), body)),
+            print_str(")"),
+        ]);
+        Box::new(Let {
+            name: "list".to_string(),
+            value,
+            exp,
+        })
+    }
+
+    fn print_array(&mut self, value: Box<Node>, elem_t: &Type) -> Box<Node> {
+        use Node::*;
+
+        let i = Box::new(Var("i".to_string()));
+        let body = seq(vec![
+            Box::new(Node::new_if(
+                Box::new(Gt(i.clone(), Box::new(Fixnum(0)))),
+                print_str(" "),
+                Box::new(Void),
+            )),
+            self.print(Box::new(ArrayRef(value.clone(), i.clone())), elem_t),
+            Box::new(SetBang {
+                name: "i".to_string(),
+                value: Box::new(Add(i.clone(), Box::new(Fixnum(1)))),
+            }),
+        ]);
+        let cond = Box::new(Lt(i, Box::new(ArrayLength(value))));
+        let exp = seq(vec![
+            print_str("#("),
+            Box::new(Node::new_while(cond, body)),
+            print_str(")"),
+        ]);
+        Box::new(Let {
+            name: "i".to_string(),
+            value: Box::new(Fixnum(0)),
+            exp,
+        })
+    }
+
+    fn lower_print(&mut self, node: Box<Node>) -> Box<Node> {
+        match *node {
+            Node::Print(exp, t) => {
+                let exp = self.lower_print(exp);
+                self.print(exp, &t)
+            }
+            Node::Assert(_, _) if self.release => Box::new(Node::Void),
+            Node::Assert(cond, pos) => {
+                let msg = Box::new(Node::Str(format!("{}: assertion failed", pos)));
+                let error = Box::new(Node::Error(msg, None, Type::Void));
+                let exp = Box::new(Node::new_if(cond, Box::new(Node::Void), error));
+                self.lower_print(exp)
+            }
+            Node::Error(msg, value, t) => {
+                let mut exps = vec![call("error_begin", vec![self.lower_print(msg)])];
+                if let Some(value) = value {
+                    exps.push(print_str(" "));
+                    let value = self.lower_print(value);
+                    exps.push(self.print(value, &t));
+                }
+                exps.push(call("error_end", Vec::new()));
+                seq(exps)
+            }
+            // the values are evaluated before the output is captured
+            Node::Format(pieces, args, types) => {
+                let vars: Vec<_> = (0..args.len()).map(|i| format!("format.{}", i)).collect();
+                let mut exps = vec![call("format_begin", Vec::new())];
+                for (i, piece) in pieces.iter().enumerate() {
+                    if !piece.is_empty() {
+                        exps.push(print_str(piece));
+                    }
+                    if let Some(var) = vars.get(i) {
+                        exps.push(self.print(Box::new(Node::Var(var.clone())), &types[i]));
+                    }
+                }
+                exps.push(call("format_end", Vec::new()));
+                let exp = seq(exps);
+                vars.into_iter()
+                    .zip(args)
+                    .rev()
+                    .fold(exp, |exp, (name, value)| {
+                        Box::new(Node::Let {
+                            name,
+                            value: self.lower_print(value),
+                            exp,
+                        })
+                    })
+            }
+            node => Box::new(node.map_children(&mut |node| self.lower_print(node))),
+        }
+    }
+}
+
+/// the value of main is not printed if it is void
+pub fn lower_print(node: Box<Node>, main_t: &Type, options: &Options) -> Box<Node> {
+    let (defs, exp) = match *node {
+        Node::Program(defs, exp) => (defs, exp),
+        node => panic!("unexpected {:?}", node),
+    };
+    let mut cxt = Context {
+        structs: HashMap::new(),
+        data: HashMap::new(),
+        printers: Vec::new(),
+        defs: Vec::new(),
+        bignum: options.bignum,
+        release: options.release,
+    };
+    for def in &defs {
+        match def.as_ref() {
+            Node::DefStruct { name, fields } => {
+                let field_types = fields.iter().map(|(_, t)| t.clone()).collect();
+                cxt.structs.insert(name.clone(), field_types);
+            }
+            Node::DefType { name, variants } => {
+                cxt.data.insert(name.clone(), variants.clone());
+            }
+            _ => {}
+        }
+    }
+    let mut defs: Vec<_> = defs.into_iter().map(|def| cxt.lower_print(def)).collect();
+    let exp = cxt.lower_print(exp);
+    let exp = match main_t {
+        Type::Void | Type::Bottom => exp,
+        t => cxt.print(exp, t),
+    };
+    defs.append(&mut cxt.defs);
+    Box::new(Node::Program(defs, exp))
+}
@@ -0,0 +1,109 @@
+/// Pack the excess arguments of a call of a variadic function into a list,
+/// so the function takes the list as its last parameter
+use crate::ast::*;
+use std::collections::HashMap;
+
+struct Context {
+    /// number of the parameters before the rest parameter
+    funs: HashMap<String, usize>,
+    /// local variables shadowing the functions
+    bound: Vec<String>,
+    /// position of the expression being lowered
+    pos: Pos,
+}
+
+impl Context {
+    fn fixed_arity(&self, fun: &Node) -> Option<(String, usize)> {
+        let name = match fun {
+            Node::Located(_, fun) => fun.var()?,
+            fun => fun.var()?,
+        };
+        if self.bound.contains(name) {
+            return None;
+        }
+        self.funs.get(name).map(|arity| (name.clone(), *arity))
+    }
+
+    fn lower(&mut self, node: Box<Node>) -> Result<Box<Node>, String> {
+        use Node::*;
+
+        let node = match *node {
+            Located(pos, exp) => {
+                self.pos = pos;
+                Located(pos, self.lower(exp)?)
+            }
+            Apply(fun, args) if self.fixed_arity(&fun).is_some() => {
+                let (name, arity) = self.fixed_arity(&fun).unwrap();
+                if args.len() < arity {
+                    return Err(format!(
+                        "{}: {} expects at least {} arguments, but got {}",
+                        self.pos,
+                        name,
+                        arity,
+                        args.len()
+                    ));
+                }
+                let mut args = args
+                    .into_iter()
+                    .map(|arg| self.lower(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let rest = args
+                    .split_off(arity)
+                    .into_iter()
+                    .rev()
+                    .fold(Box::new(Null), |list, arg| Box::new(Cons(arg, list)));
+                args.push(rest);
+                Apply(fun, args)
+            }
+            node => {
+                let mut result = Ok(());
+                let node = node.map_scoped(&mut |node, names| {
+                    let len = self.bound.len();
+                    self.bound.extend_from_slice(names);
+                    let node = match self.lower(node) {
+                        Ok(node) => node,
+                        Err(msg) => {
+                            result = Err(msg);
+                            Box::new(NOP)
+                        }
+                    };
+                    self.bound.truncate(len);
+                    node
+                });
+                result?;
+                node
+            }
+        };
+        Ok(Box::new(node))
+    }
+}
+
+pub fn lower_variadic(node: Box<Node>) -> Result<Box<Node>, String> {
+    let (defs, exp) = match *node {
+        Node::Program(defs, exp) => (defs, exp),
+        node => panic!("unexpected {:?}", node),
+    };
+    let mut cxt = Context {
+        funs: HashMap::new(),
+        bound: Vec::new(),
+        pos: Pos::default(),
+    };
+    let defs: Vec<_> = defs
+        .into_iter()
+        .map(|def| match *def {
+            Node::Variadic(def) => {
+                if let Node::Def { name, params, .. } = def.as_ref() {
+                    cxt.funs.insert(name.clone(), params.len() - 1);
+                }
+                def
+            }
+            def => Box::new(def),
+        })
+        .collect();
+    let defs = defs
+        .into_iter()
+        .map(|def| cxt.lower(def))
+        .collect::<Result<_, _>>()?;
+    let exp = cxt.lower(exp)?;
+    Ok(Box::new(Node::Program(defs, exp)))
+}
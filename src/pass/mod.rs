@@ -1,23 +1,75 @@
 mod allocate_registers;
 mod build_interference;
+mod closure_conversion;
+mod compile_match;
+mod construct_ssa;
+mod convert_assignments;
+mod destruct_ssa;
+mod eliminate_common_subexps;
+mod explicate_control;
+mod expose_allocation;
 mod flattern;
-mod lower_conditionals;
+mod fold_constants;
+mod inline_constants;
+mod inline_functions;
+mod limit_functions;
+mod lower_casts;
+mod lower_print;
+mod lower_variadic;
+mod monomorphize;
+mod optimize_peephole;
 mod partial_eval;
 mod patch_inst;
 mod print_x86;
+mod propagate_constants;
+mod propagate_copies;
+mod remove_dead_code;
+mod remove_dead_stores;
+mod reveal_functions;
+mod schedule_inst;
 mod select_inst;
+mod shrink;
+mod tag_integers;
+mod thread_jumps;
 mod type_check;
 mod uncover_live;
 mod uniquify;
+mod unroll_loops;
 
 pub use allocate_registers::allocate_registers;
 pub use build_interference::build_interference;
+pub use closure_conversion::closure_conversion;
+pub use compile_match::compile_match;
+pub use construct_ssa::construct_ssa;
+pub use convert_assignments::convert_assignments;
+pub use destruct_ssa::destruct_ssa;
+pub use eliminate_common_subexps::eliminate_common_subexps;
+pub use explicate_control::explicate_control;
+pub use expose_allocation::expose_allocation;
 pub use flattern::flattern;
-pub use lower_conditionals::lower_conditionals;
+pub use fold_constants::fold_constants;
+pub use inline_constants::inline_constants;
+pub use inline_functions::inline_functions;
+pub use limit_functions::limit_functions;
+pub use lower_casts::lower_casts;
+pub use lower_print::lower_print;
+pub use lower_variadic::lower_variadic;
+pub use monomorphize::monomorphize;
+pub use optimize_peephole::optimize_peephole;
 pub use partial_eval::partial_eval;
 pub use patch_inst::patch_inst;
 pub use print_x86::print_x86;
+pub use propagate_constants::propagate_constants;
+pub use propagate_copies::propagate_copies;
+pub use remove_dead_code::remove_dead_code;
+pub use remove_dead_stores::remove_dead_stores;
+pub use reveal_functions::reveal_functions;
+pub use schedule_inst::schedule_inst;
 pub use select_inst::select_inst;
+pub use shrink::shrink;
+pub use tag_integers::tag_integers;
+pub use thread_jumps::thread_jumps;
 pub use type_check::type_check;
 pub use uncover_live::uncover_live;
 pub use uniquify::uniquify;
+pub use unroll_loops::unroll_loops;
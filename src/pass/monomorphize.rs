@@ -0,0 +1,158 @@
+/// Generate a function for each instance of a generic function,
+/// the type variables in its body are replaced by the types of the instance
+use crate::ast::*;
+use std::collections::HashMap;
+
+#[derive(Default)]
+struct Context {
+    /// type variables and definitions of generic functions
+    generics: HashMap<String, (Vec<String>, Box<Node>)>,
+    /// instances in the order they are called, each is generated once
+    instances: Vec<(String, Vec<Type>)>,
+}
+
+impl Context {
+    fn instance(&mut self, name: String, types: Vec<Type>) -> String {
+        let instance = (name, types);
+        let index = match self.instances.iter().position(|other| *other == instance) {
+            Some(index) => index,
+            None => {
+                self.instances.push(instance);
+                self.instances.len() - 1
+            }
+        };
+        format!("{}.{}", self.instances[index].0, index)
+    }
+
+    fn monomorphize(&mut self, node: Box<Node>, bindings: &HashMap<String, Type>) -> Box<Node> {
+        use Node::*;
+
+        let subst = |t: Type| t.subst(bindings);
+        let node = match *node {
+            Instance(name, types) => {
+                let types = types.into_iter().map(subst).collect();
+                Var(self.instance(name, types))
+            }
+            Def {
+                name,
+                params,
+                ret,
+                body,
+            } => Def {
+                name,
+                params: params
+                    .into_iter()
+                    .map(|(name, t)| (name, subst(t)))
+                    .collect(),
+                ret: subst(ret),
+                body: body
+                    .into_iter()
+                    .map(|exp| self.monomorphize(exp, bindings))
+                    .collect(),
+            },
+            Lambda {
+                params,
+                ret,
+                body,
+                free_vars,
+            } => Lambda {
+                params: params
+                    .into_iter()
+                    .map(|(name, t)| (name, subst(t)))
+                    .collect(),
+                ret: subst(ret),
+                body: self.monomorphize(body, bindings),
+                free_vars: free_vars
+                    .into_iter()
+                    .map(|(name, t)| (name, subst(t)))
+                    .collect(),
+            },
+            Cast { exp, from, to, pos } => Cast {
+                exp: self.monomorphize(exp, bindings),
+                from: subst(from),
+                to: subst(to),
+                pos,
+            },
+            HasType(exp, t) => HasType(self.monomorphize(exp, bindings), subst(t)),
+            Inject(exp, t) => Inject(self.monomorphize(exp, bindings), subst(t)),
+            Project(exp, t, pos) => Project(self.monomorphize(exp, bindings), subst(t), pos),
+            MakeArray(len, init, t) => MakeArray(
+                self.monomorphize(len, bindings),
+                self.monomorphize(init, bindings),
+                subst(t),
+            ),
+            MakeHashTable(key_t, value_t) => MakeHashTable(subst(key_t), subst(value_t)),
+            Print(exp, t) => Print(self.monomorphize(exp, bindings), subst(t)),
+            Error(msg, value, t) => Error(
+                self.monomorphize(msg, bindings),
+                value.map(|value| self.monomorphize(value, bindings)),
+                subst(t),
+            ),
+            Format(pieces, args, types) => Format(
+                pieces,
+                args.into_iter()
+                    .map(|arg| self.monomorphize(arg, bindings))
+                    .collect(),
+                types.into_iter().map(subst).collect(),
+            ),
+            Values(exps, types) => Values(
+                exps.into_iter()
+                    .map(|exp| self.monomorphize(exp, bindings))
+                    .collect(),
+                types.into_iter().map(subst).collect(),
+            ),
+            node => node.map_children(&mut |node| self.monomorphize(node, bindings)),
+        };
+        Box::new(node)
+    }
+}
+
+/// generic functions are removed, an instance may call other instances
+pub fn monomorphize(node: Box<Node>) -> Box<Node> {
+    let (defs, exp) = match *node {
+        Node::Program(defs, exp) => (defs, exp),
+        node => panic!("unexpected {:?}", node),
+    };
+    let mut cxt = Context::default();
+    let mut mono_defs = Vec::with_capacity(defs.len());
+    for def in defs {
+        if let Node::Def {
+            name, params, ret, ..
+        } = def.as_ref()
+        {
+            let param_types = params.iter().map(|(_, t)| t.clone()).collect();
+            let vars = Type::Function(param_types, Box::new(ret.clone())).type_vars();
+            if !vars.is_empty() {
+                cxt.generics.insert(name.clone(), (vars, def));
+                continue;
+            }
+        }
+        mono_defs.push(def);
+    }
+    let no_bindings = HashMap::new();
+    let mut defs: Vec<_> = mono_defs
+        .into_iter()
+        .map(|def| cxt.monomorphize(def, &no_bindings))
+        .collect();
+    let exp = cxt.monomorphize(exp, &no_bindings);
+    let mut index = 0;
+    while index < cxt.instances.len() {
+        let (name, types) = cxt.instances[index].clone();
+        let (vars, def) = cxt.generics[&name].clone();
+        let bindings = vars.into_iter().zip(types).collect();
+        let def = match *cxt.monomorphize(def, &bindings) {
+            Node::Def {
+                params, ret, body, ..
+            } => Node::Def {
+                name: format!("{}.{}", name, index),
+                params,
+                ret,
+                body,
+            },
+            def => panic!("unexpected {:?}", def),
+        };
+        defs.push(Box::new(def));
+        index += 1;
+    }
+    Box::new(Node::Program(defs, exp))
+}
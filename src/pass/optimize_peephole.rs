@@ -0,0 +1,78 @@
+/// Rewrite the short sequences of the final instructions into cheaper ones,
+/// an instruction setting different flags is only rewritten if the flags are not read
+/// x86 specific pass
+use crate::ast::*;
+
+/// check if the flags before the instructions are read by them,
+/// the flags are always set again after a label
+fn flags_read(node_list: &[Box<Node>]) -> bool {
+    use Node::*;
+
+    for node in node_list {
+        match node.as_ref() {
+            node if node.reads_flags() => return true,
+            // a shift by 0 keeps the flags
+            Label(_)
+            | JMP(_)
+            | ADDQ { .. }
+            | SUBQ { .. }
+            | NEGQ(_)
+            | IMULQ { .. }
+            | ANDQ { .. }
+            | ORQ { .. }
+            | XORQ { .. }
+            | CMPQ(..)
+            | IDIVQ(_)
+            | CALLQ(..)
+            | ICALLQ(..)
+            | TAILJMP(..)
+            | ITAILJMP(..) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+pub fn optimize_peephole(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    let mut new_node_list: Vec<Box<Node>> = Vec::with_capacity(node_list.len());
+
+    for (i, node) in node_list.iter().enumerate() {
+        let rest = &node_list[i + 1..];
+        match node.as_ref() {
+            MOVQ { target, source } if target == source => {}
+            MOVQ { target, source }
+                if target.is_register() && source.fixnum() == Some(0) && !flags_read(rest) =>
+            {
+                new_node_list.push(Box::new(XORQ {
+                    target: target.clone(),
+                    arg: target.clone(),
+                }));
+            }
+            ADDQ { target, arg } if arg.fixnum().is_some() && !flags_read(rest) => {
+                let mut num = arg.fixnum().unwrap();
+                // merge with the addition of an immediate to the same target before it
+                if let Some(ADDQ {
+                    target: last_target,
+                    arg: last_arg,
+                }) = new_node_list.last().map(|node| node.as_ref())
+                {
+                    let sum = last_arg.fixnum().and_then(|last| last.checked_add(num));
+                    if let Some(sum) = sum.filter(|&sum| last_target == target && is_imm32(sum)) {
+                        new_node_list.pop();
+                        num = sum;
+                    }
+                }
+                if num != 0 {
+                    new_node_list.push(Box::new(ADDQ {
+                        target: target.clone(),
+                        arg: Box::new(Fixnum(num)),
+                    }));
+                }
+            }
+            _ => new_node_list.push(node.clone()),
+        }
+    }
+    new_node_list
+}
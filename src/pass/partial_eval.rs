@@ -1,26 +1,391 @@
 use crate::ast::Node;
 
+/// split a sum into its constant and the rest, which is evaluated as before
+fn split_constant(node: &Node) -> Option<(isize, &Node)> {
+    match node {
+        Node::Add(lhs, rhs) => match (lhs.fixnum(), rhs.fixnum()) {
+            (Some(c), None) => Some((c, rhs)),
+            (None, Some(c)) => Some((c, lhs)),
+            _ => None,
+        },
+        Node::Sub(lhs, rhs) => Some((rhs.fixnum()?.checked_neg()?, lhs)),
+        _ => None,
+    }
+}
+
+/// the constants of the operands are added together, only constants of the same sign
+/// are added, so an overflow is trapped if and only if the original sums overflow
+fn add(lhs: Box<Node>, rhs: Box<Node>) -> Node {
+    use Node::*;
+
+    if let (Some(a), Some(b)) = (lhs.fixnum(), rhs.fixnum()) {
+        if let Some(num) = a.checked_add(b) {
+            return Fixnum(num);
+        }
+    }
+    let split = |node: &Node| match node.fixnum() {
+        Some(c) => (c, None),
+        None => match split_constant(node) {
+            Some((c, rest)) => (c, Some(rest.clone())),
+            None => (0, Some(node.clone())),
+        },
+    };
+    let ((a, lhs_rest), (b, rhs_rest)) = (split(&lhs), split(&rhs));
+    match a.checked_add(b) {
+        Some(num) if a.signum() * b.signum() == 1 => {
+            let rest = match (lhs_rest, rhs_rest) {
+                (Some(lhs), Some(rhs)) => Add(Box::new(lhs), Box::new(rhs)),
+                (Some(rest), None) | (None, Some(rest)) => rest,
+                (None, None) => unreachable!("constants are folded"),
+            };
+            Add(Box::new(Fixnum(num)), Box::new(rest))
+        }
+        _ => Add(lhs, rhs),
+    }
+}
+
+/// a count the shift of a fixnum takes as it is, the other counts are left to the
+/// runtime, which masks them or shifts a big integer
+fn shift_count(count: isize) -> Option<u32> {
+    (0..isize::BITS as isize)
+        .contains(&count)
+        .then_some(count as u32)
+}
+
 pub fn partial_eval(node: Box<Node>) -> Box<Node> {
+    use Node::*;
+
     let node = match *node {
-        Node::Program(sub_node) => Node::Program(partial_eval(sub_node)),
-        Node::Neg(sub_node) => {
+        Program(defs, exp) => Program(
+            defs.into_iter().map(partial_eval).collect(),
+            partial_eval(exp),
+        ),
+        Def {
+            name,
+            params,
+            ret,
+            body,
+        } => Def {
+            name,
+            params,
+            ret,
+            body: body.into_iter().map(partial_eval).collect(),
+        },
+        Apply(fun, args) => Apply(
+            partial_eval(fun),
+            args.into_iter().map(partial_eval).collect(),
+        ),
+        Lambda {
+            params,
+            ret,
+            body,
+            free_vars,
+        } => Lambda {
+            params,
+            ret,
+            body: partial_eval(body),
+            free_vars,
+        },
+        Neg(sub_node) => {
             let sub_node = partial_eval(sub_node);
-            if let Node::Fixnum(num) = *sub_node {
-                Node::Fixnum(-num)
-            } else {
-                Node::Neg(sub_node)
+            // overflowed arithmetic is left to the runtime
+            match sub_node.fixnum().and_then(isize::checked_neg) {
+                Some(num) => Fixnum(num),
+                // the negation is pushed into a sum, so its constant is folded
+                None => match split_constant(&sub_node) {
+                    Some((c, rest)) if c.checked_neg().is_some() => {
+                        Sub(Box::new(Fixnum(-c)), Box::new(rest.clone()))
+                    }
+                    _ => Neg(sub_node),
+                },
+            }
+        }
+        Add(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            add(lhs, partial_eval(rhs))
+        }
+        Sub(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) if a.checked_sub(b).is_some() => Fixnum(a - b),
+                // subtracting a constant adds its negation
+                (None, Some(b))
+                    if b.checked_neg().is_some()
+                        && split_constant(&lhs).is_some_and(|(a, _)| a.signum() == -b.signum()) =>
+                {
+                    add(lhs, Box::new(Fixnum(-b)))
+                }
+                _ => Sub(lhs, rhs),
+            }
+        }
+        Mul(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) if a.checked_mul(b).is_some() => Fixnum(a * b),
+                _ => Mul(lhs, rhs),
             }
         }
-        Node::Add(lhs, rhs) => {
+        Quotient(lhs, rhs) => {
             let lhs = partial_eval(lhs);
             let rhs = partial_eval(rhs);
-            if lhs.fixnum().is_some() && rhs.fixnum().is_some() {
-                Node::Fixnum(lhs.fixnum().unwrap() + rhs.fixnum().unwrap())
-            } else {
-                Node::Add(lhs, rhs)
+            // leave division by zero and the overflowed quotient to the runtime
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) => match a.checked_div(b) {
+                    Some(num) => Fixnum(num),
+                    None => Quotient(lhs, rhs),
+                },
+                _ => Quotient(lhs, rhs),
+            }
+        }
+        Remainder(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) => match a.checked_rem(b) {
+                    Some(num) => Fixnum(num),
+                    None => Remainder(lhs, rhs),
+                },
+                _ => Remainder(lhs, rhs),
+            }
+        }
+        BitAnd(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) => Fixnum(a & b),
+                _ => BitAnd(lhs, rhs),
+            }
+        }
+        BitOr(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) => Fixnum(a | b),
+                _ => BitOr(lhs, rhs),
+            }
+        }
+        BitXor(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) => Fixnum(a ^ b),
+                _ => BitXor(lhs, rhs),
+            }
+        }
+        Shl(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) => {
+                    match shift_count(b).and_then(|b| Some(a << b).filter(|num| num >> b == a)) {
+                        Some(num) => Fixnum(num),
+                        None => Shl(lhs, rhs),
+                    }
+                }
+                _ => Shl(lhs, rhs),
+            }
+        }
+        Shr(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) if a >= 0 => match shift_count(b) {
+                    Some(b) => Fixnum(a >> b),
+                    None => Shr(lhs, rhs),
+                },
+                _ => Shr(lhs, rhs),
+            }
+        }
+        Sar(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) => match shift_count(b) {
+                    Some(b) => Fixnum(a >> b),
+                    None => Sar(lhs, rhs),
+                },
+                _ => Sar(lhs, rhs),
+            }
+        }
+        Min(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) => Fixnum(a.min(b)),
+                _ => Min(lhs, rhs),
+            }
+        }
+        Max(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.fixnum(), rhs.fixnum()) {
+                (Some(a), Some(b)) => Fixnum(a.max(b)),
+                _ => Max(lhs, rhs),
+            }
+        }
+        // floats are folded by the same IEEE 754 arithmetic as SSE
+        FAdd(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.flonum(), rhs.flonum()) {
+                (Some(a), Some(b)) => Flonum((a + b).to_bits()),
+                _ => FAdd(lhs, rhs),
+            }
+        }
+        FSub(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.flonum(), rhs.flonum()) {
+                (Some(a), Some(b)) => Flonum((a - b).to_bits()),
+                _ => FSub(lhs, rhs),
+            }
+        }
+        FMul(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.flonum(), rhs.flonum()) {
+                (Some(a), Some(b)) => Flonum((a * b).to_bits()),
+                _ => FMul(lhs, rhs),
+            }
+        }
+        FDiv(lhs, rhs) => {
+            let lhs = partial_eval(lhs);
+            let rhs = partial_eval(rhs);
+            match (lhs.flonum(), rhs.flonum()) {
+                (Some(a), Some(b)) => Flonum((a / b).to_bits()),
+                _ => FDiv(lhs, rhs),
+            }
+        }
+        CharToInteger(exp) => CharToInteger(partial_eval(exp)),
+        IntegerToChar(exp) => IntegerToChar(partial_eval(exp)),
+        FixnumToFlonum(exp) => {
+            let exp = partial_eval(exp);
+            match exp.fixnum() {
+                Some(num) => Flonum((num as f64).to_bits()),
+                None => FixnumToFlonum(exp),
+            }
+        }
+        FlonumToFixnum(exp) => {
+            let exp = partial_eval(exp);
+            match exp.flonum() {
+                Some(num) => Fixnum(num as isize),
+                None => FlonumToFixnum(exp),
+            }
+        }
+        BitNot(sub_node) => {
+            let sub_node = partial_eval(sub_node);
+            match sub_node.fixnum() {
+                Some(num) => Fixnum(!num),
+                None => BitNot(sub_node),
+            }
+        }
+        Not(sub_node) => {
+            let sub_node = partial_eval(sub_node);
+            match sub_node.boolean() {
+                Some(true) => False,
+                Some(false) => True,
+                None => Not(sub_node),
+            }
+        }
+        Let { name, value, exp } => Let {
+            name,
+            value: partial_eval(value),
+            exp: partial_eval(exp),
+        },
+        SetBang { name, value } => SetBang {
+            name,
+            value: partial_eval(value),
+        },
+        DefGlobal { name, value } => DefGlobal {
+            name,
+            value: partial_eval(value),
+        },
+        Begin(effects, exp) => {
+            // literals have no effect
+            let effects: Vec<_> = effects
+                .into_iter()
+                .map(partial_eval)
+                .filter(|effect| !effect.is_literal() && **effect != Void)
+                .collect();
+            let exp = partial_eval(exp);
+            if effects.is_empty() {
+                return exp;
+            }
+            Begin(effects, exp)
+        }
+        If {
+            cond,
+            mut if_exps,
+            mut else_exps,
+        } => {
+            assert_eq!(if_exps.len(), 1);
+            assert_eq!(else_exps.len(), 1);
+            let cond = partial_eval(cond);
+            let if_exp = partial_eval(if_exps.remove(0));
+            let else_exp = partial_eval(else_exps.remove(0));
+            // choose the branch if cond is known
+            match cond.boolean() {
+                Some(true) => return if_exp,
+                Some(false) => return else_exp,
+                None => If {
+                    cond,
+                    if_exps: vec![if_exp],
+                    else_exps: vec![else_exp],
+                },
+            }
+        }
+        Vector(elems) => Vector(elems.into_iter().map(partial_eval).collect()),
+        Literal(vec) => Literal(partial_eval(vec)),
+        VectorLength(vec) => VectorLength(partial_eval(vec)),
+        MakeArray(len, init, t) => MakeArray(partial_eval(len), partial_eval(init), t),
+        ArrayLength(array) => ArrayLength(partial_eval(array)),
+        Random(bound) => Random(partial_eval(bound)),
+        Exit(code) => Exit(partial_eval(code)),
+        ArrayRef(array, index) => ArrayRef(partial_eval(array), partial_eval(index)),
+        ArraySet(array, index, value) => ArraySet(
+            partial_eval(array),
+            partial_eval(index),
+            partial_eval(value),
+        ),
+        VectorRef(vec, index) => VectorRef(partial_eval(vec), partial_eval(index)),
+        VectorSet(vec, index, value) => {
+            VectorSet(partial_eval(vec), partial_eval(index), partial_eval(value))
+        }
+        StringLength(s) => {
+            let s = partial_eval(s);
+            match *s {
+                Str(s) => Fixnum(s.len() as isize),
+                s => StringLength(Box::new(s)),
+            }
+        }
+        StringRef(s, index) => StringRef(partial_eval(s), partial_eval(index)),
+        HasType(exp, t) => HasType(partial_eval(exp), t),
+        Inject(exp, t) => Inject(partial_eval(exp), t),
+        Project(exp, t, pos) => Project(partial_eval(exp), t, pos),
+        While {
+            cond_exps,
+            cond,
+            body_exps,
+        } => {
+            let cond = partial_eval(cond);
+            // the body is never run
+            if cond.boolean() == Some(false) {
+                return Box::new(Void);
+            }
+            While {
+                cond_exps,
+                cond,
+                body_exps: body_exps.into_iter().map(partial_eval).collect(),
             }
         }
-        _ => *node,
+        Eq(lhs, rhs) => Eq(partial_eval(lhs), partial_eval(rhs)),
+        Lt(lhs, rhs) => Lt(partial_eval(lhs), partial_eval(rhs)),
+        Lte(lhs, rhs) => Lte(partial_eval(lhs), partial_eval(rhs)),
+        Gt(lhs, rhs) => Gt(partial_eval(lhs), partial_eval(rhs)),
+        Gte(lhs, rhs) => Gte(partial_eval(lhs), partial_eval(rhs)),
+        node => node,
     };
     Box::new(node)
 }
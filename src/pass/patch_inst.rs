@@ -3,10 +3,33 @@
 use crate::ast::*;
 
 fn is_patchable(t: &Node) -> bool {
-    match t {
-        Node::Var(_) | Node::StackLoc(_) | Node::Fixnum(_) => true,
-        _ => false,
-    }
+    matches!(
+        t,
+        Node::Var(_) | Node::StackLoc(_) | Node::Fixnum(_) | Node::Deref(..) | Node::Global(_)
+    )
+}
+
+/// a fixnum which can only be moved to a register
+fn is_large_fixnum(t: &Node) -> bool {
+    t.fixnum().is_some_and(|n| !is_imm32(n))
+}
+
+/// SSE arithmetic through XMM15 if the target is not a xmm register
+fn patch_sse(
+    target: Box<Node>,
+    inst: impl FnOnce(Box<Node>) -> Node,
+    new_node_list: &mut Vec<Box<Node>>,
+) {
+    let reg = Box::new(Node::XMM(15));
+    new_node_list.push(Box::new(Node::MOVQ {
+        target: reg.clone(),
+        source: target.clone(),
+    }));
+    new_node_list.push(Box::new(inst(reg.clone())));
+    new_node_list.push(Box::new(Node::MOVQ {
+        target,
+        source: reg,
+    }));
 }
 
 pub fn patch_inst(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
@@ -20,6 +43,18 @@ pub fn patch_inst(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
                 // skip unnecessary move
                 continue;
             }
+            MOVQ { target, source } if target.is_xmm() && source.fixnum().is_some() => {
+                // an immediate is moved to a xmm register through RAX
+                let reg = Box::new(RAX);
+                new_node_list.push(Box::new(MOVQ {
+                    target: reg.clone(),
+                    source,
+                }));
+                new_node_list.push(Box::new(MOVQ {
+                    target,
+                    source: reg,
+                }));
+            }
             MOVQ { target, source } if is_patchable(&target) && is_patchable(&source) => {
                 // patch instruction if the two sides are both StackLoc
                 let reg = Box::new(RAX);
@@ -36,7 +71,9 @@ pub fn patch_inst(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
                 let patched_inst = Box::new(node);
                 new_node_list.push(patched_inst);
             }
-            ADDQ { target, arg } if is_patchable(&target) && is_patchable(&arg) => {
+            ADDQ { target, arg }
+                if (is_patchable(&target) && is_patchable(&arg)) || is_large_fixnum(&arg) =>
+            {
                 // patch instruction if the two sides are both StackLoc
                 let reg = Box::new(RAX);
                 let move_to_reg = Box::new(MOVQ {
@@ -56,6 +93,202 @@ pub fn patch_inst(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
                 });
                 new_node_list.push(move_back);
             }
+            SUBQ { target, arg }
+                if (is_patchable(&target) && is_patchable(&arg)) || is_large_fixnum(&arg) =>
+            {
+                // patch instruction if the two sides are both StackLoc
+                let reg = Box::new(RAX);
+                new_node_list.push(Box::new(MOVQ {
+                    target: reg.clone(),
+                    source: arg,
+                }));
+                new_node_list.push(Box::new(SUBQ { target, arg: reg }));
+            }
+            IMULQ { target, arg } if is_large_fixnum(&arg) && !is_patchable(&target) => {
+                // a large immediate is multiplied through R11, RAX may be the target
+                let reg = Box::new(R11);
+                new_node_list.push(Box::new(MOVQ {
+                    target: reg.clone(),
+                    source: arg,
+                }));
+                new_node_list.push(Box::new(IMULQ { target, arg: reg }));
+            }
+            IMULQ { target, arg } if is_patchable(&target) => {
+                // target of IMULQ must be a register
+                let arg = if is_large_fixnum(&arg) {
+                    let reg = Box::new(R11);
+                    new_node_list.push(Box::new(MOVQ {
+                        target: reg.clone(),
+                        source: arg,
+                    }));
+                    reg
+                } else {
+                    arg
+                };
+                let reg = Box::new(RAX);
+                new_node_list.push(Box::new(MOVQ {
+                    target: reg.clone(),
+                    source: target.clone(),
+                }));
+                new_node_list.push(Box::new(IMULQ {
+                    target: reg.clone(),
+                    arg,
+                }));
+                new_node_list.push(Box::new(MOVQ {
+                    target,
+                    source: reg,
+                }));
+            }
+            ANDQ { target, arg }
+                if (is_patchable(&target) && is_patchable(&arg)) || is_large_fixnum(&arg) =>
+            {
+                // patch instruction if the two sides are both StackLoc
+                let reg = Box::new(RAX);
+                new_node_list.push(Box::new(MOVQ {
+                    target: reg.clone(),
+                    source: arg,
+                }));
+                new_node_list.push(Box::new(ANDQ { target, arg: reg }));
+            }
+            ORQ { target, arg }
+                if (is_patchable(&target) && is_patchable(&arg)) || is_large_fixnum(&arg) =>
+            {
+                // patch instruction if the two sides are both StackLoc
+                let reg = Box::new(RAX);
+                new_node_list.push(Box::new(MOVQ {
+                    target: reg.clone(),
+                    source: arg,
+                }));
+                new_node_list.push(Box::new(ORQ { target, arg: reg }));
+            }
+            XORQ { target, arg }
+                if (is_patchable(&target) && is_patchable(&arg)) || is_large_fixnum(&arg) =>
+            {
+                // patch instruction if the two sides are both StackLoc
+                let reg = Box::new(RAX);
+                new_node_list.push(Box::new(MOVQ {
+                    target: reg.clone(),
+                    source: arg,
+                }));
+                new_node_list.push(Box::new(XORQ { target, arg: reg }));
+            }
+            MOVZBQ { target, source } if is_patchable(&target) => {
+                // target of MOVZBQ must be a register
+                let reg = Box::new(RAX);
+                new_node_list.push(Box::new(MOVZBQ {
+                    target: reg.clone(),
+                    source,
+                }));
+                new_node_list.push(Box::new(MOVQ {
+                    target,
+                    source: reg,
+                }));
+            }
+            LEAQ { target, source } if is_patchable(&target) => {
+                // target of LEAQ must be a register
+                let reg = Box::new(RAX);
+                new_node_list.push(Box::new(LEAQ {
+                    target: reg.clone(),
+                    source,
+                }));
+                new_node_list.push(Box::new(MOVQ {
+                    target,
+                    source: reg,
+                }));
+            }
+            IDIVQ(arg) if arg.fixnum().is_some() => {
+                // IDIVQ can't take an immediate, RAX is occupied by the dividend so use R11
+                let reg = Box::new(R11);
+                new_node_list.push(Box::new(MOVQ {
+                    target: reg.clone(),
+                    source: arg,
+                }));
+                new_node_list.push(Box::new(IDIVQ(reg)));
+            }
+            ADDSD { target, arg } if !target.is_xmm() => {
+                patch_sse(target, |target| ADDSD { target, arg }, &mut new_node_list)
+            }
+            SUBSD { target, arg } if !target.is_xmm() => {
+                patch_sse(target, |target| SUBSD { target, arg }, &mut new_node_list)
+            }
+            MULSD { target, arg } if !target.is_xmm() => {
+                patch_sse(target, |target| MULSD { target, arg }, &mut new_node_list)
+            }
+            DIVSD { target, arg } if !target.is_xmm() => {
+                patch_sse(target, |target| DIVSD { target, arg }, &mut new_node_list)
+            }
+            CVTSI2SDQ { target, source } if !target.is_xmm() || source.fixnum().is_some() => {
+                // source of CVTSI2SDQ can not be an immediate, which is moved to RAX
+                let source = if source.fixnum().is_some() {
+                    let reg = Box::new(RAX);
+                    new_node_list.push(Box::new(MOVQ {
+                        target: reg.clone(),
+                        source,
+                    }));
+                    reg
+                } else {
+                    source
+                };
+                if target.is_xmm() {
+                    new_node_list.push(Box::new(CVTSI2SDQ { target, source }));
+                } else {
+                    // target of CVTSI2SDQ must be a xmm register
+                    let reg = Box::new(XMM(15));
+                    new_node_list.push(Box::new(CVTSI2SDQ {
+                        target: reg.clone(),
+                        source,
+                    }));
+                    new_node_list.push(Box::new(MOVQ {
+                        target,
+                        source: reg,
+                    }));
+                }
+            }
+            CVTTSD2SIQ { target, source } if is_patchable(&target) || source.fixnum().is_some() => {
+                // the bits of an immediate float are moved to XMM15 through RAX
+                let source = if source.fixnum().is_some() {
+                    let reg = Box::new(XMM(15));
+                    new_node_list.push(Box::new(MOVQ {
+                        target: Box::new(RAX),
+                        source,
+                    }));
+                    new_node_list.push(Box::new(MOVQ {
+                        target: reg.clone(),
+                        source: Box::new(RAX),
+                    }));
+                    reg
+                } else {
+                    source
+                };
+                if is_patchable(&target) {
+                    // target of CVTTSD2SIQ must be a general register
+                    let reg = Box::new(RAX);
+                    new_node_list.push(Box::new(CVTTSD2SIQ {
+                        target: reg.clone(),
+                        source,
+                    }));
+                    new_node_list.push(Box::new(MOVQ {
+                        target,
+                        source: reg,
+                    }));
+                } else {
+                    new_node_list.push(Box::new(CVTTSD2SIQ { target, source }));
+                }
+            }
+            CMPQ(lhs, rhs) if lhs.is_xmm() || rhs.is_xmm() || is_large_fixnum(&lhs) => {
+                // floats are compared by their bits in general registers
+                let lhs_reg = Box::new(R11);
+                new_node_list.push(Box::new(MOVQ {
+                    target: lhs_reg.clone(),
+                    source: lhs,
+                }));
+                let rhs_reg = Box::new(RAX);
+                new_node_list.push(Box::new(MOVQ {
+                    target: rhs_reg.clone(),
+                    source: rhs,
+                }));
+                new_node_list.push(Box::new(CMPQ(lhs_reg, rhs_reg)));
+            }
             CMPQ(lhs, rhs) if is_patchable(&rhs) => {
                 // patch instruction if the two sides are both StackLoc
                 let reg = Box::new(RAX);
@@ -75,3 +308,60 @@ pub fn patch_inst(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
     }
     new_node_list
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(offset: isize) -> Box<Node> {
+        Box::new(Node::StackLoc(offset))
+    }
+
+    fn mov(target: Box<Node>, source: Box<Node>) -> Box<Node> {
+        Box::new(Node::MOVQ { target, source })
+    }
+
+    #[test]
+    fn memory_to_memory_move_goes_through_rax() {
+        let patched = patch_inst(vec![mov(stack(-8), stack(-16))]);
+        assert_eq!(
+            patched,
+            vec![
+                mov(Box::new(Node::RAX), stack(-16)),
+                mov(stack(-8), Box::new(Node::RAX)),
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_to_memory_add_goes_through_rax() {
+        let patched = patch_inst(vec![Box::new(Node::ADDQ {
+            target: stack(-8),
+            arg: stack(-16),
+        })]);
+        assert_eq!(
+            patched,
+            vec![
+                mov(Box::new(Node::RAX), stack(-16)),
+                Box::new(Node::ADDQ {
+                    target: Box::new(Node::RAX),
+                    arg: stack(-8),
+                }),
+                mov(stack(-8), Box::new(Node::RAX)),
+            ]
+        );
+    }
+
+    #[test]
+    fn move_to_itself_is_dropped() {
+        let node_list = vec![
+            mov(Box::new(Node::RCX), Box::new(Node::RCX)),
+            mov(stack(-8), stack(-8)),
+            mov(Box::new(Node::RCX), stack(-8)),
+        ];
+        assert_eq!(
+            patch_inst(node_list),
+            vec![mov(Box::new(Node::RCX), stack(-8))]
+        );
+    }
+}
@@ -2,24 +2,148 @@ use crate::ast::*;
 use std::io::{Result, Write};
 
 const WORD: usize = 8;
+/// bytes of the heap
+const HEAP_SIZE: usize = 1 << 20;
 
-pub fn print_x86(f: &mut impl Write, node_list: Vec<Box<Node>>, info: Info) -> Result<()> {
+pub fn print_x86(f: &mut impl Write, functions: Vec<Function>) -> Result<()> {
+    let mut strings = Vec::new();
+    let mut symbols = Vec::new();
+    let mut vectors = Vec::new();
+    let globals: Vec<_> = functions
+        .iter()
+        .flat_map(|function| function.info.globals.clone())
+        .collect();
+    writeln!(f, ".global main")?;
+    for function in functions {
+        print_function(f, function, &mut strings, &mut symbols, &mut vectors)?;
+    }
+
+    // runtime errors do not return
+    writeln!(f, "{}:", VECTOR_INDEX_ERROR)?;
+    writeln!(f, "CALLQ vector_index_out_of_range")?;
+    writeln!(f, "{}:", STRING_INDEX_ERROR)?;
+    writeln!(f, "CALLQ string_index_out_of_range")?;
+    writeln!(f, "{}:", OVERFLOW_ERROR)?;
+    writeln!(f, "CALLQ overflow_failed")?;
+    writeln!(f, "{}:", DIVIDE_ERROR)?;
+    writeln!(f, "CALLQ division_by_zero")?;
+    writeln!(f, "{}:", CHAR_ERROR)?;
+    writeln!(f, "CALLQ integer_not_char")?;
+    writeln!(f, "{}:", ANY_PROJECT_ERROR)?;
+    writeln!(f, "MOVQ %rax, %rdi")?;
+    writeln!(f, "MOVQ %r11, %rsi")?;
+    writeln!(f, "CALLQ any_project_failed")?;
+
+    // globals are mutable words
+    writeln!(f, ".data")?;
+    for (label, value) in globals {
+        writeln!(f, ".balign {}", WORD)?;
+        writeln!(f, "{}:", label)?;
+        writeln!(f, ".quad {}", value)?;
+    }
+    // a vector literal is the header followed by the elements, it may be mutated
+    for (i, words) in vectors.iter().enumerate() {
+        writeln!(f, ".balign {}", WORD)?;
+        writeln!(f, "{}:", vector_label(i))?;
+        for word in words {
+            writeln!(f, ".quad {}", word)?;
+        }
+    }
+
+    // a string is the length followed by the bytes
+    writeln!(f, ".section .rodata")?;
+    for (i, s) in strings.iter().enumerate() {
+        print_string(f, &string_label(i), s)?;
+    }
+    // the symbol table, each name is emitted once
+    for (i, name) in symbols.iter().enumerate() {
+        print_string(f, &symbol_label(i), name)?;
+    }
+    Ok(())
+}
+
+fn print_string(f: &mut impl Write, label: &str, s: &str) -> Result<()> {
+    writeln!(f, ".balign {}", WORD)?;
+    writeln!(f, "{}:", label)?;
+    writeln!(f, ".quad {}", s.len())?;
+    writeln!(f, ".ascii \"{}\"", escape(s))
+}
+
+fn string_label(i: usize) -> String {
+    format!("string_{}", i)
+}
+
+fn symbol_label(i: usize) -> String {
+    format!("symbol_{}", i)
+}
+
+fn vector_label(i: usize) -> String {
+    format!("vector_{}", i)
+}
+
+/// index of the string in the table, it is added if not found
+fn intern(table: &mut Vec<String>, s: String) -> usize {
+    match table.iter().position(|other| *other == s) {
+        Some(i) => i,
+        None => {
+            table.push(s);
+            table.len() - 1
+        }
+    }
+}
+
+/// escape a string for .ascii, bytes out of printable ASCII are written in octal
+fn escape(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'"' | b'\\' => format!("\\{}", b as char),
+            b' '..=b'~' => (b as char).to_string(),
+            b => format!("\\{:03o}", b),
+        })
+        .collect()
+}
+
+/// main initializes the runtime with argc and argv and exits with 0,
+/// other functions return the value in RAX,
+/// string literals and symbols are collected to be emitted once, so are vector literals
+fn print_function(
+    f: &mut impl Write,
+    function: Function,
+    strings: &mut Vec<String>,
+    symbols: &mut Vec<String>,
+    vectors: &mut Vec<Vec<isize>>,
+) -> Result<()> {
     use Node::*;
 
-    writeln!(f, ".global main")?;
-    writeln!(f, "main:")?;
+    let Function { name, body, info } = function;
+    let is_main = name == "main";
+    writeln!(f, "{}:", name)?;
     writeln!(f, "PUSHQ %rbp")?;
     writeln!(f, "MOVQ %rsp, %rbp")?;
+    for reg in &info.used_callee_saved {
+        writeln!(f, "PUSHQ {}", parse_val(Box::new(reg.clone())))?;
+    }
+    // keep RSP aligned to 16 bytes
     let mut aligned_stack_vars_count = info.stack_vars_count;
-    if info.stack_vars_count % 2 != 0 {
+    if !(info.stack_vars_count + info.used_callee_saved.len()).is_multiple_of(2) {
         aligned_stack_vars_count += 1;
     }
     if aligned_stack_vars_count > 0 {
         writeln!(f, "SUBQ ${}, %rsp", aligned_stack_vars_count * WORD)?;
     }
+    if is_main {
+        writeln!(f, "MOVQ %rsi, %rdx")?;
+        writeln!(f, "MOVQ %rdi, %rsi")?;
+        writeln!(f, "MOVQ ${}, %rdi", HEAP_SIZE)?;
+        writeln!(f, "CALLQ initialize")?;
+    }
 
-    for node in node_list {
+    for node in body {
         match *node {
+            // MOVQ also moves between general and xmm registers
+            MOVQ { target, source } if is_sse_move(&target, &source) => {
+                writeln!(f, "MOVSD {}, {}", parse_val(source), parse_val(target))?;
+            }
             MOVQ { target, source } => {
                 writeln!(f, "MOVQ {}, {}", parse_val(source), parse_val(target))?;
             }
@@ -29,23 +153,117 @@ pub fn print_x86(f: &mut impl Write, node_list: Vec<Box<Node>>, info: Info) -> R
             ADDQ { target, arg } => {
                 writeln!(f, "ADDQ {}, {}", parse_val(arg), parse_val(target))?;
             }
-            CALLQ(symbol) => {
+            SUBQ { target, arg } => {
+                writeln!(f, "SUBQ {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            IMULQ { target, arg } => {
+                writeln!(f, "IMULQ {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            NEGQ(target) => {
+                writeln!(f, "NEGQ {}", parse_val(target))?;
+            }
+            ANDQ { target, arg } => {
+                writeln!(f, "ANDQ {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            ORQ { target, arg } => {
+                writeln!(f, "ORQ {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            SALQ { target, arg } => {
+                writeln!(f, "SALQ {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            SARQ { target, arg } => {
+                writeln!(f, "SARQ {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            SHRQ { target, arg } => {
+                writeln!(f, "SHRQ {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            NOTQ(target) => {
+                writeln!(f, "NOTQ {}", parse_val(target))?;
+            }
+            XORQ { target, arg } => {
+                writeln!(f, "XORQ {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            ADDSD { target, arg } => {
+                writeln!(f, "ADDSD {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            SUBSD { target, arg } => {
+                writeln!(f, "SUBSD {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            MULSD { target, arg } => {
+                writeln!(f, "MULSD {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            DIVSD { target, arg } => {
+                writeln!(f, "DIVSD {}, {}", parse_val(arg), parse_val(target))?;
+            }
+            CVTSI2SDQ { target, source } => {
+                writeln!(f, "CVTSI2SDQ {}, {}", parse_val(source), parse_val(target))?;
+            }
+            CVTTSD2SIQ { target, source } => {
+                writeln!(f, "CVTTSD2SIQ {}, {}", parse_val(source), parse_val(target))?;
+            }
+            CALLQ(symbol, _) => {
                 writeln!(f, "CALLQ {}", symbol)?;
             }
+            ICALLQ(fun, _) => {
+                writeln!(f, "CALLQ *{}", parse_val(fun))?;
+            }
+            LEAQ { target, source } => {
+                let source = match *source {
+                    Str(s) => format!("{}(%rip)", string_label(intern(strings, s))),
+                    Symbol(name) => format!("{}(%rip)", symbol_label(intern(symbols, name))),
+                    StaticVector(elems, t) => {
+                        let header = vector_tag(elems.len(), &t);
+                        let elems = elems.iter().map(|elem| elem.fixnum().unwrap());
+                        vectors.push(std::iter::once(header).chain(elems).collect());
+                        format!("{}(%rip)", vector_label(vectors.len() - 1))
+                    }
+                    source => parse_val(Box::new(source)),
+                };
+                writeln!(f, "LEAQ {}, {}", source, parse_val(target))?;
+            }
+            CQTO => {
+                writeln!(f, "CQTO")?;
+            }
+            IDIVQ(arg) => {
+                writeln!(f, "IDIVQ {}", parse_val(arg))?;
+            }
             CMPQ(lhs, rhs) => {
                 writeln!(f, "CMPQ {}, {}", parse_val(lhs), parse_val(rhs))?;
             }
             SET(cond, reg) => {
-                assert_eq!(cond, CondCode::E, "unexpected condition code");
-                writeln!(f, "SETE {}", parse_val(reg))?;
+                writeln!(f, "SET{} {}", cond_suffix(cond), parse_val(reg))?;
+            }
+            CMOV {
+                cond,
+                source,
+                target,
+            } => {
+                let suffix = cond_suffix(cond);
+                writeln!(
+                    f,
+                    "CMOV{} {}, {}",
+                    suffix,
+                    parse_val(source),
+                    parse_val(target)
+                )?;
             }
             JMPIF(cond, label) => {
-                assert_eq!(cond, CondCode::E, "unexpected condition code");
-                writeln!(f, "JE {}", label)?;
+                writeln!(f, "J{} {}", cond_suffix(cond), label)?;
             }
             JMP(label) => {
                 writeln!(f, "JMP {}", label)?;
             }
+            TAILJMP(label, _) => {
+                // the callee returns to our caller
+                print_epilogue(f, aligned_stack_vars_count, &info.used_callee_saved)?;
+                writeln!(f, "JMP {}", label)?;
+            }
+            ITAILJMP(fun, _) => {
+                // RAX is not restored by the epilogue
+                writeln!(f, "MOVQ {}, %rax", parse_val(fun))?;
+                print_epilogue(f, aligned_stack_vars_count, &info.used_callee_saved)?;
+                writeln!(f, "JMP *%rax")?;
+            }
             Label(label) => {
                 writeln!(f, "{}:", label)?;
             }
@@ -55,26 +273,68 @@ pub fn print_x86(f: &mut impl Write, node_list: Vec<Box<Node>>, info: Info) -> R
         }
     }
 
-    // print out return value
-    writeln!(f, "MOVQ %rax, %rdi")?;
-    writeln!(f, "CALLQ print_int")?;
-    // resume the stack and return 0
-    writeln!(f, "ADDQ ${}, %rsp", aligned_stack_vars_count * WORD)?;
-    writeln!(f, "MOVQ $0, %rax")?;
-    writeln!(f, "POPQ %rbp")?;
+    if is_main {
+        writeln!(f, "XORQ %rax, %rax")?;
+    }
+    print_epilogue(f, aligned_stack_vars_count, &info.used_callee_saved)?;
     writeln!(f, "retq")?;
     Ok(())
 }
 
+/// resume the stack and the registers of the caller
+fn print_epilogue(
+    f: &mut impl Write,
+    aligned_stack_vars_count: usize,
+    used_callee_saved: &[Node],
+) -> Result<()> {
+    if aligned_stack_vars_count > 0 {
+        writeln!(f, "ADDQ ${}, %rsp", aligned_stack_vars_count * WORD)?;
+    }
+    for reg in used_callee_saved.iter().rev() {
+        writeln!(f, "POPQ {}", parse_val(Box::new(reg.clone())))?;
+    }
+    writeln!(f, "POPQ %rbp")?;
+    Ok(())
+}
+
+/// a move of a float between xmm registers and memory
+fn is_sse_move(target: &Node, source: &Node) -> bool {
+    let is_memory = |node: &Node| matches!(node, Node::StackLoc(_) | Node::Deref(..));
+    (target.is_xmm() && (source.is_xmm() || is_memory(source)))
+        || (source.is_xmm() && is_memory(target))
+}
+
+fn cond_suffix(cond: CondCode) -> &'static str {
+    use CondCode::*;
+
+    match cond {
+        E => "E",
+        L => "L",
+        Le => "LE",
+        G => "G",
+        Ge => "GE",
+        Ne => "NE",
+        O => "O",
+        Be => "BE",
+    }
+}
+
 fn parse_val(node: Box<Node>) -> String {
     use Node::*;
 
     match *node {
         Fixnum(n) => format!("${}", n),
         StackLoc(offset) => format!("{}(%rbp)", offset),
-        RAX => "%rax".to_string(),
-        RBX => "%rbx".to_string(),
+        Deref(reg, offset) => format!("{}({})", offset, parse_val(reg)),
+        Indexed(base, index, scale) => {
+            format!("({}, {}, {})", parse_val(base), parse_val(index), scale)
+        }
+        Global(name) => format!("{}(%rip)", name),
+        FunRef(label) => format!("{}(%rip)", label),
         AL => "%al".to_string(),
+        CL => "%cl".to_string(),
+        XMM(n) => format!("%xmm{}", n),
+        reg if reg.is_register() => format!("%{}", format!("{:?}", reg).to_lowercase()),
         value => {
             panic!("failed to parse node {:?}", value);
         }
@@ -0,0 +1,166 @@
+/// Replace the uses of variables assigned once with a literal by the literal,
+/// the operations of literals are evaluated and the branches of known conditions are taken
+use super::partial_eval;
+use crate::ast::*;
+use std::collections::HashMap;
+
+/// a known comparison of literals
+fn compare(cmp: &Node) -> Option<bool> {
+    use Node::*;
+
+    let (lhs, rhs) = match cmp {
+        Eq(lhs, rhs) => {
+            if let (Some(a), Some(b)) = (lhs.boolean(), rhs.boolean()) {
+                return Some(a == b);
+            }
+            (lhs.fixnum()?, rhs.fixnum()?)
+        }
+        Lt(lhs, rhs) | Lte(lhs, rhs) | Gt(lhs, rhs) | Gte(lhs, rhs) => {
+            (lhs.fixnum()?, rhs.fixnum()?)
+        }
+        _ => return None,
+    };
+    let result = match cmp {
+        Eq(..) => lhs == rhs,
+        Lt(..) => lhs < rhs,
+        Lte(..) => lhs <= rhs,
+        Gt(..) => lhs > rhs,
+        _ => lhs >= rhs,
+    };
+    Some(result)
+}
+
+/// evaluate an operation of literals, a tagged integer is only added, subtracted and compared
+fn fold(exp: Box<Node>, options: &Options) -> Box<Node> {
+    use Node::*;
+
+    if let Some(result) = compare(&exp) {
+        return Box::new(if result { True } else { False });
+    }
+    match *exp {
+        exp @ (Add(..) | Sub(..) | Neg(_)) => partial_eval(Box::new(exp)),
+        exp @ (Mul(..) | Quotient(..) | Remainder(..) | BitAnd(..) | BitOr(..) | BitXor(..)
+        | BitNot(_) | Shl(..) | Shr(..) | Sar(..) | Min(..) | Max(..) | Not(_))
+            if !options.bignum =>
+        {
+            partial_eval(Box::new(exp))
+        }
+        exp => Box::new(exp),
+    }
+}
+
+struct Context<'a> {
+    consts: HashMap<String, Node>,
+    options: &'a Options,
+}
+
+impl Context<'_> {
+    /// replace the variables of an expression by their literals
+    fn subst(&self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        let node = match *node {
+            Var(name) => match self.consts.get(&name) {
+                Some(literal) => literal.clone(),
+                None => Var(name),
+            },
+            // a literal index is not tagged and it is not checked against the length
+            VectorRef(vec, index) => VectorRef(vec, index),
+            VectorSet(vec, index, value) => VectorSet(vec, index, self.subst(value)),
+            StringRef(s, index) => StringRef(s, index),
+            node => node.map_children(&mut |node| self.subst(node)),
+        };
+        Box::new(node)
+    }
+
+    fn propagate(&self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        let node = match *node {
+            Assign(name, exp) => Assign(name, fold(self.subst(exp), self.options)),
+            IfGoto {
+                cond,
+                then_label,
+                else_label,
+            } => {
+                let cond = fold(self.subst(cond), self.options);
+                match cond.boolean() {
+                    Some(true) => Goto(then_label),
+                    Some(false) => Goto(else_label),
+                    None => IfGoto {
+                        cond,
+                        then_label,
+                        else_label,
+                    },
+                }
+            }
+            Return(value) => Return(self.subst(value)),
+            TailCall(fun, args) => {
+                TailCall(fun, args.into_iter().map(|arg| self.subst(arg)).collect())
+            }
+            node => *self.subst(Box::new(node)),
+        };
+        Box::new(node)
+    }
+}
+
+/// the variables assigned once with a small integer or a boolean, a use of the variable
+/// always comes after its assignment unless it is a parameter
+fn find_constants(params: &[(String, Type)], body: &[Box<Node>]) -> HashMap<String, Node> {
+    let mut assigns: HashMap<&String, (usize, &Node)> = HashMap::new();
+    for (name, _) in params {
+        assigns.insert(name, (2, &Node::NOP));
+    }
+    for node in body {
+        if let Node::Assign(name, exp) = node.as_ref() {
+            let entry = assigns.entry(name).or_insert((0, exp));
+            entry.0 += 1;
+        }
+    }
+    assigns
+        .into_iter()
+        .filter(|(_, (count, exp))| {
+            let small = exp.fixnum().is_some_and(is_imm32);
+            *count == 1 && (small || exp.boolean().is_some())
+        })
+        .map(|(name, (_, exp))| (name.clone(), exp.clone()))
+        .collect()
+}
+
+pub fn propagate_constants(defs: Vec<Box<Node>>, options: &Options) -> Vec<Box<Node>> {
+    use Node::*;
+
+    defs.into_iter()
+        .map(|def| match *def {
+            Def {
+                name,
+                params,
+                ret,
+                mut body,
+            } => {
+                // the folded operations may assign more literals
+                loop {
+                    let cxt = Context {
+                        consts: find_constants(&params, &body),
+                        options,
+                    };
+                    let new_body: Vec<_> = body
+                        .iter()
+                        .map(|node| cxt.propagate(node.clone()))
+                        .collect();
+                    if new_body == body {
+                        break;
+                    }
+                    body = new_body;
+                }
+                Box::new(Def {
+                    name,
+                    params,
+                    ret,
+                    body,
+                })
+            }
+            def => Box::new(def),
+        })
+        .collect()
+}
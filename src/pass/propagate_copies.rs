@@ -0,0 +1,132 @@
+/// Read the original variable instead of its copy, the copies are known from the moves
+/// between variables in the same block, so the copy may be dead after the move
+/// x86 specific pass
+use crate::ast::*;
+use std::collections::HashMap;
+
+struct Context {
+    /// variables holding the value of another variable
+    copies: HashMap<String, String>,
+}
+
+impl Context {
+    /// replace a variable read as an operand by its original
+    fn original(&self, node: Box<Node>) -> Box<Node> {
+        match node.var().and_then(|name| self.copies.get(name)) {
+            Some(name) => Box::new(Node::Var(name.clone())),
+            None => node,
+        }
+    }
+
+    fn propagate(&self, node: Box<Node>) -> Box<Node> {
+        use Node::*;
+
+        let node = match *node {
+            MOVQ { target, source } => MOVQ {
+                target,
+                source: self.original(source),
+            },
+            MOVZBQ { target, source } => MOVZBQ {
+                target,
+                source: self.original(source),
+            },
+            CMOV {
+                cond,
+                source,
+                target,
+            } => CMOV {
+                cond,
+                source: self.original(source),
+                target,
+            },
+            ADDQ { target, arg } => ADDQ {
+                target,
+                arg: self.original(arg),
+            },
+            SUBQ { target, arg } => SUBQ {
+                target,
+                arg: self.original(arg),
+            },
+            IMULQ { target, arg } => IMULQ {
+                target,
+                arg: self.original(arg),
+            },
+            ANDQ { target, arg } => ANDQ {
+                target,
+                arg: self.original(arg),
+            },
+            ORQ { target, arg } => ORQ {
+                target,
+                arg: self.original(arg),
+            },
+            XORQ { target, arg } => XORQ {
+                target,
+                arg: self.original(arg),
+            },
+            ADDSD { target, arg } => ADDSD {
+                target,
+                arg: self.original(arg),
+            },
+            SUBSD { target, arg } => SUBSD {
+                target,
+                arg: self.original(arg),
+            },
+            MULSD { target, arg } => MULSD {
+                target,
+                arg: self.original(arg),
+            },
+            DIVSD { target, arg } => DIVSD {
+                target,
+                arg: self.original(arg),
+            },
+            CVTSI2SDQ { target, source } => CVTSI2SDQ {
+                target,
+                source: self.original(source),
+            },
+            CVTTSD2SIQ { target, source } => CVTTSD2SIQ {
+                target,
+                source: self.original(source),
+            },
+            CMPQ(lhs, rhs) => CMPQ(self.original(lhs), self.original(rhs)),
+            IDIVQ(arg) => IDIVQ(self.original(arg)),
+            ICALLQ(fun, arity) => ICALLQ(self.original(fun), arity),
+            ITAILJMP(fun, arity) => ITAILJMP(self.original(fun), arity),
+            node => node,
+        };
+        Box::new(node)
+    }
+
+    /// forget the copies of the variable written by the instruction
+    fn update(&mut self, node: &Node) {
+        if let Node::Label(_) = node {
+            // a block may be entered from other blocks
+            self.copies.clear();
+            return;
+        }
+        let name = match node.written().and_then(|target| target.var()) {
+            Some(name) => name,
+            None => return,
+        };
+        self.copies
+            .retain(|copy, original| copy != name && original != name);
+        if let Node::MOVQ { source, .. } = node {
+            if let Some(original) = source.var().filter(|original| *original != name) {
+                self.copies.insert(name.clone(), original.clone());
+            }
+        }
+    }
+}
+
+pub fn propagate_copies(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    let mut cxt = Context {
+        copies: HashMap::new(),
+    };
+    node_list
+        .into_iter()
+        .map(|node| {
+            let node = cxt.propagate(node);
+            cxt.update(&node);
+            node
+        })
+        .collect()
+}
@@ -0,0 +1,124 @@
+/// Remove the blocks which can not be reached and the assignments of pure
+/// expressions to variables which are never read
+use crate::ast::*;
+use std::collections::HashSet;
+
+/// an expression without effects
+fn is_removable(exp: &Node, options: &Options) -> bool {
+    use Node::*;
+
+    match exp {
+        Var(_) | Fixnum(_) | True | False | Void | Char(_) | Flonum(_) | Str(_) | FunRef(_)
+        | Global(_) | StaticVector(..) => true,
+        exp => exp.is_pure() && !exp.may_trap(options),
+    }
+}
+
+/// the variables read by a statement
+fn uses(node: &Node) -> Vec<String> {
+    use Node::*;
+
+    match node {
+        Assign(_, exp) | Return(exp) => exp.free_vars(),
+        IfGoto { cond, .. } => cond.free_vars(),
+        TailCall(fun, args) => std::iter::once(fun)
+            .chain(args)
+            .flat_map(|exp| exp.free_vars())
+            .collect(),
+        node => node.free_vars(),
+    }
+}
+
+/// the labels reached from the entry, a block without a jump at its end
+/// falls through to the next label, a return only moves the value to the result
+fn reachable_labels(body: &[Box<Node>]) -> HashSet<String> {
+    use Node::*;
+
+    let mut labels = HashSet::new();
+    loop {
+        let len = labels.len();
+        let mut reachable = true;
+        for node in body {
+            match node.as_ref() {
+                Label(label) => reachable |= labels.contains(label),
+                _ if !reachable => {}
+                Goto(label) => {
+                    labels.insert(label.clone());
+                    reachable = false;
+                }
+                IfGoto {
+                    then_label,
+                    else_label,
+                    ..
+                } => {
+                    labels.insert(then_label.clone());
+                    labels.insert(else_label.clone());
+                    reachable = false;
+                }
+                TailCall(..) => reachable = false,
+                _ => {}
+            }
+        }
+        // a jump backward reaches a label which is already passed
+        if labels.len() == len {
+            return labels;
+        }
+    }
+}
+
+fn remove_unreachable(body: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    let labels = reachable_labels(&body);
+    let mut reachable = true;
+    body.into_iter()
+        .filter(|node| {
+            match node.as_ref() {
+                Label(label) => reachable |= labels.contains(label),
+                _ if !reachable => return false,
+                Goto(_) | IfGoto { .. } | TailCall(..) => {
+                    reachable = false;
+                    return true;
+                }
+                _ => {}
+            }
+            reachable
+        })
+        .collect()
+}
+
+/// removing an assignment may leave the variables of its expression unread
+fn remove_dead_assigns(mut body: Vec<Box<Node>>, options: &Options) -> Vec<Box<Node>> {
+    loop {
+        let used: HashSet<String> = body.iter().flat_map(|node| uses(node)).collect();
+        let len = body.len();
+        body.retain(|node| match node.as_ref() {
+            Node::Assign(name, exp) => used.contains(name) || !is_removable(exp, options),
+            _ => true,
+        });
+        if body.len() == len {
+            return body;
+        }
+    }
+}
+
+pub fn remove_dead_code(defs: Vec<Box<Node>>, options: &Options) -> Vec<Box<Node>> {
+    use Node::*;
+
+    defs.into_iter()
+        .map(|def| match *def {
+            Def {
+                name,
+                params,
+                ret,
+                body,
+            } => Box::new(Def {
+                name,
+                params,
+                ret,
+                body: remove_dead_assigns(remove_unreachable(body), options),
+            }),
+            def => Box::new(def),
+        })
+        .collect()
+}
@@ -0,0 +1,23 @@
+/// Remove the moves to variables which are not live after the move,
+/// the live sets of the removed moves are removed with them
+/// x86 specific pass
+use crate::ast::*;
+
+fn is_dead_store(node: &Node, live_after: &LiveSet) -> bool {
+    match node {
+        // a register may be read after the end of the function
+        Node::MOVQ { target, .. } => target.var().is_some_and(|name| !live_after.contains(name)),
+        _ => false,
+    }
+}
+
+pub fn remove_dead_stores(node_list: Vec<Box<Node>>, info: &mut Info) -> Vec<Box<Node>> {
+    let live_afters = std::mem::take(&mut info.live_afters);
+    let (node_list, live_afters) = node_list
+        .into_iter()
+        .zip(live_afters)
+        .filter(|(node, live_after)| !is_dead_store(node, live_after))
+        .unzip();
+    info.live_afters = live_afters;
+    node_list
+}
@@ -0,0 +1,181 @@
+/// Replace references to top-level functions and globals by their labels,
+/// a local variable shadows them, globals which are not constants are initialized by main
+use crate::ast::*;
+use std::collections::HashMap;
+
+/// Function names become labels, so keep only the characters the assembler accepts
+fn rewrite_fun(name: &str, cnt: usize) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}_{}", name, cnt)
+}
+
+/// labels of the top-level names
+struct Labels {
+    funs: HashMap<String, String>,
+    globals: HashMap<String, String>,
+    /// number of the parameters of the functions
+    arities: HashMap<String, usize>,
+}
+
+impl Labels {
+    fn is_top_level(&self, name: &str) -> bool {
+        self.funs.contains_key(name) || self.globals.contains_key(name)
+    }
+
+    /// calls of the functions computing the default values of the parameters left out
+    fn defaults(&self, name: &str, given: usize) -> Vec<Box<Node>> {
+        (given..self.arities[name])
+            .map(|i| {
+                let fun = Box::new(Node::FunRef(self.funs[&default_name(name, i)].clone()));
+                Box::new(Node::Apply(fun, Vec::new()))
+            })
+            .collect()
+    }
+}
+
+fn reveal_functions_inner(node: Box<Node>, labels: &Labels, bound: &mut Vec<String>) -> Box<Node> {
+    use Node::*;
+
+    let funs = &labels.funs;
+    let globals = &labels.globals;
+    let node = match *node {
+        Var(name) if !bound.contains(&name) && funs.contains_key(&name) => {
+            FunRef(funs[&name].clone())
+        }
+        Var(name) if !bound.contains(&name) && globals.contains_key(&name) => {
+            Global(globals[&name].clone())
+        }
+        SetBang { name, value } if !bound.contains(&name) && globals.contains_key(&name) => {
+            GlobalSet(
+                globals[&name].clone(),
+                reveal_functions_inner(value, labels, bound),
+            )
+        }
+        Apply(fun, args) => {
+            let mut args: Vec<_> = args
+                .into_iter()
+                .map(|arg| reveal_functions_inner(arg, labels, bound))
+                .collect();
+            match fun.as_ref() {
+                Var(name) if !bound.contains(name) && funs.contains_key(name) => {
+                    args.extend(labels.defaults(name, args.len()))
+                }
+                _ => {}
+            }
+            Apply(reveal_functions_inner(fun, labels, bound), args)
+        }
+        DefGlobal { name, value } => DefGlobal {
+            name: globals[&name].clone(),
+            value: reveal_functions_inner(value, labels, bound),
+        },
+        Def {
+            name,
+            params,
+            ret,
+            body,
+        } => {
+            bound.extend(params.iter().map(|(param, _)| param.clone()));
+            let body = body
+                .into_iter()
+                .map(|exp| reveal_functions_inner(exp, labels, bound))
+                .collect();
+            bound.clear();
+            Def {
+                name: funs[&name].clone(),
+                params,
+                ret,
+                body,
+            }
+        }
+        Let { name, value, exp } => {
+            let value = reveal_functions_inner(value, labels, bound);
+            bound.push(name.clone());
+            let exp = reveal_functions_inner(exp, labels, bound);
+            bound.pop();
+            Let { name, value, exp }
+        }
+        Lambda {
+            params,
+            ret,
+            body,
+            free_vars,
+        } => {
+            // functions and globals are not captured
+            let free_vars = free_vars
+                .into_iter()
+                .filter(|(name, _)| bound.contains(name) || !labels.is_top_level(name))
+                .collect();
+            bound.extend(params.iter().map(|(param, _)| param.clone()));
+            let body = reveal_functions_inner(body, labels, bound);
+            bound.truncate(bound.len() - params.len());
+            Lambda {
+                params,
+                ret,
+                body,
+                free_vars,
+            }
+        }
+        node => node.map_children(&mut |node| reveal_functions_inner(node, labels, bound)),
+    };
+    Box::new(node)
+}
+
+fn is_constant(node: &Node) -> bool {
+    use Node::*;
+
+    matches!(node, Fixnum(_) | True | False | Char(_) | Flonum(_) | Void)
+}
+
+pub fn reveal_functions(node: Box<Node>) -> Box<Node> {
+    let defs = match node.as_ref() {
+        Node::Program(defs, _) => defs,
+        node => panic!("unexpected {:?}", node),
+    };
+    let mut labels = Labels {
+        funs: HashMap::new(),
+        globals: HashMap::new(),
+        arities: HashMap::new(),
+    };
+    for (i, def) in defs.iter().enumerate() {
+        match def.as_ref() {
+            Node::Def { name, params, .. } => {
+                labels.funs.insert(name.clone(), rewrite_fun(name, i + 1));
+                labels.arities.insert(name.clone(), params.len());
+            }
+            Node::DefGlobal { name, .. } => {
+                labels
+                    .globals
+                    .insert(name.clone(), rewrite_fun(name, i + 1));
+            }
+            _ => {}
+        }
+    }
+    let (defs, exp) = match *reveal_functions_inner(node, &labels, &mut Vec::new()) {
+        Node::Program(defs, exp) => (defs, exp),
+        node => panic!("unexpected {:?}", node),
+    };
+    // initializers run in order before the expression
+    let mut inits = Vec::new();
+    let defs = defs
+        .into_iter()
+        .map(|def| match *def {
+            Node::DefGlobal { name, value } if !is_constant(&value) => {
+                inits.push(Box::new(Node::GlobalSet(name.clone(), value)));
+                Box::new(Node::DefGlobal {
+                    name,
+                    value: Box::new(Node::Void),
+                })
+            }
+            def => Box::new(def),
+        })
+        .collect();
+    let exp = if inits.is_empty() {
+        exp
+    } else {
+        Box::new(Node::Begin(inits, exp))
+    };
+    Box::new(Node::Program(defs, exp))
+}
@@ -0,0 +1,200 @@
+/// Reorder the independent instructions between the jumps, calls and uses of the flags,
+/// a load is moved away from the instruction using its value so the load finishes in between
+/// x86 specific pass
+use super::uncover_live::reads;
+use crate::ast::*;
+use std::cmp::Reverse;
+
+/// cycles until the value of a load is available
+const LOAD_LATENCY: usize = 3;
+
+/// names of the resources which are not variables or registers
+const MEMORY: &str = "(memory)";
+const FLAGS: &str = "(flags)";
+
+/// the operands of an instruction which may be moved
+fn operands(node: &Node) -> Option<Vec<&Node>> {
+    use Node::*;
+
+    match node {
+        MOVQ { target, source }
+        | LEAQ { target, source }
+        | CVTSI2SDQ { target, source }
+        | CVTTSD2SIQ { target, source } => Some(vec![target, source]),
+        ADDQ { target, arg }
+        | SUBQ { target, arg }
+        | IMULQ { target, arg }
+        | ANDQ { target, arg }
+        | ORQ { target, arg }
+        | XORQ { target, arg }
+        | SALQ { target, arg }
+        | SARQ { target, arg }
+        | SHRQ { target, arg }
+        | ADDSD { target, arg }
+        | SUBSD { target, arg }
+        | MULSD { target, arg }
+        | DIVSD { target, arg } => Some(vec![target, arg]),
+        NEGQ(target) | NOTQ(target) => Some(vec![target]),
+        _ => None,
+    }
+}
+
+/// patch inst uses RAX and XMM15 for any instruction it fixes, so the instructions
+/// using them keep their places
+fn is_movable(node: &Node) -> bool {
+    operands(node).is_some_and(|operands| {
+        !operands
+            .iter()
+            .any(|operand| matches!(operand, Node::RAX | Node::XMM(15)))
+    })
+}
+
+fn is_memory(node: &Node) -> bool {
+    matches!(node, Node::StackLoc(_) | Node::Deref(..) | Node::Global(_))
+}
+
+fn writes_flags(node: &Node) -> bool {
+    use Node::*;
+
+    matches!(
+        node,
+        ADDQ { .. }
+            | SUBQ { .. }
+            | NEGQ(_)
+            | IMULQ { .. }
+            | ANDQ { .. }
+            | ORQ { .. }
+            | XORQ { .. }
+            | SALQ { .. }
+            | SARQ { .. }
+            | SHRQ { .. }
+    )
+}
+
+struct Inst {
+    node: Box<Node>,
+    reads: LiveSet,
+    writes: LiveSet,
+    latency: usize,
+}
+
+impl Inst {
+    fn new(node: Box<Node>) -> Self {
+        let mut reads = reads(&node);
+        let mut writes = LiveSet::default();
+        for operand in operands(&node).unwrap_or_default() {
+            // the registers of an address are read even if the address is written
+            match operand {
+                Node::Deref(base, _) => reads.extend(base.var_or_reg_name()),
+                Node::Indexed(base, index, _) => {
+                    reads.extend(base.var_or_reg_name());
+                    reads.extend(index.var_or_reg_name());
+                }
+                _ => {}
+            }
+            if is_memory(operand) {
+                reads.insert(MEMORY.to_string());
+            }
+        }
+        let target = node.written().expect("instruction without target");
+        writes.extend(target.var_or_reg_name());
+        if is_memory(target) {
+            writes.insert(MEMORY.to_string());
+        }
+        if writes_flags(&node) {
+            writes.insert(FLAGS.to_string());
+        }
+        let latency = match node.as_ref() {
+            Node::MOVQ { source, .. } if is_memory(source) => LOAD_LATENCY,
+            _ => 1,
+        };
+        Inst {
+            node,
+            reads,
+            writes,
+            latency,
+        }
+    }
+
+    /// the cycles between the instruction and a later one depending on it
+    fn delay(&self, later: &Inst) -> Option<usize> {
+        if !self.writes.is_disjoint(&later.reads) {
+            Some(self.latency)
+        } else if !self.reads.is_disjoint(&later.writes) || !self.writes.is_disjoint(&later.writes)
+        {
+            Some(1)
+        } else {
+            None
+        }
+    }
+}
+
+/// list scheduling, the instruction which can start first is issued first,
+/// the one on the longest path to the end of the block wins a tie
+fn schedule_block(block: Vec<Inst>) -> Vec<Box<Node>> {
+    let len = block.len();
+    let mut succs = vec![Vec::new(); len];
+    let mut preds_count = vec![0; len];
+    for (i, inst) in block.iter().enumerate() {
+        for (j, later) in block.iter().enumerate().skip(i + 1) {
+            if let Some(delay) = inst.delay(later) {
+                succs[i].push((j, delay));
+                preds_count[j] += 1;
+            }
+        }
+    }
+    let mut height = vec![0; len];
+    for i in (0..len).rev() {
+        height[i] = succs[i]
+            .iter()
+            .map(|&(j, delay)| delay + height[j])
+            .max()
+            .unwrap_or(block[i].latency);
+    }
+
+    let mut start = vec![0; len];
+    let mut ready: Vec<usize> = (0..len).filter(|&i| preds_count[i] == 0).collect();
+    let mut order = Vec::with_capacity(len);
+    let mut cycle = 0;
+    while !ready.is_empty() {
+        let pos = (0..ready.len())
+            .min_by_key(|&pos| {
+                let i = ready[pos];
+                (start[i].max(cycle), Reverse(height[i]), i)
+            })
+            .unwrap();
+        let i = ready.swap_remove(pos);
+        cycle = start[i].max(cycle);
+        for &(j, delay) in &succs[i] {
+            start[j] = start[j].max(cycle + delay);
+            preds_count[j] -= 1;
+            if preds_count[j] == 0 {
+                ready.push(j);
+            }
+        }
+        cycle += 1;
+        order.push(i);
+    }
+
+    let mut nodes: Vec<_> = block.into_iter().map(|inst| Some(inst.node)).collect();
+    order
+        .into_iter()
+        .map(|i| nodes[i].take().unwrap())
+        .collect()
+}
+
+pub fn schedule_inst(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    let mut new_node_list = Vec::with_capacity(node_list.len());
+    let mut block = Vec::new();
+
+    for node in node_list {
+        if is_movable(&node) {
+            block.push(Inst::new(node));
+        } else {
+            new_node_list.extend(schedule_block(std::mem::take(&mut block)));
+            new_node_list.push(node);
+        }
+    }
+    new_node_list.extend(schedule_block(block));
+    new_node_list
+}
@@ -1,12 +1,651 @@
 use crate::ast::*;
 
-fn select_one_inst(node: Node, node_list: &mut Vec<Box<Node>>) {
+const WORD: usize = 8;
+
+/// booleans are represented as 1 and 0, void as 0, characters as their codes,
+/// floats as their bits
+fn select_atom(node: Box<Node>) -> Box<Node> {
+    match *node {
+        Node::True => Box::new(Node::Fixnum(1)),
+        Node::False | Node::Void => Box::new(Node::Fixnum(0)),
+        Node::Char(chr) => Box::new(Node::Fixnum(chr as isize)),
+        Node::Flonum(bits) => Box::new(Node::Fixnum(bits as isize)),
+        node => Box::new(node),
+    }
+}
+
+/// select operands of a comparison
+fn select_comparison(cmp: Node) -> Node {
+    use Node::*;
+
+    match cmp {
+        Eq(lhs, rhs) => Eq(select_atom(lhs), select_atom(rhs)),
+        Lt(lhs, rhs) => Lt(select_atom(lhs), select_atom(rhs)),
+        Lte(lhs, rhs) => Lte(select_atom(lhs), select_atom(rhs)),
+        Gt(lhs, rhs) => Gt(select_atom(lhs), select_atom(rhs)),
+        Gte(lhs, rhs) => Gte(select_atom(lhs), select_atom(rhs)),
+        node => node,
+    }
+}
+
+/// read the length from the header of a vector
+fn select_length(vec: Box<Node>, target: Box<Node>, node_list: &mut Vec<Box<Node>>) {
+    use Node::*;
+
+    node_list.push(Box::new(MOVQ {
+        target: Box::new(R11),
+        source: vec,
+    }));
+    node_list.push(Box::new(MOVQ {
+        target: target.clone(),
+        source: Box::new(Deref(Box::new(R11), 0)),
+    }));
+    node_list.push(Box::new(SARQ {
+        target: target.clone(),
+        arg: Box::new(Fixnum(1)),
+    }));
+    node_list.push(Box::new(ANDQ {
+        target,
+        arg: Box::new(Fixnum(0b111111)),
+    }));
+}
+
+/// address an element of a vector through R11, the header takes the first word
+fn select_element(
+    vec: Box<Node>,
+    index: Box<Node>,
+    options: &Options,
+    node_list: &mut Vec<Box<Node>>,
+) -> Box<Node> {
+    use Node::*;
+
+    if let Some(i) = index.fixnum() {
+        node_list.push(Box::new(MOVQ {
+            target: Box::new(R11),
+            source: vec,
+        }));
+        return Box::new(Deref(Box::new(R11), (i + 1) * WORD as isize));
+    }
+    if options.bounds_check {
+        select_length(vec.clone(), Box::new(RAX), node_list);
+        select_index_check(index.clone(), options, node_list);
+    }
+    select_indexed(vec, index, options, node_list)
+}
+
+/// header of an array, bit 0 is set if the array is not forwarded, bit 63 tells it
+/// from a vector, bit 62 is set if the elements are pointers, bits 1-61 are the length
+fn select_array_length(array: Box<Node>, target: Box<Node>, node_list: &mut Vec<Box<Node>>) {
+    use Node::*;
+
+    node_list.push(Box::new(MOVQ {
+        target: Box::new(R11),
+        source: array,
+    }));
+    node_list.push(Box::new(MOVQ {
+        target: target.clone(),
+        source: Box::new(Deref(Box::new(R11), 0)),
+    }));
+    node_list.push(Box::new(SALQ {
+        target: target.clone(),
+        arg: Box::new(Fixnum(2)),
+    }));
+    node_list.push(Box::new(SARQ {
+        target,
+        arg: Box::new(Fixnum(3)),
+    }));
+}
+
+/// address an element of an array through R11, the index is checked at runtime
+fn select_array_element(
+    array: Box<Node>,
+    index: Box<Node>,
+    options: &Options,
+    node_list: &mut Vec<Box<Node>>,
+) -> Box<Node> {
+    if options.bounds_check {
+        select_array_length(array.clone(), Box::new(Node::RAX), node_list);
+        select_index_check(index.clone(), options, node_list);
+    }
+    select_indexed(array, index, options, node_list)
+}
+
+/// jump to the handler unless the index is below the length in RAX,
+/// a negative index is a large unsigned number, so one comparison is enough
+fn select_index_check(index: Box<Node>, options: &Options, node_list: &mut Vec<Box<Node>>) {
+    use Node::*;
+
+    select_retag(Box::new(RAX), options, node_list);
+    node_list.push(Box::new(CMPQ(index, Box::new(RAX))));
+    node_list.push(Box::new(JMPIF(
+        CondCode::Be,
+        VECTOR_INDEX_ERROR.to_string(),
+    )));
+}
+
+/// address the word after the header at a variable index through R11
+fn select_indexed(
+    vec: Box<Node>,
+    index: Box<Node>,
+    options: &Options,
+    node_list: &mut Vec<Box<Node>>,
+) -> Box<Node> {
+    use Node::*;
+
+    node_list.push(Box::new(MOVQ {
+        target: Box::new(R11),
+        source: index,
+    }));
+    // a tagged index is already doubled
+    let scale = if options.bignum { 2 } else { 3 };
+    node_list.push(Box::new(SALQ {
+        target: Box::new(R11),
+        arg: Box::new(Fixnum(scale)),
+    }));
+    node_list.push(Box::new(ADDQ {
+        target: Box::new(R11),
+        arg: vec,
+    }));
+    Box::new(Deref(Box::new(R11), WORD as isize))
+}
+
+/// address a byte of a string through R11, the length takes the first word
+fn select_byte(
+    s: Box<Node>,
+    index: Box<Node>,
+    options: &Options,
+    node_list: &mut Vec<Box<Node>>,
+) -> Box<Node> {
+    use Node::*;
+
+    if options.bounds_check {
+        node_list.push(Box::new(MOVQ {
+            target: Box::new(R11),
+            source: s.clone(),
+        }));
+        node_list.push(Box::new(MOVQ {
+            target: Box::new(RAX),
+            source: Box::new(Deref(Box::new(R11), 0)),
+        }));
+        // a literal index is not tagged
+        if index.fixnum().is_none() {
+            select_retag(Box::new(RAX), options, node_list);
+        }
+        node_list.push(Box::new(CMPQ(index.clone(), Box::new(RAX))));
+        node_list.push(Box::new(JMPIF(
+            CondCode::Be,
+            STRING_INDEX_ERROR.to_string(),
+        )));
+    }
+    if let Some(i) = index.fixnum() {
+        node_list.push(Box::new(MOVQ {
+            target: Box::new(R11),
+            source: s,
+        }));
+        return Box::new(Deref(Box::new(R11), i + WORD as isize));
+    }
+    node_list.push(Box::new(MOVQ {
+        target: Box::new(R11),
+        source: index,
+    }));
+    if options.bignum {
+        node_list.push(Box::new(SARQ {
+            target: Box::new(R11),
+            arg: Box::new(Fixnum(1)),
+        }));
+    }
+    node_list.push(Box::new(ADDQ {
+        target: Box::new(R11),
+        arg: s,
+    }));
+    Box::new(Deref(Box::new(R11), WORD as isize))
+}
+
+/// check the tag of a value of Any and remove it, the length of a vector
+/// and the arity of a closure are checked too
+fn select_project(
+    exp: Box<Node>,
+    t: Type,
+    pos: Pos,
+    target: Box<Node>,
+    node_list: &mut Vec<Box<Node>>,
+) {
+    use Node::*;
+
+    // moves keep the flags of the comparison
+    let check = |expected: usize, node_list: &mut Vec<Box<Node>>| {
+        node_list.push(Box::new(CMPQ(
+            Box::new(Fixnum(expected as isize)),
+            Box::new(RAX),
+        )));
+        node_list.push(Box::new(MOVQ {
+            target: Box::new(RAX),
+            source: Box::new(Fixnum(pos.line as isize)),
+        }));
+        node_list.push(Box::new(MOVQ {
+            target: Box::new(R11),
+            source: Box::new(Fixnum(pos.col as isize)),
+        }));
+        node_list.push(Box::new(JMPIF(CondCode::Ne, ANY_PROJECT_ERROR.to_string())));
+    };
+    node_list.push(Box::new(MOVQ {
+        target: Box::new(RAX),
+        source: exp.clone(),
+    }));
+    node_list.push(Box::new(ANDQ {
+        target: Box::new(RAX),
+        arg: Box::new(Fixnum(0b111)),
+    }));
+    check(t.any_tag().unwrap() as usize, node_list);
+    node_list.push(Box::new(MOVQ {
+        target: target.clone(),
+        source: exp,
+    }));
+    if t.is_pointer() {
+        node_list.push(Box::new(ANDQ {
+            target: target.clone(),
+            arg: Box::new(Fixnum(!0b111)),
+        }));
+    } else {
+        node_list.push(Box::new(SARQ {
+            target: target.clone(),
+            arg: Box::new(Fixnum(3)),
+        }));
+    }
+    match t {
+        Type::Vector(elem_types) => {
+            select_length(target, Box::new(RAX), node_list);
+            check(elem_types.len(), node_list);
+        }
+        // the arity is the second element of a closure
+        Type::Function(params, _) => {
+            node_list.push(Box::new(MOVQ {
+                target: Box::new(R11),
+                source: target,
+            }));
+            node_list.push(Box::new(MOVQ {
+                target: Box::new(RAX),
+                source: Box::new(Deref(Box::new(R11), 2 * WORD as isize)),
+            }));
+            check(params.len(), node_list);
+        }
+        _ => {}
+    }
+}
+
+/// labels of the slow paths of big integers in a function
+struct Labels {
+    function: String,
+    cnt: usize,
+}
+
+impl Labels {
+    fn alloc(&mut self, prefix: &str) -> String {
+        let label = format!("{}.{}_{}", self.function, prefix, self.cnt);
+        self.cnt += 1;
+        label
+    }
+}
+
+/// integers are fixnums if both operands have no tag bit,
+/// otherwise the runtime computes the result
+fn select_tag_check(lhs: &Node, rhs: &Node, slow_label: &str, node_list: &mut Vec<Box<Node>>) {
+    use Node::*;
+
+    node_list.push(Box::new(MOVQ {
+        target: Box::new(RAX),
+        source: Box::new(lhs.clone()),
+    }));
+    node_list.push(Box::new(ORQ {
+        target: Box::new(RAX),
+        arg: Box::new(rhs.clone()),
+    }));
+    node_list.push(Box::new(ANDQ {
+        target: Box::new(RAX),
+        arg: Box::new(Fixnum(1)),
+    }));
+    node_list.push(Box::new(JMPIF(CondCode::Ne, slow_label.to_string())));
+}
+
+/// arithmetic of fixnums falls back to the runtime if an operand is a big integer
+/// or the result overflows, the fast path is emitted by `fast`
+fn select_bignum_arith(
+    fun: &str,
+    args: Vec<Box<Node>>,
+    target: Box<Node>,
+    labels: &mut Labels,
+    fast: impl FnOnce(&mut Vec<Box<Node>>),
+    node_list: &mut Vec<Box<Node>>,
+) {
+    use Node::*;
+
+    let slow_label = labels.alloc("bignum");
+    let end_label = labels.alloc("bignum_end");
+    let rhs = args.last().unwrap();
+    select_tag_check(&args[0], rhs, &slow_label, node_list);
+    fast(node_list);
+    node_list.push(Box::new(JMPIF(CondCode::O, slow_label.clone())));
+    node_list.push(Box::new(JMP(end_label.clone())));
+    node_list.push(Box::new(Label(slow_label)));
+    let arity = args.len();
+    select_args(args, node_list);
+    node_list.push(Box::new(CALLQ(fun.to_string(), arity)));
+    node_list.push(Box::new(MOVQ {
+        target,
+        source: Box::new(RAX),
+    }));
+    node_list.push(Box::new(Label(end_label)));
+}
+
+/// a comparison of big integers compares the result of the runtime against 0,
+/// `branch` emits the use of the flags on both paths
+fn select_bignum_comparison(
+    lhs: Box<Node>,
+    rhs: Box<Node>,
+    labels: &mut Labels,
+    mut branch: impl FnMut(&mut Vec<Box<Node>>),
+    node_list: &mut Vec<Box<Node>>,
+) {
+    use Node::*;
+
+    let slow_label = labels.alloc("bignum");
+    let end_label = labels.alloc("bignum_end");
+    select_tag_check(&lhs, &rhs, &slow_label, node_list);
+    // CMPQ compares the second operand against the first one
+    node_list.push(Box::new(CMPQ(rhs.clone(), lhs.clone())));
+    branch(node_list);
+    node_list.push(Box::new(JMP(end_label.clone())));
+    node_list.push(Box::new(Label(slow_label)));
+    select_args(vec![lhs, rhs], node_list);
+    node_list.push(Box::new(CALLQ("integer_compare".to_string(), 2)));
+    node_list.push(Box::new(CMPQ(Box::new(Fixnum(0)), Box::new(RAX))));
+    branch(node_list);
+    node_list.push(Box::new(Label(end_label)));
+}
+
+/// shift the result of an integer operation into the representation of the mode
+fn select_retag(target: Box<Node>, options: &Options, node_list: &mut Vec<Box<Node>>) {
+    if options.bignum {
+        node_list.push(Box::new(Node::SALQ {
+            target,
+            arg: Box::new(Node::Fixnum(1)),
+        }));
+    }
+}
+
+/// a power of two is a shift, 3, 5 and 9 are an address of an index scaled by 2, 4 and 8
+fn is_reducible_factor(n: isize) -> bool {
+    (n > 1 && n.count_ones() == 1) || matches!(n, 3 | 5 | 9)
+}
+
+fn is_reducible(lhs: &Node, rhs: &Node) -> bool {
+    [lhs, rhs]
+        .iter()
+        .any(|arg| arg.fixnum().is_some_and(is_reducible_factor))
+}
+
+/// the shift and the address computation set no overflow flag,
+/// so the multiplication must not be checked
+fn select_reduced_mul(
+    target: Box<Node>,
+    value: Box<Node>,
+    n: isize,
+    node_list: &mut Vec<Box<Node>>,
+) {
+    use Node::*;
+
+    if n.count_ones() == 1 {
+        node_list.push(Box::new(MOVQ {
+            target: target.clone(),
+            source: value,
+        }));
+        node_list.push(Box::new(SALQ {
+            target,
+            arg: Box::new(Fixnum(n.trailing_zeros() as isize)),
+        }));
+    } else {
+        // the operands of an address must be registers
+        let reg = Box::new(R11);
+        node_list.push(Box::new(MOVQ {
+            target: reg.clone(),
+            source: value,
+        }));
+        node_list.push(Box::new(LEAQ {
+            target,
+            source: Box::new(Indexed(reg.clone(), reg, n as usize - 1)),
+        }));
+    }
+}
+
+/// jump to the handler if the last arithmetic overflowed
+fn select_overflow_check(options: &Options, node_list: &mut Vec<Box<Node>>) {
+    if options.overflow_check {
+        node_list.push(Box::new(Node::JMPIF(
+            CondCode::O,
+            OVERFLOW_ERROR.to_string(),
+        )));
+    }
+}
+
+/// IDIVQ traps if the divisor is 0, or if the minimum is divided by -1,
+/// the quotient then overflows, so it is checked with the overflow check,
+/// while the remainder is 0, so the division is skipped by jumping to the returned label
+fn select_divisor_check(
+    lhs: &Node,
+    rhs: &Node,
+    target: &Node,
+    is_remainder: bool,
+    options: &Options,
+    labels: &mut Labels,
+    node_list: &mut Vec<Box<Node>>,
+) -> Option<String> {
+    use Node::*;
+
+    if rhs.fixnum().is_none_or(|n| n == 0) {
+        node_list.push(Box::new(CMPQ(Box::new(Fixnum(0)), Box::new(rhs.clone()))));
+        node_list.push(Box::new(JMPIF(CondCode::E, DIVIDE_ERROR.to_string())));
+    }
+    let may_trap =
+        rhs.fixnum().is_none_or(|n| n == -1) && lhs.fixnum().is_none_or(|n| n == isize::MIN);
+    if !may_trap || !(is_remainder || options.overflow_check) {
+        return None;
+    }
+    let div_label = labels.alloc("div");
+    node_list.push(Box::new(CMPQ(Box::new(Fixnum(-1)), Box::new(rhs.clone()))));
+    node_list.push(Box::new(JMPIF(CondCode::Ne, div_label.clone())));
+    let end_label = if is_remainder {
+        let end_label = labels.alloc("div_end");
+        node_list.push(Box::new(MOVQ {
+            target: Box::new(target.clone()),
+            source: Box::new(Fixnum(0)),
+        }));
+        node_list.push(Box::new(JMP(end_label.clone())));
+        Some(end_label)
+    } else {
+        node_list.push(Box::new(CMPQ(
+            Box::new(Fixnum(isize::MIN)),
+            Box::new(lhs.clone()),
+        )));
+        node_list.push(Box::new(JMPIF(CondCode::E, OVERFLOW_ERROR.to_string())));
+        None
+    };
+    node_list.push(Box::new(Label(div_label)));
+    end_label
+}
+
+/// jump to the handler unless the integer is a code point below 0x110000,
+/// which is not a surrogate, a negative integer is a large unsigned number
+fn select_char_check(code: Box<Node>, node_list: &mut Vec<Box<Node>>) {
+    use Node::*;
+
+    node_list.push(Box::new(MOVQ {
+        target: Box::new(RAX),
+        source: Box::new(Fixnum(0x110000)),
+    }));
+    node_list.push(Box::new(CMPQ(code.clone(), Box::new(RAX))));
+    node_list.push(Box::new(JMPIF(CondCode::Be, CHAR_ERROR.to_string())));
+    // the surrogates are 0xD800 to 0xDFFF
+    node_list.push(Box::new(MOVQ {
+        target: Box::new(RAX),
+        source: code,
+    }));
+    node_list.push(Box::new(SUBQ {
+        target: Box::new(RAX),
+        arg: Box::new(Fixnum(0xD800)),
+    }));
+    node_list.push(Box::new(CMPQ(Box::new(Fixnum(0x7FF)), Box::new(RAX))));
+    node_list.push(Box::new(JMPIF(CondCode::Be, CHAR_ERROR.to_string())));
+}
+
+/// move the arguments of a call into the argument registers
+fn select_args(args: Vec<Box<Node>>, node_list: &mut Vec<Box<Node>>) {
+    for (arg, reg) in args.into_iter().zip(ARGUMENT_REGISTERS.iter()) {
+        node_list.push(Box::new(Node::MOVQ {
+            target: Box::new(reg.clone()),
+            source: select_atom(arg),
+        }));
+    }
+}
+
+fn select_one_inst(
+    node: Node,
+    options: &Options,
+    labels: &mut Labels,
+    node_list: &mut Vec<Box<Node>>,
+) {
     use Node::*;
 
     match node {
         Assign(var_name, sub_node) => {
             let target = Box::new(Var(var_name));
             match *sub_node {
+                Add(lhs, rhs) if options.bignum => {
+                    let args = vec![lhs.clone(), rhs.clone()];
+                    let fast = |node_list: &mut Vec<Box<Node>>| {
+                        node_list.push(Box::new(MOVQ {
+                            target: target.clone(),
+                            source: lhs,
+                        }));
+                        node_list.push(Box::new(ADDQ {
+                            target: target.clone(),
+                            arg: rhs,
+                        }));
+                    };
+                    select_bignum_arith(
+                        "integer_add",
+                        args,
+                        target.clone(),
+                        labels,
+                        fast,
+                        node_list,
+                    );
+                }
+
+                Sub(lhs, rhs) if options.bignum => {
+                    let args = vec![lhs.clone(), rhs.clone()];
+                    let fast = |node_list: &mut Vec<Box<Node>>| {
+                        node_list.push(Box::new(MOVQ {
+                            target: target.clone(),
+                            source: lhs,
+                        }));
+                        node_list.push(Box::new(SUBQ {
+                            target: target.clone(),
+                            arg: rhs,
+                        }));
+                    };
+                    select_bignum_arith(
+                        "integer_sub",
+                        args,
+                        target.clone(),
+                        labels,
+                        fast,
+                        node_list,
+                    );
+                }
+
+                // one of the operands is untagged, so the product keeps the tag
+                Mul(lhs, rhs) if options.bignum => {
+                    let args = vec![lhs.clone(), rhs.clone()];
+                    let fast = |node_list: &mut Vec<Box<Node>>| {
+                        node_list.push(Box::new(MOVQ {
+                            target: target.clone(),
+                            source: lhs,
+                        }));
+                        node_list.push(Box::new(SARQ {
+                            target: target.clone(),
+                            arg: Box::new(Fixnum(1)),
+                        }));
+                        node_list.push(Box::new(IMULQ {
+                            target: target.clone(),
+                            arg: rhs,
+                        }));
+                    };
+                    select_bignum_arith(
+                        "integer_mul",
+                        args,
+                        target.clone(),
+                        labels,
+                        fast,
+                        node_list,
+                    );
+                }
+
+                Neg(sub_node) if options.bignum => {
+                    let args = vec![sub_node.clone()];
+                    let fast = |node_list: &mut Vec<Box<Node>>| {
+                        node_list.push(Box::new(MOVQ {
+                            target: target.clone(),
+                            source: sub_node,
+                        }));
+                        node_list.push(Box::new(NEGQ(target.clone())));
+                    };
+                    select_bignum_arith(
+                        "integer_neg",
+                        args,
+                        target.clone(),
+                        labels,
+                        fast,
+                        node_list,
+                    );
+                }
+
+                // integers are ordered by the runtime if one of them is a big integer
+                cmp @ Lt(..) | cmp @ Lte(..) | cmp @ Gt(..) | cmp @ Gte(..) if options.bignum => {
+                    let cmp = select_comparison(cmp);
+                    let (cond_code, lhs, rhs) = cmp.comparison().unwrap();
+                    let branch = |node_list: &mut Vec<Box<Node>>| {
+                        node_list.push(Box::new(SET(cond_code.clone(), Box::new(AL))));
+                        node_list.push(Box::new(MOVZBQ {
+                            source: Box::new(AL),
+                            target: target.clone(),
+                        }));
+                    };
+                    let (lhs, rhs) = (Box::new(lhs.clone()), Box::new(rhs.clone()));
+                    select_bignum_comparison(lhs, rhs, labels, branch, node_list);
+                }
+
+                CharToInteger(sub_node) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: sub_node,
+                    }));
+                    select_retag(target, options, node_list);
+                }
+
+                IntegerToChar(sub_node) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: sub_node,
+                    }));
+                    if options.bignum {
+                        node_list.push(Box::new(SARQ {
+                            target: target.clone(),
+                            arg: Box::new(Fixnum(1)),
+                        }));
+                    }
+                    select_char_check(target, node_list);
+                }
+
                 Add(lhs, rhs) => {
                     let (assign, other) = if lhs.var().is_some() || lhs.fixnum().is_some() {
                         (lhs, rhs)
@@ -18,20 +657,390 @@ fn select_one_inst(node: Node, node_list: &mut Vec<Box<Node>>) {
                         source: assign,
                     }));
                     node_list.push(Box::new(ADDQ { target, arg: other }));
+                    select_overflow_check(options, node_list);
+                }
+
+                Sub(lhs, rhs) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: lhs,
+                    }));
+                    node_list.push(Box::new(SUBQ { target, arg: rhs }));
+                    select_overflow_check(options, node_list);
+                }
+
+                Mul(lhs, rhs) if !options.overflow_check && is_reducible(&lhs, &rhs) => {
+                    let (value, n) = match rhs.fixnum() {
+                        Some(n) if is_reducible_factor(n) => (lhs, n),
+                        _ => (rhs, lhs.fixnum().unwrap()),
+                    };
+                    select_reduced_mul(target, value, n, node_list);
+                }
+
+                Mul(lhs, rhs) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: lhs,
+                    }));
+                    node_list.push(Box::new(IMULQ { target, arg: rhs }));
+                    select_overflow_check(options, node_list);
+                }
+
+                op @ FAdd(..) | op @ FSub(..) | op @ FMul(..) | op @ FDiv(..) => {
+                    type Inst = fn(Box<Node>, Box<Node>) -> Node;
+                    let (lhs, rhs, inst): (_, _, Inst) = match op {
+                        FAdd(lhs, rhs) => (lhs, rhs, |target, arg| ADDSD { target, arg }),
+                        FSub(lhs, rhs) => (lhs, rhs, |target, arg| SUBSD { target, arg }),
+                        FMul(lhs, rhs) => (lhs, rhs, |target, arg| MULSD { target, arg }),
+                        FDiv(lhs, rhs) => (lhs, rhs, |target, arg| DIVSD { target, arg }),
+                        _ => panic!(),
+                    };
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: select_atom(lhs),
+                    }));
+                    node_list.push(Box::new(inst(target, rhs)));
+                }
+
+                FixnumToFlonum(sub_node) => node_list.push(Box::new(CVTSI2SDQ {
+                    target,
+                    source: sub_node,
+                })),
+
+                // a float literal is converted from its bits
+                FlonumToFixnum(sub_node) => node_list.push(Box::new(CVTTSD2SIQ {
+                    target,
+                    source: select_atom(sub_node),
+                })),
+
+                div @ Quotient(..) | div @ Remainder(..) => {
+                    // IDIVQ divides RDX:RAX, the quotient is in RAX and the remainder is in RDX
+                    let (lhs, rhs, result) = match div {
+                        Quotient(lhs, rhs) => (lhs, rhs, RAX),
+                        Remainder(lhs, rhs) => (lhs, rhs, RDX),
+                        _ => panic!(),
+                    };
+                    let end_label = select_divisor_check(
+                        &lhs,
+                        &rhs,
+                        &target,
+                        result == RDX,
+                        options,
+                        labels,
+                        node_list,
+                    );
+                    node_list.push(Box::new(MOVQ {
+                        target: Box::new(RAX),
+                        source: lhs,
+                    }));
+                    node_list.push(Box::new(CQTO));
+                    node_list.push(Box::new(IDIVQ(rhs)));
+                    node_list.push(Box::new(MOVQ {
+                        target,
+                        source: Box::new(result),
+                    }));
+                    if let Some(end_label) = end_label {
+                        node_list.push(Box::new(Label(end_label)));
+                    }
                 }
 
-                Eq(lhs, rhs) => {
-                    node_list.push(Box::new(CMPQ(lhs, rhs)));
-                    node_list.push(Box::new(SET(CondCode::E, Box::new(AL))));
+                BitAnd(lhs, rhs) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: lhs,
+                    }));
+                    node_list.push(Box::new(ANDQ { target, arg: rhs }));
+                }
+
+                BitOr(lhs, rhs) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: lhs,
+                    }));
+                    node_list.push(Box::new(ORQ { target, arg: rhs }));
+                }
+
+                BitXor(lhs, rhs) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: lhs,
+                    }));
+                    node_list.push(Box::new(XORQ { target, arg: rhs }));
+                }
+
+                // the smaller or larger operand is chosen by a conditional move in RAX
+                select @ Min(..) | select @ Max(..) => {
+                    let (lhs, rhs, cond) = match select {
+                        Min(lhs, rhs) => (lhs, rhs, CondCode::G),
+                        Max(lhs, rhs) => (lhs, rhs, CondCode::L),
+                        _ => panic!(),
+                    };
+                    node_list.push(Box::new(MOVQ {
+                        target: Box::new(RAX),
+                        source: lhs,
+                    }));
+                    node_list.push(Box::new(MOVQ {
+                        target: Box::new(R11),
+                        source: rhs,
+                    }));
+                    node_list.push(Box::new(CMPQ(Box::new(R11), Box::new(RAX))));
+                    node_list.push(Box::new(CMOV {
+                        cond,
+                        source: Box::new(R11),
+                        target: Box::new(RAX),
+                    }));
+                    node_list.push(Box::new(MOVQ {
+                        target,
+                        source: Box::new(RAX),
+                    }));
+                }
+
+                shift @ Shl(..) | shift @ Shr(..) | shift @ Sar(..) => {
+                    type Inst = fn(Box<Node>, Box<Node>) -> Node;
+                    let (lhs, rhs, inst): (_, _, Inst) = match shift {
+                        Shl(lhs, rhs) => (lhs, rhs, |target, arg| SALQ { target, arg }),
+                        Shr(lhs, rhs) => (lhs, rhs, |target, arg| SHRQ { target, arg }),
+                        Sar(lhs, rhs) => (lhs, rhs, |target, arg| SARQ { target, arg }),
+                        _ => panic!(),
+                    };
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: lhs,
+                    }));
+                    // variable shift count must be in CL
+                    let arg = if rhs.fixnum().is_some() {
+                        rhs
+                    } else {
+                        node_list.push(Box::new(MOVQ {
+                            target: Box::new(RCX),
+                            source: rhs,
+                        }));
+                        Box::new(CL)
+                    };
+                    node_list.push(Box::new(inst(target, arg)));
+                }
+
+                BitNot(sub_node) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: sub_node,
+                    }));
+                    node_list.push(Box::new(NOTQ(target)));
+                }
+
+                Neg(sub_node) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: sub_node,
+                    }));
+                    node_list.push(Box::new(NEGQ(target)));
+                    select_overflow_check(options, node_list);
+                }
+
+                Not(sub_node) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: select_atom(sub_node),
+                    }));
+                    node_list.push(Box::new(XORQ {
+                        target,
+                        arg: Box::new(Fixnum(1)),
+                    }));
+                }
+
+                cmp @ Eq(..) | cmp @ Lt(..) | cmp @ Lte(..) | cmp @ Gt(..) | cmp @ Gte(..) => {
+                    let cmp = select_comparison(cmp);
+                    let (cond_code, lhs, rhs) = cmp.comparison().unwrap();
+                    // CMPQ compares the second operand against the first one
+                    node_list.push(Box::new(CMPQ(Box::new(rhs.clone()), Box::new(lhs.clone()))));
+                    node_list.push(Box::new(SET(cond_code, Box::new(AL))));
                     node_list.push(Box::new(MOVZBQ {
                         source: Box::new(AL),
                         target,
                     }));
                 }
 
-                Read => {
+                Allocate(len, t) => {
+                    // bump the free pointer, the vector is addressed by R11
+                    node_list.push(Box::new(MOVQ {
+                        target: Box::new(R11),
+                        source: Box::new(Global("free_ptr".to_string())),
+                    }));
+                    node_list.push(Box::new(ADDQ {
+                        target: Box::new(Global("free_ptr".to_string())),
+                        arg: Box::new(Fixnum(((len + 1) * WORD) as isize)),
+                    }));
+                    node_list.push(Box::new(MOVQ {
+                        target: Box::new(Deref(Box::new(R11), 0)),
+                        source: Box::new(Fixnum(vector_tag(len, &t))),
+                    }));
+                    node_list.push(Box::new(MOVQ {
+                        target,
+                        source: Box::new(R11),
+                    }));
+                }
+
+                VectorLength(vec) => {
+                    select_length(vec, target.clone(), node_list);
+                    select_retag(target, options, node_list);
+                }
+
+                // the runtime allocates the array and fills it with the initial value
+                MakeArray(len, init, t) => {
+                    let is_pointer = Box::new(Fixnum(t.is_pointer() as isize));
+                    select_args(vec![len, init, is_pointer], node_list);
+                    if options.bignum {
+                        node_list.push(Box::new(SARQ {
+                            target: Box::new(ARGUMENT_REGISTERS[0].clone()),
+                            arg: Box::new(Fixnum(1)),
+                        }));
+                    }
+                    node_list.push(Box::new(CALLQ("make_array".to_string(), 3)));
+                    node_list.push(Box::new(MOVQ {
+                        target,
+                        source: Box::new(RAX),
+                    }));
+                }
+
+                // the process exit routine flushes the output, it does not return
+                Exit(code) => {
+                    select_args(vec![code], node_list);
+                    if options.bignum {
+                        node_list.push(Box::new(SARQ {
+                            target: Box::new(ARGUMENT_REGISTERS[0].clone()),
+                            arg: Box::new(Fixnum(1)),
+                        }));
+                    }
+                    node_list.push(Box::new(CALLQ("exit".to_string(), 1)));
+                    node_list.push(Box::new(MOVQ {
+                        target,
+                        source: Box::new(RAX),
+                    }));
+                }
+
+                // the runtime draws the fixnum from its generator
+                Random(bound) => {
+                    select_args(vec![bound], node_list);
+                    if options.bignum {
+                        node_list.push(Box::new(SARQ {
+                            target: Box::new(ARGUMENT_REGISTERS[0].clone()),
+                            arg: Box::new(Fixnum(1)),
+                        }));
+                    }
+                    node_list.push(Box::new(CALLQ("random_int".to_string(), 1)));
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: Box::new(RAX),
+                    }));
+                    select_retag(target, options, node_list);
+                }
+
+                ArrayLength(array) => {
+                    select_array_length(array, target.clone(), node_list);
+                    select_retag(target, options, node_list);
+                }
+
+                ArrayRef(array, index) => {
+                    let elem = select_array_element(array, index, options, node_list);
+                    node_list.push(Box::new(MOVQ {
+                        target,
+                        source: elem,
+                    }));
+                }
+
+                VectorRef(vec, index) => {
+                    let elem = select_element(vec, index, options, node_list);
+                    node_list.push(Box::new(MOVQ {
+                        target,
+                        source: elem,
+                    }));
+                }
+
+                StringLength(s) => {
+                    node_list.push(Box::new(MOVQ {
+                        target: Box::new(R11),
+                        source: s,
+                    }));
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: Box::new(Deref(Box::new(R11), 0)),
+                    }));
+                    select_retag(target, options, node_list);
+                }
+
+                StringRef(s, index) => {
+                    let byte = select_byte(s, index, options, node_list);
+                    node_list.push(Box::new(MOVZBQ {
+                        target,
+                        source: byte,
+                    }));
+                }
+
+                Apply(fun, args) => {
+                    // arguments are passed in registers, the result is returned in RAX
+                    let arity = args.len();
+                    select_args(args, node_list);
+                    match *fun {
+                        FunRef(label) => node_list.push(Box::new(CALLQ(label, arity))),
+                        fun => node_list.push(Box::new(ICALLQ(Box::new(fun), arity))),
+                    }
+                    node_list.push(Box::new(MOVQ {
+                        target,
+                        source: Box::new(RAX),
+                    }));
+                }
+
+                Inject(exp, t) => {
+                    // pointers are aligned to 8 bytes, other values are shifted
+                    node_list.push(Box::new(MOVQ {
+                        target: target.clone(),
+                        source: select_atom(exp),
+                    }));
+                    if !t.is_pointer() {
+                        node_list.push(Box::new(SALQ {
+                            target: target.clone(),
+                            arg: Box::new(Fixnum(3)),
+                        }));
+                    }
+                    node_list.push(Box::new(ORQ {
+                        target,
+                        arg: Box::new(Fixnum(t.any_tag().unwrap())),
+                    }));
+                }
+
+                Project(exp, t, pos) => select_project(exp, t, pos, target, node_list),
+
+                node @ Global(_) => node_list.push(Box::new(MOVQ {
+                    target,
+                    source: Box::new(node),
+                })),
+
+                node @ FunRef(_) | node @ Str(_) | node @ Symbol(_) => {
+                    node_list.push(Box::new(LEAQ {
+                        target,
+                        source: Box::new(node),
+                    }))
+                }
+
+                // the elements are emitted as words after the header
+                StaticVector(elems, t) => node_list.push(Box::new(LEAQ {
+                    target,
+                    source: Box::new(StaticVector(
+                        elems.into_iter().map(select_atom).collect(),
+                        t,
+                    )),
+                })),
+
+                Read(t) => {
                     let rax_node = Box::new(RAX);
-                    let call_node = Box::new(CALLQ("read_int"));
+                    let fun = match t {
+                        Type::Boolean => "read_bool",
+                        Type::Char => "read_char",
+                        // big integers are read by the runtime
+                        _ if options.bignum => "read_integer",
+                        _ => "read_int",
+                    };
+                    let call_node = Box::new(CALLQ(fun.to_string(), 0));
                     let move_node = Box::new(MOVQ {
                         target,
                         source: rax_node,
@@ -49,16 +1058,10 @@ fn select_one_inst(node: Node, node_list: &mut Vec<Box<Node>>) {
                     node_list.push(move_node);
                 }
 
-                node @ True | node @ False => {
-                    let num = match node {
-                        True => 1,
-                        False => 0,
-                        _ => panic!(),
-                    };
-                    let node = Box::new(Fixnum(num));
+                node @ True | node @ False | node @ Void | node @ Char(_) | node @ Flonum(_) => {
                     let move_node = Box::new(MOVQ {
                         target,
-                        source: node,
+                        source: select_atom(Box::new(node)),
                     });
                     node_list.push(move_node);
                 }
@@ -69,49 +1072,136 @@ fn select_one_inst(node: Node, node_list: &mut Vec<Box<Node>>) {
             }
         }
 
-        If {
+        IfGoto {
             cond,
-            if_exps,
-            else_exps,
-            if_live_afters,
-            else_live_afters,
+            then_label,
+            else_label,
         } => {
-            assert!(cond.var().is_some(), "cond must be var or literal");
-            let if_exps = select_inst(if_exps);
-            let else_exps = select_inst(else_exps);
-            node_list.push(Box::new(If {
-                cond,
-                if_exps,
-                else_exps,
-                if_live_afters,
-                else_live_afters,
+            assert!(
+                cond.var().is_some() || cond.comparison().is_some(),
+                "cond must be var or comparison"
+            );
+            let cond = select_comparison(*cond);
+            match cond.comparison() {
+                Some((cond_code, lhs, rhs)) if options.bignum && cond_code != CondCode::E => {
+                    let branch = |node_list: &mut Vec<Box<Node>>| {
+                        node_list.push(Box::new(JMPIF(cond_code.clone(), then_label.clone())));
+                        node_list.push(Box::new(JMP(else_label.clone())));
+                    };
+                    let (lhs, rhs) = (Box::new(lhs.clone()), Box::new(rhs.clone()));
+                    select_bignum_comparison(lhs, rhs, labels, branch, node_list);
+                    return;
+                }
+                Some((cond_code, lhs, rhs)) => {
+                    // jump by the comparison directly
+                    node_list.push(Box::new(CMPQ(Box::new(rhs.clone()), Box::new(lhs.clone()))));
+                    node_list.push(Box::new(JMPIF(cond_code, then_label)));
+                }
+                None => {
+                    node_list.push(Box::new(CMPQ(Box::new(Fixnum(1)), Box::new(cond))));
+                    node_list.push(Box::new(JMPIF(CondCode::E, then_label)));
+                }
+            }
+            node_list.push(Box::new(JMP(else_label)));
+        }
+
+        GlobalSet(label, value) => node_list.push(Box::new(MOVQ {
+            target: Box::new(Global(label)),
+            source: select_atom(value),
+        })),
+
+        VectorSet(vec, index, value) => {
+            let elem = select_element(vec, index, options, node_list);
+            node_list.push(Box::new(MOVQ {
+                target: elem,
+                source: select_atom(value),
             }));
         }
 
-        Program(sub_node) => match sub_node.as_ref() {
-            Var(_) | Fixnum(_) => {
-                let target = Box::new(RAX);
-                node_list.push(Box::new(MOVQ {
-                    target,
-                    source: sub_node,
-                }));
-            }
-            val => {
-                panic!("unexpected {:?}", val);
+        ArraySet(array, index, value) => {
+            let elem = select_array_element(array, index, options, node_list);
+            node_list.push(Box::new(MOVQ {
+                target: elem,
+                source: select_atom(value),
+            }));
+        }
+
+        Collect(bytes) => {
+            node_list.push(Box::new(MOVQ {
+                target: Box::new(ARGUMENT_REGISTERS[0].clone()),
+                source: Box::new(Fixnum(bytes as isize)),
+            }));
+            node_list.push(Box::new(CALLQ("collect".to_string(), 1)));
+        }
+
+        Goto(label) => node_list.push(Box::new(JMP(label))),
+
+        node @ Label(_) => node_list.push(Box::new(node)),
+
+        TailCall(fun, args) => {
+            let arity = args.len();
+            select_args(args, node_list);
+            match *fun {
+                FunRef(label) => node_list.push(Box::new(TAILJMP(label, arity))),
+                fun => node_list.push(Box::new(ITAILJMP(Box::new(fun), arity))),
             }
-        },
+        }
+
+        // the value is returned in RAX
+        Return(value) => node_list.push(Box::new(MOVQ {
+            target: Box::new(RAX),
+            source: select_atom(value),
+        })),
         val => {
             panic!("unexpected {:?}", val);
         }
     }
 }
 
-pub fn select_inst(node_list: Vec<Box<Node>>) -> Vec<Box<Node>> {
-    let mut new_node_list = Vec::with_capacity(node_list.len());
-
-    for node in node_list {
-        select_one_inst(*node, &mut new_node_list);
+/// Select instructions of each definition, parameters are moved out of the argument registers,
+/// the operands are still variables until allocate registers assigns their homes,
+/// the constant values of globals are kept in the info of main
+pub fn select_inst(defs: Vec<Box<Node>>, options: &Options) -> Vec<Function> {
+    let mut globals = Vec::new();
+    let mut functions: Vec<_> = defs
+        .into_iter()
+        .filter_map(|def| match *def {
+            Node::DefGlobal { name, value } => {
+                let value = select_atom(value).fixnum().expect("constant global");
+                globals.push((name, value));
+                None
+            }
+            Node::Def {
+                name, params, body, ..
+            } => {
+                let mut new_node_list = Vec::with_capacity(body.len());
+                for ((param, _), reg) in params.into_iter().zip(ARGUMENT_REGISTERS.iter()) {
+                    new_node_list.push(Box::new(Node::MOVQ {
+                        target: Box::new(Node::Var(param)),
+                        source: Box::new(reg.clone()),
+                    }));
+                }
+                let mut labels = Labels {
+                    function: name.clone(),
+                    cnt: 0,
+                };
+                for node in body {
+                    select_one_inst(*node, options, &mut labels, &mut new_node_list);
+                }
+                Some(Function {
+                    name,
+                    body: new_node_list,
+                    info: Info::default(),
+                })
+            }
+            node => panic!("unexpected {:?}", node),
+        })
+        .collect();
+    if let Some(main) = functions
+        .iter_mut()
+        .find(|function| function.name == "main")
+    {
+        main.info.globals = globals;
     }
-
-    new_node_list
+    functions
 }
@@ -0,0 +1,696 @@
+/// Rewrite derived forms into the core language
+use super::limit_functions::pack_constructor;
+use crate::ast::{accessor_name, hidden_name, mutator_name, predicate_name, Node, Type};
+
+/// a struct is a vector of its fields
+fn struct_defs(name: String, fields: Vec<(String, Type)>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    let struct_t = Type::Named(name.clone());
+    let this = Box::new(Var("struct".to_string()));
+    let value = Box::new(Var("value".to_string()));
+    let vector_t = Type::Vector(fields.iter().map(|(_, t)| t.clone()).collect());
+    let elems = fields
+        .iter()
+        .map(|(field, _)| Box::new(Var(field.clone())))
+        .collect();
+    let mut defs = Vec::with_capacity(fields.len() * 2 + 1);
+    for (i, (field, t)) in fields.iter().enumerate() {
+        let index = Box::new(Fixnum(i as isize));
+        defs.push(Box::new(Def {
+            name: accessor_name(&name, field),
+            params: vec![("struct".to_string(), struct_t.clone())],
+            ret: t.clone(),
+            body: vec![Box::new(VectorRef(this.clone(), index.clone()))],
+        }));
+        defs.push(Box::new(Def {
+            name: mutator_name(&name, field),
+            params: vec![
+                ("struct".to_string(), struct_t.clone()),
+                ("value".to_string(), t.clone()),
+            ],
+            ret: Type::Void,
+            body: vec![Box::new(VectorSet(this.clone(), index, value.clone()))],
+        }));
+    }
+    let (params, body) =
+        pack_constructor(fields, Box::new(HasType(Box::new(Vector(elems)), vector_t)));
+    defs.insert(
+        0,
+        Box::new(Def {
+            name,
+            params,
+            ret: struct_t,
+            body: vec![body],
+        }),
+    );
+    defs
+}
+
+/// a value of a data type is a vector of the index of its variant and the fields
+fn data_defs(name: String, variants: Vec<(String, Vec<Type>)>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    let data_t = Type::Named(name);
+    let mut defs = Vec::with_capacity(variants.len() * 2);
+    for (tag, (variant, field_types)) in variants.into_iter().enumerate() {
+        let params: Vec<_> = field_types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (format!("field{}", i), t.clone()))
+            .collect();
+        let elems = std::iter::once(Box::new(Fixnum(tag as isize)))
+            .chain(params.iter().map(|(param, _)| Box::new(Var(param.clone()))))
+            .collect();
+        let vector_t = Type::Vector(std::iter::once(Type::Fixnum).chain(field_types).collect());
+        let (params, body) =
+            pack_constructor(params, Box::new(HasType(Box::new(Vector(elems)), vector_t)));
+        defs.push(Box::new(Def {
+            name: predicate_name(&variant),
+            params: vec![("data".to_string(), data_t.clone())],
+            ret: Type::Boolean,
+            body: vec![Box::new(Eq(
+                Box::new(VectorRef(
+                    Box::new(Var("data".to_string())),
+                    Box::new(Fixnum(0)),
+                )),
+                Box::new(Fixnum(tag as isize)),
+            ))],
+        }));
+        defs.push(Box::new(Def {
+            name: variant,
+            params,
+            ret: data_t.clone(),
+            body: vec![body],
+        }));
+    }
+    defs
+}
+
+/// a named let is a while loop over the state of the parameters, a call of the loop
+/// assigns the state and runs the body again, another value in tail position is the result
+fn named_let(name: String, bindings: Vec<(String, Box<Node>)>, body: Box<Node>) -> Box<Node> {
+    use Node::*;
+
+    let again = format!("{}.again", name);
+    let result = format!("{}.result", name);
+    let states: Vec<_> = (0..bindings.len())
+        .map(|i| format!("{}.{}", name, i))
+        .collect();
+    let body = loop_tail(shrink(body), &name, &states, &again, &result);
+    let body = bindings
+        .iter()
+        .zip(states.iter())
+        .rev()
+        .fold(body, |exp, ((param, _), state)| {
+            Box::new(Let {
+                name: param.clone(),
+                value: Box::new(Var(state.clone())),
+                exp,
+            })
+        });
+    let stop = Box::new(SetBang {
+        name: again.clone(),
+        value: Box::new(False),
+    });
+    let while_exp = Node::new_while(
+        Box::new(Var(again.clone())),
+        Box::new(Begin(vec![stop], body)),
+    );
+    let exp = Box::new(Let {
+        name: again,
+        value: Box::new(True),
+        exp: Box::new(Let {
+            name: result.clone(),
+            value: Box::new(Void),
+            exp: Box::new(Begin(vec![Box::new(while_exp)], Box::new(Var(result)))),
+        }),
+    });
+    bindings
+        .into_iter()
+        .zip(states)
+        .rev()
+        .fold(exp, |exp, ((_, value), state)| {
+            Box::new(Let {
+                name: state,
+                value: shrink(value),
+                exp,
+            })
+        })
+}
+
+/// the vector of each clause is bound before its variables, so a value does not see them
+fn let_values(bindings: Vec<(Vec<String>, Box<Node>)>, exp: Box<Node>) -> Box<Node> {
+    use Node::*;
+
+    let temp = |i: usize| format!("values.{}", i);
+    let exp = bindings
+        .iter()
+        .enumerate()
+        .rev()
+        .fold(shrink(exp), |exp, (i, (names, _))| {
+            names
+                .iter()
+                .enumerate()
+                .rev()
+                .fold(exp, |exp, (index, name)| {
+                    let value = VectorRef(Box::new(Var(temp(i))), Box::new(Fixnum(index as isize)));
+                    Box::new(Let {
+                        name: name.clone(),
+                        value: Box::new(value),
+                        exp,
+                    })
+                })
+        });
+    bindings
+        .into_iter()
+        .enumerate()
+        .rev()
+        .fold(exp, |exp, (i, (_, value))| {
+            Box::new(Let {
+                name: temp(i),
+                value: shrink(value),
+                exp,
+            })
+        })
+}
+
+/// a do loop runs the body while the test fails, the values of the variables are
+/// kept in hidden variables between the runs, so each run binds the variables afresh
+/// and the closures made by a run keep its values, the initial values are evaluated
+/// before any variable is bound
+fn do_loop(
+    bindings: Vec<(String, Box<Node>, Option<Box<Node>>)>,
+    test: Box<Node>,
+    result: Box<Node>,
+    body: Box<Node>,
+) -> Box<Node> {
+    use Node::*;
+
+    let temp = |i: usize| hidden_name(&format!("do.{}", i));
+    let running = hidden_name("do.running");
+    let mut names = Vec::with_capacity(bindings.len());
+    let mut inits = Vec::with_capacity(bindings.len());
+    let mut steps = Vec::with_capacity(bindings.len());
+    for (i, (name, init, step)) in bindings.into_iter().enumerate() {
+        // a variable without a step keeps the value of the run
+        let step = step
+            .map(shrink)
+            .unwrap_or_else(|| Box::new(Var(name.clone())));
+        steps.push((temp(i), step));
+        inits.push((temp(i), shrink(init)));
+        names.push(name);
+    }
+    let bind = |exp: Box<Node>| {
+        names.iter().enumerate().rev().fold(exp, |exp, (i, name)| {
+            Box::new(Let {
+                name: name.clone(),
+                value: Box::new(Var(temp(i))),
+                exp,
+            })
+        })
+    };
+    let assign = |name: String, value: Box<Node>| Box::new(SetBang { name, value });
+    // the test may assign the variables, so the result sees them through the temps
+    let mut stop: Vec<_> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| assign(temp(i), Box::new(Var(name.clone()))))
+        .collect();
+    stop.push(assign(running.clone(), Box::new(False)));
+    // the steps only see the variables, so the temps are assigned one by one
+    let mut again = vec![shrink(body)];
+    again.extend(steps.into_iter().map(|(temp, step)| assign(temp, step)));
+    let run = bind(Box::new(Node::new_if(
+        shrink(test),
+        Box::new(Begin(stop, Box::new(Void))),
+        Box::new(Begin(again, Box::new(Void))),
+    )));
+    let while_exp = Node::new_while(Box::new(Var(running.clone())), run);
+    let exp = Box::new(Let {
+        name: running,
+        value: Box::new(True),
+        exp: Box::new(Begin(vec![Box::new(while_exp)], bind(shrink(result)))),
+    });
+    inits.into_iter().rev().fold(exp, |exp, (temp, init)| {
+        Box::new(Let {
+            name: temp,
+            value: init,
+            exp,
+        })
+    })
+}
+
+/// rewrite the expressions in tail position of the body of a loop,
+/// the arguments of a call are evaluated before the state is assigned,
+/// a let which shadows the loop is a result
+fn loop_tail(
+    node: Box<Node>,
+    name: &str,
+    states: &[String],
+    again: &str,
+    result: &str,
+) -> Box<Node> {
+    use Node::*;
+
+    let tail = |exp| loop_tail(exp, name, states, again, result);
+    let node = match *node {
+        If {
+            cond,
+            if_exps,
+            else_exps,
+        } => If {
+            cond,
+            if_exps: if_exps.into_iter().map(tail).collect(),
+            else_exps: else_exps.into_iter().map(tail).collect(),
+        },
+        Begin(effects, exp) => Begin(effects, tail(exp)),
+        Let {
+            name: var,
+            value,
+            exp,
+        } if var != name => Let {
+            name: var,
+            value,
+            exp: tail(exp),
+        },
+        Apply(fun, args) if fun.var().map(String::as_str) == Some(name) => {
+            let temps: Vec<_> = (0..args.len())
+                .map(|i| format!("{}.arg{}", name, i))
+                .collect();
+            let assigns = states
+                .iter()
+                .zip(temps.iter())
+                .map(|(state, temp)| {
+                    Box::new(SetBang {
+                        name: state.clone(),
+                        value: Box::new(Var(temp.clone())),
+                    })
+                })
+                .collect();
+            let next = Box::new(SetBang {
+                name: again.to_string(),
+                value: Box::new(True),
+            });
+            let exp = Box::new(Begin(assigns, next));
+            return args
+                .into_iter()
+                .zip(temps)
+                .rev()
+                .fold(exp, |exp, (value, temp)| {
+                    Box::new(Let {
+                        name: temp,
+                        value,
+                        exp,
+                    })
+                });
+        }
+        node => SetBang {
+            name: result.to_string(),
+            value: Box::new(node),
+        },
+    };
+    Box::new(node)
+}
+
+/// the runtime exits before an element of the empty list is loaded
+fn pair_ref(list: Box<Node>, index: isize, error: &str) -> Box<Node> {
+    use Node::*;
+
+    let pair = "pair".to_string();
+    Box::new(Let {
+        name: pair.clone(),
+        value: list,
+        exp: Box::new(If {
+            cond: Box::new(Eq(Box::new(Var(pair.clone())), Box::new(Fixnum(0)))),
+            if_exps: vec![Box::new(Apply(
+                Box::new(FunRef(error.to_string())),
+                Vec::new(),
+            ))],
+            else_exps: vec![Box::new(VectorRef(
+                Box::new(Var(pair)),
+                Box::new(Fixnum(index)),
+            ))],
+        }),
+    })
+}
+
+pub fn shrink(node: Box<Node>) -> Box<Node> {
+    use Node::*;
+
+    let node = match *node {
+        Program(defs, exp) => {
+            let defs = defs
+                .into_iter()
+                .flat_map(|def| match *def {
+                    DefStruct { name, fields } => struct_defs(name, fields),
+                    DefType { name, variants } => data_defs(name, variants),
+                    def => vec![shrink(Box::new(def))],
+                })
+                .collect();
+            Program(defs, shrink(exp))
+        }
+        Def {
+            name,
+            params,
+            ret,
+            body,
+        } => Def {
+            name,
+            params,
+            ret,
+            body: body.into_iter().map(shrink).collect(),
+        },
+        Apply(fun, args) => Apply(shrink(fun), args.into_iter().map(shrink).collect()),
+        Lambda {
+            params,
+            ret,
+            body,
+            free_vars,
+        } => Lambda {
+            params,
+            ret,
+            body: shrink(body),
+            free_vars,
+        },
+        Neg(sub_node) => Neg(shrink(sub_node)),
+        Not(sub_node) => Not(shrink(sub_node)),
+        Add(lhs, rhs) => Add(shrink(lhs), shrink(rhs)),
+        Sub(lhs, rhs) => Sub(shrink(lhs), shrink(rhs)),
+        Mul(lhs, rhs) => Mul(shrink(lhs), shrink(rhs)),
+        FAdd(lhs, rhs) => FAdd(shrink(lhs), shrink(rhs)),
+        FSub(lhs, rhs) => FSub(shrink(lhs), shrink(rhs)),
+        FMul(lhs, rhs) => FMul(shrink(lhs), shrink(rhs)),
+        FDiv(lhs, rhs) => FDiv(shrink(lhs), shrink(rhs)),
+        FixnumToFlonum(exp) => FixnumToFlonum(shrink(exp)),
+        FlonumToFixnum(exp) => FlonumToFixnum(shrink(exp)),
+        Quotient(lhs, rhs) => Quotient(shrink(lhs), shrink(rhs)),
+        Remainder(lhs, rhs) => Remainder(shrink(lhs), shrink(rhs)),
+        BitAnd(lhs, rhs) => BitAnd(shrink(lhs), shrink(rhs)),
+        BitOr(lhs, rhs) => BitOr(shrink(lhs), shrink(rhs)),
+        BitXor(lhs, rhs) => BitXor(shrink(lhs), shrink(rhs)),
+        BitNot(sub_node) => BitNot(shrink(sub_node)),
+        Shl(lhs, rhs) => Shl(shrink(lhs), shrink(rhs)),
+        Shr(lhs, rhs) => Shr(shrink(lhs), shrink(rhs)),
+        Sar(lhs, rhs) => Sar(shrink(lhs), shrink(rhs)),
+        Min(lhs, rhs) => Min(shrink(lhs), shrink(rhs)),
+        Max(lhs, rhs) => Max(shrink(lhs), shrink(rhs)),
+        // shift left by a positive count, otherwise shift right arithmetically
+        Ash(lhs, rhs) => match rhs.fixnum() {
+            Some(count) if count >= 0 => Shl(shrink(lhs), rhs),
+            Some(count) => Sar(shrink(lhs), Box::new(Fixnum(-count))),
+            None => {
+                let n = Box::new(Var("ash.n".to_string()));
+                let k = Box::new(Var("ash.k".to_string()));
+                let shift = Node::new_if(
+                    Box::new(Lt(k.clone(), Box::new(Fixnum(0)))),
+                    Box::new(Sar(n.clone(), Box::new(Neg(k.clone())))),
+                    Box::new(Shl(n, k)),
+                );
+                Let {
+                    name: "ash.n".to_string(),
+                    value: shrink(lhs),
+                    exp: Box::new(Let {
+                        name: "ash.k".to_string(),
+                        value: shrink(rhs),
+                        exp: Box::new(shift),
+                    }),
+                }
+            }
+        },
+        Eq(lhs, rhs) => Eq(shrink(lhs), shrink(rhs)),
+        Lt(lhs, rhs) => Lt(shrink(lhs), shrink(rhs)),
+        Lte(lhs, rhs) => Lte(shrink(lhs), shrink(rhs)),
+        Gt(lhs, rhs) => Gt(shrink(lhs), shrink(rhs)),
+        Gte(lhs, rhs) => Gte(shrink(lhs), shrink(rhs)),
+        // rhs is only evaluated if lhs does not decide the result
+        And(lhs, rhs) => Node::new_if(shrink(lhs), shrink(rhs), Box::new(False)),
+        Or(lhs, rhs) => Node::new_if(shrink(lhs), Box::new(True), shrink(rhs)),
+        Let { name, value, exp } => Let {
+            name,
+            value: shrink(value),
+            exp: shrink(exp),
+        },
+        // each binding is visible to the bindings after it
+        LetStar { bindings, exp } => {
+            return bindings
+                .into_iter()
+                .rev()
+                .fold(shrink(exp), |exp, (name, value)| {
+                    Box::new(Let {
+                        name,
+                        value: shrink(value),
+                        exp,
+                    })
+                });
+        }
+        // bind every variable first, then assign the values in order
+        LetRec { bindings, exp } => {
+            let names: Vec<String> = bindings.iter().map(|(name, _)| name.clone()).collect();
+            let assigns = bindings
+                .into_iter()
+                .map(|(name, value)| {
+                    Box::new(SetBang {
+                        name,
+                        value: shrink(value),
+                    })
+                })
+                .collect();
+            let exp = Box::new(Begin(assigns, shrink(exp)));
+            return names.into_iter().rev().fold(exp, |exp, name| {
+                Box::new(Let {
+                    name,
+                    value: Box::new(Void),
+                    exp,
+                })
+            });
+        }
+        NamedLet {
+            name,
+            bindings,
+            body,
+            ..
+        } => return named_let(name, bindings, body),
+        SetBang { name, value } => SetBang {
+            name,
+            value: shrink(value),
+        },
+        DefGlobal { name, value } => DefGlobal {
+            name,
+            value: shrink(value),
+        },
+        Begin(effects, exp) => Begin(effects.into_iter().map(shrink).collect(), shrink(exp)),
+        If {
+            cond,
+            if_exps,
+            else_exps,
+        } => If {
+            cond: shrink(cond),
+            if_exps: if_exps.into_iter().map(shrink).collect(),
+            else_exps: else_exps.into_iter().map(shrink).collect(),
+        },
+        // the clauses are tested in order, the value is void if no clause matches
+        Cond { clauses, else_exp } => {
+            let else_exp = match else_exp {
+                Some(exp) => shrink(exp),
+                None => Box::new(Void),
+            };
+            return clauses
+                .into_iter()
+                .rev()
+                .fold(else_exp, |else_exp, (test, exp)| {
+                    Box::new(Node::new_if(shrink(test), shrink(exp), else_exp))
+                });
+        }
+        Do {
+            bindings,
+            test,
+            result,
+            body,
+        } => return do_loop(bindings, test, result, body),
+        // the results are returned in a vector
+        Values(exps, types) => HasType(
+            Box::new(Vector(exps.into_iter().map(shrink).collect())),
+            Type::Vector(types),
+        ),
+        LetValues { bindings, exp } => return let_values(bindings, exp),
+        // the value of body is dropped
+        When(cond, body) => Node::new_if(
+            shrink(cond),
+            Box::new(Begin(vec![shrink(body)], Box::new(Void))),
+            Box::new(Void),
+        ),
+        Unless(cond, body) => Node::new_if(
+            shrink(cond),
+            Box::new(Void),
+            Box::new(Begin(vec![shrink(body)], Box::new(Void))),
+        ),
+        Vector(elems) => Vector(elems.into_iter().map(shrink).collect()),
+        Literal(vec) => Literal(shrink(vec)),
+        VectorLength(vec) => VectorLength(shrink(vec)),
+        MakeArray(len, init, t) => MakeArray(shrink(len), shrink(init), t),
+        ArrayLength(array) => ArrayLength(shrink(array)),
+        Random(bound) => Random(shrink(bound)),
+        Exit(code) => Exit(shrink(code)),
+        Raise(msg) => Apply(
+            Box::new(FunRef("raise_exception".to_string())),
+            vec![shrink(msg)],
+        ),
+        CommandLineArguments => Apply(
+            Box::new(FunRef("command_line_arguments".to_string())),
+            Vec::new(),
+        ),
+        CallCC(fun) => Apply(Box::new(FunRef("call_cc".to_string())), vec![shrink(fun)]),
+        WithHandler(handler, thunk) => Apply(
+            Box::new(FunRef("with_handler".to_string())),
+            vec![shrink(thunk), shrink(handler)],
+        ),
+        ArrayRef(array, index) => ArrayRef(shrink(array), shrink(index)),
+        ArraySet(array, index, value) => ArraySet(shrink(array), shrink(index), shrink(value)),
+        VectorRef(vec, index) => VectorRef(shrink(vec), shrink(index)),
+        VectorSet(vec, index, value) => VectorSet(shrink(vec), shrink(index), shrink(value)),
+        // a box is a vector of one element
+        HasType(exp, Type::Box(t)) => match *exp {
+            MakeBox(exp) => HasType(Box::new(Vector(vec![shrink(exp)])), Type::Vector(vec![*t])),
+            exp => panic!("unexpected {:?}", exp),
+        },
+        // a pair is a vector of the car and the cdr
+        HasType(exp, Type::List(t)) => match *exp {
+            Cons(car, cdr) => HasType(
+                Box::new(Vector(vec![shrink(car), shrink(cdr)])),
+                Type::Vector(vec![(*t).clone(), Type::List(t)]),
+            ),
+            exp => panic!("unexpected {:?}", exp),
+        },
+        Car(exp) => *pair_ref(shrink(exp), 0, "car_of_empty_list"),
+        Cdr(exp) => *pair_ref(shrink(exp), 1, "cdr_of_empty_list"),
+        // the empty list is the null pointer
+        Null => Fixnum(0),
+        IsNull(exp) => Eq(shrink(exp), Box::new(Fixnum(0))),
+        Unbox(exp) => VectorRef(shrink(exp), Box::new(Fixnum(0))),
+        SetBox(exp, value) => VectorSet(shrink(exp), Box::new(Fixnum(0)), shrink(value)),
+        HasType(exp, t) => HasType(shrink(exp), t),
+        // string primitives are calls to the runtime
+        StringEq(lhs, rhs) => Apply(
+            Box::new(FunRef("string_equal".to_string())),
+            vec![shrink(lhs), shrink(rhs)],
+        ),
+        // the runtime allocates the string on the heap
+        StringAppend(lhs, rhs) => Apply(
+            Box::new(FunRef("string_append".to_string())),
+            vec![shrink(lhs), shrink(rhs)],
+        ),
+        // the runtime compares string keys by their bytes
+        MakeHashTable(Type::String, _) => {
+            Apply(Box::new(FunRef("make_string_hash".to_string())), Vec::new())
+        }
+        MakeHashTable(..) => Apply(Box::new(FunRef("make_hash".to_string())), Vec::new()),
+        // the runtime exits if the key is not found and there is no default
+        HashTableRef(table, key, default) => {
+            let (fun, args) = match default {
+                Some(default) => (
+                    "hash_ref_default",
+                    vec![shrink(table), shrink(key), shrink(default)],
+                ),
+                None => ("hash_ref", vec![shrink(table), shrink(key)]),
+            };
+            Apply(Box::new(FunRef(fun.to_string())), args)
+        }
+        HashTableSet(table, key, value) => Apply(
+            Box::new(FunRef("hash_set".to_string())),
+            vec![shrink(table), shrink(key), shrink(value)],
+        ),
+        // characters are represented by their codes, the conversions only change
+        // the representation of integers with big integers
+        CharToInteger(exp) => match *shrink(exp) {
+            Char(chr) => Fixnum(chr as isize),
+            exp => CharToInteger(Box::new(exp)),
+        },
+        IntegerToChar(exp) => IntegerToChar(shrink(exp)),
+        StringLength(s) => StringLength(shrink(s)),
+        StringRef(s, index) => StringRef(shrink(s), shrink(index)),
+        Inject(exp, t) => Inject(shrink(exp), t),
+        Project(exp, t, pos) => Project(shrink(exp), t, pos),
+        While {
+            cond_exps,
+            cond,
+            body_exps,
+        } => While {
+            cond_exps,
+            cond: shrink(cond),
+            body_exps: body_exps.into_iter().map(shrink).collect(),
+        },
+        node => node,
+    };
+    Box::new(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Node::*;
+
+    fn var(name: &str) -> Box<Node> {
+        Box::new(Var(name.to_string()))
+    }
+
+    #[test]
+    fn and_or_become_branches() {
+        assert_eq!(
+            shrink(Box::new(And(var("a"), var("b")))),
+            Box::new(Node::new_if(var("a"), var("b"), Box::new(False)))
+        );
+        assert_eq!(
+            shrink(Box::new(Or(var("a"), var("b")))),
+            Box::new(Node::new_if(var("a"), Box::new(True), var("b")))
+        );
+    }
+
+    #[test]
+    fn let_star_becomes_nested_lets() {
+        let node = Box::new(LetStar {
+            bindings: vec![
+                ("x".to_string(), Box::new(Fixnum(1))),
+                ("y".to_string(), var("x")),
+            ],
+            exp: var("y"),
+        });
+        let expected = Box::new(Let {
+            name: "x".to_string(),
+            value: Box::new(Fixnum(1)),
+            exp: Box::new(Let {
+                name: "y".to_string(),
+                value: var("x"),
+                exp: var("y"),
+            }),
+        });
+        assert_eq!(shrink(node), expected);
+    }
+
+    #[test]
+    fn cond_becomes_nested_branches() {
+        let node = Box::new(Cond {
+            clauses: vec![
+                (var("a"), Box::new(Fixnum(1))),
+                (var("b"), Box::new(Fixnum(2))),
+            ],
+            else_exp: None,
+        });
+        let expected = Box::new(Node::new_if(
+            var("a"),
+            Box::new(Fixnum(1)),
+            Box::new(Node::new_if(var("b"), Box::new(Fixnum(2)), Box::new(Void))),
+        ));
+        assert_eq!(shrink(node), expected);
+    }
+
+    #[test]
+    fn when_drops_the_value_of_the_body() {
+        let expected = Box::new(Node::new_if(
+            var("a"),
+            Box::new(Begin(vec![var("b")], Box::new(Void))),
+            Box::new(Void),
+        ));
+        assert_eq!(shrink(Box::new(When(var("a"), var("b")))), expected);
+    }
+}
@@ -0,0 +1,78 @@
+/// Tag integers when big integers are enabled, a fixnum is shifted left by 1
+/// and a big integer is a pointer with bit 0 set,
+/// operations without a fast path in instruction selection call the runtime
+use crate::ast::*;
+
+/// fixnums of 63 bits keep their tag
+const FIXNUM_MAX: isize = (1 << 62) - 1;
+const FIXNUM_MIN: isize = -(1 << 62);
+
+fn call(fun: &str, args: Vec<Box<Node>>) -> Node {
+    Node::Apply(Box::new(Node::FunRef(fun.to_string())), args)
+}
+
+/// a literal index addresses the element directly, so it is not tagged
+fn tag_index(index: Box<Node>) -> Box<Node> {
+    match *index {
+        index @ Node::Fixnum(_) => Box::new(index),
+        index => tag_integers(Box::new(index)),
+    }
+}
+
+/// a big integer can not be chosen by a conditional move, so the operands are compared
+fn select(lhs: Box<Node>, rhs: Box<Node>, min: bool) -> Node {
+    use Node::*;
+
+    let a = Box::new(Var("select.lhs".to_string()));
+    let b = Box::new(Var("select.rhs".to_string()));
+    let (if_exp, else_exp) = if min {
+        (a.clone(), b.clone())
+    } else {
+        (b.clone(), a.clone())
+    };
+    let exp = Node::new_if(Box::new(Lt(a, b)), if_exp, else_exp);
+    Let {
+        name: "select.lhs".to_string(),
+        value: tag_integers(lhs),
+        exp: Box::new(Let {
+            name: "select.rhs".to_string(),
+            value: tag_integers(rhs),
+            exp: Box::new(exp),
+        }),
+    }
+}
+
+pub fn tag_integers(node: Box<Node>) -> Box<Node> {
+    use Node::*;
+
+    let node = match *node {
+        Fixnum(n) if (FIXNUM_MIN..=FIXNUM_MAX).contains(&n) => Fixnum(n << 1),
+        Fixnum(n) => call("integer_from_fixnum", vec![Box::new(Fixnum(n))]),
+        VectorRef(vec, index) => VectorRef(tag_integers(vec), tag_index(index)),
+        VectorSet(vec, index, value) => {
+            VectorSet(tag_integers(vec), tag_index(index), tag_integers(value))
+        }
+        StringRef(s, index) => StringRef(tag_integers(s), tag_index(index)),
+        Quotient(lhs, rhs) => call(
+            "integer_quotient",
+            vec![tag_integers(lhs), tag_integers(rhs)],
+        ),
+        Remainder(lhs, rhs) => call(
+            "integer_remainder",
+            vec![tag_integers(lhs), tag_integers(rhs)],
+        ),
+        BitAnd(lhs, rhs) => call("integer_and", vec![tag_integers(lhs), tag_integers(rhs)]),
+        BitOr(lhs, rhs) => call("integer_or", vec![tag_integers(lhs), tag_integers(rhs)]),
+        BitXor(lhs, rhs) => call("integer_xor", vec![tag_integers(lhs), tag_integers(rhs)]),
+        BitNot(exp) => call("integer_not", vec![tag_integers(exp)]),
+        Shl(lhs, rhs) => call("integer_shl", vec![tag_integers(lhs), tag_integers(rhs)]),
+        Shr(lhs, rhs) => call("integer_shr", vec![tag_integers(lhs), tag_integers(rhs)]),
+        Sar(lhs, rhs) => call("integer_sar", vec![tag_integers(lhs), tag_integers(rhs)]),
+        Min(lhs, rhs) => select(lhs, rhs, true),
+        Max(lhs, rhs) => select(lhs, rhs, false),
+        FixnumToFlonum(exp) => call("integer_to_flonum", vec![tag_integers(exp)]),
+        FlonumToFixnum(exp) => call("integer_from_flonum", vec![tag_integers(exp)]),
+        node => node.map_children(&mut tag_integers),
+    };
+    Box::new(node)
+}
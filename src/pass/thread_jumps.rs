@@ -0,0 +1,160 @@
+/// Jump straight to the end of a chain of blocks which only jump on, merge a block
+/// into its only predecessor when the predecessor only goes to it, and drop the jumps
+/// to the label right after them
+use crate::ast::*;
+use crate::cfg::Cfg;
+use std::collections::HashMap;
+
+fn is_jump(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Goto(_) | Node::IfGoto { .. } | Node::TailCall(..)
+    )
+}
+
+/// the label a jump to the label ends up at
+fn resolve<'a>(forward: &'a HashMap<String, String>, mut label: &'a String) -> &'a String {
+    // a loop of empty blocks jumps forever, so it is kept as it is
+    let mut steps = 0;
+    while let Some(next) = forward.get(label) {
+        if steps == forward.len() {
+            break;
+        }
+        label = next;
+        steps += 1;
+    }
+    label
+}
+
+fn thread(name: &str, body: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    let mut cfg = Cfg::new(name, body);
+    let mut forward = HashMap::new();
+    for (i, block) in cfg.blocks.iter().enumerate().skip(1) {
+        match block.body.as_slice() {
+            [node] => {
+                if let Goto(label) = node.as_ref() {
+                    forward.insert(block.label.clone(), label.clone());
+                }
+            }
+            // the last block goes on to the end of the function, which has no label
+            [] if i + 1 < cfg.blocks.len() => {
+                forward.insert(block.label.clone(), cfg.blocks[i + 1].label.clone());
+            }
+            _ => {}
+        }
+    }
+    for block in &mut cfg.blocks {
+        for node in &mut block.body {
+            let threaded = match node.as_ref() {
+                Goto(label) => Goto(resolve(&forward, label).clone()),
+                IfGoto {
+                    cond,
+                    then_label,
+                    else_label,
+                } => {
+                    let then_label = resolve(&forward, then_label);
+                    let else_label = resolve(&forward, else_label);
+                    if then_label == else_label {
+                        Goto(then_label.clone())
+                    } else {
+                        IfGoto {
+                            cond: cond.clone(),
+                            then_label: then_label.clone(),
+                            else_label: else_label.clone(),
+                        }
+                    }
+                }
+                _ => continue,
+            };
+            **node = threaded;
+        }
+    }
+    // the blocks jumped over are not reached any more
+    let cfg = Cfg::new(name, cfg.into_body(|_| true));
+    let order = cfg.reverse_postorder();
+    cfg.into_body(|i| order.contains(&i))
+}
+
+/// merge one block into its only predecessor
+fn merge(name: &str, body: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    let mut cfg = Cfg::new(name, body);
+    let len = cfg.blocks.len();
+    let found = (1..len).find_map(|b| {
+        let block = &cfg.blocks[b];
+        let pred = match block.preds.as_slice() {
+            [pred] if *pred != b && cfg.blocks[*pred].succs == [b] => *pred,
+            _ => return None,
+        };
+        // a moved block which falls through must jump to its next block
+        let falls_through = !block.body.iter().any(|node| is_jump(node));
+        if pred + 1 != b && falls_through && b + 1 == len {
+            return None;
+        }
+        Some((pred, b, falls_through))
+    });
+    let (pred, b, falls_through) = match found {
+        Some(found) => found,
+        None => return cfg.into_body(|_| true),
+    };
+    let mut body = std::mem::take(&mut cfg.blocks[b].body);
+    if pred + 1 != b && falls_through {
+        body.push(Box::new(Node::Goto(cfg.blocks[b + 1].label.clone())));
+    }
+    let pred_body = &mut cfg.blocks[pred].body;
+    if let Some(pos) = pred_body.iter().position(|node| is_jump(node)) {
+        pred_body.truncate(pos);
+    }
+    pred_body.extend(body);
+    cfg.into_body(|i| i != b)
+}
+
+fn thread_body(name: &str, mut body: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    loop {
+        let new_body = merge(name, thread(name, body.clone()));
+        if new_body == body {
+            break;
+        }
+        body = new_body;
+    }
+    // the jump to the next label falls through instead
+    let mut new_body: Vec<Box<Node>> = Vec::with_capacity(body.len());
+    for node in body {
+        if let (Label(label), Some(Goto(target))) =
+            (node.as_ref(), new_body.last().map(|node| node.as_ref()))
+        {
+            if label == target {
+                new_body.pop();
+            }
+        }
+        new_body.push(node);
+    }
+    new_body
+}
+
+pub fn thread_jumps(defs: Vec<Box<Node>>) -> Vec<Box<Node>> {
+    use Node::*;
+
+    defs.into_iter()
+        .map(|def| match *def {
+            Def {
+                name,
+                params,
+                ret,
+                body,
+            } => {
+                let body = thread_body(&name, body);
+                Box::new(Def {
+                    name,
+                    params,
+                    ret,
+                    body,
+                })
+            }
+            def => Box::new(def),
+        })
+        .collect()
+}
@@ -1,8 +1,9 @@
+use super::limit_functions::constructor_types;
 use crate::ast::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::result::Result;
 
-fn expect_type_eq(expected: Type, actual: Type) -> Result<(), String> {
+fn expect_type_eq(expected: &Type, actual: &Type) -> Result<(), String> {
     if expected != actual {
         return Err(format!(
             "Incorrect type: expected {:?} actual {:?}",
@@ -12,58 +13,1292 @@ fn expect_type_eq(expected: Type, actual: Type) -> Result<(), String> {
     Ok(())
 }
 
-fn type_check_node(node: Box<Node>, var_types: &mut HashMap<String, Type>) -> Result<Type, String> {
+/// prefix the message by the position of the expression,
+/// which is unknown for the expressions made by the passes before
+fn at(pos: Pos, msg: String) -> String {
+    if pos == Pos::default() {
+        msg
+    } else {
+        format!("{}: {}", pos, msg)
+    }
+}
+
+/// a cast is inserted between types which only differ by Any,
+/// vectors are shared so their elements are never cast
+fn castable(from: &Type, to: &Type) -> bool {
+    match (from, to) {
+        _ if from == to => true,
+        (Type::Any, t) | (t, Type::Any) => match t {
+            Type::Function(params, ret) => {
+                params.iter().all(|t| castable(t, &Type::Any)) && castable(ret, &Type::Any)
+            }
+            t => t.any_tag().is_some(),
+        },
+        (Type::Function(from_params, from_ret), Type::Function(to_params, to_ret)) => {
+            from_params.len() == to_params.len()
+                && from_params
+                    .iter()
+                    .zip(to_params)
+                    .all(|(f, t)| castable(t, f))
+                && castable(from_ret, to_ret)
+        }
+        _ => false,
+    }
+}
+
+/// wrap the expression by a cast if the type differs from the expected one
+fn insert_cast(node: &mut Node, from: Type, to: &Type, pos: Pos) -> Result<(), String> {
+    if from == *to || from == Type::Bottom || (from == Type::Null && matches!(to, Type::List(_))) {
+        return Ok(());
+    }
+    if !castable(&from, to) {
+        return expect_type_eq(to, &from).map_err(|msg| at(pos, msg));
+    }
+    let exp = Box::new(std::mem::take(node));
+    *node = Node::Cast {
+        exp,
+        from,
+        to: to.clone(),
+        pos,
+    };
+    Ok(())
+}
+
+/// branches are cast to Any if one of them is Any,
+/// the empty list joins with any list, branches which do not return are ignored
+fn join_types(types: &[Type]) -> Result<Type, String> {
+    if !types.is_empty() && types.iter().all(|t| *t == Type::Bottom) {
+        return Ok(Type::Bottom);
+    }
+    let types: Vec<_> = types.iter().filter(|t| **t != Type::Bottom).collect();
+    if types.contains(&&Type::Any) {
+        return Ok(Type::Any);
+    }
+    if let Some(t @ Type::List(_)) = types.iter().find(|t| matches!(t, Type::List(_))) {
+        for other in &types {
+            if **other != Type::Null {
+                expect_type_eq(t, other)?;
+            }
+        }
+        return Ok((*t).clone());
+    }
+    match types.split_first() {
+        Some((t, rest)) => {
+            for other in rest {
+                expect_type_eq(t, other)?;
+            }
+            Ok((*t).clone())
+        }
+        None => Ok(Type::Void),
+    }
+}
+
+/// remove the position of an expression
+fn unlocate(node: &mut Node) -> Pos {
+    match node {
+        Node::Located(pos, exp) => {
+            let pos = *pos;
+            *node = std::mem::take(exp.as_mut());
+            pos
+        }
+        _ => Pos::default(),
+    }
+}
+
+fn type_check_located(
+    node: &mut Node,
+    var_types: &mut HashMap<String, Type>,
+) -> Result<(Type, Pos), String> {
+    let pos = match node {
+        Node::Located(pos, _) => *pos,
+        _ => Pos::default(),
+    };
+    Ok((type_check_node(node, var_types)?, pos))
+}
+
+/// Check an expression against the expected type,
+/// a cast is inserted if the types only differ by Any
+fn type_check_exp(
+    node: &mut Node,
+    expected: &Type,
+    var_types: &mut HashMap<String, Type>,
+) -> Result<(), String> {
+    let (t, pos) = type_check_located(node, var_types)?;
+    insert_cast(node, t, expected, pos)
+}
+
+/// the tag of Any can only tell apart types whose components are Any
+fn expect_ground_type(t: &Type) -> Result<(), String> {
+    if t.any_tag().is_none() {
+        return Err(format!("Cannot convert {:?} from or to Any", t));
+    }
+    Ok(())
+}
+
+/// the constructor, accessors and mutators of a struct, which are generated by shrink
+fn struct_functions(name: &str, fields: &[(String, Type)]) -> Vec<(String, Vec<Type>, Type)> {
+    let struct_t = Type::Named(name.to_string());
+    let field_types = constructor_types(fields.iter().map(|(_, t)| t.clone()).collect());
+    let mut funs = vec![(name.to_string(), field_types, struct_t.clone())];
+    for (field, t) in fields {
+        funs.push((
+            accessor_name(name, field),
+            vec![struct_t.clone()],
+            t.clone(),
+        ));
+        funs.push((
+            mutator_name(name, field),
+            vec![struct_t.clone(), t.clone()],
+            Type::Void,
+        ));
+    }
+    funs
+}
+
+/// the constructors and predicates of a data type, which are generated by shrink
+fn data_functions(name: &str, variants: &[(String, Vec<Type>)]) -> Vec<(String, Vec<Type>, Type)> {
+    let data_t = Type::Named(name.to_string());
+    let mut funs = Vec::with_capacity(variants.len() * 2);
+    for (variant, field_types) in variants {
+        funs.push((
+            variant.clone(),
+            constructor_types(field_types.clone()),
+            data_t.clone(),
+        ));
+        funs.push((predicate_name(variant), vec![data_t.clone()], Type::Boolean));
+    }
+    funs
+}
+
+/// every named type in the type is defined by the program
+fn expect_declared_type(t: &Type, names: &HashSet<String>) -> Result<(), String> {
+    match t {
+        Type::Named(name) if !names.contains(name) => Err(format!("Unknown type {}", name)),
+        Type::Vector(elem_types) | Type::Values(elem_types) => elem_types
+            .iter()
+            .try_for_each(|t| expect_declared_type(t, names)),
+        Type::Box(t) | Type::List(t) | Type::Array(t) | Type::Optional(t) => {
+            expect_declared_type(t, names)
+        }
+        Type::HashTable(key_t, value_t) => {
+            expect_declared_type(key_t, names)?;
+            expect_declared_type(value_t, names)
+        }
+        Type::Function(params, ret) => params
+            .iter()
+            .chain(std::iter::once(ret.as_ref()))
+            .try_for_each(|t| expect_declared_type(t, names)),
+        _ => Ok(()),
+    }
+}
+
+/// an undeclared capitalized type in the signature of a function is a type variable
+fn generalize(t: &Type, is_var: &dyn Fn(&str) -> bool) -> Type {
+    let generalize = |t: &Type| Box::new(generalize(t, is_var));
+    match t {
+        Type::Named(name) if is_var(name) => Type::Var(name.clone()),
+        Type::Vector(types) => Type::Vector(types.iter().map(|t| *generalize(t)).collect()),
+        Type::Values(types) => Type::Values(types.iter().map(|t| *generalize(t)).collect()),
+        Type::Box(t) => Type::Box(generalize(t)),
+        Type::List(t) => Type::List(generalize(t)),
+        Type::Array(t) => Type::Array(generalize(t)),
+        Type::Optional(t) => Type::Optional(generalize(t)),
+        Type::HashTable(key_t, value_t) => Type::HashTable(generalize(key_t), generalize(value_t)),
+        Type::Function(params, ret) => Type::Function(
+            params.iter().map(|t| *generalize(t)).collect(),
+            generalize(ret),
+        ),
+        t => t.clone(),
+    }
+}
+
+/// the types written in the body of a generic function may use its type variables
+fn generalize_body(node: Box<Node>, vars: &[String]) -> Box<Node> {
+    let is_var = |name: &str| vars.iter().any(|var| var == name);
+    let node = match *node {
+        Node::Lambda {
+            params,
+            ret,
+            body,
+            free_vars,
+        } => Node::Lambda {
+            params: params
+                .into_iter()
+                .map(|(name, t)| (name, generalize(&t, &is_var)))
+                .collect(),
+            ret: generalize(&ret, &is_var),
+            body: generalize_body(body, vars),
+            free_vars,
+        },
+        Node::Ann(exp, t, pos) => {
+            Node::Ann(generalize_body(exp, vars), generalize(&t, &is_var), pos)
+        }
+        Node::MakeHashTable(key_t, value_t) => {
+            Node::MakeHashTable(generalize(&key_t, &is_var), generalize(&value_t, &is_var))
+        }
+        node => node.map_children(&mut |node| generalize_body(node, vars)),
+    };
+    Box::new(node)
+}
+
+/// bind the type variables of a generic type to match the type of an argument,
+/// the empty list and expressions which do not return leave them to other arguments
+fn unify(generic: &Type, t: &Type, bindings: &mut HashMap<String, Type>) {
+    match (generic, t) {
+        (_, Type::Bottom) => {}
+        (Type::Var(name), t) => {
+            if matches!(bindings.get(name), None | Some(Type::Null)) {
+                bindings.insert(name.clone(), t.clone());
+            }
+        }
+        (Type::Vector(generics), Type::Vector(types))
+        | (Type::Values(generics), Type::Values(types)) => {
+            for (generic, t) in generics.iter().zip(types) {
+                unify(generic, t, bindings);
+            }
+        }
+        (Type::Box(generic), Type::Box(t))
+        | (Type::List(generic), Type::List(t))
+        | (Type::Array(generic), Type::Array(t)) => unify(generic, t, bindings),
+        (Type::HashTable(generic_key, generic_value), Type::HashTable(key_t, value_t)) => {
+            unify(generic_key, key_t, bindings);
+            unify(generic_value, value_t, bindings);
+        }
+        (Type::Function(generic_params, generic_ret), Type::Function(params, ret)) => {
+            for (generic, t) in generic_params.iter().zip(params) {
+                unify(generic, t, bindings);
+            }
+            unify(generic_ret, ret, bindings);
+        }
+        _ => {}
+    }
+}
+
+fn expect_arity(expected: usize, actual: usize) -> Result<(), String> {
+    if expected != actual {
+        return Err(format!(
+            "Function expects {} arguments, got {}",
+            expected, actual
+        ));
+    }
+    Ok(())
+}
+
+/// type of a parameter when its argument is passed
+fn without_default(t: &Type) -> &Type {
+    match t {
+        Type::Optional(t) => t,
+        t => t,
+    }
+}
+
+/// type of a function used as a value, which takes all of its parameters
+fn without_defaults(t: &Type) -> Type {
+    match t {
+        Type::Function(params, ret) => Type::Function(
+            params.iter().map(|t| without_default(t).clone()).collect(),
+            ret.clone(),
+        ),
+        t => t.clone(),
+    }
+}
+
+/// a call of a generic function is checked against the types bound by the arguments,
+/// a type variable which no argument determines is Void
+fn type_check_instance(
+    vars: &[String],
+    fun_t: &Type,
+    args: &mut [Box<Node>],
+    pos: Pos,
+    var_types: &mut HashMap<String, Type>,
+) -> Result<(Vec<Type>, Type), String> {
+    let (param_types, ret) = match fun_t {
+        Type::Function(param_types, ret) => (param_types, ret),
+        t => panic!("unexpected generic {:?}", t),
+    };
+    expect_arity(param_types.len(), args.len()).map_err(|msg| at(pos, msg))?;
+    let mut bindings = HashMap::new();
+    let mut arg_types = Vec::with_capacity(args.len());
+    for (param_t, arg) in param_types.iter().zip(args.iter_mut()) {
+        let (t, pos) = type_check_located(arg, var_types)?;
+        unify(param_t, &t, &mut bindings);
+        arg_types.push((t, pos));
+    }
+    let types: Vec<_> = vars
+        .iter()
+        .map(|var| bindings.get(var).cloned().unwrap_or(Type::Void))
+        .collect();
+    let bindings = vars.iter().cloned().zip(types.iter().cloned()).collect();
+    for ((param_t, arg), (t, pos)) in param_types.iter().zip(args.iter_mut()).zip(arg_types) {
+        insert_cast(arg, t, &param_t.subst(&bindings), pos)?;
+    }
+    Ok((types, ret.subst(&bindings)))
+}
+
+/// Check a pattern against the type of the matched value and bind its variables,
+/// a variant is looked up by its constructor and its fields by the hidden name,
+/// as the constructor may take them packed
+fn type_check_pattern(
+    pattern: &Pattern,
+    t: &Type,
+    var_types: &HashMap<String, Type>,
+    bindings: &mut Vec<(String, Type)>,
+) -> Result<(), String> {
+    match pattern {
+        Pattern::Wildcard => {}
+        Pattern::Var(name) => {
+            if bindings.iter().any(|(var, _)| var == name) {
+                return Err(format!("Duplicate variable {} in pattern", name));
+            }
+            bindings.push((name.clone(), t.clone()));
+        }
+        Pattern::Literal(literal) => {
+            let literal_t = match literal {
+                Node::Fixnum(_) => Type::Fixnum,
+                Node::Char(_) => Type::Char,
+                Node::Symbol(_) => Type::Symbol,
+                _ => Type::Boolean,
+            };
+            expect_type_eq(t, &literal_t)?;
+        }
+        Pattern::Vector(patterns) => match t {
+            Type::Vector(elem_types) if elem_types.len() == patterns.len() => {
+                for (pattern, t) in patterns.iter().zip(elem_types) {
+                    type_check_pattern(pattern, t, var_types, bindings)?;
+                }
+            }
+            t => {
+                return Err(format!(
+                    "Incorrect type: expected Vector of {} elements actual {:?}",
+                    patterns.len(),
+                    t
+                ))
+            }
+        },
+        Pattern::Variant(name, patterns) => {
+            match (var_types.get(name), var_types.get(&hidden_name(name))) {
+                (Some(Type::Function(_, ret)), Some(Type::Vector(field_types)))
+                    if matches!(**ret, Type::Named(_)) =>
+                {
+                    expect_type_eq(t, ret)?;
+                    if field_types.len() != patterns.len() {
+                        return Err(format!(
+                            "Variant {} has {} fields, got {}",
+                            name,
+                            field_types.len(),
+                            patterns.len()
+                        ));
+                    }
+                    for (pattern, t) in patterns.iter().zip(field_types) {
+                        type_check_pattern(pattern, t, var_types, bindings)?;
+                    }
+                }
+                _ => return Err(format!("Unknown variant {}", name)),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the variable goes out of scope
+fn restore(var_types: &mut HashMap<String, Type>, name: &str, shadowed: Option<Type>) {
+    match shadowed {
+        Some(t) => var_types.insert(name.to_string(), t),
+        None => var_types.remove(name),
+    };
+}
+
+/// Check the index of a vector and return the type of the element,
+/// the index must be a literal unless all elements have the same type
+fn type_check_index(
+    vec_t: &Type,
+    index: &mut Node,
+    var_types: &mut HashMap<String, Type>,
+) -> Result<Type, String> {
+    let elem_types = match vec_t {
+        Type::Vector(elem_types) => elem_types,
+        t => return Err(format!("Incorrect type: expected Vector actual {:?}", t)),
+    };
+    type_check_exp(index, &Type::Fixnum, var_types)?;
+    match index.fixnum() {
+        Some(i) => match elem_types.get(i as usize) {
+            Some(t) if i >= 0 => Ok(t.clone()),
+            _ => Err(format!(
+                "Index {} out of range for a vector of length {}",
+                i,
+                elem_types.len()
+            )),
+        },
+        None => match elem_types.split_first() {
+            Some((t, rest)) if rest.iter().all(|elem_t| elem_t == t) => Ok(t.clone()),
+            _ => Err(format!("Vector index must be a literal for {:?}", vec_t)),
+        },
+    }
+}
+
+/// Check an array and return the type of the elements
+fn type_check_array(
+    array: &mut Node,
+    var_types: &mut HashMap<String, Type>,
+) -> Result<Type, String> {
+    match type_check_node(array, var_types)? {
+        Type::Array(elem_t) => Ok(*elem_t),
+        t => Err(format!("Incorrect type: expected Array actual {:?}", t)),
+    }
+}
+
+/// Check a hash table and return the types of the keys and the values
+fn type_check_hash_table(
+    table: &mut Node,
+    var_types: &mut HashMap<String, Type>,
+) -> Result<(Type, Type), String> {
+    match type_check_node(table, var_types)? {
+        Type::HashTable(key_t, value_t) => Ok((*key_t, *value_t)),
+        t => Err(format!("Incorrect type: expected HashTable actual {:?}", t)),
+    }
+}
+
+/// the loop of a named let is only called in tail position, a let which shadows
+/// the loop ends it, other variables which shadow the loop are taken as the loop
+fn is_tail_loop(node: &Node, name: &str, is_tail: bool) -> bool {
     use Node::*;
 
-    let t = match *node {
-        Program(exp) => type_check_node(exp, var_types)?,
+    let is_loop = |fun: &Node| match fun {
+        Located(_, fun) => fun.var().map(String::as_str) == Some(name),
+        fun => fun.var().map(String::as_str) == Some(name),
+    };
+    match node {
+        Located(_, exp) => is_tail_loop(exp, name, is_tail),
+        Var(var) => var != name,
+        Apply(fun, args) if is_loop(fun) => {
+            is_tail && args.iter().all(|arg| is_tail_loop(arg, name, false))
+        }
+        If {
+            cond,
+            if_exps,
+            else_exps,
+        } => {
+            is_tail_loop(cond, name, false)
+                && if_exps
+                    .iter()
+                    .chain(else_exps.iter())
+                    .all(|exp| is_tail_loop(exp, name, is_tail))
+        }
+        Begin(effects, exp) => {
+            effects
+                .iter()
+                .all(|effect| is_tail_loop(effect, name, false))
+                && is_tail_loop(exp, name, is_tail)
+        }
+        Let {
+            name: var, value, ..
+        } if var == name => is_tail_loop(value, name, false),
+        Let { value, exp, .. } => {
+            is_tail_loop(value, name, false) && is_tail_loop(exp, name, is_tail)
+        }
+        // the variables of the loops would clash
+        NamedLet { name: inner, .. } if inner == name => false,
+        LetStar { bindings, exp } => {
+            bindings
+                .iter()
+                .all(|(_, value)| is_tail_loop(value, name, false))
+                && is_tail_loop(exp, name, is_tail)
+        }
+        LetValues { bindings, exp } => {
+            bindings
+                .iter()
+                .all(|(_, value)| is_tail_loop(value, name, false))
+                && is_tail_loop(exp, name, is_tail)
+        }
+        Cond { clauses, else_exp } => {
+            clauses.iter().all(|(test, exp)| {
+                is_tail_loop(test, name, false) && is_tail_loop(exp, name, is_tail)
+            }) && else_exp.iter().all(|exp| is_tail_loop(exp, name, is_tail))
+        }
+        Match { exp, clauses, .. } => {
+            is_tail_loop(exp, name, false)
+                && clauses
+                    .iter()
+                    .all(|(_, body)| is_tail_loop(body, name, is_tail))
+        }
+        node => node
+            .children()
+            .into_iter()
+            .all(|child| is_tail_loop(child, name, false)),
+    }
+}
+
+/// closure conversion needs the types of the captured variables
+fn captured_vars(
+    body: &Node,
+    params: &[(String, Type)],
+    var_types: &mut HashMap<String, Type>,
+) -> Result<Vec<(String, Type)>, String> {
+    let mut vars = Vec::new();
+    for name in body.free_vars() {
+        // a generic function is instantiated by its calls
+        if matches!(var_types.get(&name), Some(Type::Generic(..))) {
+            continue;
+        }
+        if params.iter().all(|(param, _)| param != &name) {
+            let t = type_check_node(&mut Node::Var(name.clone()), var_types)?;
+            vars.push((name, t));
+        }
+    }
+    Ok(vars)
+}
+
+/// Check the type of a node, vectors are annotated with their types
+fn type_check_node(node: &mut Node, var_types: &mut HashMap<String, Type>) -> Result<Type, String> {
+    use Node::*;
+
+    let pos = unlocate(node);
+    // the expression replaces the annotation once it is checked
+    if let Ann(exp, t, pos) = node {
+        let (t, pos) = (t.clone(), *pos);
+        let mut exp = std::mem::take(exp);
+        let (exp_t, _) = type_check_located(&mut exp, var_types)?;
+        insert_cast(&mut exp, exp_t, &t, pos)?;
+        *node = *exp;
+        return Ok(t);
+    }
+    let t = match node {
+        Program(defs, exp) => {
+            let mut names = HashSet::new();
+            for def in defs.iter() {
+                if let DefStruct { name, .. } | DefType { name, .. } = def.as_ref() {
+                    if !names.insert(name.clone()) {
+                        return Err(format!("Duplicate type {}", name));
+                    }
+                }
+            }
+            for def in defs.iter_mut() {
+                if let Def {
+                    params, ret, body, ..
+                } = def.as_mut()
+                {
+                    let is_var = |name: &str| {
+                        !names.contains(name) && name.starts_with(|c: char| c.is_ascii_uppercase())
+                    };
+                    for (_, t) in params.iter_mut() {
+                        *t = generalize(t, &is_var);
+                    }
+                    *ret = generalize(ret, &is_var);
+                    let param_types = params.iter().map(|(_, t)| t.clone()).collect();
+                    let vars = Type::Function(param_types, Box::new(ret.clone())).type_vars();
+                    if !vars.is_empty() {
+                        body[0] = generalize_body(std::mem::take(&mut body[0]), &vars);
+                    }
+                }
+            }
+            // functions are visible to every definition
+            for def in defs.iter() {
+                let funs = match def.as_ref() {
+                    Def {
+                        name, params, ret, ..
+                    } => {
+                        let param_types = params.iter().map(|(_, t)| t.clone()).collect();
+                        vec![(name.clone(), param_types, ret.clone())]
+                    }
+                    DefStruct { name, fields } => struct_functions(name, fields),
+                    DefType { name, variants } => data_functions(name, variants),
+                    _ => continue,
+                };
+                for (name, param_types, ret) in funs {
+                    for t in param_types.iter().chain(std::iter::once(&ret)) {
+                        expect_declared_type(t, &names)?;
+                    }
+                    let t = Type::Function(param_types, Box::new(ret));
+                    let vars = t.type_vars();
+                    let t = if vars.is_empty() {
+                        t
+                    } else if t != without_defaults(&t) {
+                        return Err(format!(
+                            "Generic function {} can not have default values",
+                            name
+                        ));
+                    } else {
+                        Type::Generic(vars, Box::new(t))
+                    };
+                    if var_types.insert(name.clone(), t).is_some() {
+                        return Err(format!("Duplicate function {}", name));
+                    }
+                }
+                if let DefType { variants, .. } = def.as_ref() {
+                    for (variant, field_types) in variants {
+                        var_types.insert(hidden_name(variant), Type::Vector(field_types.clone()));
+                    }
+                }
+            }
+            // globals are initialized in order, functions are visible to them
+            for def in defs.iter_mut() {
+                if let DefGlobal { name, value } = def.as_mut() {
+                    let t = type_check_node(value, var_types)?;
+                    if var_types.insert(name.clone(), t).is_some() {
+                        return Err(format!("Duplicate global {}", name));
+                    }
+                }
+            }
+            for def in defs {
+                type_check_node(def, var_types)?;
+            }
+            type_check_node(exp, var_types)?
+        }
+        Def {
+            params, ret, body, ..
+        } => {
+            assert_eq!(body.len(), 1);
+            let mut var_types = var_types.clone();
+            for (name, t) in params {
+                // the default value is passed by the caller
+                if let Type::Optional(param_t) = t {
+                    *t = *param_t.clone();
+                }
+                var_types.insert(name.clone(), t.clone());
+            }
+            type_check_exp(&mut body[0], ret, &mut var_types)?;
+            Type::Void
+        }
+        DefStruct { .. } | DefType { .. } | DefGlobal { .. } => Type::Void,
+        Apply(fun, args) => {
+            // a function name is called directly
+            let fun_pos = unlocate(fun);
+            let fun_t = match fun.as_ref() {
+                Var(name) => var_types
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| at(fun_pos, format!("Unknown function {}", name)))?,
+                _ => type_check_node(fun, var_types)?,
+            };
+            let (param_types, ret) = match fun_t {
+                Type::Function(param_types, ret) => (param_types, ret),
+                // the type variables are bound by the types of the arguments
+                Type::Generic(vars, fun_t) => {
+                    let name = match fun.as_ref() {
+                        Var(name) => name.clone(),
+                        fun => panic!("unexpected generic {:?}", fun),
+                    };
+                    let (types, ret) = type_check_instance(&vars, &fun_t, args, pos, var_types)?;
+                    **fun = Instance(name, types);
+                    return Ok(ret);
+                }
+                Type::Any if args.len() < ARGUMENT_REGISTERS.len() => {
+                    let param_types = vec![Type::Any; args.len()];
+                    let t = Type::Function(param_types.clone(), Box::new(Type::Any));
+                    insert_cast(fun, Type::Any, &t, fun_pos)?;
+                    (param_types, Box::new(Type::Any))
+                }
+                // the function is never made, so the call does not return either
+                Type::Bottom => {
+                    for arg in args.iter_mut() {
+                        type_check_node(arg, var_types)?;
+                    }
+                    return Ok(Type::Bottom);
+                }
+                t => return Err(format!("Incorrect type: expected Function actual {:?}", t)),
+            };
+            // the parameters with default values may be left out
+            let required = param_types
+                .iter()
+                .take_while(|t| !matches!(t, Type::Optional(_)))
+                .count();
+            if args.len() < required || args.len() >= param_types.len() {
+                expect_arity(param_types.len(), args.len()).map_err(|msg| at(pos, msg))?;
+            }
+            for (param_t, arg) in param_types.iter().zip(args.iter_mut()) {
+                type_check_exp(arg, without_default(param_t), var_types)?;
+            }
+            *ret
+        }
         Fixnum(_) => Type::Fixnum,
+        Str(_) => Type::String,
+        Symbol(_) => Type::Symbol,
+        Char(_) => Type::Char,
+        CharToInteger(exp) => {
+            type_check_exp(exp, &Type::Char, var_types)?;
+            Type::Fixnum
+        }
+        IntegerToChar(exp) => {
+            type_check_exp(exp, &Type::Fixnum, var_types)?;
+            Type::Char
+        }
+        Flonum(_) => Type::Flonum,
+        FixnumToFlonum(exp) => {
+            type_check_exp(exp, &Type::Fixnum, var_types)?;
+            Type::Flonum
+        }
+        FlonumToFixnum(exp) => {
+            type_check_exp(exp, &Type::Flonum, var_types)?;
+            Type::Fixnum
+        }
         True | False => Type::Boolean,
-        Var(name) => *var_types.get(&name).expect("unknown"),
-        Read => Type::Fixnum,
+        Void => Type::Void,
+        Var(name) => match var_types.get(name) {
+            // the closure takes one of the argument registers
+            Some(Type::Function(params, _)) if params.len() >= ARGUMENT_REGISTERS.len() => {
+                return Err(format!(
+                    "Function {} takes too many parameters to be a value",
+                    name
+                ));
+            }
+            Some(Type::Generic(..)) => {
+                return Err(format!("Generic function {} can only be called", name))
+            }
+            Some(t) => without_defaults(t),
+            None => return Err(at(pos, format!("Unknown variable {}", name))),
+        },
+        Lambda {
+            params,
+            ret,
+            body,
+            free_vars,
+        } => {
+            *free_vars = captured_vars(body, params, var_types)?;
+            let mut var_types = var_types.clone();
+            for (name, t) in params.iter() {
+                var_types.insert(name.clone(), t.clone());
+            }
+            type_check_exp(body, ret, &mut var_types)?;
+            let param_types = params.iter().map(|(_, t)| t.clone()).collect();
+            Type::Function(param_types, Box::new(ret.clone()))
+        }
+        Read(t) => t.clone(),
+        CommandLineArguments => Type::Array(Box::new(Type::String)),
+        Random(bound) => {
+            type_check_exp(bound, &Type::Fixnum, var_types)?;
+            Type::Fixnum
+        }
+        Exit(code) => {
+            type_check_exp(code, &Type::Fixnum, var_types)?;
+            Type::Bottom
+        }
+        Assert(cond, _) => {
+            type_check_exp(cond, &Type::Boolean, var_types)?;
+            Type::Void
+        }
+        Raise(msg) => {
+            type_check_exp(msg, &Type::String, var_types)?;
+            Type::Bottom
+        }
+        // the continuation takes the result of the function
+        CallCC(fun) => match type_check_node(fun, var_types)? {
+            Type::Function(params, ret) if params.len() == 1 => match &params[0] {
+                Type::Function(k_params, _) if *k_params == [*ret.clone()] => *ret,
+                t => {
+                    return Err(format!(
+                        "Incorrect type: expected ({:?} -> T) actual {:?}",
+                        ret, t
+                    ))
+                }
+            },
+            t => {
+                return Err(format!(
+                    "Incorrect type: expected ((T -> R) -> T) actual {:?}",
+                    t
+                ))
+            }
+        },
+        // the result of the handler joins with the result of the thunk
+        WithHandler(handler, thunk) => {
+            let (handler_t, handler_pos) = type_check_located(handler, var_types)?;
+            let handler_ret = match &handler_t {
+                Type::Function(params, ret) if *params == [Type::String] => *ret.clone(),
+                t => {
+                    return Err(format!(
+                        "Incorrect type: expected (String -> T) actual {:?}",
+                        t
+                    ))
+                }
+            };
+            let (ret, body, free_vars) = match thunk.as_mut() {
+                Lambda {
+                    ret,
+                    body,
+                    free_vars,
+                    ..
+                } => (ret, body, free_vars),
+                thunk => panic!("unexpected thunk {:?}", thunk),
+            };
+            *free_vars = captured_vars(body, &[], var_types)?;
+            let (body_t, body_pos) = type_check_located(body, &mut var_types.clone())?;
+            let t = join_types(&[body_t.clone(), handler_ret])?;
+            insert_cast(body, body_t, &t, body_pos)?;
+            let expected_t = Type::Function(vec![Type::String], Box::new(t.clone()));
+            insert_cast(handler, handler_t, &expected_t, handler_pos)?;
+            *ret = t.clone();
+            t
+        }
+        Error(msg, value, t) => {
+            type_check_exp(msg, &Type::String, var_types)?;
+            if let Some(value) = value {
+                *t = type_check_node(value, var_types)?;
+            }
+            Type::Bottom
+        }
+        Print(exp, t) => {
+            *t = type_check_node(exp, var_types)?;
+            Type::Void
+        }
+        Format(_, args, types) => {
+            *types = args
+                .iter_mut()
+                .map(|arg| type_check_node(arg, var_types))
+                .collect::<Result<_, _>>()?;
+            Type::String
+        }
+        Values(exps, types) => {
+            *types = exps
+                .iter_mut()
+                .map(|exp| type_check_node(exp, var_types))
+                .collect::<Result<_, _>>()?;
+            Type::Values(types.clone())
+        }
+        // the values are evaluated before any variable is bound
+        LetValues { bindings, exp } => {
+            let mut bound = Vec::new();
+            for (names, value) in bindings.iter_mut() {
+                let (t, pos) = type_check_located(value, var_types)?;
+                let types = match t {
+                    Type::Values(types) if types.len() == names.len() => types,
+                    Type::Bottom => vec![Type::Bottom; names.len()],
+                    // one value is returned by itself, so it is wrapped as the results
+                    t if names.len() == 1 && !matches!(t, Type::Values(_)) => {
+                        let exp = Box::new(std::mem::take(value.as_mut()));
+                        **value = Values(vec![exp], vec![t.clone()]);
+                        vec![t]
+                    }
+                    t => {
+                        return Err(format!(
+                            "{}: expected {} values, but got {:?}",
+                            pos,
+                            names.len(),
+                            t
+                        ))
+                    }
+                };
+                bound.extend(names.iter().cloned().zip(types));
+            }
+            let mut var_types = var_types.clone();
+            var_types.extend(bound);
+            type_check_node(exp, &mut var_types)?
+        }
         Let { name, value, exp } => {
             let t = type_check_node(value, var_types)?;
-            var_types.insert(name, t);
-            type_check_node(exp, var_types)?
+            let shadowed = var_types.insert(name.clone(), t);
+            let t = type_check_node(exp, var_types)?;
+            restore(var_types, name, shadowed);
+            t
         }
-        Not(exp) => {
+        // the lambdas are bound to their annotated types first, so every value sees them
+        LetRec { bindings, exp } => {
+            let shadowed: Vec<_> = bindings
+                .iter()
+                .map(|(name, _)| var_types.get(name).cloned())
+                .collect();
+            for (name, value) in bindings.iter() {
+                let value = match value.as_ref() {
+                    Located(_, value) => value.as_ref(),
+                    value => value,
+                };
+                if let Lambda { params, ret, .. } = value {
+                    let param_types = params.iter().map(|(_, t)| t.clone()).collect();
+                    let t = Type::Function(param_types, Box::new(ret.clone()));
+                    var_types.insert(name.clone(), t);
+                }
+            }
+            for (name, value) in bindings.iter_mut() {
+                let t = type_check_node(value, var_types)?;
+                var_types.insert(name.clone(), t);
+            }
+            let t = type_check_node(exp, var_types)?;
+            for ((name, _), shadowed) in bindings.iter().zip(shadowed).rev() {
+                restore(var_types, name, shadowed);
+            }
+            t
+        }
+        LetStar { bindings, exp } => {
+            let mut shadowed = Vec::with_capacity(bindings.len());
+            for (name, value) in bindings.iter_mut() {
+                let t = type_check_node(value, var_types)?;
+                shadowed.push(var_types.insert(name.clone(), t));
+            }
             let t = type_check_node(exp, var_types)?;
-            expect_type_eq(Type::Boolean, t)?;
+            for ((name, _), shadowed) in bindings.iter().zip(shadowed).rev() {
+                restore(var_types, name, shadowed);
+            }
             t
         }
-        Add(lhs, rhs) => {
-            let lhs_t = type_check_node(lhs, var_types)?;
-            let rhs_t = type_check_node(rhs, var_types)?;
-            expect_type_eq(Type::Fixnum, lhs_t)?;
-            expect_type_eq(Type::Fixnum, rhs_t)?;
+        // the loop does not return, so a call joins with the other results
+        NamedLet {
+            name,
+            bindings,
+            body,
+            ..
+        } => {
+            let mut param_types = Vec::with_capacity(bindings.len());
+            for (_, value) in bindings.iter_mut() {
+                param_types.push(type_check_node(value, var_types)?);
+            }
+            let mut body_types = var_types.clone();
+            for ((param, _), t) in bindings.iter().zip(param_types.iter()) {
+                body_types.insert(param.clone(), t.clone());
+            }
+            let loop_t = Type::Function(param_types.clone(), Box::new(Type::Bottom));
+            body_types.insert(name.clone(), loop_t);
+            if is_tail_loop(body, name, true) {
+                return type_check_node(body, &mut body_types);
+            }
+            // otherwise the loop is a recursive function returning the other results
+            let ret = type_check_node(&mut body.clone(), &mut body_types)?;
+            let loop_t = Type::Function(param_types.clone(), Box::new(ret.clone()));
+            let shadowed = var_types.insert(name.clone(), loop_t);
+            let mut lambda = Lambda {
+                params: bindings
+                    .iter()
+                    .map(|(param, _)| param.clone())
+                    .zip(param_types)
+                    .collect(),
+                ret: ret.clone(),
+                body: std::mem::take(body),
+                free_vars: Vec::new(),
+            };
+            type_check_node(&mut lambda, var_types)?;
+            restore(var_types, name, shadowed);
+            // the initial values are bound first, so they do not see the loop
+            let temps: Vec<_> = (0..bindings.len())
+                .map(|i| format!("{}.{}", name, i))
+                .collect();
+            let args = temps
+                .iter()
+                .map(|temp| Box::new(Var(temp.clone())))
+                .collect();
+            let exp = Box::new(LetRec {
+                bindings: vec![(name.clone(), Box::new(lambda))],
+                exp: Box::new(Apply(Box::new(Var(name.clone())), args)),
+            });
+            let exp = std::mem::take(bindings).into_iter().zip(temps).rev().fold(
+                exp,
+                |exp, ((_, value), temp)| {
+                    Box::new(Let {
+                        name: temp,
+                        value,
+                        exp,
+                    })
+                },
+            );
+            *node = *exp;
+            ret
+        }
+        SetBang { name, value } => {
+            let var = Box::new(Var(name.clone()));
+            let var_t = type_check_node(&mut Located(pos, var), var_types)?;
+            type_check_exp(value, &var_t, var_types)?;
+            Type::Void
+        }
+        Begin(effects, exp) => {
+            for effect in effects {
+                type_check_node(effect, var_types)?;
+            }
+            type_check_node(exp, var_types)?
+        }
+        Not(exp) => {
+            type_check_exp(exp, &Type::Boolean, var_types)?;
+            Type::Boolean
+        }
+        Neg(exp) | BitNot(exp) => {
+            type_check_exp(exp, &Type::Fixnum, var_types)?;
+            Type::Fixnum
+        }
+        And(lhs, rhs) | Or(lhs, rhs) => {
+            type_check_exp(lhs, &Type::Boolean, var_types)?;
+            type_check_exp(rhs, &Type::Boolean, var_types)?;
+            Type::Boolean
+        }
+        Add(lhs, rhs)
+        | Sub(lhs, rhs)
+        | Mul(lhs, rhs)
+        | Quotient(lhs, rhs)
+        | Remainder(lhs, rhs)
+        | BitAnd(lhs, rhs)
+        | BitOr(lhs, rhs)
+        | BitXor(lhs, rhs)
+        | Ash(lhs, rhs)
+        | Shl(lhs, rhs)
+        | Shr(lhs, rhs)
+        | Sar(lhs, rhs)
+        | Min(lhs, rhs)
+        | Max(lhs, rhs) => {
+            type_check_exp(lhs, &Type::Fixnum, var_types)?;
+            type_check_exp(rhs, &Type::Fixnum, var_types)?;
             Type::Fixnum
         }
-        Eq(lhs, rhs) | Lt(lhs, rhs) | Lte(lhs, rhs) | Gt(lhs, rhs) | Gte(lhs, rhs) => {
-            let lhs_t = type_check_node(lhs, var_types)?;
-            let rhs_t = type_check_node(rhs, var_types)?;
-            expect_type_eq(lhs_t, rhs_t)?;
+        FAdd(lhs, rhs) | FSub(lhs, rhs) | FMul(lhs, rhs) | FDiv(lhs, rhs) => {
+            type_check_exp(lhs, &Type::Flonum, var_types)?;
+            type_check_exp(rhs, &Type::Flonum, var_types)?;
+            Type::Flonum
+        }
+        Eq(lhs, rhs) => {
+            // the other side is injected if one side is Any
+            let (lhs_t, lhs_pos) = type_check_located(lhs, var_types)?;
+            let (rhs_t, rhs_pos) = type_check_located(rhs, var_types)?;
+            let t = join_types(&[lhs_t.clone(), rhs_t.clone()])?;
+            insert_cast(lhs, lhs_t, &t, lhs_pos)?;
+            insert_cast(rhs, rhs_t, &t, rhs_pos)?;
+            Type::Boolean
+        }
+        StringEq(lhs, rhs) => {
+            type_check_exp(lhs, &Type::String, var_types)?;
+            type_check_exp(rhs, &Type::String, var_types)?;
+            Type::Boolean
+        }
+        StringLength(s) => {
+            type_check_exp(s, &Type::String, var_types)?;
+            Type::Fixnum
+        }
+        // a character is the code of the byte
+        StringRef(s, index) => {
+            type_check_exp(s, &Type::String, var_types)?;
+            type_check_exp(index, &Type::Fixnum, var_types)?;
+            Type::Char
+        }
+        StringAppend(lhs, rhs) => {
+            type_check_exp(lhs, &Type::String, var_types)?;
+            type_check_exp(rhs, &Type::String, var_types)?;
+            Type::String
+        }
+        Lt(lhs, rhs) | Lte(lhs, rhs) | Gt(lhs, rhs) | Gte(lhs, rhs) => {
+            type_check_exp(lhs, &Type::Fixnum, var_types)?;
+            type_check_exp(rhs, &Type::Fixnum, var_types)?;
             Type::Boolean
         }
         If {
             cond,
-            mut if_exps,
-            mut else_exps,
-            ..
+            if_exps,
+            else_exps,
         } => {
             assert_eq!(if_exps.len(), 1);
             assert_eq!(else_exps.len(), 1);
-            let cond_t = type_check_node(cond, var_types)?;
-            expect_type_eq(cond_t, Type::Boolean)?;
-            let if_t = type_check_node(if_exps.remove(0), var_types)?;
-            let else_t = type_check_node(else_exps.remove(0), var_types)?;
-            expect_type_eq(if_t, else_t)?;
-            if_t
+            type_check_exp(cond, &Type::Boolean, var_types)?;
+            let (if_t, if_pos) = type_check_located(&mut if_exps[0], var_types)?;
+            let (else_t, else_pos) = type_check_located(&mut else_exps[0], var_types)?;
+            let t = join_types(&[if_t.clone(), else_t.clone()])?;
+            insert_cast(&mut if_exps[0], if_t, &t, if_pos)?;
+            insert_cast(&mut else_exps[0], else_t, &t, else_pos)?;
+            t
+        }
+        Cond { clauses, else_exp } => {
+            let mut branches = Vec::with_capacity(clauses.len() + 1);
+            for (test, exp) in clauses.iter_mut() {
+                type_check_exp(test, &Type::Boolean, var_types)?;
+                branches.push(type_check_located(exp, var_types)?);
+            }
+            match else_exp {
+                Some(exp) => branches.push(type_check_located(exp, var_types)?),
+                // the value is void if no clause matches
+                None => {
+                    if let Some((t, _)) = branches.iter().find(|(t, _)| *t != Type::Void) {
+                        return Err(format!(
+                            "Incorrect type: cond without else must be Void, got {:?}",
+                            t
+                        ));
+                    }
+                }
+            }
+            let types: Vec<_> = branches.iter().map(|(t, _)| t.clone()).collect();
+            let t = join_types(&types)?;
+            let exps = clauses
+                .iter_mut()
+                .map(|(_, exp)| exp)
+                .chain(else_exp.iter_mut());
+            for (exp, (from, pos)) in exps.zip(branches) {
+                insert_cast(exp, from, &t, pos)?;
+            }
+            t
+        }
+        Match { exp, clauses, .. } => {
+            let exp_t = type_check_node(exp, var_types)?;
+            let mut branches = Vec::with_capacity(clauses.len());
+            for (pattern, body) in clauses.iter_mut() {
+                let mut bindings = Vec::new();
+                type_check_pattern(pattern, &exp_t, var_types, &mut bindings)?;
+                let shadowed: Vec<_> = bindings
+                    .iter()
+                    .map(|(name, t)| var_types.insert(name.clone(), t.clone()))
+                    .collect();
+                branches.push(type_check_located(body, var_types)?);
+                for ((name, _), shadowed) in bindings.iter().zip(shadowed).rev() {
+                    restore(var_types, name, shadowed);
+                }
+            }
+            let types: Vec<_> = branches.iter().map(|(t, _)| t.clone()).collect();
+            let t = join_types(&types)?;
+            for ((_, body), (from, pos)) in clauses.iter_mut().zip(branches) {
+                insert_cast(body, from, &t, pos)?;
+            }
+            t
+        }
+        // the steps are checked against the types of the initial values
+        Do {
+            bindings,
+            test,
+            result,
+            body,
+        } => {
+            let mut loop_types = var_types.clone();
+            for (name, init, _) in bindings.iter_mut() {
+                loop_types.insert(name.clone(), type_check_node(init, var_types)?);
+            }
+            for (name, _, step) in bindings.iter_mut() {
+                if let Some(step) = step {
+                    let t = loop_types[name.as_str()].clone();
+                    type_check_exp(step, &t, &mut loop_types)?;
+                }
+            }
+            type_check_exp(test, &Type::Boolean, &mut loop_types)?;
+            type_check_node(body, &mut loop_types)?;
+            type_check_node(result, &mut loop_types)?
+        }
+        When(cond, body) | Unless(cond, body) => {
+            type_check_exp(cond, &Type::Boolean, var_types)?;
+            type_check_node(body, var_types)?;
+            Type::Void
+        }
+        While {
+            cond, body_exps, ..
+        } => {
+            assert_eq!(body_exps.len(), 1);
+            type_check_exp(cond, &Type::Boolean, var_types)?;
+            type_check_node(&mut body_exps[0], var_types)?;
+            Type::Void
+        }
+        Literal(vec) => type_check_node(vec, var_types)?,
+        Vector(elems) => {
+            if elems.len() > MAX_VECTOR_LEN {
+                return Err(format!(
+                    "{}: a vector has at most {} elements, but got {}",
+                    pos,
+                    MAX_VECTOR_LEN,
+                    elems.len()
+                ));
+            }
+            let mut elem_types = Vec::with_capacity(elems.len());
+            for elem in elems {
+                elem_types.push(type_check_node(elem, var_types)?);
+            }
+            Type::Vector(elem_types)
+        }
+        VectorLength(vec) => {
+            let vec_t = type_check_node(vec, var_types)?;
+            match vec_t {
+                Type::Vector(_) => Type::Fixnum,
+                t => return Err(format!("Incorrect type: expected Vector actual {:?}", t)),
+            }
+        }
+        VectorRef(vec, index) => {
+            let vec_t = type_check_node(vec, var_types)?;
+            type_check_index(&vec_t, index, var_types)?
+        }
+        VectorSet(vec, index, value) => {
+            let vec_t = type_check_node(vec, var_types)?;
+            let elem_t = type_check_index(&vec_t, index, var_types)?;
+            type_check_exp(value, &elem_t, var_types)?;
+            Type::Void
+        }
+        Null => Type::Null,
+        Cons(car, cdr) => {
+            let (car_t, car_pos) = type_check_located(car, var_types)?;
+            match type_check_node(cdr, var_types)? {
+                Type::Null | Type::Bottom => Type::List(Box::new(car_t)),
+                Type::List(elem_t) => {
+                    insert_cast(car, car_t, &elem_t, car_pos)?;
+                    Type::List(elem_t)
+                }
+                t => return Err(format!("Incorrect type: expected List actual {:?}", t)),
+            }
+        }
+        Car(exp) | Cdr(exp) => {
+            let list_t = type_check_node(exp, var_types)?;
+            match (&*node, list_t) {
+                (Car(_), Type::List(elem_t)) => *elem_t,
+                (_, t @ Type::List(_)) => t,
+                (_, t) => return Err(format!("Incorrect type: expected List actual {:?}", t)),
+            }
+        }
+        IsNull(exp) => match type_check_node(exp, var_types)? {
+            Type::List(_) | Type::Null => Type::Boolean,
+            t => return Err(format!("Incorrect type: expected List actual {:?}", t)),
+        },
+        MakeArray(len, init, elem_t) => {
+            type_check_exp(len, &Type::Fixnum, var_types)?;
+            *elem_t = type_check_node(init, var_types)?;
+            Type::Array(Box::new(elem_t.clone()))
+        }
+        ArrayLength(array) => {
+            type_check_array(array, var_types)?;
+            Type::Fixnum
+        }
+        ArrayRef(array, index) => {
+            let elem_t = type_check_array(array, var_types)?;
+            type_check_exp(index, &Type::Fixnum, var_types)?;
+            elem_t
+        }
+        ArraySet(array, index, value) => {
+            let elem_t = type_check_array(array, var_types)?;
+            type_check_exp(index, &Type::Fixnum, var_types)?;
+            type_check_exp(value, &elem_t, var_types)?;
+            Type::Void
+        }
+        MakeHashTable(key_t, value_t) => {
+            Type::HashTable(Box::new(key_t.clone()), Box::new(value_t.clone()))
+        }
+        HashTableRef(table, key, default) => {
+            let (key_t, value_t) = type_check_hash_table(table, var_types)?;
+            type_check_exp(key, &key_t, var_types)?;
+            if let Some(default) = default {
+                type_check_exp(default, &value_t, var_types)?;
+            }
+            value_t
+        }
+        HashTableSet(table, key, value) => {
+            let (key_t, value_t) = type_check_hash_table(table, var_types)?;
+            type_check_exp(key, &key_t, var_types)?;
+            type_check_exp(value, &value_t, var_types)?;
+            Type::Void
+        }
+        MakeBox(exp) => Type::Box(Box::new(type_check_node(exp, var_types)?)),
+        Unbox(exp) => match type_check_node(exp, var_types)? {
+            Type::Box(t) => *t,
+            t => return Err(format!("Incorrect type: expected Box actual {:?}", t)),
+        },
+        SetBox(exp, value) => {
+            let elem_t = match type_check_node(exp, var_types)? {
+                Type::Box(t) => *t,
+                t => return Err(format!("Incorrect type: expected Box actual {:?}", t)),
+            };
+            type_check_exp(value, &elem_t, var_types)?;
+            Type::Void
+        }
+        Inject(exp, t) => {
+            expect_ground_type(t)?;
+            type_check_exp(exp, t, var_types)?;
+            Type::Any
+        }
+        Project(exp, t, _) => {
+            expect_ground_type(t)?;
+            type_check_exp(exp, &Type::Any, var_types)?;
+            t.clone()
         }
         e => panic!("unexpected {:?}", e),
     };
+
+    // expose allocation needs the type of a vector
+    if let Vector(_) | MakeBox(_) | Cons(..) = node {
+        let vector = std::mem::take(node);
+        *node = HasType(Box::new(vector), t.clone());
+    }
     Ok(t)
 }
 
-pub fn type_check(exp: Box<Node>, info: &mut Info) -> Result<Type, String> {
+pub fn type_check(exp: &mut Node, info: &mut Info) -> Result<Type, String> {
     type_check_node(exp, &mut info.vars_types)
 }
@@ -1,21 +1,25 @@
 use crate::ast::*;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
 fn add_var(s: &mut LiveSet, node: &Node) {
-    if let Node::Var(var_name) = &node {
-        s.insert(var_name.to_owned());
+    if let Some(name) = node.var_or_reg_name() {
+        s.insert(name);
     }
 }
 
 fn remove_var(s: &mut LiveSet, node: &Node) {
-    if let Node::Var(var_name) = &node {
-        s.remove(var_name);
+    if let Some(name) = node.var_or_reg_name() {
+        s.remove(&name);
     }
 }
 
 /// Check read set and write set of a node
 /// return (node, read set, write set)
-fn check_read_write(node: Box<Node>, live_set: &mut LiveSet) -> Box<Node> {
+fn check_read_write(
+    node: Box<Node>,
+    live_set: &mut LiveSet,
+    label_live: &HashMap<String, LiveSet>,
+) -> Box<Node> {
     use Node::*;
 
     let node = match *node {
@@ -24,6 +28,20 @@ fn check_read_write(node: Box<Node>, live_set: &mut LiveSet) -> Box<Node> {
             add_var(live_set, &source);
             MOVZBQ { target, source }
         }
+        // the target is only written if the condition holds
+        CMOV {
+            cond,
+            source,
+            target,
+        } => {
+            add_var(live_set, &target);
+            add_var(live_set, &source);
+            CMOV {
+                cond,
+                source,
+                target,
+            }
+        }
         MOVQ { target, source } => {
             remove_var(live_set, &target);
             add_var(live_set, &source);
@@ -35,51 +53,152 @@ fn check_read_write(node: Box<Node>, live_set: &mut LiveSet) -> Box<Node> {
             add_var(live_set, &arg);
             ADDQ { target, arg }
         }
-        If {
-            cond,
-            if_exps,
-            else_exps,
-            ..
-        } => {
-            // if branch
-            let mut if_live_afters: VecDeque<LiveSet> = Default::default();
-            let mut if_live_after = live_set.clone();
-            let if_exps = uncover_live_inner(if_exps, &mut if_live_after, &mut if_live_afters);
-
-            // else branch
-            let mut else_live_afters: VecDeque<LiveSet> = Default::default();
-            let mut else_live_after = live_set.clone();
-            let else_exps =
-                uncover_live_inner(else_exps, &mut else_live_after, &mut else_live_afters);
-
-            // clear live_set
-            // use if_live_after | else_live_after | cond as new live_set
-            live_set.clear();
-
-            // union if branch
-            for n in if_live_after {
-                live_set.insert(n);
+        SUBQ { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            SUBQ { target, arg }
+        }
+        IMULQ { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            IMULQ { target, arg }
+        }
+        NEGQ(target) => {
+            add_var(live_set, &target);
+            NEGQ(target)
+        }
+        ANDQ { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            ANDQ { target, arg }
+        }
+        ORQ { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            ORQ { target, arg }
+        }
+        SALQ { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            SALQ { target, arg }
+        }
+        SARQ { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            SARQ { target, arg }
+        }
+        SHRQ { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            SHRQ { target, arg }
+        }
+        NOTQ(target) => {
+            add_var(live_set, &target);
+            NOTQ(target)
+        }
+        XORQ { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            XORQ { target, arg }
+        }
+        ADDSD { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            ADDSD { target, arg }
+        }
+        SUBSD { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            SUBSD { target, arg }
+        }
+        MULSD { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            MULSD { target, arg }
+        }
+        DIVSD { target, arg } => {
+            add_var(live_set, &target);
+            add_var(live_set, &arg);
+            DIVSD { target, arg }
+        }
+        CVTSI2SDQ { target, source } => {
+            remove_var(live_set, &target);
+            add_var(live_set, &source);
+            CVTSI2SDQ { target, source }
+        }
+        CVTTSD2SIQ { target, source } => {
+            remove_var(live_set, &target);
+            add_var(live_set, &source);
+            CVTTSD2SIQ { target, source }
+        }
+        CQTO => {
+            remove_var(live_set, &RDX);
+            add_var(live_set, &RAX);
+            CQTO
+        }
+        IDIVQ(arg) => {
+            // IDIVQ reads and writes both RAX and RDX
+            add_var(live_set, &RAX);
+            add_var(live_set, &RDX);
+            add_var(live_set, &arg);
+            IDIVQ(arg)
+        }
+        CALLQ(label, arity) => {
+            // the call overwrites caller-saved registers and reads the arguments
+            for reg in CALLER_SAVED_REGISTERS.iter() {
+                remove_var(live_set, reg);
             }
-
-            // union else branch
-            for n in else_live_after {
-                live_set.insert(n);
+            for reg in ARGUMENT_REGISTERS.iter().take(arity) {
+                add_var(live_set, reg);
             }
-
-            check_read_write(cond.clone(), live_set);
-
-            If {
-                cond,
-                if_exps,
-                else_exps,
-                if_live_afters: if_live_afters.into(),
-                else_live_afters: else_live_afters.into(),
+            CALLQ(label, arity)
+        }
+        ICALLQ(fun, arity) => {
+            for reg in CALLER_SAVED_REGISTERS.iter() {
+                remove_var(live_set, reg);
+            }
+            for reg in ARGUMENT_REGISTERS.iter().take(arity) {
+                add_var(live_set, reg);
+            }
+            add_var(live_set, &fun);
+            ICALLQ(fun, arity)
+        }
+        LEAQ { target, source } => {
+            remove_var(live_set, &target);
+            LEAQ { target, source }
+        }
+        ITAILJMP(fun, arity) => {
+            live_set.clear();
+            for reg in ARGUMENT_REGISTERS.iter().take(arity) {
+                add_var(live_set, reg);
+            }
+            add_var(live_set, &fun);
+            ITAILJMP(fun, arity)
+        }
+        TAILJMP(label, arity) => {
+            // the function does not continue after the jump
+            live_set.clear();
+            for reg in ARGUMENT_REGISTERS.iter().take(arity) {
+                add_var(live_set, reg);
             }
+            TAILJMP(label, arity)
         }
-        var_node @ Var(_) => {
-            // read variable
-            add_var(live_set, &var_node);
-            var_node
+        CMPQ(lhs, rhs) => {
+            add_var(live_set, &lhs);
+            add_var(live_set, &rhs);
+            CMPQ(lhs, rhs)
+        }
+        JMP(label) => {
+            // live set after a jump is the live set before its target
+            *live_set = label_live.get(&label).cloned().unwrap_or_default();
+            JMP(label)
+        }
+        JMPIF(cond_code, label) => {
+            // both the target and the next instruction may be executed
+            if let Some(target_live) = label_live.get(&label) {
+                live_set.extend(target_live.iter().cloned());
+            }
+            JMPIF(cond_code, label)
         }
         node => {
             // do nothing
@@ -89,43 +208,148 @@ fn check_read_write(node: Box<Node>, live_set: &mut LiveSet) -> Box<Node> {
     Box::new(node)
 }
 
-fn uncover_live_inner(
+/// the variables and registers read by an instruction
+pub fn reads(node: &Node) -> LiveSet {
+    let mut live_set = LiveSet::default();
+    check_read_write(Box::new(node.clone()), &mut live_set, &HashMap::default());
+    live_set
+}
+
+/// walk the list backward once, record the live set before each label
+fn uncover_live_once(
     node_list: Vec<Box<Node>>,
-    live_after: &mut LiveSet,
+    label_live: &mut HashMap<String, LiveSet>,
     live_afters: &mut VecDeque<LiveSet>,
-) -> Vec<Box<Node>> {
+) -> (Vec<Box<Node>>, bool) {
     // start from a empty set
+    let mut live_after = LiveSet::default();
+    let mut changed = false;
     let mut new_node_list = VecDeque::with_capacity(node_list.len());
 
     // search list reversely
     for node in node_list.into_iter().rev() {
-        let node = check_read_write(node, live_after);
-        new_node_list.push_front(node);
         live_afters.push_front(live_after.clone());
+        if let Node::Label(label) = node.as_ref() {
+            if label_live.get(label) != Some(&live_after) {
+                label_live.insert(label.to_owned(), live_after.clone());
+                changed = true;
+            }
+        }
+        let node = check_read_write(node, &mut live_after, label_live);
+        new_node_list.push_front(node);
     }
+    live_afters.push_front(live_after);
 
-    assert_eq!(
-        new_node_list.len(),
-        live_afters.len(),
-        "generated live set for each instruction"
-    );
+    (new_node_list.into(), changed)
+}
 
-    new_node_list.into()
+pub fn uncover_live(mut node_list: Vec<Box<Node>>, info: &mut Info) -> Vec<Box<Node>> {
+    // iterate until the live sets of labels reach a fixed point,
+    // loops need more than one pass since jumps go backward
+    let mut label_live = HashMap::default();
+    loop {
+        let mut live_afters: VecDeque<LiveSet> = Default::default();
+        let (new_node_list, changed) =
+            uncover_live_once(node_list, &mut label_live, &mut live_afters);
+        node_list = new_node_list;
+        if !changed {
+            debug_assert!(
+                live_afters[0].iter().all(|name| ARGUMENT_REGISTERS
+                    .iter()
+                    .any(|reg| reg.var_or_reg_name().as_ref() == Some(name))),
+                "only parameters are live before first instruction"
+            );
+            live_afters.pop_front();
+            info.live_afters = live_afters.into();
+            return node_list;
+        }
+    }
 }
 
-pub fn uncover_live(node_list: Vec<Box<Node>>, info: &mut Info) -> Vec<Box<Node>> {
-    let mut initial_live_after: LiveSet = Default::default();
-    let mut live_afters: VecDeque<LiveSet> = Default::default();
-    let new_node_list = uncover_live_inner(node_list, &mut initial_live_after, &mut live_afters);
-
-    // fix the set of live afters by pending a empty set
-    debug_assert!(
-        live_afters[0].is_empty(),
-        "set before first instruction is empty"
-    );
-    live_afters.pop_front();
-    live_afters.push_back(Default::default());
-
-    info.live_afters = live_afters.into();
-    new_node_list
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Box<Node> {
+        Box::new(Node::Var(name.to_string()))
+    }
+
+    fn label(name: &str) -> Box<Node> {
+        Box::new(Node::Label(name.to_string()))
+    }
+
+    fn live_afters(node_list: Vec<Box<Node>>) -> Vec<LiveSet> {
+        let mut info = Info::default();
+        uncover_live(node_list, &mut info);
+        info.live_afters
+    }
+
+    fn live(vars: &[&str]) -> LiveSet {
+        vars.iter().map(|var| var.to_string()).collect()
+    }
+
+    #[test]
+    fn loop_keeps_variables_live_around_back_edge() {
+        let node_list = vec![
+            Box::new(Node::MOVQ {
+                target: var("y"),
+                source: Box::new(Node::RDI),
+            }),
+            Box::new(Node::MOVQ {
+                target: var("i"),
+                source: Box::new(Node::Fixnum(0)),
+            }),
+            label("loop"),
+            Box::new(Node::CMPQ(Box::new(Node::Fixnum(10)), var("i"))),
+            Box::new(Node::JMPIF(CondCode::Ge, "end".to_string())),
+            Box::new(Node::ADDQ {
+                target: var("i"),
+                arg: var("y"),
+            }),
+            Box::new(Node::JMP("loop".to_string())),
+            label("end"),
+            Box::new(Node::MOVQ {
+                target: Box::new(Node::RAX),
+                source: var("i"),
+            }),
+        ];
+        let live_afters = live_afters(node_list);
+        // y is only read inside the loop, so it is live after the jump back
+        assert_eq!(live_afters[5], live(&["i", "y"]));
+        assert_eq!(live_afters[2], live(&["i", "y"]));
+        assert_eq!(live_afters[7], live(&["i"]));
+        assert_eq!(live_afters[8], live(&[]));
+    }
+
+    #[test]
+    fn branch_joins_live_sets_of_both_successors() {
+        let node_list = vec![
+            Box::new(Node::MOVQ {
+                target: var("x"),
+                source: Box::new(Node::RDI),
+            }),
+            Box::new(Node::MOVQ {
+                target: var("y"),
+                source: Box::new(Node::RSI),
+            }),
+            Box::new(Node::CMPQ(Box::new(Node::Fixnum(0)), var("x"))),
+            Box::new(Node::JMPIF(CondCode::E, "else".to_string())),
+            Box::new(Node::MOVQ {
+                target: Box::new(Node::RAX),
+                source: var("x"),
+            }),
+            Box::new(Node::JMP("end".to_string())),
+            label("else"),
+            Box::new(Node::MOVQ {
+                target: Box::new(Node::RAX),
+                source: var("y"),
+            }),
+            label("end"),
+        ];
+        let live_afters = live_afters(node_list);
+        assert_eq!(live_afters[2], live(&["x", "y"]));
+        assert_eq!(live_afters[3], live(&["x"]));
+        assert_eq!(live_afters[6], live(&["y"]));
+        assert_eq!(live_afters[4], live(&[]));
+    }
 }
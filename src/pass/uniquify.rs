@@ -1,4 +1,4 @@
-use crate::ast::Node;
+use crate::ast::{Info, Node, Type};
 use std::collections::HashMap;
 
 fn rewrite_var(mut name: String, cnt: usize) -> String {
@@ -6,25 +6,211 @@ fn rewrite_var(mut name: String, cnt: usize) -> String {
     name
 }
 
-fn uniquify_inner(node: Box<Node>, cxt: &mut HashMap<String, usize>) -> Box<Node> {
+struct Context<'a> {
+    /// number of the binding each name in scope refers to
+    scopes: HashMap<String, usize>,
+    info: &'a mut Info,
+}
+
+impl Context<'_> {
+    /// a binding gets a fresh number, the shadowed one is returned to be restored
+    fn bind(&mut self, name: &str) -> (usize, Option<usize>) {
+        self.info.vars_count += 1;
+        let count = self.info.vars_count;
+        (count, self.scopes.insert(name.to_string(), count))
+    }
+
+    fn unbind(&mut self, name: &str, shadowed: Option<usize>) {
+        match shadowed {
+            Some(count) => self.scopes.insert(name.to_string(), count),
+            None => self.scopes.remove(name),
+        };
+    }
+
+    fn rename(&self, name: String) -> String {
+        let count = self.scopes.get(&name).copied().unwrap_or_default();
+        rewrite_var(name, count)
+    }
+}
+
+/// parameters are bound in the body
+fn uniquify_params(
+    params: Vec<(String, Type)>,
+    body: Vec<Box<Node>>,
+    cxt: &mut Context,
+) -> (Vec<(String, Type)>, Vec<Box<Node>>) {
+    let counts: Vec<_> = params.iter().map(|(param, _)| cxt.bind(param)).collect();
+    let body = body
+        .into_iter()
+        .map(|exp| uniquify_inner(exp, cxt))
+        .collect();
+    // the shadowed bindings are restored in reverse
+    for ((param, _), (_, shadowed)) in params.iter().zip(&counts).rev() {
+        cxt.unbind(param, *shadowed);
+    }
+    let params = params
+        .into_iter()
+        .zip(counts)
+        .map(|((param, t), (count, _))| (rewrite_var(param, count), t))
+        .collect();
+    (params, body)
+}
+
+fn uniquify_inner(node: Box<Node>, cxt: &mut Context) -> Box<Node> {
     use Node::*;
 
     let node = match *node {
-        Program(sub_node) => Program(uniquify_inner(sub_node, cxt)),
+        Program(defs, exp) => {
+            let defs = defs
+                .into_iter()
+                .map(|def| uniquify_inner(def, cxt))
+                .collect();
+            Program(defs, uniquify_inner(exp, cxt))
+        }
+        Def {
+            name,
+            params,
+            ret,
+            body,
+        } => {
+            let (params, body) = uniquify_params(params, body, cxt);
+            Def {
+                name,
+                params,
+                ret,
+                body,
+            }
+        }
+        Lambda {
+            params,
+            ret,
+            body,
+            free_vars,
+        } => {
+            let free_vars = free_vars
+                .into_iter()
+                .map(|(name, t)| (cxt.rename(name), t))
+                .collect();
+            let (params, mut body) = uniquify_params(params, vec![body], cxt);
+            Lambda {
+                params,
+                ret,
+                body: body.remove(0),
+                free_vars,
+            }
+        }
+        Apply(fun, args) => Apply(
+            uniquify_inner(fun, cxt),
+            args.into_iter()
+                .map(|arg| uniquify_inner(arg, cxt))
+                .collect(),
+        ),
         Neg(sub_node) => Neg(uniquify_inner(sub_node, cxt)),
+        Not(sub_node) => Not(uniquify_inner(sub_node, cxt)),
         Add(lhs, rhs) => Add(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
-        Var(var_name) => {
-            let count = cxt.get(&var_name).map(|cnt| *cnt).unwrap_or_default();
-            let new_var_name = rewrite_var(var_name, count);
-            Var(new_var_name)
+        Sub(lhs, rhs) => Sub(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Mul(lhs, rhs) => Mul(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        FAdd(lhs, rhs) => FAdd(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        FSub(lhs, rhs) => FSub(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        FMul(lhs, rhs) => FMul(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        FDiv(lhs, rhs) => FDiv(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        CharToInteger(exp) => CharToInteger(uniquify_inner(exp, cxt)),
+        IntegerToChar(exp) => IntegerToChar(uniquify_inner(exp, cxt)),
+        FixnumToFlonum(exp) => FixnumToFlonum(uniquify_inner(exp, cxt)),
+        FlonumToFixnum(exp) => FlonumToFixnum(uniquify_inner(exp, cxt)),
+        Quotient(lhs, rhs) => Quotient(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Remainder(lhs, rhs) => Remainder(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        BitAnd(lhs, rhs) => BitAnd(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        BitOr(lhs, rhs) => BitOr(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        BitXor(lhs, rhs) => BitXor(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        BitNot(sub_node) => BitNot(uniquify_inner(sub_node, cxt)),
+        Shl(lhs, rhs) => Shl(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Shr(lhs, rhs) => Shr(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Sar(lhs, rhs) => Sar(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Min(lhs, rhs) => Min(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Max(lhs, rhs) => Max(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Eq(lhs, rhs) => Eq(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Lt(lhs, rhs) => Lt(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Lte(lhs, rhs) => Lte(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Gt(lhs, rhs) => Gt(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        Gte(lhs, rhs) => Gte(uniquify_inner(lhs, cxt), uniquify_inner(rhs, cxt)),
+        If {
+            cond,
+            if_exps,
+            else_exps,
+        } => If {
+            cond: uniquify_inner(cond, cxt),
+            if_exps: if_exps
+                .into_iter()
+                .map(|exp| uniquify_inner(exp, cxt))
+                .collect(),
+            else_exps: else_exps
+                .into_iter()
+                .map(|exp| uniquify_inner(exp, cxt))
+                .collect(),
+        },
+        Vector(elems) => Vector(
+            elems
+                .into_iter()
+                .map(|elem| uniquify_inner(elem, cxt))
+                .collect(),
+        ),
+        Literal(vec) => Literal(uniquify_inner(vec, cxt)),
+        VectorLength(vec) => VectorLength(uniquify_inner(vec, cxt)),
+        MakeArray(len, init, t) => {
+            MakeArray(uniquify_inner(len, cxt), uniquify_inner(init, cxt), t)
         }
+        ArrayLength(array) => ArrayLength(uniquify_inner(array, cxt)),
+        Random(bound) => Random(uniquify_inner(bound, cxt)),
+        Exit(code) => Exit(uniquify_inner(code, cxt)),
+        ArrayRef(array, index) => ArrayRef(uniquify_inner(array, cxt), uniquify_inner(index, cxt)),
+        ArraySet(array, index, value) => ArraySet(
+            uniquify_inner(array, cxt),
+            uniquify_inner(index, cxt),
+            uniquify_inner(value, cxt),
+        ),
+        VectorRef(vec, index) => VectorRef(uniquify_inner(vec, cxt), uniquify_inner(index, cxt)),
+        VectorSet(vec, index, value) => VectorSet(
+            uniquify_inner(vec, cxt),
+            uniquify_inner(index, cxt),
+            uniquify_inner(value, cxt),
+        ),
+        StringLength(s) => StringLength(uniquify_inner(s, cxt)),
+        StringRef(s, index) => StringRef(uniquify_inner(s, cxt), uniquify_inner(index, cxt)),
+        HasType(exp, t) => HasType(uniquify_inner(exp, cxt), t),
+        Inject(exp, t) => Inject(uniquify_inner(exp, cxt), t),
+        Project(exp, t, pos) => Project(uniquify_inner(exp, cxt), t, pos),
+        While {
+            cond_exps,
+            cond,
+            body_exps,
+        } => While {
+            cond_exps,
+            cond: uniquify_inner(cond, cxt),
+            body_exps: body_exps
+                .into_iter()
+                .map(|exp| uniquify_inner(exp, cxt))
+                .collect(),
+        },
+        Begin(effects, exp) => Begin(
+            effects
+                .into_iter()
+                .map(|effect| uniquify_inner(effect, cxt))
+                .collect(),
+            uniquify_inner(exp, cxt),
+        ),
+        Var(var_name) => Var(cxt.rename(var_name)),
+        GlobalSet(label, value) => GlobalSet(label, uniquify_inner(value, cxt)),
+        SetBang { name, value } => SetBang {
+            name: cxt.rename(name),
+            value: uniquify_inner(value, cxt),
+        },
         Let { name, value, exp } => {
-            let count = cxt.get(&name).map(|cnt| *cnt).unwrap_or_default() + 1;
-            // increase cnt in sub node
-            cxt.insert(name.clone(), count);
+            // value is evaluated in the outer scope
+            let value = uniquify_inner(value, cxt);
+            let (count, shadowed) = cxt.bind(&name);
             let sub_node = uniquify_inner(exp, cxt);
-            // set cnt back
-            cxt.insert(name.clone(), count - 1);
+            cxt.unbind(&name, shadowed);
             let new_var_name = rewrite_var(name, count);
             Let {
                 name: new_var_name,
@@ -37,7 +223,11 @@ fn uniquify_inner(node: Box<Node>, cxt: &mut HashMap<String, usize>) -> Box<Node
     Box::new(node)
 }
 
-pub fn uniquify(node: Box<Node>) -> Box<Node> {
-    let mut cxt = HashMap::default();
+/// every binding is renamed to a fresh name, the counter is kept in the info
+pub fn uniquify(node: Box<Node>, info: &mut Info) -> Box<Node> {
+    let mut cxt = Context {
+        scopes: HashMap::new(),
+        info,
+    };
     uniquify_inner(node, &mut cxt)
 }
@@ -0,0 +1,141 @@
+/// Unroll the counted loops, a loop compares its counter to a literal bound
+/// and it steps the counter by a literal at the end of its body, the unrolled loop
+/// runs the body several times for one test, the remaining iterations run in the loop,
+/// a loop whose body calls a function or whose counter is captured by a lambda is left
+use crate::ast::*;
+use std::collections::HashSet;
+
+/// the largest unrolled body, counted in nodes, the copies are only made below it
+const UNROLL_BUDGET: usize = 128;
+
+/// check if the expression assigns the variable
+fn assigns(node: &Node, name: &str) -> bool {
+    matches!(node, Node::SetBang { name: var, .. } if var == name)
+        || node
+            .children()
+            .into_iter()
+            .any(|child| assigns(child, name))
+}
+
+/// check if the expression calls a function, which may assign the counter
+fn calls(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Apply(..) | Node::CallCC(_) | Node::WithHandler(..)
+    ) || node.children().into_iter().any(calls)
+}
+
+/// the free variables of the lambdas in the expression
+fn captured_vars(node: &Node, vars: &mut HashSet<String>) {
+    if let Node::Lambda { .. } = node {
+        vars.extend(node.free_vars());
+    }
+    for child in node.children() {
+        captured_vars(child, vars);
+    }
+}
+
+/// the step of an assignment adding a literal to the counter
+fn step(value: &Node, counter: &str) -> Option<isize> {
+    use Node::*;
+
+    match value {
+        Add(lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+            (Var(var), Fixnum(step)) | (Fixnum(step), Var(var)) if var == counter => Some(*step),
+            _ => None,
+        },
+        Sub(lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+            (Var(var), Fixnum(step)) if var == counter => step.checked_neg(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// the test of the unrolled loop, which holds if the next iterations all run
+fn unrolled_cond(
+    cond: &Node,
+    body: &Node,
+    factor: usize,
+    captured: &HashSet<String>,
+) -> Option<Node> {
+    use Node::*;
+
+    let (counter, bound, upward) = match cond {
+        Lt(lhs, rhs) | Lte(lhs, rhs) => (lhs.var()?, rhs.fixnum()?, true),
+        Gt(lhs, rhs) | Gte(lhs, rhs) => (lhs.var()?, rhs.fixnum()?, false),
+        _ => return None,
+    };
+    let (effects, last) = match body {
+        Begin(effects, last) => (effects, last),
+        _ => return None,
+    };
+    let step = match last.as_ref() {
+        SetBang { name, value } if name == counter => step(value, counter)?,
+        _ => return None,
+    };
+    if (upward && step <= 0) || (!upward && step >= 0) {
+        return None;
+    }
+    if captured.contains(counter)
+        || calls(body)
+        || effects.iter().any(|effect| assigns(effect, counter))
+    {
+        return None;
+    }
+    let bound = Box::new(Fixnum(
+        bound.checked_sub(step.checked_mul(factor as isize - 1)?)?,
+    ));
+    let counter = Box::new(Var(counter.clone()));
+    Some(match cond {
+        Lt(..) => Lt(counter, bound),
+        Lte(..) => Lte(counter, bound),
+        Gt(..) => Gt(counter, bound),
+        _ => Gte(counter, bound),
+    })
+}
+
+pub fn unroll_loops(node: Box<Node>, factor: usize) -> Box<Node> {
+    let mut captured = HashSet::new();
+    captured_vars(&node, &mut captured);
+    unroll(node, factor, &captured)
+}
+
+fn unroll(node: Box<Node>, factor: usize, captured: &HashSet<String>) -> Box<Node> {
+    use Node::*;
+
+    let node = match *node {
+        While {
+            cond_exps,
+            cond,
+            mut body_exps,
+        } if cond_exps.is_empty() && body_exps.len() == 1 => {
+            let body = unroll(body_exps.remove(0), factor, captured);
+            let unrolled = match unrolled_cond(&cond, &body, factor, captured) {
+                Some(unrolled) if body.size().saturating_mul(factor) <= UNROLL_BUDGET => unrolled,
+                _ => {
+                    return Box::new(While {
+                        cond_exps,
+                        cond,
+                        body_exps: vec![body],
+                    })
+                }
+            };
+            let copies = vec![body.clone(); factor - 1];
+            Begin(
+                vec![Box::new(While {
+                    cond_exps: Vec::new(),
+                    cond: Box::new(unrolled),
+                    body_exps: vec![Box::new(Begin(copies, body.clone()))],
+                })],
+                Box::new(While {
+                    cond_exps,
+                    cond,
+                    body_exps: vec![body],
+                }),
+            )
+        }
+        node => node.map_children(&mut |node| unroll(node, factor, captured)),
+    };
+    Box::new(node)
+}
@@ -0,0 +1,451 @@
+use crate::ast::{Function, Info, Node, Options, Type};
+use crate::pass;
+use crate::validate;
+
+/// The program in the form of a stage of the pipeline
+pub enum Ir {
+    /// the expression tree, up to expose allocation
+    Exp(Box<Node>),
+    /// the statements of each definition, up to destruct ssa
+    Defs(Vec<Box<Node>>),
+    /// the instructions of each function, up to optimize peephole
+    Functions(Vec<Function>),
+    /// the assembly printed by print x86
+    Asm(Vec<u8>),
+}
+
+pub struct Program {
+    pub ir: Ir,
+    /// type of the value of the program, known after type check
+    pub t: Type,
+    pub info: Info,
+}
+
+impl Program {
+    pub fn new(exp: Box<Node>) -> Self {
+        Program {
+            ir: Ir::Exp(exp),
+            t: Type::Void,
+            info: Info::default(),
+        }
+    }
+}
+
+fn unexpected(name: &str) -> String {
+    format!("pass {} got the program in another form", name)
+}
+
+/// A transformation of the program
+pub trait Pass {
+    fn name(&self) -> &str;
+
+    /// an optimization, the program is compiled correctly without it
+    fn is_optional(&self) -> bool {
+        false
+    }
+
+    /// the passes which must run before this one, if they are in the pipeline
+    fn runs_after(&self) -> &[&str] {
+        &[]
+    }
+
+    /// the passes which must run after this one, if they are in the pipeline
+    fn runs_before(&self) -> &[&str] {
+        &[]
+    }
+
+    fn run(&self, prog: Program) -> Result<Program, String>;
+}
+
+/// the source optimizations see the integers untagged
+const SOURCE_END: &[&str] = &["tag_integers", "reveal_functions"];
+
+type Run = Box<dyn Fn(Program) -> Result<Program, String>>;
+
+/// a pass calling a function of the pass module
+struct Stage {
+    name: &'static str,
+    optional: bool,
+    after: &'static [&'static str],
+    before: &'static [&'static str],
+    run: Run,
+}
+
+impl Stage {
+    fn new(name: &'static str, run: impl Fn(Program) -> Result<Program, String> + 'static) -> Self {
+        Stage {
+            name,
+            optional: false,
+            after: &[],
+            before: &[],
+            run: Box::new(run),
+        }
+    }
+
+    /// an optimization, which may run anywhere between the passes
+    fn between(mut self, after: &'static [&'static str], before: &'static [&'static str]) -> Self {
+        self.optional = true;
+        self.after = after;
+        self.before = before;
+        self
+    }
+
+    fn exp(name: &'static str, f: impl Fn(Box<Node>) -> Box<Node> + 'static) -> Self {
+        Self::try_exp(name, move |exp| Ok(f(exp)))
+    }
+
+    fn try_exp(
+        name: &'static str,
+        f: impl Fn(Box<Node>) -> Result<Box<Node>, String> + 'static,
+    ) -> Self {
+        Self::new(name, move |prog| match prog.ir {
+            Ir::Exp(exp) => Ok(Program {
+                ir: Ir::Exp(f(exp)?),
+                ..prog
+            }),
+            _ => Err(unexpected(name)),
+        })
+    }
+
+    fn defs(name: &'static str, f: impl Fn(Vec<Box<Node>>) -> Vec<Box<Node>> + 'static) -> Self {
+        Self::new(name, move |prog| match prog.ir {
+            Ir::Defs(defs) => Ok(Program {
+                ir: Ir::Defs(f(defs)),
+                ..prog
+            }),
+            _ => Err(unexpected(name)),
+        })
+    }
+
+    /// a pass over the instructions of each function
+    fn body(
+        name: &'static str,
+        f: impl Fn(Vec<Box<Node>>, &mut Info) -> Vec<Box<Node>> + 'static,
+    ) -> Self {
+        Self::new(name, move |prog| match prog.ir {
+            Ir::Functions(functions) => {
+                let functions = functions
+                    .into_iter()
+                    .map(|mut function| {
+                        let body = std::mem::take(&mut function.body);
+                        function.body = f(body, &mut function.info);
+                        function
+                    })
+                    .collect();
+                Ok(Program {
+                    ir: Ir::Functions(functions),
+                    ..prog
+                })
+            }
+            _ => Err(unexpected(name)),
+        })
+    }
+}
+
+impl Pass for Stage {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn is_optional(&self) -> bool {
+        self.optional
+    }
+
+    fn runs_after(&self) -> &[&str] {
+        self.after
+    }
+
+    fn runs_before(&self) -> &[&str] {
+        self.before
+    }
+
+    fn run(&self, prog: Program) -> Result<Program, String> {
+        (self.run)(prog)
+    }
+}
+
+/// A property of the program from the output of a pass on, which the later passes keep
+struct Invariant {
+    name: &'static str,
+    after: &'static str,
+    /// the pass whose output is not checked any more
+    until: &'static str,
+    check: fn(&Ir) -> Result<(), String>,
+}
+
+const INVARIANTS: &[Invariant] = &[
+    Invariant {
+        name: "unique variable names",
+        after: "uniquify",
+        // the closures bind their free variables again
+        until: "closure_conversion",
+        check: |ir| match ir {
+            Ir::Exp(exp) => validate::unique_names(exp),
+            _ => Ok(()),
+        },
+    },
+    Invariant {
+        name: "atomic operands",
+        after: "flattern",
+        until: "select_inst",
+        check: |ir| match ir {
+            Ir::Defs(defs) => validate::atomic_operands(defs),
+            _ => Ok(()),
+        },
+    },
+    Invariant {
+        name: "no variables",
+        after: "allocate_registers",
+        until: "print_x86",
+        check: |ir| match ir {
+            Ir::Functions(functions) => validate::no_vars(functions),
+            _ => Ok(()),
+        },
+    },
+];
+
+/// The passes compiling a program in order
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    /// check the invariants after each pass
+    validate: bool,
+}
+
+impl PassManager {
+    /// the pipeline for the options, the passes enabled by the options are included
+    pub fn new(options: &Options) -> Self {
+        let mut passes: Vec<Stage> = vec![
+            Stage::try_exp("lower_variadic", pass::lower_variadic),
+            Stage::try_exp("inline_constants", pass::inline_constants),
+            Stage::exp("limit_functions", pass::limit_functions),
+            Stage::new("type_check", |mut prog| match prog.ir {
+                Ir::Exp(ref mut exp) => {
+                    prog.t = pass::type_check(exp, &mut prog.info)?;
+                    Ok(prog)
+                }
+                _ => Err(unexpected("type_check")),
+            }),
+            Stage::exp("monomorphize", pass::monomorphize),
+            Stage::exp("lower_casts", pass::lower_casts),
+            Stage::try_exp("compile_match", pass::compile_match),
+            {
+                let options = options.clone();
+                Stage::new("lower_print", move |prog| match prog.ir {
+                    Ir::Exp(exp) => Ok(Program {
+                        ir: Ir::Exp(pass::lower_print(exp, &prog.t, &options)),
+                        ..prog
+                    }),
+                    _ => Err(unexpected("lower_print")),
+                })
+            },
+            Stage::exp("shrink", pass::shrink),
+            Stage::exp("partial_eval", pass::partial_eval).between(&["shrink"], SOURCE_END),
+        ];
+        if options.unroll > 1 {
+            let factor = options.unroll;
+            passes.push(
+                Stage::exp("unroll_loops", move |exp| pass::unroll_loops(exp, factor))
+                    .between(&["shrink"], SOURCE_END),
+            );
+        }
+        if options.bignum {
+            passes.push(Stage::exp("tag_integers", pass::tag_integers));
+        }
+        passes.extend([
+            Stage::exp("reveal_functions", pass::reveal_functions),
+            Stage::exp("inline_functions", pass::inline_functions)
+                .between(&["reveal_functions"], &["uniquify"]),
+            Stage::new("uniquify", |mut prog| match prog.ir {
+                Ir::Exp(exp) => {
+                    prog.ir = Ir::Exp(pass::uniquify(exp, &mut prog.info));
+                    Ok(prog)
+                }
+                _ => Err(unexpected("uniquify")),
+            }),
+            Stage::exp("convert_assignments", pass::convert_assignments),
+            Stage::exp("closure_conversion", pass::closure_conversion),
+            Stage::exp("expose_allocation", pass::expose_allocation),
+            Stage::new("flattern", |prog| match prog.ir {
+                Ir::Exp(exp) => Ok(Program {
+                    ir: Ir::Defs(pass::flattern(exp)),
+                    ..prog
+                }),
+                _ => Err(unexpected("flattern")),
+            }),
+            Stage::defs("eliminate_common_subexps", pass::eliminate_common_subexps)
+                .between(&["flattern"], &["explicate_control"]),
+            Stage::defs("explicate_control", pass::explicate_control),
+        ]);
+        let cloned = options.clone();
+        passes.push(
+            Stage::defs("propagate_constants", move |defs| {
+                pass::propagate_constants(defs, &cloned)
+            })
+            // the branches it takes leave blocks which can not be reached
+            .between(&["explicate_control"], &["remove_dead_code"]),
+        );
+        let cloned = options.clone();
+        passes.push(
+            Stage::defs("remove_dead_code", move |defs| {
+                pass::remove_dead_code(defs, &cloned)
+            })
+            .between(&["explicate_control"], &["construct_ssa", "select_inst"]),
+        );
+        passes.push(Stage::defs("thread_jumps", pass::thread_jumps).between(
+            &["explicate_control", "remove_dead_code"],
+            &["construct_ssa", "select_inst"],
+        ));
+        if options.ssa {
+            passes.push(Stage::defs("construct_ssa", pass::construct_ssa).between(
+                &["explicate_control", "remove_dead_code"],
+                &["destruct_ssa"],
+            ));
+            passes.push(Stage::defs("destruct_ssa", pass::destruct_ssa));
+        }
+        let cloned = options.clone();
+        passes.push(Stage::new("select_inst", move |prog| match prog.ir {
+            Ir::Defs(defs) => Ok(Program {
+                ir: Ir::Functions(pass::select_inst(defs, &cloned)),
+                ..prog
+            }),
+            _ => Err(unexpected("select_inst")),
+        }));
+        passes.extend([
+            Stage::body("fold_constants", |body, _| pass::fold_constants(body))
+                .between(&["select_inst"], &["uncover_live"]),
+            Stage::body("propagate_copies", |body, _| pass::propagate_copies(body))
+                .between(&["select_inst"], &["uncover_live"]),
+        ]);
+        if options.schedule {
+            passes.push(
+                Stage::body("schedule_inst", |body, _| pass::schedule_inst(body))
+                    .between(&["select_inst"], &["uncover_live"]),
+            );
+        }
+        passes.extend([
+            Stage::body("uncover_live", pass::uncover_live),
+            Stage::body("remove_dead_stores", pass::remove_dead_stores)
+                .between(&["uncover_live"], &["build_interference"]),
+            Stage::body("build_interference", pass::build_interference),
+            Stage::body("allocate_registers", pass::allocate_registers),
+            Stage::body("patch_inst", |body, _| pass::patch_inst(body)),
+            Stage::body("optimize_peephole", |body, _| pass::optimize_peephole(body))
+                .between(&["patch_inst"], &["print_x86"]),
+            Stage::new("print_x86", |prog| match prog.ir {
+                Ir::Functions(functions) => {
+                    let mut buf = Vec::new();
+                    pass::print_x86(&mut buf, functions).map_err(|err| err.to_string())?;
+                    Ok(Program {
+                        ir: Ir::Asm(buf),
+                        ..prog
+                    })
+                }
+                _ => Err(unexpected("print_x86")),
+            }),
+        ]);
+        PassManager {
+            passes: passes
+                .into_iter()
+                .map(|stage| Box::new(stage) as Box<dyn Pass>)
+                .collect(),
+            validate: options.validate,
+        }
+    }
+
+    pub fn passes(&self) -> &[Box<dyn Pass>] {
+        &self.passes
+    }
+
+    /// leave an optimization out of the pipeline
+    pub fn skip(&mut self, name: &str) -> Result<(), String> {
+        let pos = self
+            .position(name)
+            .ok_or_else(|| format!("Pass {} is not in the pipeline", name))?;
+        if !self.passes[pos].is_optional() {
+            return Err(format!("Pass {} can not be skipped", name));
+        }
+        self.passes.remove(pos);
+        Ok(())
+    }
+
+    /// run only the optimizations of the names in their order, each one as early as
+    /// its constraints allow after the previous one
+    pub fn reorder(&mut self, names: &[String]) -> Result<(), String> {
+        let (mut optimizations, required): (Vec<_>, Vec<_>) = std::mem::take(&mut self.passes)
+            .into_iter()
+            .partition(|pass| pass.is_optional());
+        self.passes = required;
+        let mut pos = 0;
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                return Err(format!("Pass {} is listed more than once", name));
+            }
+            let found = optimizations.iter().position(|pass| pass.name() == name);
+            let pass = match found {
+                Some(found) => optimizations.remove(found),
+                None if self.position(name).is_some() => {
+                    return Err(format!("Pass {} can not be reordered", name))
+                }
+                None => return Err(format!("Pass {} is not in the pipeline", name)),
+            };
+            for after in pass.runs_after() {
+                if let Some(i) = self.position(after) {
+                    pos = pos.max(i + 1);
+                }
+            }
+            self.passes.insert(pos, pass);
+            pos += 1;
+        }
+        self.validate()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|pass| pass.name() == name)
+    }
+
+    /// check each pass runs between the passes of its constraints
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, pass) in self.passes.iter().enumerate() {
+            for after in pass.runs_after() {
+                if self.position(after).is_some_and(|j| j > i) {
+                    return Err(format!("Pass {} must run after {}", pass.name(), after));
+                }
+            }
+            for before in pass.runs_before() {
+                if self.position(before).is_some_and(|j| j < i) {
+                    return Err(format!("Pass {} must run before {}", pass.name(), before));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// run the passes in order, the hook is called with each pass and the program it returns
+    /// before the program is validated
+    pub fn run(
+        &self,
+        mut prog: Program,
+        mut hook: impl FnMut(&dyn Pass, &Program),
+    ) -> Result<Program, String> {
+        let mut invariants: Vec<&Invariant> = Vec::new();
+        for pass in &self.passes {
+            prog = pass.run(prog)?;
+            hook(pass.as_ref(), &prog);
+            if !self.validate {
+                continue;
+            }
+            invariants.retain(|invariant| invariant.until != pass.name());
+            invariants.extend(
+                INVARIANTS
+                    .iter()
+                    .filter(|invariant| invariant.after == pass.name()),
+            );
+            for invariant in &invariants {
+                (invariant.check)(&prog.ir).map_err(|msg| {
+                    format!("Pass {} broke {}: {}", pass.name(), invariant.name, msg)
+                })?;
+            }
+        }
+        Ok(prog)
+    }
+}
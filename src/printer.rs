@@ -5,11 +5,178 @@ pub fn print_ast(node: Box<Node>) {
 
     match *node {
         Fixnum(num) => print!("{}", num),
-        Program(node) => {
-            print!("(program ");
+        Str(s) => print!("{:?}", s),
+        Symbol(name) => print!("'{}", name),
+        Char(chr) => print!("#\\{}", chr),
+        CharToInteger(exp) => {
+            print!("(char->integer ");
+            print_ast(exp);
+            print!(")");
+        }
+        IntegerToChar(exp) => {
+            print!("(integer->char ");
+            print_ast(exp);
+            print!(")");
+        }
+        Flonum(bits) => print!("{:?}", f64::from_bits(bits)),
+        FixnumToFlonum(exp) => {
+            print!("(fixnum->flonum ");
+            print_ast(exp);
+            print!(")");
+        }
+        FlonumToFixnum(exp) => {
+            print!("(flonum->fixnum ");
+            print_ast(exp);
+            print!(")");
+        }
+        FAdd(lhs, rhs) => {
+            print!("(+. ");
+            print_ast(lhs);
+            print!(" ");
+            print_ast(rhs);
+            print!(")");
+        }
+        FSub(lhs, rhs) => {
+            print!("(-. ");
+            print_ast(lhs);
+            print!(" ");
+            print_ast(rhs);
+            print!(")");
+        }
+        FMul(lhs, rhs) => {
+            print!("(*. ");
+            print_ast(lhs);
+            print!(" ");
+            print_ast(rhs);
+            print!(")");
+        }
+        FDiv(lhs, rhs) => {
+            print!("(/. ");
+            print_ast(lhs);
+            print!(" ");
+            print_ast(rhs);
+            print!(")");
+        }
+        DefGlobal { name, value } => {
+            print!("(define {} ", name);
+            print_ast(value);
+            print!(")");
+        }
+        Variadic(def) => {
+            print!("(variadic ");
+            print_ast(def);
+            print!(")");
+        }
+        DefConst { name, value } => {
+            print!("(define-const {} ", name);
+            print_ast(value);
+            print!(")");
+        }
+        Program(defs, node) => {
+            print!("(program");
+            for def in defs {
+                println!();
+                print_ast(def);
+            }
+            println!();
             print_ast(node);
             print!(")");
         }
+        Def {
+            name,
+            params,
+            ret,
+            body,
+        } => {
+            print!("(define ({}", name);
+            for (param, t) in params {
+                print!(" [{} : {:?}]", param, t);
+            }
+            print!(") : {:?}", ret);
+            for node in body {
+                println!();
+                print_ast(node);
+            }
+            print!(")");
+        }
+        DefStruct { name, fields } => {
+            print!("(define-struct {} (", name);
+            for (i, (field, t)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    print!(" ");
+                }
+                print!("[{} : {:?}]", field, t);
+            }
+            print!("))");
+        }
+        DefType { name, variants } => {
+            print!("(define-type {}", name);
+            for (variant, types) in variants {
+                print!(" ({}", variant);
+                for t in types {
+                    print!(" {:?}", t);
+                }
+                print!(")");
+            }
+            print!(")");
+        }
+        Provide(names) => {
+            print!("(provide");
+            for name in names {
+                print!(" {}", name);
+            }
+            print!(")");
+        }
+        Require(path, _) => {
+            print!("(require {:?})", path);
+        }
+        Apply(fun, args) => {
+            print!("(");
+            print_ast(fun);
+            for arg in args {
+                print!(" ");
+                print_ast(arg);
+            }
+            print!(")");
+        }
+        TailCall(fun, args) => {
+            print!("(tail-call ");
+            print_ast(fun);
+            for arg in args {
+                print!(" ");
+                print_ast(arg);
+            }
+            print!(")");
+        }
+        Lambda {
+            params, ret, body, ..
+        } => {
+            print!("(lambda (");
+            for (i, (param, t)) in params.into_iter().enumerate() {
+                if i > 0 {
+                    print!(" ");
+                }
+                print!("[{} : {:?}]", param, t);
+            }
+            print!(") : {:?} ", ret);
+            print_ast(body);
+            print!(")");
+        }
+        FunRef(label) => {
+            print!("(fun-ref {})", label);
+        }
+        Instance(name, types) => {
+            print!("(inst {}", name);
+            for t in types {
+                print!(" {:?}", t);
+            }
+            print!(")");
+        }
+        Return(value) => {
+            print!("(return ");
+            print_ast(value);
+            print!(")");
+        }
         Neg(node) => {
             print!("(- ");
             print_ast(node);
@@ -22,8 +189,188 @@ pub fn print_ast(node: Box<Node>) {
             print_ast(right);
             print!(")");
         }
-        Read => {
-            print!("(read)");
+        Sub(left, right) => {
+            print!("(- ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        Mul(left, right) => {
+            print!("(* ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        Quotient(left, right) => {
+            print!("(quotient ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        Remainder(left, right) => {
+            print!("(remainder ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        BitAnd(left, right) => {
+            print!("(bit-and ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        BitOr(left, right) => {
+            print!("(bit-or ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        BitXor(left, right) => {
+            print!("(bit-xor ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        Ash(left, right) => {
+            print!("(ash ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        Shl(left, right) => {
+            print!("(shl ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        Shr(left, right) => {
+            print!("(shr ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        Sar(left, right) => {
+            print!("(sar ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        Min(left, right) => {
+            print!("(min ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        Max(left, right) => {
+            print!("(max ");
+            print_ast(left);
+            print!(" ");
+            print_ast(right);
+            print!(")");
+        }
+        BitNot(node) => {
+            print!("(bit-not ");
+            print_ast(node);
+            print!(")");
+        }
+        Read(t) => match t {
+            Type::Boolean => print!("(read-bool)"),
+            Type::Char => print!("(read-char)"),
+            _ => print!("(read)"),
+        },
+        CommandLineArguments => print!("(command-line-arguments)"),
+        Random(bound) => {
+            print!("(random ");
+            print_ast(bound);
+            print!(")");
+        }
+        Exit(code) => {
+            print!("(exit ");
+            print_ast(code);
+            print!(")");
+        }
+        Assert(cond, _) => {
+            print!("(assert ");
+            print_ast(cond);
+            print!(")");
+        }
+        Raise(msg) => {
+            print!("(raise ");
+            print_ast(msg);
+            print!(")");
+        }
+        CallCC(fun) => {
+            print!("(call/cc ");
+            print_ast(fun);
+            print!(")");
+        }
+        WithHandler(handler, thunk) => {
+            print!("(with-handler ");
+            print_ast(handler);
+            print!(" ");
+            print_ast(thunk);
+            print!(")");
+        }
+        Error(msg, value, _) => {
+            print!("(error ");
+            print_ast(msg);
+            if let Some(value) = value {
+                print!(" ");
+                print_ast(value);
+            }
+            print!(")");
+        }
+        Format(pieces, args, _) => {
+            print!("(format {:?}", pieces.join("~a").replace('\n', "~%"));
+            for arg in args {
+                print!(" ");
+                print_ast(arg);
+            }
+            print!(")");
+        }
+        Values(exps, _) => {
+            print!("(values");
+            for exp in exps {
+                print!(" ");
+                print_ast(exp);
+            }
+            print!(")");
+        }
+        LetValues { bindings, exp } => {
+            print!("(let-values (");
+            for (i, (names, value)) in bindings.into_iter().enumerate() {
+                if i > 0 {
+                    print!(" ");
+                }
+                print!("[({}) ", names.join(" "));
+                print_ast(value);
+                print!("]");
+            }
+            print!(") ");
+            print_ast(exp);
+            print!(")");
+        }
+        Ann(exp, t, _) => {
+            print!("(ann ");
+            print_ast(exp);
+            print!(" {:?})", t);
+        }
+        Print(exp, _) => {
+            print!("(print ");
+            print_ast(exp);
+            print!(")");
         }
         Var(var) => print!("{}", var),
         Let { name, value, exp } => {
@@ -33,21 +380,92 @@ pub fn print_ast(node: Box<Node>) {
             print_ast(exp);
             print!(")");
         }
+        node @ LetStar { .. } | node @ LetRec { .. } => {
+            let (bindings, exp) = match node {
+                LetStar { bindings, exp } => {
+                    print!("(let* (");
+                    (bindings, exp)
+                }
+                LetRec { bindings, exp } => {
+                    print!("(letrec (");
+                    (bindings, exp)
+                }
+                node => panic!("unexpected {:?}", node),
+            };
+            for (i, (name, value)) in bindings.into_iter().enumerate() {
+                if i > 0 {
+                    print!(" ");
+                }
+                print!("[{} ", name);
+                print_ast(value);
+                print!("]");
+            }
+            print!(") ");
+            print_ast(exp);
+            print!(")");
+        }
+        NamedLet {
+            name,
+            bindings,
+            body,
+            ..
+        } => {
+            print!("(let {} (", name);
+            for (i, (param, value)) in bindings.into_iter().enumerate() {
+                if i > 0 {
+                    print!(" ");
+                }
+                print!("[{} ", param);
+                print_ast(value);
+                print!("]");
+            }
+            print!(") ");
+            print_ast(body);
+            print!(")");
+        }
+        SetBang { name, value } => {
+            print!("(set! {} ", name);
+            print_ast(value);
+            print!(")");
+        }
+        Begin(effects, exp) => {
+            print!("(begin");
+            for effect in effects {
+                print!(" ");
+                print_ast(effect);
+            }
+            print!(" ");
+            print_ast(exp);
+            print!(")");
+        }
         Not(exp) => {
             print!("(not ");
             print_ast(exp);
             print!(")");
         }
-        Eq(lhs, rhs) => {
-            print!("(== ");
+        And(lhs, rhs) => {
+            print!("(and ");
             print_ast(lhs);
             print!(" ");
             print_ast(rhs);
             print!(")");
         }
-        b @ True | b @ False => {
-            print!("{:?}", b);
+        Or(lhs, rhs) => {
+            print!("(or ");
+            print_ast(lhs);
+            print!(" ");
+            print_ast(rhs);
+            print!(")");
         }
+        Eq(lhs, rhs) => {
+            print!("(eq? ");
+            print_ast(lhs);
+            print!(" ");
+            print_ast(rhs);
+            print!(")");
+        }
+        True => print!("#t"),
+        False => print!("#f"),
         op @ Lt(_, _) | op @ Lte(_, _) | op @ Gt(_, _) | op @ Gte(_, _) => {
             print!("(");
             let (lhs, rhs) = match op {
@@ -82,7 +500,19 @@ pub fn print_ast(node: Box<Node>) {
             print_ast(node);
             print!(")");
         }
-        reg @ RAX | reg @ RBX | reg @ AL => {
+        Phi(args) => {
+            print!("(phi");
+            for (label, value) in args {
+                print!(" [{} ", label);
+                print_ast(value);
+                print!("]");
+            }
+            print!(")");
+        }
+        reg @ AL | reg @ CL => {
+            print!("(reg {:?})", reg);
+        }
+        reg if reg.is_register() || reg.is_xmm() => {
             print!("(reg {:?})", reg);
         }
         MOVQ { target, source } => {
@@ -97,8 +527,105 @@ pub fn print_ast(node: Box<Node>) {
             print!(" ");
             print_ast(target);
         }
-        CALLQ(fname) => {
-            print!("CALLQ {}", fname);
+        SUBQ { target, arg } => {
+            print!("SUBQ ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        IMULQ { target, arg } => {
+            print!("IMULQ ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        NEGQ(target) => {
+            print!("NEGQ ");
+            print_ast(target);
+        }
+        ANDQ { target, arg } => {
+            print!("ANDQ ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        ORQ { target, arg } => {
+            print!("ORQ ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        SALQ { target, arg } => {
+            print!("SALQ ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        SARQ { target, arg } => {
+            print!("SARQ ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        SHRQ { target, arg } => {
+            print!("SHRQ ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        NOTQ(target) => {
+            print!("NOTQ ");
+            print_ast(target);
+        }
+        XORQ { target, arg } => {
+            print!("XORQ ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        ADDSD { target, arg } => {
+            print!("ADDSD ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        SUBSD { target, arg } => {
+            print!("SUBSD ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        MULSD { target, arg } => {
+            print!("MULSD ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        DIVSD { target, arg } => {
+            print!("DIVSD ");
+            print_ast(arg);
+            print!(" ");
+            print_ast(target);
+        }
+        CVTSI2SDQ { target, source } => {
+            print!("CVTSI2SDQ ");
+            print_ast(source);
+            print!(" ");
+            print_ast(target);
+        }
+        CVTTSD2SIQ { target, source } => {
+            print!("CVTTSD2SIQ ");
+            print_ast(source);
+            print!(" ");
+            print_ast(target);
+        }
+        CALLQ(fname, arity) => {
+            print!("CALLQ {} {}", fname, arity);
+        }
+        CQTO => print!("CQTO"),
+        IDIVQ(arg) => {
+            print!("IDIVQ ");
+            print_ast(arg);
         }
         CMPQ(lhs, rhs) => {
             print!("CMPQ ");
@@ -116,18 +643,61 @@ pub fn print_ast(node: Box<Node>) {
             print!(" ");
             print_ast(target);
         }
+        CMOV {
+            cond,
+            source,
+            target,
+        } => {
+            print!("CMOV {:?} ", cond);
+            print_ast(source);
+            print!(" ");
+            print_ast(target);
+        }
         JMPIF(cond_code, label) => {
             print!("(jmp-if {:?} {})", cond_code, label);
         }
         JMP(label) => {
             print!("(jmp {})", label);
         }
+        TAILJMP(label, arity) => {
+            print!("(tail-jmp {} {})", label, arity);
+        }
+        ITAILJMP(fun, arity) => {
+            print!("(tail-jmp *");
+            print_ast(fun);
+            print!(" {})", arity);
+        }
+        ICALLQ(fun, arity) => {
+            print!("CALLQ *");
+            print_ast(fun);
+            print!(" {}", arity);
+        }
+        LEAQ { target, source } => {
+            print!("LEAQ ");
+            print_ast(source);
+            print!(" ");
+            print_ast(target);
+        }
         Label(label) => {
             print!("(label {})", label);
         }
         StackLoc(offset) => {
             print!("(deref RBP {})", offset);
         }
+        Deref(reg, offset) => {
+            print!("(deref {:?} {})", reg, offset);
+        }
+        Indexed(base, index, scale) => {
+            print!("(indexed {:?} {:?} {})", base, index, scale);
+        }
+        Global(name) => {
+            print!("(global {})", name);
+        }
+        GlobalSet(label, value) => {
+            print!("(global-set! {} ", label);
+            print_ast(value);
+            print!(")");
+        }
         If {
             cond,
             if_exps,
@@ -148,10 +718,316 @@ pub fn print_ast(node: Box<Node>) {
             }
             print!(")\n)");
         }
+        Match { exp, clauses, .. } => {
+            print!("(match ");
+            print_ast(exp);
+            for (pattern, body) in clauses {
+                print!(" [");
+                print_pattern(pattern);
+                print!(" ");
+                print_ast(body);
+                print!("]");
+            }
+            print!(")");
+        }
+        Cond { clauses, else_exp } => {
+            print!("(cond");
+            for (test, exp) in clauses {
+                print!(" [");
+                print_ast(test);
+                print!(" ");
+                print_ast(exp);
+                print!("]");
+            }
+            if let Some(exp) = else_exp {
+                print!(" [else ");
+                print_ast(exp);
+                print!("]");
+            }
+            print!(")");
+        }
+        When(cond, body) => {
+            print!("(when ");
+            print_ast(cond);
+            print!(" ");
+            print_ast(body);
+            print!(")");
+        }
+        Unless(cond, body) => {
+            print!("(unless ");
+            print_ast(cond);
+            print!(" ");
+            print_ast(body);
+            print!(")");
+        }
+        Do {
+            bindings,
+            test,
+            result,
+            body,
+        } => {
+            print!("(do (");
+            for (i, (name, init, step)) in bindings.into_iter().enumerate() {
+                if i > 0 {
+                    print!(" ");
+                }
+                print!("[{} ", name);
+                print_ast(init);
+                if let Some(step) = step {
+                    print!(" ");
+                    print_ast(step);
+                }
+                print!("]");
+            }
+            print!(") (");
+            print_ast(test);
+            print!(" ");
+            print_ast(result);
+            print!(") ");
+            print_ast(body);
+            print!(")");
+        }
+        Vector(elems) => {
+            print!("(vector");
+            for elem in elems {
+                print!(" ");
+                print_ast(elem);
+            }
+            print!(")");
+        }
+        Literal(vec) => {
+            print!("(literal ");
+            print_ast(vec);
+            print!(")");
+        }
+        StaticVector(elems, _) => {
+            print!("#(");
+            for (i, elem) in elems.into_iter().enumerate() {
+                if i > 0 {
+                    print!(" ");
+                }
+                print_ast(elem);
+            }
+            print!(")");
+        }
+        VectorLength(vec) => {
+            print!("(vector-length ");
+            print_ast(vec);
+            print!(")");
+        }
+        VectorRef(vec, index) => {
+            print!("(vector-ref ");
+            print_ast(vec);
+            print!(" ");
+            print_ast(index);
+            print!(")");
+        }
+        VectorSet(vec, index, value) => {
+            print!("(vector-set! ");
+            print_ast(vec);
+            print!(" ");
+            print_ast(index);
+            print!(" ");
+            print_ast(value);
+            print!(")");
+        }
+        Null => print!("null"),
+        Cons(car, cdr) => {
+            print!("(cons ");
+            print_ast(car);
+            print!(" ");
+            print_ast(cdr);
+            print!(")");
+        }
+        Car(exp) => {
+            print!("(car ");
+            print_ast(exp);
+            print!(")");
+        }
+        Cdr(exp) => {
+            print!("(cdr ");
+            print_ast(exp);
+            print!(")");
+        }
+        IsNull(exp) => {
+            print!("(null? ");
+            print_ast(exp);
+            print!(")");
+        }
+        MakeArray(len, init, _) => {
+            print!("(make-array ");
+            print_ast(len);
+            print!(" ");
+            print_ast(init);
+            print!(")");
+        }
+        ArrayLength(array) => {
+            print!("(array-length ");
+            print_ast(array);
+            print!(")");
+        }
+        ArrayRef(array, index) => {
+            print!("(array-ref ");
+            print_ast(array);
+            print!(" ");
+            print_ast(index);
+            print!(")");
+        }
+        ArraySet(array, index, value) => {
+            print!("(array-set! ");
+            print_ast(array);
+            print!(" ");
+            print_ast(index);
+            print!(" ");
+            print_ast(value);
+            print!(")");
+        }
+        MakeHashTable(key_t, value_t) => print!("(make-hash {:?} {:?})", key_t, value_t),
+        HashTableRef(table, key, default) => {
+            print!("(hash-ref ");
+            print_ast(table);
+            print!(" ");
+            print_ast(key);
+            if let Some(default) = default {
+                print!(" ");
+                print_ast(default);
+            }
+            print!(")");
+        }
+        HashTableSet(table, key, value) => {
+            print!("(hash-set! ");
+            print_ast(table);
+            print!(" ");
+            print_ast(key);
+            print!(" ");
+            print_ast(value);
+            print!(")");
+        }
+        MakeBox(exp) => {
+            print!("(box ");
+            print_ast(exp);
+            print!(")");
+        }
+        Unbox(exp) => {
+            print!("(unbox ");
+            print_ast(exp);
+            print!(")");
+        }
+        SetBox(exp, value) => {
+            print!("(set-box! ");
+            print_ast(exp);
+            print!(" ");
+            print_ast(value);
+            print!(")");
+        }
+        HasType(exp, t) => {
+            print!("(has-type ");
+            print_ast(exp);
+            print!(" {:?})", t);
+        }
+        Inject(exp, t) => {
+            print!("(inject ");
+            print_ast(exp);
+            print!(" {:?})", t);
+        }
+        Project(exp, t, _) => {
+            print!("(project ");
+            print_ast(exp);
+            print!(" {:?})", t);
+        }
+        StringEq(lhs, rhs) => {
+            print!("(string=? ");
+            print_ast(lhs);
+            print!(" ");
+            print_ast(rhs);
+            print!(")");
+        }
+        StringLength(s) => {
+            print!("(string-length ");
+            print_ast(s);
+            print!(")");
+        }
+        StringRef(s, index) => {
+            print!("(string-ref ");
+            print_ast(s);
+            print!(" ");
+            print_ast(index);
+            print!(")");
+        }
+        StringAppend(lhs, rhs) => {
+            print!("(string-append ");
+            print_ast(lhs);
+            print!(" ");
+            print_ast(rhs);
+            print!(")");
+        }
+        Cast { exp, from, to, .. } => {
+            print!("(cast ");
+            print_ast(exp);
+            print!(" {:?} {:?})", from, to);
+        }
+        Located(_, exp) => print_ast(exp),
+        Allocate(len, t) => {
+            print!("(allocate {} {:?})", len, t);
+        }
+        Collect(bytes) => {
+            print!("(collect {})", bytes);
+        }
+        While {
+            cond_exps,
+            cond,
+            body_exps,
+        } => {
+            print!("(while\n(\n");
+            for node in cond_exps {
+                print_ast(node);
+                println!();
+            }
+            print!(") (");
+            print_ast(cond);
+            print!(")\n(\n");
+            for node in body_exps {
+                print_ast(node);
+                println!();
+            }
+            print!(")\n)");
+        }
+        Void => print!("(void)"),
+        Goto(label) => {
+            print!("(goto {})", label);
+        }
+        IfGoto {
+            cond,
+            then_label,
+            else_label,
+        } => {
+            print!("(if ");
+            print_ast(cond);
+            print!(" (goto {}) (goto {}))", then_label, else_label);
+        }
         val => panic!("unexpected {:?}", val),
     }
 }
 
+fn print_pattern(pattern: Pattern) {
+    match pattern {
+        Pattern::Wildcard => print!("_"),
+        Pattern::Var(name) => print!("{}", name),
+        Pattern::Literal(literal) => print_ast(Box::new(literal)),
+        Pattern::Vector(patterns) => print_patterns("vector", patterns),
+        Pattern::Variant(name, patterns) => print_patterns(&name, patterns),
+    }
+}
+
+fn print_patterns(head: &str, patterns: Vec<Pattern>) {
+    print!("({}", head);
+    for pattern in patterns {
+        print!(" ");
+        print_pattern(pattern);
+    }
+    print!(")");
+}
+
 pub fn print_stmt(node_list: Vec<Box<Node>>) {
     for node in node_list {
         print_ast(node);
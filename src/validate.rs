@@ -0,0 +1,121 @@
+/// Invariants of the program between the passes, each check returns
+/// the first node breaking the invariant
+use crate::ast::{Function, Node};
+use std::collections::HashSet;
+
+/// the variables the node binds around its sub expressions
+fn bound_vars(node: &Node) -> Vec<&String> {
+    use Node::*;
+
+    match node {
+        Def { params, .. } | Lambda { params, .. } => params.iter().map(|(name, _)| name).collect(),
+        Let { name, .. } => vec![name],
+        LetStar { bindings, .. } | LetRec { bindings, .. } => {
+            bindings.iter().map(|(name, _)| name).collect()
+        }
+        NamedLet { name, bindings, .. } => std::iter::once(name)
+            .chain(bindings.iter().map(|(param, _)| param))
+            .collect(),
+        LetValues { bindings, .. } => bindings.iter().flat_map(|(names, _)| names).collect(),
+        Do { bindings, .. } => bindings.iter().map(|(name, _, _)| name).collect(),
+        Match { clauses, .. } => clauses
+            .iter()
+            .flat_map(|(pattern, _)| pattern.vars())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// a variable is bound once in the whole program
+pub fn unique_names(exp: &Node) -> Result<(), String> {
+    fn visit<'a>(node: &'a Node, seen: &mut HashSet<&'a String>) -> Result<(), String> {
+        for name in bound_vars(node) {
+            if !seen.insert(name) {
+                return Err(format!("variable {} is bound more than once", name));
+            }
+        }
+        node.children()
+            .into_iter()
+            .try_for_each(|child| visit(child, seen))
+    }
+
+    visit(exp, &mut HashSet::new())
+}
+
+fn is_atom(exp: &Node) -> bool {
+    exp.var().is_some()
+        || exp.is_literal()
+        || matches!(
+            exp,
+            Node::Void | Node::Str(_) | Node::FunRef(_) | Node::Global(_)
+        )
+}
+
+/// an atom or an operation of atoms
+fn is_simple(exp: &Node) -> bool {
+    is_atom(exp) || exp.children().into_iter().all(is_atom)
+}
+
+fn atomic_stmts(stmts: &[Box<Node>]) -> Result<(), String> {
+    use Node::*;
+
+    for stmt in stmts {
+        let atomic = match stmt.as_ref() {
+            Assign(_, exp) | Return(exp) | IfGoto { cond: exp, .. } => is_simple(exp),
+            TailCall(fun, args) => std::iter::once(fun).chain(args).all(|exp| is_atom(exp)),
+            // the branches and loops are left to explicate control
+            If {
+                cond,
+                if_exps,
+                else_exps,
+            } => {
+                atomic_stmts(if_exps)?;
+                atomic_stmts(else_exps)?;
+                is_simple(cond)
+            }
+            While {
+                cond_exps,
+                cond,
+                body_exps,
+            } => {
+                atomic_stmts(cond_exps)?;
+                atomic_stmts(body_exps)?;
+                is_simple(cond)
+            }
+            stmt => stmt.children().into_iter().all(is_atom),
+        };
+        if !atomic {
+            return Err(format!("an operand of {:?} is not an atom", stmt));
+        }
+    }
+    Ok(())
+}
+
+/// the operands of the operations in the statements are atoms
+pub fn atomic_operands(defs: &[Box<Node>]) -> Result<(), String> {
+    for def in defs {
+        if let Node::Def { body, .. } = def.as_ref() {
+            atomic_stmts(body)?;
+        }
+    }
+    Ok(())
+}
+
+/// every variable is allocated to a register or a stack location
+pub fn no_vars(functions: &[Function]) -> Result<(), String> {
+    for function in functions {
+        for inst in &function.body {
+            if let Some(var) = inst
+                .operands()
+                .into_iter()
+                .find_map(|operand| operand.var())
+            {
+                return Err(format!(
+                    "variable {} is left in {:?} of {}",
+                    var, inst, function.name
+                ));
+            }
+        }
+    }
+    Ok(())
+}
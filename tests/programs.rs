@@ -0,0 +1,387 @@
+//! Compile programs with the runtime, then check their output and exit code
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Once;
+
+fn tmp_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+}
+
+/// the runtime is built once for every test
+fn runtime() -> PathBuf {
+    static BUILD: Once = Once::new();
+    let object = tmp_dir().join("runtime.o");
+    BUILD.call_once(|| {
+        let status = Command::new("cc")
+            .args(["-c", "-o"])
+            .arg(&object)
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/runtime/runtime.c"))
+            .status()
+            .expect("cc");
+        assert!(status.success(), "runtime build failed");
+    });
+    object
+}
+
+/// the error of the compiler, or the executable
+fn compile(name: &str, source: &str, flags: &[&str]) -> Result<PathBuf, String> {
+    let source_path = tmp_dir().join(format!("{}.rkt", name));
+    fs::write(&source_path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_learn-compiler"))
+        .args(flags)
+        .arg(&source_path)
+        .output()
+        .unwrap();
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+    let asm = tmp_dir().join(format!("{}.s", name));
+    fs::write(&asm, &output.stdout).unwrap();
+    let exe = tmp_dir().join(name);
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&exe)
+        .arg(runtime())
+        .arg(&asm)
+        .arg("-lm")
+        .status()
+        .unwrap();
+    assert!(status.success(), "{}: link failed", name);
+    Ok(exe)
+}
+
+/// the output and the exit code of the program
+fn run(name: &str, source: &str, flags: &[&str], input: &str) -> (String, i32) {
+    let exe = compile(name, source, flags).unwrap_or_else(|err| panic!("{}: {}", name, err));
+    let mut child = Command::new(exe)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    (text, output.status.code().unwrap_or(-1))
+}
+
+#[test]
+fn exit_as_operand() {
+    assert_eq!(
+        run("exit_add", "(+ 1 (exit 2))", &[], ""),
+        (String::new(), 2)
+    );
+    assert_eq!(run("exit_neg", "(- (exit 7))", &[], ""), (String::new(), 7));
+    let source = "(+ 1 (if (< (read) 0) (exit 3) 4))";
+    assert_eq!(run("exit_branch", source, &[], "-1"), (String::new(), 3));
+    assert_eq!(run("exit_branch", source, &[], "1"), ("5".to_string(), 0));
+}
+
+#[test]
+fn exit_as_condition() {
+    assert_eq!(
+        run("exit_if", "(if (exit 1) 1 2)", &[], ""),
+        (String::new(), 1)
+    );
+}
+
+#[test]
+fn string_ref_literal_index_checked() {
+    let source = "(string-ref (string-append \"a\" \"b\") 100000)";
+    let (output, code) = run("sref_oob", source, &["--bounds-check"], "");
+    assert!(output.contains("string index out of range"), "{}", output);
+    assert_eq!(code, 1);
+    let source = "(char->integer (string-ref (string-append \"a\" \"b\") 1))";
+    let flags = ["--bounds-check", "--bignum"];
+    assert_eq!(run("sref_last", source, &flags, ""), ("98".to_string(), 0));
+}
+
+#[test]
+fn macro_name_rebound_locally() {
+    let source = "(define-syntax m (syntax-rules () [(_ x) (* x 10)]))
+        (+ (let ([m 3]) m) (m 4))";
+    assert_eq!(run("macro_rebound", source, &[], ""), ("43".to_string(), 0));
+}
+
+#[test]
+fn macro_refers_to_global() {
+    let source = "(define one 1)
+        (define-syntax inc (syntax-rules () [(_ x) (+ x one)]))
+        (let ([one 100]) (inc 5))";
+    assert_eq!(run("macro_global", source, &[], ""), ("6".to_string(), 0));
+}
+
+#[test]
+fn car_of_empty_list() {
+    let (output, code) = run("car_empty", "(car (cdr (list 1)))", &[], "");
+    assert!(output.contains("car of empty list"), "{}", output);
+    assert_eq!(code, 1);
+    let source = "(cdr (cdr (list 1)))";
+    let (output, code) = run("cdr_empty", source, &["--bignum"], "");
+    assert!(output.contains("cdr of empty list"), "{}", output);
+    assert_eq!(code, 1);
+    let source = "(car (cdr (list 1 2)))";
+    assert_eq!(run("car_pair", source, &[], ""), ("2".to_string(), 0));
+}
+
+#[test]
+fn unroll_huge_factor() {
+    let source = "(let* ([i 0] [s 0])
+        (begin (while (< i 10) (begin (set! s (+ s i)) (set! i (+ i 1)))) s))";
+    let flags = ["--unroll=100000000000"];
+    assert_eq!(
+        run("unroll_huge", source, &flags, ""),
+        ("45".to_string(), 0)
+    );
+}
+
+#[test]
+fn hash_string_keys_by_content() {
+    let source = "(let ([h (make-hash String Integer)])
+        (begin
+          (hash-set! h (string-append \"a\" \"b\") 1)
+          (hash-set! h \"ab\" (+ (hash-ref h \"ab\") 1))
+          (print (hash-ref h \"ab\"))
+          (hash-ref h \"abc\" 7)))";
+    assert_eq!(run("hash_strings", source, &[], ""), ("27".to_string(), 0));
+}
+
+#[test]
+fn hash_tables_traced_by_collector() {
+    let source =
+        "(define (fill [h : (HashTable Integer (Vector Integer Integer))] [i : Integer]) : Integer
+          (if (< i 2000) (begin (hash-set! h i (vector i (* i i))) (fill h (+ i 1))) 0))
+        (define (churn [i : Integer] [s : Integer]) : Integer
+          (if (< i 300)
+              (let ([h (make-hash Integer (Vector Integer Integer))])
+                (begin (fill h 0) (churn (+ i 1) (+ s (vector-ref (hash-ref h 1999) 1)))))
+              s))
+        (let ([h (make-hash Integer (Vector Integer Integer))])
+          (begin (fill h 0) (+ (churn 0 0) (vector-ref (hash-ref h 1234) 1))))";
+    let expected = "1200323056".to_string();
+    assert_eq!(run("hash_gc", source, &[], ""), (expected, 0));
+}
+
+#[test]
+fn letrec_self_recursive() {
+    let source = "(letrec ([f (lambda ([n : Integer]) : Integer
+                         (if (== n 0) 1 (* n (f (- n 1)))))])
+        (f 5))";
+    assert_eq!(run("letrec_self", source, &[], ""), ("120".to_string(), 0));
+}
+
+#[test]
+fn letrec_mutually_recursive() {
+    let source = "(letrec ([even? (lambda ([n : Integer]) : Boolean
+                             (if (== n 0) #t (odd? (- n 1))))]
+                 [odd? (lambda ([n : Integer]) : Boolean
+                            (if (== n 0) #f (even? (- n 1))))])
+        (if (even? 10) (if (odd? 7) 1 2) 3))";
+    assert_eq!(run("letrec_mutual", source, &[], ""), ("1".to_string(), 0));
+}
+
+#[test]
+fn for_range_with_step() {
+    let source = "(let ([s 0])
+        (begin (for ([i (range 10 0 -3)]) (set! s (+ (* s 100) i))) s))";
+    assert_eq!(
+        run("for_step", source, &[], ""),
+        ("10070401".to_string(), 0)
+    );
+}
+
+#[test]
+fn for_range_in_source_order() {
+    let source = "(let ([s 0])
+        (begin (for ([i (range (read) (read))]) (set! s (+ s i))) s))";
+    assert_eq!(run("for_order", source, &[], "3 6"), ("12".to_string(), 0));
+}
+
+#[test]
+fn for_keeps_user_variables() {
+    let source = "(let* ([for.end 100] [s 0])
+        (begin (for ([i (range 3)]) (set! s (+ s for.end))) s))";
+    assert_eq!(run("for_capture", source, &[], ""), ("300".to_string(), 0));
+}
+
+#[test]
+fn do_loop_steps() {
+    let source = "(do ([i 0 (+ i 1)] [acc 1]) ((== i 4) acc) (set! acc (* acc 2)))";
+    assert_eq!(run("do_steps", source, &[], ""), ("16".to_string(), 0));
+}
+
+#[test]
+fn do_closures_keep_each_run() {
+    let source = "(let ([fs (make-array 3 (lambda () : Integer 0))])
+        (do ([i 0 (+ i 1)])
+            ((== i 3) (+ (* 100 ((array-ref fs 0))) (+ (* 10 ((array-ref fs 1))) ((array-ref fs 2)))))
+          (array-set! fs i (lambda () : Integer i))))";
+    assert_eq!(run("do_closures", source, &[], ""), ("12".to_string(), 0));
+}
+
+#[test]
+fn do_keeps_user_variables() {
+    let source = "(let ([do.0 100]) (do ([i 0 (+ i 1)] [s 0 (+ s do.0)]) ((== i 3) s)))";
+    assert_eq!(run("do_capture", source, &[], ""), ("300".to_string(), 0));
+}
+
+#[test]
+fn vector_literal_constant() {
+    let source = "(define (pair) : (Vector Integer Integer) #(1 2))
+        (+ (vector-ref (pair) 0) (vector-ref #(3 4) 1))";
+    assert_eq!(run("literal_const", source, &[], ""), ("5".to_string(), 0));
+}
+
+#[test]
+fn vector_literal_fresh_in_loop() {
+    let source = "(let ([s 0])
+        (begin
+          (for ([i (range 3)])
+            (let ([v #(0)])
+              (begin (set! s (+ s (vector-ref v 0))) (vector-set! v 0 (+ (vector-ref v 0) 1)))))
+          s))";
+    assert_eq!(run("literal_loop", source, &[], ""), ("0".to_string(), 0));
+}
+
+#[test]
+fn vector_literal_fresh_per_call() {
+    let source = "(define (pair) : (Vector Integer Integer) #(1 2))
+        (begin (vector-set! (pair) 0 100) (vector-ref (pair) 0))";
+    assert_eq!(run("literal_call", source, &[], ""), ("1".to_string(), 0));
+}
+
+#[test]
+fn tail_branches_without_dead_code_removal() {
+    let source = "(define (f [n : Integer]) : Integer (if (== n 0) 5 (+ n 1)))
+        (define (g [n : Integer]) : Integer (if (== n 0) (f 3) (if (< n 5) 7 (g (- n 1)))))
+        (+ (f 0) (+ (f 4) (+ (g 9) (g 0))))";
+    let flags = ["--skip=remove_dead_code"];
+    assert_eq!(
+        run("tail_no_dce", source, &flags, ""),
+        ("21".to_string(), 0)
+    );
+}
+
+#[test]
+fn bignum_churn_and_shift() {
+    let source = "(let* ([s 4611686018427387903] [i 0])
+        (begin
+          (while (< i 1000000) (begin (set! s (+ s 1)) (set! s (- s 1)) (set! i (+ i 1))))
+          (print (quotient (* s s) s))
+          (print (ash -3 100))
+          (eq? (+ s 1) (+ s 1))))";
+    let expected = "4611686018427387903-3802951800684688204490109616128#t".to_string();
+    assert_eq!(
+        run("bignum_churn", source, &["--bignum"], ""),
+        (expected, 0)
+    );
+}
+
+#[test]
+fn struct_with_many_fields() {
+    let source = "(define-struct p ([a : Integer] [b : Integer] [c : Integer] [d : Integer]
+          [e : Integer] [f : Integer] [g : Integer]))
+        (let ([x (p 1 2 3 4 5 6 7)])
+          (begin (set-p-g! x 10) (+ (p-a x) (+ (p-f x) (p-g x)))))";
+    assert_eq!(run("struct_many", source, &[], ""), ("17".to_string(), 0));
+}
+
+#[test]
+fn variant_with_many_fields() {
+    let source = "(define-type Shape
+          [Big Integer Integer Integer Integer Integer Integer Integer]
+          [Small Integer])
+        (define (area [s : Shape]) : Integer
+          (match s
+            [(Big a b c d e f g) (+ a (+ f g))]
+            [(Small a) a]))
+        (+ (area (Big 1 2 3 4 5 6 7)) (area (Small 30)))";
+    assert_eq!(run("variant_many", source, &[], ""), ("44".to_string(), 0));
+}
+
+#[test]
+fn defaults_with_many_params() {
+    let source = "(define (f [a : Integer] [b : Integer] [c : Integer] [d : Integer] [e : Integer]
+          [g : Integer 6] [h : Integer 7]) : Integer
+          (+ a (+ g h)))
+        (+ (f 1 2 3 4 5) (+ (f 1 2 3 4 5 10) (f 1 2 3 4 5 10 20)))";
+    assert_eq!(run("defaults_many", source, &[], ""), ("63".to_string(), 0));
+}
+
+#[test]
+fn division_by_zero() {
+    for (name, source) in [
+        ("quotient_zero", "(quotient 7 (read))"),
+        ("remainder_zero", "(remainder 7 (read))"),
+    ] {
+        let (output, code) = run(name, source, &[], "0");
+        assert!(output.contains("division by zero"), "{}", output);
+        assert_eq!(code, 1);
+    }
+}
+
+#[test]
+fn division_of_minimum_by_minus_one() {
+    let quotient = "(quotient (- (- 0 9223372036854775807) (read)) (- 0 (read)))";
+    let (output, code) = run("quotient_min", quotient, &["--overflow-check"], "1 1");
+    assert!(output.contains("fixnum overflow"), "{}", output);
+    assert_eq!(code, 1);
+    let remainder = "(remainder (- (- 0 9223372036854775807) (read)) (- 0 (read)))";
+    assert_eq!(
+        run("remainder_min", remainder, &[], "1 1"),
+        ("0".to_string(), 0)
+    );
+}
+
+#[test]
+fn integer_to_char_checks_code_point() {
+    let source = "(integer->char (read))";
+    assert_eq!(run("int_char", source, &[], "65"), ("A".to_string(), 0));
+    for input in ["-1", "55296", "57343", "1114112"] {
+        let (output, code) = run("int_char", source, &[], input);
+        assert!(output.contains("integer is not a character"), "{}", output);
+        assert_eq!(code, 1);
+    }
+}
+
+#[test]
+fn unknown_option_rejected() {
+    let err = compile("unknown_option", "1", &["--bounds-chek"]).unwrap_err();
+    assert!(err.contains("unknown option --bounds-chek"), "{}", err);
+}
+
+#[test]
+fn read_out_of_range_integer() {
+    assert_eq!(
+        run("read_min", "(read)", &[], "-9223372036854775808"),
+        ("-9223372036854775808".to_string(), 0)
+    );
+    for input in ["9223372036854775808", "-9223372036854775809"] {
+        let (output, code) = run("read_range", "(read)", &[], input);
+        assert!(output.contains("malformed integer input"), "{}", output);
+        assert_eq!(code, 1);
+    }
+}
+
+#[test]
+fn malformed_type_printed_as_source() {
+    let err = compile("bad_type", "(let ([x : (Vector Integer [a b]) 1]) x)", &[]).unwrap_err();
+    assert!(err.contains("expected type, got (a b)"), "{}", err);
+}
+
+#[test]
+fn type_errors_located() {
+    let err = compile("unknown_var", "(+ 1 y)", &[]).unwrap_err();
+    assert!(err.contains("1:6: Unknown variable y"), "{}", err);
+    let source = "(define (f [x : Integer]) : Integer x)\n(f 1 2)";
+    let err = compile("arity", source, &[]).unwrap_err();
+    assert!(err.contains("2:1: Function expects 1 arguments"), "{}", err);
+}